@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crate::model::{cuttertype::CutterType, papersize::PaperSize};
 use serde::{Serialize, Deserialize};
 
@@ -143,6 +143,10 @@ pub struct Args {
     #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
     pub start_dir_y: f64,
 
+    /// Start direction as an angle in degrees from the x-axis towards the y-axis, used instead of the direction components
+    #[arg(long, default_value = None, allow_negative_numbers = true, value_name = "DEGREES")]
+    pub start_angle: Option<f64>,
+
     /// Use perturbation angle for direction changes at bounce 
     #[arg(long, short='p', default_value_t = true, action = clap::ArgAction::Set)]
     pub perturb: bool,
@@ -173,7 +177,7 @@ pub struct Args {
     pub stop_time: f64,
 
     /// Stop when we have reached this coverage percentage
-    /// This is a soft limit, the simulation will still run until the specified bounces or time is reached if specified
+    /// As with all stop conditions the simulation stops as soon as the first of them is reached
     #[arg(long, short = 'c', default_value_t = 0.0,
         value_parser = clap::builder::ValueParser::new(|s: &str| -> Result<f64, String> {
             let val: f64 = s.parse().map_err(|_| "Not a valid coverage percent".to_string())?;
@@ -194,6 +198,16 @@ pub struct Args {
     #[arg(long, short = 'd', default_value_t = 0.0)]
     pub stop_distance: f64,
 
+    /// Stop condition expression, e.g. "coverage>=95 or time>=4h". Combined with the other stop options
+    /// so that the first condition reached stops the simulation. See the user guide for the metrics available
+    #[arg(long, default_value = None, value_name = "EXPR",
+        value_parser = clap::builder::ValueParser::new(|s: &str| -> Result<String, String> {
+            crate::stopcond::parse(s)?;
+            Ok(s.to_string())
+        })
+    )]
+    pub stop_when: Option<String>,
+
     /// Verbosity during simulation 
     #[arg(long, default_value_t = 0)]
     pub verbosity: usize,
@@ -250,6 +264,10 @@ pub struct Args {
     #[arg(long, short = 'R', default_value_t = false, action = clap::ArgAction::Set)]
     pub show_progress: bool,
 
+    /// Show a live view of the running simulation in the terminal with keys for pause, single step and speed
+    #[arg(long, default_value_t = false, action = clap::ArgAction::Set)]
+    pub tui: bool,
+
     /// Cutter type to use for the simulation (default: "blade")
     #[arg(long, short = 'T',  value_name = "CUTTER-TYPE",
          value_parser = clap::builder::EnumValueParser::<CutterType>::new(),
@@ -302,10 +320,36 @@ pub struct Args {
     )]
     pub battery_charge_time: f64,
 
-    /// Path to map file with obstacles
+    /// Path to map file with obstacles. YAML, GeoJSON (.geojson), SVG (.svg) and raster masks (.png, .bmp) are supported
     #[arg(short = 'M', long, default_value = None, value_name = "MAP-FILE")]
     pub map_file_name: Option<String>,
 
+    /// Number of simulation units per SVG unit when the map file is an SVG drawing
+    #[arg(long, default_value_t = 1.0, value_name = "SCALE",
+        value_parser = clap::builder::ValueParser::new(|s: &str| -> Result<f64, String> {
+            let val: f64 = s.parse().map_err(|_| "Not a valid scale".to_string())?;
+            if val > 0.0 {
+                Ok(val)
+            } else {
+                Err(format!("Scale must be positive, got {}", val))
+            }
+        })
+    )]
+    pub svg_scale: f64,
+
+    /// Size in units of a pixel when the map file is a raster mask (.png or .bmp), e.g. meters per pixel
+    #[arg(long, default_value_t = 0.05, value_name = "UNITS-PER-PIXEL",
+        value_parser = clap::builder::ValueParser::new(|s: &str| -> Result<f64, String> {
+            let val: f64 = s.parse().map_err(|_| "Not a valid resolution".to_string())?;
+            if val > 0.0 {
+                Ok(val)
+            } else {
+                Err(format!("Resolution must be positive, got {}", val))
+            }
+        })
+    )]
+    pub raster_resolution: f64,
+
     /// Show or hide gridlines in the output image
     #[arg(long, short = 'G', default_value_t = false, action = clap::ArgAction::Set)]
     pub show_gridlines: bool,
@@ -314,6 +358,11 @@ pub struct Args {
     #[arg(long, short = 'Q', default_value = None, value_name = "DATABASE-FILE")]
     pub database_file: Option<String>,
 
+    /// Also store the final grid and the path of the cutter, compressed, with the result in the database
+    /// so the image can be rendered again later with the render command
+    #[arg(long, default_value_t = false, action = clap::ArgAction::Set)]
+    pub db_store_grid: bool,
+
     /// Quiet, no output at all
     #[arg(long, short = 'q', default_value_t = false, action = clap::ArgAction::Set)]
     pub quiet: bool,
@@ -359,6 +408,19 @@ pub struct Args {
     #[arg(long, default_value_t = false, action = clap::ArgAction::Set)]
     pub delete_frames: bool,
 
+    /// Save an image every n:th simulation step in the snapshot directory as frame_<step>.png, 0 for none
+    #[arg(long, default_value_t = 0, value_name = "STEPS")]
+    pub snapshot_steps: u64,
+
+    /// Save an image each time the coverage has increased this many percent (multiple of 0.1) in the
+    /// snapshot directory as frame_<coverage in tenths of a percent>.png, 0 for none
+    #[arg(long, default_value_t = 0.0, value_name = "PERCENT")]
+    pub snapshot_coverage: f64,
+
+    /// Directory for the snapshot images
+    #[arg(long, default_value = "snapshots", value_name = "SNAPSHOT-DIR")]
+    pub snapshot_dir: String,
+
     /// Color theme to use as an string, possible values: "default", "green30", "blue", "high_contrast", "pure_green", "gray_green"
     #[arg(long, default_value = None, value_name = "COLOR-THEME")]
     pub color_theme: Option<String>,
@@ -527,6 +589,228 @@ pub struct Args {
     /// Store zsh completion in "/usr/local/share/zsh/site-functions" and bash completion in "/usr/local/share/bash-completion/completions"
     #[arg(long, action = clap::ArgAction::SetTrue)]
     pub generate_completions: bool,
+
+    /// Optional command. Without a command the simulation is run once and the results are printed.
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+}
+
+/// Commands that change what the program does with the simulation
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run the simulation and serve a live view of it to a browser on localhost
+    Serve(ServeArgs),
+
+    /// Check a map file for errors and lawn areas the cutter cannot reach, without simulating.
+    /// The cutter radius, cell size, grid size and start position options are used for the checks.
+    ValidateMap(ValidateMapArgs),
+
+    /// Generate a random map file from a seed for benchmarking strategies on many different gardens.
+    /// The lawn size is given by --grid-width and --grid-height (default 30 x 20), the seed by
+    /// --random-seed (random if not given) and the whole lawn is kept reachable for --radius.
+    GenerateMap(GenerateMapArgs),
+
+    /// List, show, compare and export the simulation runs stored in a results database. The
+    /// database is given with --database-file or --db.
+    Db(DbArgs),
+
+    /// Render the image of a stored run from its stored grid without running the simulation again.
+    /// The run must have been stored with --db-store-grid. The image is written to the file given
+    /// with -o, or result_<ID>.png, using the --color-theme, --dpi, --paper-size and
+    /// --show-gridlines options.
+    Render(RenderArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RenderArgs {
+    /// Result ID of the run to render
+    pub id: i64,
+
+    /// Results database to read, defaults to the file given with --database-file
+    #[arg(long, value_name = "DATABASE-FILE")]
+    pub db: Option<String>,
+
+    /// Draw the recorded path of the cutter on top of the coverage
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub path: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DbArgs {
+    /// Results database to read, defaults to the file given with --database-file
+    #[arg(long, value_name = "DATABASE-FILE")]
+    pub db: Option<String>,
+
+    #[command(subcommand)]
+    pub command: DbCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum DbCommand {
+    /// List the stored runs, oldest first
+    List(DbFilterArgs),
+
+    /// Show the model parameters and results of a stored run
+    Show {
+        /// Result ID of the run
+        id: i64,
+    },
+
+    /// Show the model parameters and results of two stored runs side by side. Only the values
+    /// that differ are shown unless --all is given.
+    Compare {
+        /// Result ID of the first run
+        first: i64,
+        /// Result ID of the second run
+        second: i64,
+        /// Also show the values that are the same in both runs
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        all: bool,
+    },
+
+    /// Export the model parameters and results of the stored runs as CSV or JSON
+    Export(DbExportArgs),
+
+    /// Show the mean, standard deviation and 95% confidence interval of the coverage and cutting
+    /// time of the stored runs of each experiment. An experiment is all runs with the same model
+    /// parameters apart from the random seed and output files.
+    Stats(DbStatsArgs),
+}
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct DbFilterArgs {
+    /// Only runs with a map file name that contains this text
+    #[arg(long)]
+    pub map: Option<String>,
+
+    /// Only runs with this cutter type
+    #[arg(long)]
+    pub cutter_type: Option<String>,
+
+    /// Only runs with this random seed
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Only runs of this experiment, i.e. with the same parameters apart from the random seed
+    #[arg(long)]
+    pub experiment: Option<i64>,
+
+    /// Only runs stored on or after this date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub since: Option<String>,
+
+    /// Only runs stored on or before this date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub until: Option<String>,
+
+    /// Only the most recent N of the matching runs
+    #[arg(long)]
+    pub limit: Option<usize>,
+}
+
+fn parse_date(s: &str) -> Result<String, String> {
+    chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.to_string())
+        .map_err(|_| format!("Not a valid date '{s}', use YYYY-MM-DD"))
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DbExportArgs {
+    #[command(flatten)]
+    pub filter: DbFilterArgs,
+
+    /// Export as comma separated values, one line per run (the default)
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "json")]
+    pub csv: bool,
+
+    /// Export as a JSON array with the model and result of each run
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub json: bool,
+
+    /// File to write the export to, standard output if not given
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DbStatsArgs {
+    #[command(flatten)]
+    pub filter: DbFilterArgs,
+
+    /// Print the statistics as JSON
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub json: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ValidateMapArgs {
+    /// Map file to validate, defaults to the map file given with --map-file-name
+    #[arg(value_name = "MAP-FILE")]
+    pub map_file: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct GenerateMapArgs {
+    /// Name of the YAML map file to write
+    #[arg(value_name = "OUTPUT-FILE")]
+    pub output: String,
+
+    /// Name of the generated map
+    #[arg(long, default_value = None)]
+    pub name: Option<String>,
+
+    /// Number of obstacles
+    #[arg(long, short = 'n', default_value_t = 10)]
+    pub obstacles: usize,
+
+    /// Comma separated obstacle types with optional relative weights, e.g. "circle:3,rectangle,line:0.5".
+    /// Types: circle, rectangle, rotated_rectangle, ellipse, polygon, line
+    #[arg(long, default_value = "circle,rectangle,rotated_rectangle,ellipse,polygon,line",
+        value_parser = clap::builder::ValueParser::new(|s: &str| -> Result<String, String> {
+            crate::mapfile::generate::parse_obstacle_types(s).map(|_| s.to_string())
+        }))]
+    pub obstacle_types: String,
+
+    /// Smallest obstacle size (diameter or longest side)
+    #[arg(long, default_value_t = 0.5)]
+    pub min_size: f64,
+
+    /// Largest obstacle size (diameter or longest side)
+    #[arg(long, default_value_t = 3.0)]
+    pub max_size: f64,
+
+    /// Distribution of the obstacle sizes between the smallest and largest size
+    #[arg(long, value_enum, default_value_t = crate::mapfile::generate::SizeDistribution::Uniform)]
+    pub size_distribution: crate::mapfile::generate::SizeDistribution,
+
+    /// Smallest distance between two obstacles and between an obstacle and the lawn edge
+    #[arg(long, default_value_t = 0.5)]
+    pub clearance: f64,
+
+    /// Number of walls across the lawn with a gap only a little wider than the cutter
+    #[arg(long, default_value_t = 0)]
+    pub narrow_passages: usize,
+
+    /// Complexity of the lawn outline from 0 (rectangular) to 10 (many deep indents)
+    #[arg(long, default_value_t = 0,
+        value_parser = clap::value_parser!(u32).range(0..=crate::mapfile::generate::MAX_BOUNDARY_COMPLEXITY as i64))]
+    pub boundary_complexity: u32,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ServeArgs {
+    /// Port on localhost where the live view is served
+    #[arg(long, short = 'p', default_value_t = 8080)]
+    pub port: u16,
+
+    /// Real time in seconds between two full snapshot images sent to the browser
+    #[arg(long, default_value_t = 5.0)]
+    pub snapshot_interval: f64,
+
+    /// Number of simulated seconds per real second. Use 0 to run as fast as possible
+    #[arg(long, default_value_t = 60.0)]
+    pub realtime_factor: f64,
 }
 
 
@@ -548,6 +832,7 @@ impl Args {
             velocity: if self.velocity != 0.3 { self.velocity } else { other.velocity },
             start_dir_x: if self.start_dir_x != 0.0 { self.start_dir_x } else { other.start_dir_x },
             start_dir_y: if self.start_dir_y != 0.0 { self.start_dir_y } else { other.start_dir_y },
+            start_angle: self.start_angle.or(other.start_angle),
             perturb: if !self.perturb { self.perturb } else { other.perturb },
             perturb_segment: if self.perturb_segment { self.perturb_segment } else { other.perturb_segment },
             perturb_segment_percent: if self.perturb_segment_percent != 0.5 { self.perturb_segment_percent } else { other.perturb_segment_percent },
@@ -556,6 +841,7 @@ impl Args {
             stop_coverage: if self.stop_coverage > 0.0 { self.stop_coverage } else { other.stop_coverage },
             stop_simsteps: if self.stop_simsteps > 0 { self.stop_simsteps } else { other.stop_simsteps },
             stop_distance: if self.stop_distance > 0.0 { self.stop_distance } else { other.stop_distance },
+            stop_when: self.stop_when.or(other.stop_when),
             verbosity: if self.verbosity > 0 { self.verbosity } else { other.verbosity },
             parallel: if !self.parallel { self.parallel } else { other.parallel },
             random_seed: if self.random_seed > 0 { self.random_seed } else { other.random_seed },
@@ -563,6 +849,7 @@ impl Args {
             image_height_mm: if self.image_height_mm != 297 { self.image_height_mm } else { other.image_height_mm },
             track_center: if !self.track_center { self.track_center } else { other.track_center },
             show_progress: if !self.show_progress { self.show_progress } else { other.show_progress },
+            tui: if self.tui { self.tui } else { other.tui },
             cutter_type: if self.cutter_type != CutterType::Blade { self.cutter_type } else { other.cutter_type },
             dpi: if self.dpi != 300 { self.dpi } else { other.dpi },
             json_output: if self.json_output { self.json_output } else { other.json_output },
@@ -570,8 +857,11 @@ impl Args {
             battery_charge_time: if self.battery_charge_time != 120.0 { self.battery_charge_time } else { other.battery_charge_time },
             paper_size: if self.paper_size != PaperSize::A4 { self.paper_size } else { other.paper_size },
             map_file_name: self.map_file_name.or(other.map_file_name),
+            svg_scale: if self.svg_scale != 1.0 { self.svg_scale } else { other.svg_scale },
+            raster_resolution: if self.raster_resolution != 0.05 { self.raster_resolution } else { other.raster_resolution },
             show_gridlines: if self.show_gridlines { self.show_gridlines } else { other.show_gridlines },
             database_file: self.database_file.or(other.database_file),
+            db_store_grid: if self.db_store_grid { self.db_store_grid } else { other.db_store_grid },
             quiet: if self.quiet { self.quiet } else { other.quiet },
             generate_frames: if self.generate_frames { self.generate_frames } else { other.generate_frames },
             frame_rate: if self.frame_rate != 5 { self.frame_rate } else { other.frame_rate },
@@ -581,6 +871,9 @@ impl Args {
             hw_encoding: if self.hw_encoding { self.hw_encoding } else { other.hw_encoding },
             delete_frames: if self.delete_frames { self.delete_frames } else { other.delete_frames },
             animation_speedup: if self.animation_speedup != 1 { self.animation_speedup } else { other.animation_speedup },
+            snapshot_steps: if self.snapshot_steps != 0 { self.snapshot_steps } else { other.snapshot_steps },
+            snapshot_coverage: if self.snapshot_coverage != 0.0 { self.snapshot_coverage } else { other.snapshot_coverage },
+            snapshot_dir: if self.snapshot_dir != "snapshots" { self.snapshot_dir } else { other.snapshot_dir },
             color_theme: self.color_theme.or(other.color_theme),
             wheel_slippage: if self.wheel_slippage { self.wheel_slippage } else { other.wheel_slippage },
             slippage_probability: if self.slippage_probability != 0.02 { self.slippage_probability } else { other.slippage_probability },
@@ -601,6 +894,7 @@ impl Args {
             show_image_label: if self.show_image_label { self.show_image_label } else { other.show_image_label },
             generate_json_files: if self.generate_json_files { self.generate_json_files } else { other.generate_json_files },
             generate_completions: if self.generate_completions { self.generate_completions } else { other.generate_completions },
+            command: self.command.or(other.command),
         }
     }
 }
@@ -0,0 +1,205 @@
+//! Manifest of a `grunner` batch. The manifest records every run of the batch and whether it
+//! completed, so an interrupted or partly failed batch can be resumed without running the
+//! completed runs again.
+
+use crate::args::DbFilterArgs;
+use crate::db::Database;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+
+/// File name of the manifest in the grunner output directory
+pub const MANIFEST_FILE_NAME: &str = "batch-manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Not run yet, or the batch was interrupted while it was running
+    Pending,
+    Completed,
+    /// All attempts failed
+    Failed,
+}
+
+/// One run of a batch, a design point with one random seed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchRun {
+    pub point: usize,
+    pub seed: u64,
+    /// gridcover arguments of the design point, without the options grunner sets for each run
+    pub args: Vec<String>,
+    pub status: RunStatus,
+    pub attempts: u32,
+    /// Result ID of the run in the results database once it has completed
+    pub result_id: Option<i64>,
+    pub experiment_id: Option<i64>,
+    pub coverage_percent: Option<f64>,
+    pub cutting_time_seconds: Option<f64>,
+    /// The error of each failed attempt
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+impl BatchRun {
+    pub fn new(point: usize, seed: u64, args: Vec<String>) -> Self {
+        BatchRun {
+            point,
+            seed,
+            args,
+            status: RunStatus::Pending,
+            attempts: 0,
+            result_id: None,
+            experiment_id: None,
+            coverage_percent: None,
+            cutting_time_seconds: None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn record_completion(
+        &mut self,
+        result_id: Option<i64>,
+        experiment_id: Option<i64>,
+        coverage_percent: f64,
+        cutting_time_seconds: f64,
+    ) {
+        self.attempts += 1;
+        self.status = RunStatus::Completed;
+        self.result_id = result_id;
+        self.experiment_id = experiment_id;
+        self.coverage_percent = Some(coverage_percent);
+        self.cutting_time_seconds = Some(cutting_time_seconds);
+    }
+
+    /// Record an attempt that failed, the run is failed when it has no attempts left
+    pub fn record_failure(&mut self, error: &str, attempts_left: bool) {
+        self.attempts += 1;
+        self.errors.push(strip_ansi_codes(error));
+        self.status = if attempts_left { RunStatus::Pending } else { RunStatus::Failed };
+    }
+
+    /// The run is the same run as `other` of an earlier batch
+    fn same_run(&self, other: &BatchRun) -> bool {
+        self.point == other.point && self.seed == other.seed && self.args == other.args
+    }
+}
+
+/// Remove the color codes of a colored error message before it is written to the manifest
+fn strip_ansi_codes(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the escape sequence up to and including its final letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchManifest {
+    /// Name of the experiment the batch runs
+    pub name: String,
+    /// Results database the runs are stored in
+    pub database: String,
+    pub started_at: String,
+    pub updated_at: String,
+    pub runs: Vec<BatchRun>,
+}
+
+impl BatchManifest {
+    pub fn new(name: &str, database: &str, runs: Vec<BatchRun>) -> Self {
+        let now = chrono::Local::now().to_rfc3339();
+        BatchManifest {
+            name: name.to_string(),
+            database: database.to_string(),
+            started_at: now.clone(),
+            updated_at: now,
+            runs,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Cannot read batch manifest '{path}': {e}"))?;
+        Ok(serde_json::from_str(&content).map_err(|e| format!("Invalid batch manifest '{path}': {e}"))?)
+    }
+
+    /// Write the manifest to a temporary file that then replaces the manifest, so an interrupted
+    /// batch never leaves a partly written manifest
+    pub fn save(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.updated_at = chrono::Local::now().to_rfc3339();
+        let tmp_path = format!("{path}.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)
+            .map_err(|e| format!("Cannot write batch manifest '{tmp_path}': {e}"))?;
+        fs::rename(&tmp_path, path).map_err(|e| format!("Cannot replace batch manifest '{path}': {e}"))?;
+        Ok(())
+    }
+
+    /// Take over the runs of the batch that are already stored in the results database. A run is
+    /// stored if the database has a run with its seed in the experiment of its design point,
+    /// found by the hash of the parameters of the point, so runs completed after the earlier
+    /// manifest was last saved are found too. A run the earlier manifest records as completed
+    /// with one of these results is taken from it, the others from the database. All other runs
+    /// are run again. Returns the number of runs taken over.
+    pub fn resume_from(
+        &mut self,
+        previous: Option<&BatchManifest>,
+        parameter_hashes: &[Option<String>],
+    ) -> Result<usize, Box<dyn Error>> {
+        let db = Database::new(&self.database)?;
+        if let Some(previous) = previous {
+            self.started_at = previous.started_at.clone();
+        }
+        let experiment_ids = parameter_hashes
+            .iter()
+            .map(|hash| Ok(match hash {
+                Some(hash) => db.find_experiment(hash)?,
+                None => None,
+            }))
+            .collect::<Result<Vec<Option<i64>>, Box<dyn Error>>>()?;
+        let mut resumed = 0;
+        for run in self.runs.iter_mut() {
+            let Some(experiment_id) = experiment_ids[run.point] else {
+                continue;
+            };
+            let filter = DbFilterArgs {
+                experiment: Some(experiment_id),
+                seed: Some(run.seed),
+                ..Default::default()
+            };
+            let stored = db.list_runs(&filter)?;
+            let Some(first) = stored.first() else {
+                continue;
+            };
+            let recorded = previous.and_then(|previous| {
+                previous.runs.iter().find(|done| {
+                    done.status == RunStatus::Completed
+                        && done.same_run(run)
+                        && stored.iter().any(|s| done.result_id == Some(s.result_id))
+                })
+            });
+            match recorded {
+                Some(done) => *run = done.clone(),
+                None => run.record_completion(
+                    Some(first.result_id),
+                    Some(experiment_id),
+                    first.coverage_percent,
+                    first.cutting_time_seconds as f64,
+                ),
+            }
+            resumed += 1;
+        }
+        Ok(resumed)
+    }
+
+    pub fn count(&self, status: RunStatus) -> usize {
+        self.runs.iter().filter(|run| run.status == status).count()
+    }
+}
@@ -1,16 +1,41 @@
 // use num_cpus;
 use clap::Parser;
 use colored::Colorize;
+use gridcover::args::read_args_from_file;
+use gridcover::batchmanifest::{BatchManifest, BatchRun, MANIFEST_FILE_NAME, RunStatus};
+use gridcover::db::experiment::{parameter_hash, parameter_set};
+use gridcover::db::query::csv_line;
+use gridcover::db::store_simulation_to_database;
+use gridcover::experimentfile::{ExperimentFile, check_reserved_args};
+use gridcover::image::try_save_image;
+use gridcover::mapfile::prepared::{PreparedMap, PreparedMapKey};
+use gridcover::metric::RunMetric;
+use gridcover::optimize::{INITIAL_SIGMA, SepCmaEs, clip_to_unit_cube, random_point};
+use gridcover::optimizefile::OptimizeFile;
+use gridcover::stats::SampleStats;
+use gridcover::{SimConfig, SimModel, SimulationBuilder, SnapshotMetric, SnapshotObserver, StepCallback, Vector};
 use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 use rayon::prelude::*;
+use serde_json::json;
+use std::collections::HashMap;
 use std::fs;
+use std::ops::ControlFlow;
 use std::process::Command;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 // Examples:
 // ./target/release/grunner -o "/tmp/grunner" --delete-frames -v -a "\-S 456 \-W 5 \-H 5 \-s 0.01" -m 10000 -s 5
 // ./target/release/grunner -o "/tmp/grunner" --delete-output-dir -v -a "\-M assets/mapex01.yaml \-S 4786 \-W 5 \-H 5 \-s 0.01" -m 100000  -s 6
 // ./target/release/grunner -o "/tmp/grunner" --delete-output-dir --delete-frames -v -a "\-M assets/mapex01.yaml \-S 4786 \-W 5 \-H 5 \-s 0.01" -m 200000  -s 7
+// ./target/release/grunner -o "/tmp/grunner" --delete-output-dir -e assets/experiments/radius-sweep.yaml
+// ./target/release/grunner -o "/tmp/grunner" --resume --run-timeout 600 -e assets/experiments/radius-sweep.yaml
+// ./target/release/grunner -o "/tmp/grunner" -O assets/experiments/optimize-time-to-95.yaml
+// ./target/release/grunner -o "/tmp/grunner" -a "\-M assets/maps/mapex01.yaml" --monte-carlo time_to_coverage=95 --tolerance 60
 
 /// Runner for gridcover over a range of coverage values
 #[derive(Parser, Debug)]
@@ -24,7 +49,7 @@ struct Args {
     #[arg(long, short = 'o', default_value = "runner_results")]
     output_dir: String,
 
-    /// Common arguments to pass to each gridcover run (quoted string, e.g. "--foo bar")
+    /// Common gridcover arguments used for each run (quoted string, e.g. "--foo bar")
     #[arg(long, short = 'a', default_value = "")]
     common_args: String,
 
@@ -60,14 +85,864 @@ struct Args {
     /// Delete output directory if it exists
     #[arg(long, default_value_t = false)]
     delete_output_dir: bool,
+
+    /// Run the parameter sweep declared in this experiment file instead of the coverage or steps sweep
+    #[arg(long, short = 'e', value_name = "EXPERIMENT-FILE")]
+    experiment_file: Option<String>,
+
+    /// Search for the best parameter values declared in this optimisation file
+    #[arg(long, short = 'O', value_name = "OPTIMIZE-FILE", conflicts_with = "experiment_file")]
+    optimize_file: Option<String>,
+
+    /// Run more and more seeds until the 95% confidence interval of the mean of this metric is
+    /// narrower than the tolerance, e.g. "time_to_coverage=95" or "coverage_at_time=3600"
+    #[arg(long, value_name = "METRIC", conflicts_with_all = ["experiment_file", "optimize_file"])]
+    monte_carlo: Option<RunMetric>,
+
+    /// Largest half width of the 95% confidence interval of the Monte Carlo metric, in seconds or percent
+    #[arg(long, default_value_t = 1.0, value_name = "TOLERANCE")]
+    tolerance: f64,
+
+    /// Number of seeds run before the Monte Carlo confidence interval is first checked
+    #[arg(long, default_value_t = 10, value_name = "SEEDS")]
+    min_seeds: u64,
+
+    /// Largest number of seeds run in Monte Carlo mode, also if the tolerance is not reached
+    #[arg(long, default_value_t = 1000, value_name = "SEEDS")]
+    max_seeds: u64,
+
+    /// The first seed of the Monte Carlo runs, the seeds are first_seed, first_seed + 1, ...
+    #[arg(long, default_value_t = 1, value_name = "SEED")]
+    first_seed: u64,
+
+    /// Resume the experiment in the output directory, the runs stored in the results database
+    /// are not run again. Without an experiment file the simulation of the image series is
+    /// skipped if all its images are saved.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["delete_output_dir", "optimize_file", "monte_carlo"])]
+    resume: bool,
+
+    /// Number of times an experiment run that timed out or could not be stored in the database
+    /// is retried, other failures repeat with the same arguments and seed and are not retried
+    #[arg(long, default_value_t = 2, value_name = "RETRIES")]
+    retries: u32,
+
+    /// Time limit in seconds for each experiment run, a run that takes longer fails, 0 for none
+    #[arg(long, default_value_t = 0, value_name = "SECONDS")]
+    run_timeout: u64,
+}
+
+/// Outcome of one simulation run
+struct RunOutcome {
+    /// Result and experiment the run was stored as, None if it was not stored in a database
+    result_id: Option<i64>,
+    experiment_id: Option<i64>,
+    coverage_percent: f64,
+    cutting_time_seconds: f64,
+    /// Value of the metric asked for, None if no metric was given
+    metric_value: Option<f64>,
+    /// The run was stopped before the metric was known, see `RunMetric::is_censored()`
+    censored: bool,
+}
+
+/// A run that failed for a reason outside the simulation, a timeout or a database error, and
+/// may succeed when it is run again
+#[derive(Debug)]
+struct TransientError(String);
+
+impl std::fmt::Display for TransientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TransientError {}
+
+/// The maps loaded and rasterised so far, shared by all runs with the same map, grid and cutter size
+#[derive(Default)]
+struct MapCache {
+    maps: Mutex<HashMap<PreparedMapKey, Arc<PreparedMap>>>,
+}
+
+impl MapCache {
+    /// The prepared map for the configuration, loaded and rasterised on first use. The cache is
+    /// locked while a map is prepared so parallel runs never rasterise the same map twice.
+    fn get(&self, config: &SimConfig) -> Result<Arc<PreparedMap>, Box<dyn std::error::Error>> {
+        let key = PreparedMapKey::new(config)?;
+        // A run that panicked while preparing a map leaves the cache as it was, so the lock can
+        // be used again
+        let mut maps = self.maps.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(map) = maps.get(&key) {
+            return Ok(map.clone());
+        }
+        let map = Arc::new(PreparedMap::new(config)?);
+        maps.insert(key, map.clone());
+        Ok(map)
+    }
+}
+
+/// Parse gridcover command line arguments, merged with the arguments file they refer to
+fn parse_sim_args(sim_args: &[String]) -> Result<gridcover::args::Args, Box<dyn std::error::Error>> {
+    let mut args = gridcover::args::Args::try_parse_from(
+        std::iter::once("gridcover").chain(sim_args.iter().map(String::as_str)),
+    )?;
+    if let Some(args_read_file) = args.args_read_file_name.clone() {
+        args = args.merge_with(read_args_from_file(&args_read_file)?);
+    }
+    Ok(args)
+}
+
+/// The common gridcover arguments of runs that grunner gives a seed and a database, which must
+/// not set these options themselves
+fn seeded_common_args(args: &Args) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let common_args = shell_words::split(&args.common_args)?;
+    check_reserved_args(&common_args, "--common-args")?;
+    Ok(common_args)
+}
+
+/// Run one simulation in-process with the given gridcover command line arguments. The map is
+/// taken from the cache so it is only loaded and rasterised once for all runs using it.
+/// The result is stored in the database and the image saved as specified by the arguments.
+/// A simulation still running when the timeout has passed is abandoned and reported as failed,
+/// and a panic in a simulation is returned as an error so that it does not end the whole batch.
+fn run_simulation(
+    sim_args: &[String],
+    maps: &MapCache,
+    timeout: Option<Duration>,
+    metric: Option<RunMetric>,
+) -> Result<RunOutcome, Box<dyn std::error::Error>> {
+    std::panic::catch_unwind(AssertUnwindSafe(|| simulate(sim_args, maps, timeout, metric))).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_string());
+        Err(format!("Simulation panicked: {message}").into())
+    })
+}
+
+fn simulate(
+    sim_args: &[String],
+    maps: &MapCache,
+    timeout: Option<Duration>,
+    metric: Option<RunMetric>,
+) -> Result<RunOutcome, Box<dyn std::error::Error>> {
+    let args = parse_sim_args(sim_args)?;
+    let config = SimConfig::from(&args);
+    let prepared_map = maps.get(&config)?;
+
+    let mut sim = SimulationBuilder::new(config).prepared_map(prepared_map).build()?;
+    match timeout {
+        Some(timeout) => {
+            let start = Instant::now();
+            sim.run_until(|_| start.elapsed() >= timeout);
+            if !sim.is_finished() {
+                return Err(TransientError(format!("Timed out after {} seconds", timeout.as_secs())).into());
+            }
+        }
+        None => {
+            sim.run();
+        }
+    }
+    let model = sim.into_model();
+
+    let stored = match &args.database_file {
+        Some(db_path) => Some(store_simulation_to_database(&model, db_path).map_err(|e| TransientError(e.to_string()))?),
+        None => None,
+    };
+    try_save_image(&model, None);
+    Ok(RunOutcome {
+        result_id: stored.as_ref().map(|ids| ids.result_id),
+        experiment_id: stored.map(|ids| ids.experiment_id),
+        coverage_percent: model.grid.as_ref().map_or(0.0, |grid| grid.get_coverage_percent()),
+        cutting_time_seconds: model.sim_time_elapsed,
+        metric_value: metric.map(|metric| metric.value(&model)),
+        censored: metric.is_some_and(|metric| metric.is_censored(&model)),
+    })
+}
+
+/// The hash of the parameters of the experiment a run is stored in, from the model as the run
+/// starts. All seeds of a design point have the same parameters.
+fn run_parameter_hash(sim_args: &[String], maps: &MapCache) -> Result<String, Box<dyn std::error::Error>> {
+    let args = parse_sim_args(sim_args)?;
+    let config = SimConfig::from(&args);
+    let prepared_map = maps.get(&config)?;
+    let sim = SimulationBuilder::new(config).prepared_map(prepared_map).build()?;
+    Ok(parameter_hash(&parameter_set(sim.model())))
+}
+
+/// Shortest time between two saves of the batch manifest while the runs complete
+const MANIFEST_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Coverage of the last image in the coverage series, in tenths of a percent
+const LAST_COVERAGE_TENTHS: u64 = 990;
+
+/// Run a single simulation that saves an image in the output directory at the milestones
+/// `1, 1 + interval, ...` up to `last`, counted in steps or tenths of a percent coverage, named
+/// as the frames of the video. The simulation stops at the last milestone and is stored in the
+/// database.
+fn run_snapshot_series(
+    common_args: &[String],
+    output_dir: &str,
+    db_file: &str,
+    metric: SnapshotMetric,
+    interval: u64,
+    last: u64,
+    pb: &ProgressBar,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim_args = common_args.to_vec();
+    sim_args.extend(["-Q".to_string(), db_file.to_string(), "-q".to_string(), "true".to_string()]);
+    match metric {
+        SnapshotMetric::Steps => sim_args.extend(["-m".to_string(), last.to_string()]),
+        SnapshotMetric::Coverage => sim_args.extend(["-c".to_string(), (last as f64 / 10.0).to_string()]),
+    }
+    let args = parse_sim_args(&sim_args)?;
+
+    let progress = pb.clone();
+    let mut position = 0;
+    let mut sim = SimulationBuilder::new(SimConfig::from(&args))
+        .observer(SnapshotObserver::new(output_dir, metric, 1, interval, Some(last)))
+        .observer(StepCallback(move |model: &mut SimModel, _: &Vector| {
+            let reached = match metric {
+                SnapshotMetric::Steps => model.sim_steps,
+                SnapshotMetric::Coverage => {
+                    (model.grid.as_ref().map_or(0.0, |grid| grid.get_coverage_percent()) * 10.0) as u64
+                }
+            };
+            if reached > position {
+                position = reached.min(last);
+                progress.set_position(position);
+            }
+            ControlFlow::Continue(())
+        }))
+        .build()?;
+    sim.run();
+    store_simulation_to_database(&sim.into_model(), db_file)?;
+    Ok(())
+}
+
+/// Mean, standard deviation and 95% confidence interval for the summary, empty if unknown
+fn stats_fields(stats: &SampleStats) -> [String; 3] {
+    let field = |value: f64| if stats.count > 0 && value.is_finite() { value.to_string() } else { String::new() };
+    [field(stats.mean), field(stats.stddev), field(stats.ci95)]
+}
+
+/// Run all design points of an experiment file with all seeds on the rayon pool, store the runs
+/// in one database and write the statistics of each design point to the summary CSV file
+fn run_experiment(args: &Args, experiment_file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let experiment = ExperimentFile::load(experiment_file)?;
+    let output_dir = &args.output_dir;
+    let common_args: Vec<String> = seeded_common_args(args)?
+        .into_iter()
+        .chain(experiment.base_args()?)
+        .collect();
+    let points = experiment.design_points()?;
+    let seeds: Vec<u64> = experiment.seed_values().collect();
+    let db_file = format!("{output_dir}/{}", experiment.outputs.database);
+    let summary_file = format!("{output_dir}/{}", experiment.outputs.summary);
+
+    // Check the arguments of every design point and prepare its map before anything is run
+    let maps = MapCache::default();
+    let point_args: Vec<Vec<String>> = points
+        .iter()
+        .map(|point| {
+            let sim_args: Vec<String> = common_args.iter().cloned().chain(experiment.point_args(point)).collect();
+            let point_args = parse_sim_args(&sim_args).map_err(|e| format!("Design point {}: {e}", point.index))?;
+            if let Err(err) = maps.get(&SimConfig::from(&point_args)) {
+                // The runs of the point fail with the same error and are reported as failed
+                if !args.quiet {
+                    eprintln!("{} {} ({})", "Cannot prepare map for design point".yellow().bold(), point.index, err);
+                }
+            }
+            Ok(sim_args)
+        })
+        .collect::<Result<_, Box<dyn std::error::Error>>>()?;
+
+    let manifest_file = format!("{output_dir}/{MANIFEST_FILE_NAME}");
+    let runs: Vec<BatchRun> = points
+        .iter()
+        .flat_map(|point| {
+            let point_args = &point_args[point.index];
+            seeds.iter().map(move |&seed| BatchRun::new(point.index, seed, point_args.clone()))
+        })
+        .collect();
+    // The gridcover arguments of a run, the arguments of its design point followed by the
+    // options grunner sets for each run
+    let run_args = |run: &BatchRun| {
+        let (point, seed) = (run.point, run.seed);
+        let mut sim_args = run.args.clone();
+        sim_args.extend(["-S".to_string(), seed.to_string()]);
+        sim_args.extend(["-Q".to_string(), db_file.clone()]);
+        sim_args.extend(["-q".to_string(), "true".to_string()]);
+        sim_args.extend(["--db-store-grid".to_string(), experiment.outputs.store_grid.to_string()]);
+        if experiment.outputs.images {
+            sim_args.extend(["-o".to_string(), format!("{output_dir}/point_{point:04}_seed_{seed}.png")]);
+        }
+        sim_args
+    };
+    let mut manifest = BatchManifest::new(&experiment.name, &db_file, runs);
+    if args.resume {
+        // The experiment of each design point, found in the database by the hash of its parameters
+        let parameter_hashes: Vec<Option<String>> = points
+            .iter()
+            .map(|point| {
+                let run = manifest.runs.iter().find(|run| run.point == point.index)?;
+                run_parameter_hash(&run_args(run), &maps).ok()
+            })
+            .collect();
+        let previous = if fs::metadata(&manifest_file).is_ok() { Some(BatchManifest::load(&manifest_file)?) } else { None };
+        let resumed = manifest.resume_from(previous.as_ref(), &parameter_hashes)?;
+        if !args.quiet {
+            println!(
+                "{} {} of {} runs already completed",
+                "Resuming experiment:".color(colored::Color::Green).bold(),
+                resumed,
+                manifest.runs.len()
+            );
+        }
+    } else if fs::metadata(&manifest_file).is_ok() && !args.quiet {
+        println!(
+            "{}",
+            "Replacing the batch manifest of an earlier batch, use --resume to continue it instead"
+                .color(colored::Color::Yellow)
+                .bold()
+        );
+    }
+    manifest.save(&manifest_file)?;
+
+    let pending: Vec<usize> = (0..manifest.runs.len())
+        .filter(|&i| manifest.runs[i].status != RunStatus::Completed)
+        .collect();
+    if !args.quiet {
+        println!(
+            "{} \"{}\": {} design points x {} seeds = {} runs, {} to run",
+            "Running experiment".color(colored::Color::Green).bold(),
+            experiment.name,
+            points.len(),
+            seeds.len(),
+            manifest.runs.len(),
+            pending.len()
+        );
+    }
+
+    let pb = ProgressBar::new(pending.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:50.cyan/blue}] ({percent}%) {msg}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    if !args.quiet {
+        pb.set_message("Running simulations ...");
+    }
+
+    let timeout = (args.run_timeout > 0).then(|| Duration::from_secs(args.run_timeout));
+    let batch = Mutex::new((manifest, Instant::now()));
+    pending.par_iter().for_each(|&i| {
+        let (point, seed, sim_args) = {
+            let (manifest, _) = &*batch.lock().expect("Batch manifest lock");
+            let run = &manifest.runs[i];
+            (run.point, run.seed, run_args(run))
+        };
+
+        for attempt in 0..=args.retries {
+            let result = run_simulation(&sim_args, &maps, timeout, None);
+            let mut guard = batch.lock().expect("Batch manifest lock");
+            let (manifest, last_saved) = &mut *guard;
+            let run = &mut manifest.runs[i];
+            match result {
+                Ok(outcome) => run.record_completion(
+                    outcome.result_id,
+                    outcome.experiment_id,
+                    outcome.coverage_percent,
+                    outcome.cutting_time_seconds,
+                ),
+                Err(err) => {
+                    // The simulation is deterministic, only a timeout or database error may not repeat
+                    let retry = attempt < args.retries && err.is::<TransientError>();
+                    run.record_failure(&err.to_string(), retry);
+                    pb.suspend(|| {
+                        eprintln!(
+                            "{}: point {} seed {} attempt {} ({}){}",
+                            "gridcover failed for design point".color(colored::Color::Red).bold(),
+                            point,
+                            seed,
+                            attempt + 1,
+                            err,
+                            if retry { ", retrying" } else { "" }
+                        )
+                    });
+                }
+            }
+            let done = run.status != RunStatus::Pending;
+            if last_saved.elapsed() >= MANIFEST_SAVE_INTERVAL {
+                if let Err(err) = manifest.save(&manifest_file) {
+                    pb.suspend(|| eprintln!("{} {}", "Warning:".color(colored::Color::Yellow).bold(), err));
+                }
+                *last_saved = Instant::now();
+            }
+            if done {
+                break;
+            }
+        }
+        if !args.quiet {
+            pb.inc(1);
+        }
+    });
+    let (mut manifest, _) = batch.into_inner().expect("Batch manifest lock");
+    manifest.save(&manifest_file)?;
+    if !args.quiet {
+        pb.finish_with_message("All simulations complete!");
+    }
+
+    let mut header: Vec<String> = vec!["point".into(), "experiment_id".into()];
+    header.extend(experiment.parameters.iter().map(|p| p.name()));
+    header.extend(
+        ["runs", "failed", "coverage_mean", "coverage_stddev", "coverage_ci95", "time_mean", "time_stddev", "time_ci95"]
+            .map(String::from),
+    );
+    let mut summary = csv_line(header.iter().map(String::as_str));
+    for point in &points {
+        let point_runs: Vec<&BatchRun> = manifest
+            .runs
+            .iter()
+            .filter(|run| run.point == point.index && run.status == RunStatus::Completed)
+            .collect();
+        let coverage = SampleStats::from_values(&point_runs.iter().filter_map(|r| r.coverage_percent).collect::<Vec<_>>());
+        let time = SampleStats::from_values(&point_runs.iter().filter_map(|r| r.cutting_time_seconds).collect::<Vec<_>>());
+        let fields: Vec<String> = [
+            point.index.to_string(),
+            point_runs
+                .iter()
+                .find_map(|r| r.experiment_id)
+                .map_or(String::new(), |id| id.to_string()),
+        ]
+        .into_iter()
+        .chain(point.values.iter().cloned())
+        .chain([
+            point_runs.len().to_string(),
+            (seeds.len() - point_runs.len()).to_string(),
+        ])
+        .chain(stats_fields(&coverage))
+        .chain(stats_fields(&time))
+        .collect();
+        summary.push_str(&csv_line(fields.iter().map(String::as_str)));
+    }
+    fs::write(&summary_file, summary).map_err(|e| format!("Cannot write summary file '{summary_file}': {e}"))?;
+
+    let failed: Vec<&BatchRun> = manifest.runs.iter().filter(|run| run.status == RunStatus::Failed).collect();
+    if !failed.is_empty() {
+        eprintln!("{}", "Failed runs:".color(colored::Color::Red).bold());
+        for run in &failed {
+            eprintln!("  point {} seed {}, {} attempts:", run.point, run.seed, run.attempts);
+            for (attempt, error) in run.errors.iter().enumerate() {
+                eprintln!("    {}: {}", attempt + 1, error);
+            }
+        }
+    }
+
+    if !args.quiet {
+        println!(
+            "{} {} runs ({} failed) stored in '{}', summary written to '{}'",
+            "Experiment complete:".color(colored::Color::Green).bold(),
+            manifest.runs.len(),
+            failed.len(),
+            db_file,
+            summary_file
+        );
+    }
+    if !failed.is_empty() {
+        return Err(format!(
+            "{} of {} runs failed, the errors are also in '{manifest_file}'. Use --resume to run them again.",
+            failed.len(),
+            manifest.runs.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// A candidate tried by the optimisation and the statistics of the objective over the seeds
+struct Candidate {
+    /// "random" or "cma-es"
+    phase: &'static str,
+    generation: usize,
+    /// Position in the unit cube of the parameter bounds
+    point: Vec<f64>,
+    values: Vec<f64>,
+    stats: SampleStats,
+    failed: usize,
+    /// Runs stopped before the objective was known, they are left out of the statistics
+    censored: usize,
+}
+
+impl Candidate {
+    /// Objective value for the optimiser, smaller is better. A candidate without any successful
+    /// run, or with a run stopped before the objective was known, is worse than all others.
+    fn objective(&self, minimize: bool) -> f64 {
+        match (self.stats.count, self.censored, minimize) {
+            (0, _, _) | (_, 1.., _) => f64::INFINITY,
+            (_, _, true) => self.stats.mean,
+            (_, _, false) => -self.stats.mean,
+        }
+    }
+
+    /// The objective with its confidence interval and the number of censored runs, if any
+    fn summary(&self) -> String {
+        match self.censored {
+            0 => mean_with_ci(&self.stats),
+            censored => format!("{}, {censored} runs stopped before the objective was known", mean_with_ci(&self.stats)),
+        }
+    }
+}
+
+/// Run every point with every seed on the rayon pool and collect the objective of each point
+#[allow(clippy::too_many_arguments)]
+fn evaluate_points(
+    optimize: &OptimizeFile,
+    common_args: &[String],
+    db_file: Option<&str>,
+    maps: &MapCache,
+    points: Vec<Vec<f64>>,
+    seeds: &[u64],
+    (phase, generation): (&'static str, usize),
+    pb: &ProgressBar,
+) -> Vec<Candidate> {
+    let runs: Vec<(usize, u64)> = (0..points.len())
+        .flat_map(|i| seeds.iter().map(move |&seed| (i, seed)))
+        .collect();
+    let results: Vec<(usize, Option<RunOutcome>)> = runs
+        .par_iter()
+        .map(|&(i, seed)| {
+            let mut sim_args = common_args.to_vec();
+            sim_args.extend(optimize.point_args(&optimize.parameter_values(&points[i])));
+            sim_args.extend(["-S".to_string(), seed.to_string()]);
+            sim_args.extend(["-q".to_string(), "true".to_string()]);
+            if let Some(db_file) = db_file {
+                sim_args.extend(["-Q".to_string(), db_file.to_string()]);
+            }
+            let outcome = match run_simulation(&sim_args, maps, None, Some(optimize.objective)) {
+                Ok(outcome) => Some(outcome),
+                Err(err) => {
+                    pb.suspend(|| {
+                        eprintln!(
+                            "{}: {} seed {} ({})",
+                            "gridcover failed for candidate".color(colored::Color::Red).bold(),
+                            optimize.point_args(&optimize.parameter_values(&points[i])).join(" "),
+                            seed,
+                            err
+                        )
+                    });
+                    None
+                }
+            };
+            pb.inc(1);
+            (i, outcome)
+        })
+        .collect();
+
+    points
+        .into_iter()
+        .enumerate()
+        .map(|(i, point)| {
+            let outcomes: Vec<&RunOutcome> =
+                results.iter().filter(|(j, _)| *j == i).filter_map(|(_, outcome)| outcome.as_ref()).collect();
+            let values: Vec<f64> = outcomes
+                .iter()
+                .filter(|outcome| !outcome.censored)
+                .filter_map(|outcome| outcome.metric_value)
+                .collect();
+            let censored = outcomes.iter().filter(|outcome| outcome.censored).count();
+            Candidate {
+                phase,
+                generation,
+                failed: seeds.len() - outcomes.len(),
+                censored,
+                stats: SampleStats::from_values(&values),
+                values: optimize.parameter_values(&point),
+                point,
+            }
+        })
+        .collect()
+}
+
+/// Format a metric mean with its 95% confidence interval
+fn mean_with_ci(stats: &SampleStats) -> String {
+    if stats.count == 0 {
+        "no successful runs".to_string()
+    } else if stats.ci95.is_finite() {
+        format!("{:.2} ± {:.2} (95% CI, {} runs)", stats.mean, stats.ci95, stats.count)
+    } else {
+        format!("{:.2} ({} run)", stats.mean, stats.count)
+    }
 }
 
-fn insert_decimal(s: &str) -> Option<String> {
-    if s.len() < 2 || !s.chars().all(|c| c.is_ascii_digit()) {
-        return None;
+/// Search the parameter space of an optimisation file, first with uniformly random candidates
+/// and then with CMA-ES started from the best of them. The best candidate is run again with new
+/// seeds for an unbiased estimate of its objective, and every candidate is written to the
+/// history CSV file.
+fn run_optimization(args: &Args, optimize_file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let optimize = OptimizeFile::load(optimize_file)?;
+    let output_dir = &args.output_dir;
+    let common_args: Vec<String> = seeded_common_args(args)?
+        .into_iter()
+        .chain(optimize.base_args()?)
+        .chain(optimize.objective.stop_args())
+        .collect();
+    let db_file = optimize.outputs.database.as_ref().map(|db| format!("{output_dir}/{db}"));
+    let history_file = format!("{output_dir}/{}", optimize.outputs.history);
+    let seeds: Vec<u64> = optimize.seed_values().collect();
+    let validation_seeds: Vec<u64> = optimize.validation_seed_values().collect();
+    let dim = optimize.parameters.len();
+    let minimize = optimize.objective.minimize();
+    let population = optimize.population();
+
+    // Check the arguments at the lower and upper bounds of all parameters before anything is run
+    let maps = MapCache::default();
+    for (bound, unit) in [("lower", 0.0), ("upper", 1.0)] {
+        let sim_args: Vec<String> = common_args
+            .iter()
+            .cloned()
+            .chain(optimize.point_args(&optimize.parameter_values(&vec![unit; dim])))
+            .collect();
+        let bound_args = parse_sim_args(&sim_args).map_err(|e| format!("Parameters at the {bound} bounds: {e}"))?;
+        maps.get(&SimConfig::from(&bound_args))
+            .map_err(|e| format!("Parameters at the {bound} bounds: {e}"))?;
+    }
+
+    let candidate_count = optimize.search.random_samples + optimize.search.generations * population;
+    let total_runs = candidate_count * seeds.len() + validation_seeds.len();
+    if !args.quiet {
+        println!(
+            "{} \"{}\": {} {} of {} candidates x {} seeds + {} validation runs = {} runs",
+            "Optimising".color(colored::Color::Green).bold(),
+            optimize.name,
+            if minimize { "minimise" } else { "maximise" },
+            optimize.objective.description(),
+            candidate_count,
+            seeds.len(),
+            validation_seeds.len(),
+            total_runs
+        );
+    }
+    let pb = if args.quiet { ProgressBar::hidden() } else { ProgressBar::new(total_runs as u64) };
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:50.cyan/blue}] ({percent}%) {msg}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    let evaluate = |points: Vec<Vec<f64>>, seeds: &[u64], phase: (&'static str, usize)| {
+        evaluate_points(&optimize, &common_args, db_file.as_deref(), &maps, points, seeds, phase, &pb)
+    };
+    let best_of = |history: &[Candidate]| -> Option<usize> {
+        (0..history.len()).min_by(|&a, &b| history[a].objective(minimize).total_cmp(&history[b].objective(minimize)))
+    };
+
+    let mut rng = StdRng::seed_from_u64(optimize.search.seed);
+    pb.set_message("Random search ...");
+    let points = (0..optimize.search.random_samples).map(|_| random_point(dim, &mut rng)).collect();
+    let mut history = evaluate(points, &seeds, ("random", 0));
+
+    let start = best_of(&history).map_or_else(|| vec![0.5; dim], |best| history[best].point.clone());
+    let mut cma = SepCmaEs::new(start, INITIAL_SIGMA, population);
+    for generation in 1..=optimize.search.generations {
+        pb.set_message(format!("CMA-ES generation {generation} ..."));
+        let points = cma.ask(&mut rng);
+        let clipped = points.iter().map(|point| clip_to_unit_cube(point)).collect();
+        let candidates = evaluate(clipped, &seeds, ("cma-es", generation));
+        let objectives: Vec<f64> = candidates.iter().map(|c| c.objective(minimize)).collect();
+        cma.tell(&points, &objectives);
+        history.extend(candidates);
+    }
+
+    let best = &history[best_of(&history).expect("At least one candidate")];
+    pb.set_message("Validating the best candidate ...");
+    let validation = if validation_seeds.is_empty() {
+        None
+    } else {
+        evaluate(vec![best.point.clone()], &validation_seeds, ("validation", 0)).pop()
+    };
+    pb.finish_with_message("Optimisation complete!");
+
+    let mut header: Vec<String> = vec!["candidate".into(), "phase".into(), "generation".into()];
+    header.extend(optimize.parameters.iter().map(|p| p.name()));
+    header.extend(["runs", "failed", "censored", "mean", "stddev", "ci95"].map(String::from));
+    let mut csv = csv_line(header.iter().map(String::as_str));
+    for (index, candidate) in history.iter().chain(validation.iter()).enumerate() {
+        let fields: Vec<String> = [index.to_string(), candidate.phase.to_string(), candidate.generation.to_string()]
+            .into_iter()
+            .chain(candidate.values.iter().map(f64::to_string))
+            .chain([candidate.stats.count.to_string(), candidate.failed.to_string(), candidate.censored.to_string()])
+            .chain(stats_fields(&candidate.stats))
+            .collect();
+        csv.push_str(&csv_line(fields.iter().map(String::as_str)));
+    }
+    fs::write(&history_file, csv).map_err(|e| format!("Cannot write history file '{history_file}': {e}"))?;
+
+    if best.objective(minimize).is_infinite() {
+        return Err("No candidate reached the objective with all seeds, raise the stop limits in base_args".into());
+    }
+    if !args.quiet {
+        println!("{} {}", "Best candidate:".color(colored::Color::Green).bold(), optimize.point_args(&best.values).join(" "));
+        println!("  {}: {}", optimize.objective.description(), best.summary());
+        if let Some(validation) = &validation {
+            println!("  with {} new seeds: {}", validation_seeds.len(), validation.summary());
+        }
+        // The candidates whose mean is within the confidence interval of the best can not be
+        // told apart from it with the seeds used, their parameter ranges show how sharp the optimum is
+        let close: Vec<&Candidate> = history
+            .iter()
+            .filter(|c| c.objective(minimize).is_finite() && (c.stats.mean - best.stats.mean).abs() <= best.stats.ci95)
+            .collect();
+        println!("  {} candidates within the confidence interval of the best:", close.len());
+        for (i, parameter) in optimize.parameters.iter().enumerate() {
+            let values: Vec<f64> = close.iter().map(|c| c.values[i]).collect();
+            let stats = SampleStats::from_values(&values);
+            println!("    {}: {} to {}", parameter.name(), stats.min, stats.max);
+        }
+        println!("History of all candidates written to '{history_file}'");
+    }
+    Ok(())
+}
+
+/// File in the output directory the Monte Carlo result is written to
+const MONTE_CARLO_FILE_NAME: &str = "monte-carlo.json";
+
+/// Run seeds in parallel batches until the 95% confidence interval of the mean of the metric is
+/// narrower than the tolerance, or the largest number of seeds has been run. The first batch has
+/// the smallest number of seeds, then one seed per core is added per batch and all runs of the
+/// last batch are included. The result, with the seeds used, is written to a JSON file.
+fn run_monte_carlo(args: &Args, metric: RunMetric) -> Result<(), Box<dyn std::error::Error>> {
+    if args.tolerance <= 0.0 {
+        return Err("The tolerance must be > 0".into());
+    }
+    if args.min_seeds < 2 || args.max_seeds < args.min_seeds {
+        return Err("Give at least 2 seeds with --min-seeds and --max-seeds >= --min-seeds".into());
+    }
+    let output_dir = &args.output_dir;
+    let db_file = format!("{output_dir}/results.db");
+    let result_file = format!("{output_dir}/{MONTE_CARLO_FILE_NAME}");
+    let common_args: Vec<String> = seeded_common_args(args)?
+        .into_iter()
+        .chain(metric.stop_args())
+        .collect();
+    let maps = MapCache::default();
+    maps.get(&SimConfig::from(&parse_sim_args(&common_args)?))?;
+
+    if !args.quiet {
+        println!(
+            "{} {} until the 95% confidence interval is within ±{}",
+            "Estimating".color(colored::Color::Green).bold(),
+            metric.description(),
+            args.tolerance
+        );
+    }
+    let pb = if args.quiet { ProgressBar::hidden() } else { ProgressBar::new(args.max_seeds) };
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:50.cyan/blue}] {pos} seeds {msg}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+
+    let mut results: Vec<(u64, f64)> = Vec::new();
+    let mut failed_seeds: Vec<u64> = Vec::new();
+    // Runs stopped before the metric was known, their values would bias the estimate
+    let mut censored_seeds: Vec<u64> = Vec::new();
+    let mut next_seed = args.first_seed;
+    let last_seed = args.first_seed + args.max_seeds;
+    let mut stats = SampleStats::default();
+    let mut batch_size = args.min_seeds;
+    while next_seed < last_seed {
+        let seeds: Vec<u64> = (next_seed..(next_seed + batch_size).min(last_seed)).collect();
+        next_seed += seeds.len() as u64;
+        let batch: Vec<(u64, Result<RunOutcome, String>)> = seeds
+            .par_iter()
+            .map(|&seed| {
+                let mut sim_args = common_args.clone();
+                sim_args.extend(["-S".to_string(), seed.to_string()]);
+                sim_args.extend(["-Q".to_string(), db_file.clone()]);
+                sim_args.extend(["-q".to_string(), "true".to_string()]);
+                let outcome = run_simulation(&sim_args, &maps, None, Some(metric)).map_err(|err| err.to_string());
+                pb.inc(1);
+                (seed, outcome)
+            })
+            .collect();
+        for (seed, outcome) in batch {
+            match outcome {
+                Ok(outcome) if outcome.censored => censored_seeds.push(seed),
+                Ok(outcome) => results.push((seed, outcome.metric_value.unwrap_or(f64::NAN))),
+                Err(err) => {
+                    pb.suspend(|| {
+                        eprintln!("{}: seed {} ({})", "gridcover failed".color(colored::Color::Red).bold(), seed, err)
+                    });
+                    failed_seeds.push(seed);
+                }
+            }
+        }
+        stats = SampleStats::from_values(&results.iter().map(|(_, value)| *value).collect::<Vec<_>>());
+        pb.set_message(format!("mean {:.2} ± {:.2}", stats.mean, stats.ci95));
+        // The estimate is not reported with censored runs, so more seeds do not help
+        if !censored_seeds.is_empty() || (results.len() as u64 >= args.min_seeds && stats.ci95 <= args.tolerance) {
+            break;
+        }
+        batch_size = args.cores.max(1) as u64;
+    }
+    let converged = results.len() as u64 >= args.min_seeds && stats.ci95 <= args.tolerance && censored_seeds.is_empty();
+    pb.finish_and_clear();
+
+    let seeds: Vec<u64> = results.iter().map(|(seed, _)| *seed).collect();
+    let result = json!({
+        "Metric": metric.description(),
+        "Gridcover args": common_args,
+        "Tolerance": args.tolerance,
+        "Converged": converged,
+        "Runs": results.len(),
+        "Statistics": stats.to_json(),
+        "Seeds": seeds,
+        "Failed seeds": failed_seeds,
+        "Censored seeds": censored_seeds,
+        "Values": results.iter().map(|(_, value)| *value).collect::<Vec<_>>(),
+    });
+    fs::write(&result_file, serde_json::to_string_pretty(&result)?)
+        .map_err(|e| format!("Cannot write Monte Carlo result '{result_file}': {e}"))?;
+
+    if !args.quiet {
+        println!("{} {}", format!("{}:", metric.description()).color(colored::Color::Green).bold(), mean_with_ci(&stats));
+        println!(
+            "  standard deviation {:.2}, {} runs with seeds {} to {}{}",
+            stats.stddev,
+            results.len(),
+            seeds.first().copied().unwrap_or_default(),
+            seeds.last().copied().unwrap_or_default(),
+            if failed_seeds.is_empty() { String::new() } else { format!(" ({} failed seeds left out)", failed_seeds.len()) }
+        );
+        if !censored_seeds.is_empty() {
+            println!(
+                "  {} {} runs stopped before the metric was known are left out",
+                "Warning:".color(colored::Color::Yellow).bold(),
+                censored_seeds.len()
+            );
+        }
+        println!("Result and seeds written to '{result_file}', runs stored in '{db_file}'");
+    }
+    if !censored_seeds.is_empty() {
+        return Err(format!(
+            "{} of {} runs were stopped by another stop condition before the metric was known, so the \
+             estimate is biased. Remove or raise the other stop conditions in the common arguments, or lower the target of the metric",
+            censored_seeds.len(),
+            results.len() + censored_seeds.len()
+        )
+        .into());
+    }
+    if !converged {
+        return Err(format!(
+            "The confidence interval ±{:.2} is still wider than ±{} after {} seeds, raise --max-seeds or the tolerance",
+            stats.ci95, args.tolerance, args.max_seeds
+        )
+        .into());
     }
-    let (head, tail) = s.split_at(s.len() - 1);
-    Some(format!("{head}.{tail}"))
+    Ok(())
 }
 
 #[allow(clippy::collapsible_if)]
@@ -141,29 +1016,54 @@ fn main() {
         )
     });
 
-    // Range of coverage values (1 to 99)
-    let coverage_values: Vec<u32> = (1..=990).collect();
+    if let Some(metric) = args.monte_carlo {
+        if let Err(err) = run_monte_carlo(&args, metric) {
+            eprintln!(
+                "{} {}",
+                "Monte Carlo estimate failed:".color(colored::Color::Red).bold(),
+                err
+            );
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let steps: Vec<u64> = if args.max_steps > 0 {
-        (1..=args.max_steps).collect()
-    } else {
-        vec![0] // Default to 0 if max_steps is not set
-    };
+    if let Some(optimize_file) = &args.optimize_file {
+        if let Err(err) = run_optimization(&args, optimize_file) {
+            eprintln!(
+                "{} {}",
+                "Optimisation failed:".color(colored::Color::Red).bold(),
+                err
+            );
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    // Setup progress bar
-    if args.max_steps > 0 {
-        if !args.quiet {
-            println!(
-                "{}",
-                "Running simulations for steps ..."
-                    .color(colored::Color::Green)
-                    .bold()
+    if let Some(experiment_file) = &args.experiment_file {
+        if let Err(err) = run_experiment(&args, experiment_file) {
+            eprintln!(
+                "{} {}",
+                "Experiment failed:".color(colored::Color::Red).bold(),
+                err
             );
+            std::process::exit(1);
         }
-    } else if !args.quiet {
+        return;
+    }
+
+    // The series is either by steps or by coverage, counted in tenths of a percent
+    let (metric, last) = if args.max_steps > 0 {
+        (SnapshotMetric::Steps, args.max_steps)
+    } else {
+        (SnapshotMetric::Coverage, LAST_COVERAGE_TENTHS)
+    };
+
+    if !args.quiet {
+        let series = if args.max_steps > 0 { "steps" } else { "coverage values" };
         println!(
             "{}",
-            "Running simulations for coverage values ..."
+            format!("Running simulation for {series} ...")
                 .color(colored::Color::Green)
                 .bold()
         );
@@ -180,12 +1080,28 @@ fn main() {
         return;
     }
 
-    if !args.skip_simulation {
-        let pb = if args.max_steps == 0 {
-            ProgressBar::new(coverage_values.len() as u64)
-        } else {
-            ProgressBar::new((steps.len() / speedup) as u64)
-        };
+    // Every speedup:th step is saved, the coverage series always has every tenth of a percent
+    let interval = if args.max_steps > 0 { speedup as u64 } else { 1 };
+    // The series is a single simulation, it is resumed by skipping it once all images are saved
+    let series_saved = args.resume
+        && (1..=last)
+            .step_by(interval as usize)
+            .all(|milestone| fs::metadata(metric.file_name(output_dir, milestone)).is_ok());
+    if args.resume && !args.quiet {
+        println!(
+            "{}",
+            if series_saved {
+                "Resuming: all images of the series are saved, the simulation is skipped"
+            } else {
+                "Resuming: images of the series are missing, the simulation is run again"
+            }
+            .color(colored::Color::Green)
+            .bold()
+        );
+    }
+
+    if !args.skip_simulation && !series_saved {
+        let pb = if args.quiet { ProgressBar::hidden() } else { ProgressBar::new(last) };
 
         pb.set_style(
             ProgressStyle::default_bar()
@@ -194,7 +1110,7 @@ fn main() {
                 .progress_chars("#>-"),
         );
         if !args.quiet {
-            pb.set_message("Running simulations ...");
+            pb.set_message("Running simulation ...");
         }
         // Parse common_args string into Vec<String>
         let common_args: Vec<String> = if args.common_args.trim().is_empty() {
@@ -205,102 +1121,19 @@ fn main() {
 
         let db_file = format!("{output_dir}/results.db");
 
-        if args.max_steps > 0 {
-            // Use rayon for parallelism
-            steps
-                .par_iter()
-                .enumerate()
-                .filter(|(i, _)| i % speedup == 0)
-                .for_each(|(_, &step)| {
-                    let image_file = format!("{output_dir}/frame_{step:08}.png");
-
-                    let mut sim_args = common_args.clone();
-                    sim_args.push("-o".to_string());
-                    sim_args.push(image_file.clone());
-                    sim_args.push("-Q".to_string());
-                    sim_args.push(db_file.clone());
-                    sim_args.push("-q".to_string());
-                    sim_args.push("true".to_string());
-                    sim_args.push("-m".to_string());
-                    sim_args.push(step.to_string());
-
-                    let status = Command::new("gridcover")
-                        .args(&sim_args)
-                        .status()
-                        .unwrap_or_else(|_| {
-                            panic!(
-                                "{}",
-                                "Failed to start gridcover process"
-                                    .color(colored::Color::Red)
-                                    .bold()
-                                    .to_string()
-                            )
-                        });
-
-                    if !status.success() {
-                        eprintln!(
-                            "{}: {}",
-                            "gridcover failed for coverage at step"
-                                .color(colored::Color::Red)
-                                .bold(),
-                            step
-                        );
-                    }
-                    if !args.quiet {
-                        pb.inc(1);
-                    }
-                });
-        } else {
-            // Use rayon for parallelism
-            coverage_values.par_iter().for_each(|&coverage| {
-                let image_file = format!("{output_dir}/frame_{coverage:04}.png");
-
-                let mut sim_args = common_args.clone();
-                sim_args.push("-o".to_string());
-                sim_args.push(image_file.clone());
-                sim_args.push("-Q".to_string());
-                sim_args.push(db_file.clone());
-                sim_args.push("-q".to_string());
-                sim_args.push("true".to_string());
-
-                sim_args.push("-c".to_string());
-                sim_args.push(
-                    insert_decimal(&coverage.to_string()).unwrap_or_else(|| coverage.to_string()),
-                );
-
-                let status = Command::new("gridcover")
-                    .args(&sim_args)
-                    .status()
-                    .unwrap_or_else(|_| {
-                        panic!(
-                            "{}",
-                            "Failed to start gridcover process"
-                                .color(colored::Color::Red)
-                                .bold()
-                                .to_string()
-                        )
-                    });
-
-                if !status.success() {
-                    eprintln!(
-                        "{}: {}%",
-                        "'gridcover' failed for coverage"
-                            .color(colored::Color::Red)
-                            .bold(),
-                        coverage
-                    );
-                }
-
-                if !args.quiet {
-                    // Increment progress bar
-                    pb.inc(1);
-                }
-            });
+        if let Err(err) = run_snapshot_series(&common_args, output_dir, &db_file, metric, interval, last, &pb) {
+            pb.abandon();
+            eprintln!(
+                "{}: {}",
+                "Simulation failed".color(colored::Color::Red).bold(),
+                err
+            );
+            return;
         }
 
         if !args.quiet {
             // Finish progress bar
-            pb.finish_with_message("All simulations complete!");
+            pb.finish_with_message("All frames saved!");
         }
     }
 
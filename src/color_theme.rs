@@ -8,6 +8,9 @@ pub struct ColorTheme {
     pub text_color: [u8; 3],
     pub text_background_adjustment: f32,
     pub obstacle_color: [u8; 3],
+    /// Cells the cutter drives over without cutting
+    pub no_cut_color: [u8; 3],
+    pub virtual_fence_color: [u8; 3],
     pub center_color: [u8; 3],
     pub coverage_shades: Vec<[u8; 3]>,
 }
@@ -97,6 +100,8 @@ impl ColorThemeManager {
             text_background_adjustment: 0.4,
             grid_line_color: [0, 0, 0],
             obstacle_color: [150, 0, 0],
+            no_cut_color: [190, 170, 120],
+            virtual_fence_color: [255, 140, 0],
             center_color: [0, 0, 0],
             coverage_shades: vec![
                 [240, 255, 240], // Honeydew (very light green)
@@ -133,6 +138,8 @@ impl ColorThemeManager {
             text_background_adjustment: 0.4,
             grid_line_color: [0, 0, 0],
             obstacle_color: [150, 0, 0],
+            no_cut_color: [190, 170, 120],
+            virtual_fence_color: [255, 140, 0],
             center_color: [0, 0, 0],
             coverage_shades: vec![
                 [240, 255, 240], // Honeydew (very light green)
@@ -178,6 +185,8 @@ impl ColorThemeManager {
             text_background_adjustment: 0.4,
             grid_line_color: [0, 0, 0],
             obstacle_color: [150, 0, 0],
+            no_cut_color: [190, 170, 120],
+            virtual_fence_color: [255, 140, 0],
             center_color: [0, 0, 0],
             coverage_shades: vec![
                 [240, 255, 240],
@@ -223,6 +232,8 @@ impl ColorThemeManager {
             text_background_adjustment: 0.2,
             grid_line_color: [0, 0, 0],
             obstacle_color: [150, 0, 0],
+            no_cut_color: [190, 170, 120],
+            virtual_fence_color: [255, 140, 0],
             center_color: [0, 0, 0],
             coverage_shades: vec![
                 [240, 255, 240],
@@ -268,6 +279,8 @@ impl ColorThemeManager {
             text_background_adjustment: 0.3,
             grid_line_color: [0, 0, 0],
             obstacle_color: [150, 0, 0],
+            no_cut_color: [190, 170, 120],
+            virtual_fence_color: [255, 140, 0],
             center_color: [0, 0, 0],
             coverage_shades: vec![
                 [240, 248, 255], // Alice blue (very light)
@@ -304,6 +317,8 @@ impl ColorThemeManager {
             text_background_adjustment: 0.1,
             grid_line_color: [128, 128, 128],
             obstacle_color: [0, 0, 255],
+            no_cut_color: [160, 160, 160],
+            virtual_fence_color: [255, 0, 255],
             center_color: [0, 0, 0],
             coverage_shades: vec![
                 [255, 255, 0], // Yellow
@@ -0,0 +1,168 @@
+use crate::args::Args;
+use crate::model::{cuttertype::CutterType, papersize::PaperSize};
+use serde::{Deserialize, Serialize};
+
+/// All parameters that define a simulation run.
+///
+/// This is the library counterpart of the command line arguments and contains only what the
+/// simulation itself needs. Defaults are the same as for the command line program. The
+/// `perturb_segment_percent` is given in percent just as on the command line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimConfig {
+    pub image_file_name: Option<String>,
+    pub step_size: f64,
+    pub radius: f64,
+    pub blade_len: f64,
+    pub grid_width: f64,
+    pub grid_height: f64,
+    pub cell_size: f64,
+    pub start_x: f64,
+    pub start_y: f64,
+    pub velocity: f64,
+    pub start_dir_x: f64,
+    pub start_dir_y: f64,
+    pub perturb_segment: bool,
+    pub perturb_segment_percent: f64,
+    pub stop_bounces: usize,
+    pub stop_time: f64,
+    pub stop_coverage: f64,
+    pub stop_simsteps: u64,
+    pub stop_distance: f64,
+    /// Stop condition expression, see `crate::stopcond`
+    pub stop_when: Option<String>,
+    pub verbosity: usize,
+    pub parallel: bool,
+    pub random_seed: u64,
+    pub image_width_mm: u32,
+    pub image_height_mm: u32,
+    pub paper_size: PaperSize,
+    pub track_center: bool,
+    pub show_progress: bool,
+    pub tui: bool,
+    pub cutter_type: CutterType,
+    pub dpi: u32,
+    pub battery_run_time: f64,
+    pub battery_charge_time: f64,
+    pub map_file_name: Option<String>,
+    pub svg_scale: f64,
+    pub raster_resolution: f64,
+    pub show_gridlines: bool,
+    pub quiet: bool,
+    pub generate_frames: bool,
+    pub frame_rate: u64,
+    pub frames_dir: String,
+    pub create_animation: bool,
+    pub animation_file_name: String,
+    pub animation_speedup: u64,
+    pub hw_encoding: bool,
+    pub delete_frames: bool,
+    /// Save an image every n:th step in `snapshot_dir`, 0 for none
+    pub snapshot_steps: u64,
+    /// Save an image each time the coverage has increased this many percent in `snapshot_dir`, 0 for none
+    pub snapshot_coverage: f64,
+    pub snapshot_dir: String,
+    pub color_theme: Option<String>,
+    pub wheel_slippage: bool,
+    pub slippage_probability: f64,
+    pub slippage_min_distance: f64,
+    pub slippage_max_distance: f64,
+    pub slippage_radius_min: f64,
+    pub slippage_radius_max: f64,
+    pub slippage_check_activation_distance: f64,
+    pub slippage_adjustment_step: f64,
+    pub wheel_inbalance: bool,
+    pub wheel_inbalance_radius_min: f64,
+    pub wheel_inbalance_radius_max: f64,
+    pub wheel_inbalance_adjustment_step: f64,
+    pub show_quad_tree: bool,
+    pub min_qnode_size: f64,
+    pub use_quad_tree: bool,
+    pub save_quad_tree: bool,
+    pub show_image_label: bool,
+    pub generate_json_files: bool,
+    /// Record the path of the cutter so it can be stored in the database with the final grid
+    pub db_store_grid: bool,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig::from(&Args::default())
+    }
+}
+
+impl From<&Args> for SimConfig {
+    fn from(args: &Args) -> Self {
+        SimConfig {
+            image_file_name: args.image_file_name.clone(),
+            step_size: args.step_size,
+            radius: args.radius,
+            blade_len: args.blade_len,
+            grid_width: args.grid_width,
+            grid_height: args.grid_height,
+            cell_size: args.cell_size,
+            start_x: args.start_x,
+            start_y: args.start_y,
+            velocity: args.velocity,
+            start_dir_x: args.start_angle.map_or(args.start_dir_x, |angle| angle.to_radians().cos()),
+            start_dir_y: args.start_angle.map_or(args.start_dir_y, |angle| angle.to_radians().sin()),
+            perturb_segment: args.perturb_segment,
+            perturb_segment_percent: args.perturb_segment_percent,
+            stop_bounces: args.stop_bounces,
+            stop_time: args.stop_time,
+            stop_coverage: args.stop_coverage,
+            stop_simsteps: args.stop_simsteps,
+            stop_distance: args.stop_distance,
+            stop_when: args.stop_when.clone(),
+            verbosity: args.verbosity,
+            parallel: args.parallel,
+            random_seed: args.random_seed,
+            image_width_mm: args.image_width_mm,
+            image_height_mm: args.image_height_mm,
+            paper_size: args.paper_size,
+            track_center: args.track_center,
+            show_progress: args.show_progress,
+            tui: args.tui,
+            cutter_type: args.cutter_type,
+            dpi: args.dpi,
+            battery_run_time: args.battery_run_time,
+            battery_charge_time: args.battery_charge_time,
+            map_file_name: args.map_file_name.clone(),
+            svg_scale: args.svg_scale,
+            raster_resolution: args.raster_resolution,
+            show_gridlines: args.show_gridlines,
+            quiet: args.quiet,
+            generate_frames: args.generate_frames,
+            frame_rate: args.frame_rate,
+            frames_dir: args.frames_dir.clone(),
+            create_animation: args.create_animation,
+            animation_file_name: args.animation_file_name.clone(),
+            animation_speedup: args.animation_speedup,
+            hw_encoding: args.hw_encoding,
+            delete_frames: args.delete_frames,
+            snapshot_steps: args.snapshot_steps,
+            snapshot_coverage: args.snapshot_coverage,
+            snapshot_dir: args.snapshot_dir.clone(),
+            color_theme: args.color_theme.clone(),
+            wheel_slippage: args.wheel_slippage,
+            slippage_probability: args.slippage_probability,
+            slippage_min_distance: args.slippage_min_distance,
+            slippage_max_distance: args.slippage_max_distance,
+            slippage_radius_min: args.slippage_radius_min,
+            slippage_radius_max: args.slippage_radius_max,
+            slippage_check_activation_distance: args.slippage_check_activation_distance,
+            slippage_adjustment_step: args.slippage_adjustment_step,
+            wheel_inbalance: args.wheel_inbalance,
+            wheel_inbalance_radius_min: args.wheel_inbalance_radius_min,
+            wheel_inbalance_radius_max: args.wheel_inbalance_radius_max,
+            wheel_inbalance_adjustment_step: args.wheel_inbalance_adjustment_step,
+            show_quad_tree: args.show_quad_tree,
+            min_qnode_size: args.min_qnode_size,
+            use_quad_tree: args.use_quad_tree,
+            save_quad_tree: args.save_quad_tree,
+            show_image_label: args.show_image_label,
+            generate_json_files: args.generate_json_files,
+            db_store_grid: args.db_store_grid,
+        }
+    }
+}
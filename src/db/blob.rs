@@ -0,0 +1,142 @@
+//! Compressed binary encoding of the final grid and the cutter path that can be stored with a
+//! result. Both start with a four byte tag that also gives the format version, followed by zlib
+//! compressed little-endian data.
+//!
+//! - Grid: number of cells in x and y (u32), the cell size (f64), the number of covered cells (u32)
+//!   and then for each cell, column by column, the kind of cell (u8) and for covered cells the
+//!   number of times it was visited (u32)
+//! - Path: number of points (u32) followed by the x and y coordinate (f32) of each point
+
+use crate::model::coverageinfo::CoverageInfo;
+use crate::model::grid::{Cell, Grid};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::error::Error;
+use std::io::{Read, Write};
+
+const GRID_TAG: &[u8; 4] = b"GCG1";
+const PATH_TAG: &[u8; 4] = b"GCP1";
+
+const CELL_EMPTY: u8 = 0;
+const CELL_COVERED: u8 = 1;
+const CELL_CENTER_POINT: u8 = 2;
+const CELL_OBSTACLE: u8 = 3;
+const CELL_NO_CUT: u8 = 4;
+const CELL_VIRTUAL_FENCE: u8 = 5;
+
+fn compress(tag: &[u8; 4], data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut encoder = ZlibEncoder::new(tag.to_vec(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn decompress(tag: &[u8; 4], blob: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if blob.len() < tag.len() || &blob[..tag.len()] != tag {
+        return Err(format!("Unknown stored data format, expected '{}'", String::from_utf8_lossy(tag)).into());
+    }
+    let mut data = Vec::new();
+    ZlibDecoder::new(&blob[tag.len()..]).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Reads little-endian values from decompressed data
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], Box<dyn Error>> {
+        if self.data.len() < N {
+            return Err("Stored data is truncated".into());
+        }
+        let (bytes, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(bytes.try_into()?)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, Box<dyn Error>> {
+        Ok(f32::from_le_bytes(self.bytes()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, Box<dyn Error>> {
+        Ok(f64::from_le_bytes(self.bytes()?))
+    }
+}
+
+/// Encode the obstacles, zones and visit counts of the grid
+pub fn encode_grid(grid: &Grid) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut data = Vec::with_capacity(20 + grid.cells_x * grid.cells_y);
+    data.extend((grid.cells_x as u32).to_le_bytes());
+    data.extend((grid.cells_y as u32).to_le_bytes());
+    data.extend(grid.cell_size.to_le_bytes());
+    data.extend((grid.covered_cells as u32).to_le_bytes());
+    for cell in grid.get_cell_iter() {
+        match cell {
+            Cell::Empty => data.push(CELL_EMPTY),
+            Cell::Covered(info) | Cell::CenterPoint(info) => {
+                data.push(if matches!(cell, Cell::Covered(_)) { CELL_COVERED } else { CELL_CENTER_POINT });
+                data.extend((info.times_visited as u32).to_le_bytes());
+            }
+            Cell::Obstacle => data.push(CELL_OBSTACLE),
+            Cell::NoCut => data.push(CELL_NO_CUT),
+            Cell::VirtualFence => data.push(CELL_VIRTUAL_FENCE),
+        }
+    }
+    compress(GRID_TAG, &data)
+}
+
+/// Decode a grid encoded by `encode_grid()`. The bounce number that covered each cell is not
+/// stored and is set to 0.
+pub fn decode_grid(blob: &[u8]) -> Result<Grid, Box<dyn Error>> {
+    let data = decompress(GRID_TAG, blob)?;
+    let mut reader = Reader { data: &data };
+    let cells_x = reader.u32()? as usize;
+    let cells_y = reader.u32()? as usize;
+    let cell_size = reader.f64()?;
+    let covered_cells = reader.u32()? as usize;
+    let mut grid = Grid::new(cells_x, cells_y, cell_size);
+    for column in grid.cells.iter_mut() {
+        for cell in column.iter_mut() {
+            *cell = match reader.u8()? {
+                CELL_EMPTY => Cell::Empty,
+                CELL_COVERED => Cell::Covered(CoverageInfo::new(0, reader.u32()? as usize)),
+                CELL_CENTER_POINT => Cell::CenterPoint(CoverageInfo::new(0, reader.u32()? as usize)),
+                CELL_OBSTACLE => Cell::Obstacle,
+                CELL_NO_CUT => Cell::NoCut,
+                CELL_VIRTUAL_FENCE => Cell::VirtualFence,
+                kind => return Err(format!("Unknown cell kind {kind} in stored grid").into()),
+            };
+        }
+    }
+    grid.update_obstacle_cells_count();
+    grid.covered_cells = covered_cells;
+    Ok(grid)
+}
+
+/// Encode the recorded positions of the cutter
+pub fn encode_path(path: &[[f32; 2]]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut data = Vec::with_capacity(4 + 8 * path.len());
+    data.extend((path.len() as u32).to_le_bytes());
+    for [x, y] in path {
+        data.extend(x.to_le_bytes());
+        data.extend(y.to_le_bytes());
+    }
+    compress(PATH_TAG, &data)
+}
+
+/// Decode a path encoded by `encode_path()`
+pub fn decode_path(blob: &[u8]) -> Result<Vec<[f32; 2]>, Box<dyn Error>> {
+    let data = decompress(PATH_TAG, blob)?;
+    let mut reader = Reader { data: &data };
+    let count = reader.u32()? as usize;
+    (0..count).map(|_| Ok([reader.f32()?, reader.f32()?])).collect()
+}
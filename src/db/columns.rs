@@ -0,0 +1,225 @@
+//! The columns of the `models` and `results` tables and where their values are found in the
+//! model and result JSON of a simulation. The same table is used to store a run and to rebuild
+//! the JSON of a stored run, so `db show` prints a run exactly like the simulation does.
+
+use super::{get_bool_as_i64_from_json, get_f64_from_json, get_i64_from_json, get_string_from_json};
+use rusqlite::types::Value as SqlValue;
+use serde_json::{Map, Value as JsonValue};
+
+/// How a value is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Kind {
+    Real,
+    Integer,
+    /// Stored as 0 or 1
+    Bool,
+    Text,
+}
+
+/// A column and the path of keys to its value below the "Model" or "Result" root key
+#[derive(Debug, Clone, Copy)]
+pub(super) struct JsonColumn {
+    pub column: &'static str,
+    pub kind: Kind,
+    pub path: &'static [&'static str],
+}
+
+impl JsonColumn {
+    const fn new(column: &'static str, kind: Kind, path: &'static [&'static str]) -> Self {
+        JsonColumn { column, kind, path }
+    }
+
+    /// The value to store, taken from the JSON below the root key
+    pub fn sql_value(&self, data: &JsonValue) -> SqlValue {
+        let value = self.path.iter().fold(data, |value, key| &value[key]);
+        match self.kind {
+            Kind::Real => get_f64_from_json(value).into(),
+            Kind::Integer => get_i64_from_json(value).into(),
+            Kind::Bool => get_bool_as_i64_from_json(value).into(),
+            Kind::Text => get_string_from_json(value).into(),
+        }
+    }
+}
+
+/// Rebuild the JSON below the root key from stored columns. Columns that are not in the table,
+/// or are NULL because they were added after the run was stored, are left out.
+pub(super) fn columns_to_json(table: &[JsonColumn], columns: &[(String, JsonValue)]) -> JsonValue {
+    let mut root = Map::new();
+    for column in table {
+        let Some((_, value)) = columns.iter().find(|(name, _)| name == column.column) else {
+            continue;
+        };
+        if value.is_null() {
+            continue;
+        }
+        let value = match column.kind {
+            Kind::Bool => JsonValue::Bool(value.as_i64().unwrap_or_default() != 0),
+            _ => value.clone(),
+        };
+        let (last, parents) = column.path.split_last().expect("Column path is not empty");
+        let mut object = &mut root;
+        for key in parents {
+            object = object
+                .entry(key.to_string())
+                .or_insert_with(|| JsonValue::Object(Map::new()))
+                .as_object_mut()
+                .expect("Column paths do not overlap with values");
+        }
+        object.insert(last.to_string(), value);
+    }
+    JsonValue::Object(root)
+}
+
+/// Columns of the `models` table apart from the experiment
+pub(super) const MODEL_COLUMNS: &[JsonColumn] = &[
+    JsonColumn::new("cutter_battery_charge_time", Kind::Real, &["Cutter", "Battery", "Charge Time"]),
+    JsonColumn::new("cutter_battery_run_time", Kind::Real, &["Cutter", "Battery", "Run Time"]),
+    JsonColumn::new("cutter_blade_length", Kind::Real, &["Cutter", "Blade Length"]),
+    JsonColumn::new("cutter_radius", Kind::Real, &["Cutter", "Radius"]),
+    JsonColumn::new("cutter_radius_in_cells", Kind::Integer, &["Cutter", "Radius in cells"]),
+    JsonColumn::new("cutter_type", Kind::Text, &["Cutter", "Type"]),
+    JsonColumn::new("cutter_velocity", Kind::Real, &["Cutter", "Velocity"]),
+    JsonColumn::new("cutter_wheel_inbalance_adjustment_step", Kind::Real, &["Cutter", "Wheel Inbalance", "Adjustment step"]),
+    JsonColumn::new("cutter_wheel_inbalance_enabled", Kind::Bool, &["Cutter", "Wheel Inbalance", "Enabled"]),
+    JsonColumn::new("cutter_wheel_inbalance_radius", Kind::Real, &["Cutter", "Wheel Inbalance", "Radius"]),
+    JsonColumn::new("cutter_wheel_inbalance_radius_max", Kind::Real, &["Cutter", "Wheel Inbalance", "Radius max"]),
+    JsonColumn::new("cutter_wheel_inbalance_radius_min", Kind::Real, &["Cutter", "Wheel Inbalance", "Radius min"]),
+    JsonColumn::new("cutter_wheel_slippage_activation_check_distance", Kind::Real, &["Cutter", "Wheel Slippage", "Activation check distance"]),
+    JsonColumn::new("cutter_wheel_slippage_adjustment_step", Kind::Real, &["Cutter", "Wheel Slippage", "Adjustment step"]),
+    JsonColumn::new("cutter_wheel_slippage_enabled", Kind::Bool, &["Cutter", "Wheel Slippage", "Enabled"]),
+    JsonColumn::new("cutter_wheel_slippage_max_duration_distance", Kind::Real, &["Cutter", "Wheel Slippage", "Max Duration Distance"]),
+    JsonColumn::new("cutter_wheel_slippage_min_duration_distance", Kind::Real, &["Cutter", "Wheel Slippage", "Min Duration Distance"]),
+    JsonColumn::new("cutter_wheel_slippage_probability", Kind::Real, &["Cutter", "Wheel Slippage", "Probability"]),
+    JsonColumn::new("cutter_wheel_slippage_radius_max", Kind::Real, &["Cutter", "Wheel Slippage", "Radius max"]),
+    JsonColumn::new("cutter_wheel_slippage_radius_min", Kind::Real, &["Cutter", "Wheel Slippage", "Radius min"]),
+    JsonColumn::new("frames_animation_file_name", Kind::Text, &["Frames", "Animation File Name"]),
+    JsonColumn::new("frames_animation_speedup", Kind::Integer, &["Frames", "Animation Speedup"]),
+    JsonColumn::new("frames_create_animation", Kind::Bool, &["Frames", "Create Animation"]),
+    JsonColumn::new("frames_delete_frames", Kind::Bool, &["Frames", "Delete Frames"]),
+    JsonColumn::new("frames_directory", Kind::Text, &["Frames", "Directory"]),
+    JsonColumn::new("frames_enabled", Kind::Bool, &["Frames", "Enabled"]),
+    JsonColumn::new("frames_hw_encoding", Kind::Bool, &["Frames", "HW Encoding"]),
+    JsonColumn::new("frames_rate_fps", Kind::Integer, &["Frames", "Rate (fps)"]),
+    JsonColumn::new("grid_cell_size", Kind::Real, &["Grid", "Cell Size"]),
+    JsonColumn::new("grid_height_units", Kind::Real, &["Grid", "Height (units)"]),
+    JsonColumn::new("grid_hor__cells", Kind::Integer, &["Grid", "Hor. Cells"]),
+    JsonColumn::new("grid_map_file_name", Kind::Text, &["Grid", "Map File Name"]),
+    JsonColumn::new("grid_obstacles_cells_with_obstacle", Kind::Integer, &["Grid", "Obstacles", "Cells with obstacle"]),
+    JsonColumn::new("grid_obstacles_num_obstacles", Kind::Integer, &["Grid", "Obstacles", "Num obstacles"]),
+    JsonColumn::new("grid_obstacles_percent", Kind::Real, &["Grid", "Obstacles", "Percent"]),
+    JsonColumn::new("grid_total_cells", Kind::Integer, &["Grid", "Total Cells"]),
+    JsonColumn::new("grid_ver__cells", Kind::Integer, &["Grid", "Ver. Cells"]),
+    JsonColumn::new("grid_width_units", Kind::Real, &["Grid", "Width (units)"]),
+    JsonColumn::new("image_color_theme", Kind::Text, &["Image", "Color Theme"]),
+    JsonColumn::new("image_dpi", Kind::Integer, &["Image", "DPI"]),
+    JsonColumn::new("image_image_file_name", Kind::Text, &["Image", "Image File Name"]),
+    JsonColumn::new("image_image_size_mm_height", Kind::Integer, &["Image", "Image Size (mm)", "Height"]),
+    JsonColumn::new("image_image_size_mm_width", Kind::Integer, &["Image", "Image Size (mm)", "Width"]),
+    JsonColumn::new("image_paper_size_format", Kind::Text, &["Image", "Paper Size", "format"]),
+    JsonColumn::new("image_paper_size_height_mm", Kind::Real, &["Image", "Paper Size", "height_mm"]),
+    JsonColumn::new("image_paper_size_width_mm", Kind::Real, &["Image", "Paper Size", "width_mm"]),
+    JsonColumn::new("image_show_gridlines", Kind::Bool, &["Image", "Show Gridlines"]),
+    JsonColumn::new("simulation_perturb_segment", Kind::Bool, &["Simulation", "Perturb Segment"]),
+    JsonColumn::new("simulation_perturb_segment_percent", Kind::Real, &["Simulation", "Perturb Segment Percent"]),
+    JsonColumn::new("simulation_perturb_at_bounces", Kind::Bool, &["Simulation", "Perturb at Bounces"]),
+    JsonColumn::new("simulation_quiet", Kind::Bool, &["Simulation", "Quiet"]),
+    JsonColumn::new("simulation_show_progress", Kind::Bool, &["Simulation", "Show Progress"]),
+    JsonColumn::new("simulation_step_size", Kind::Real, &["Simulation", "Step Size"]),
+    JsonColumn::new("simulation_track_center", Kind::Bool, &["Simulation", "Track Center"]),
+    JsonColumn::new("simulation_verbosity", Kind::Integer, &["Simulation", "Verbosity"]),
+    JsonColumn::new("simulation_random_seed", Kind::Integer, &["Simulation", "Random Seed"]),
+    JsonColumn::new("start_direction_angle_deg", Kind::Real, &["Start", "Direction", "Angle (deg)"]),
+    JsonColumn::new("start_direction_dirx", Kind::Real, &["Start", "Direction", "DirX"]),
+    JsonColumn::new("start_direction_diry", Kind::Real, &["Start", "Direction", "DirY"]),
+    JsonColumn::new("start_position_x", Kind::Real, &["Start", "Position", "X"]),
+    JsonColumn::new("start_position_y", Kind::Real, &["Start", "Position", "Y"]),
+    JsonColumn::new("stop_conditions_bounces", Kind::Integer, &["Stop Conditions", "Bounces"]),
+    JsonColumn::new("stop_conditions_coverage_percent", Kind::Real, &["Stop Conditions", "Coverage (%)"]),
+    JsonColumn::new("stop_conditions_distance_units", Kind::Real, &["Stop Conditions", "Distance (units)"]),
+    JsonColumn::new("stop_conditions_simulation_steps", Kind::Integer, &["Stop Conditions", "Simulation Steps"]),
+    JsonColumn::new("stop_conditions_time_seconds", Kind::Real, &["Stop Conditions", "Time (seconds)"]),
+    JsonColumn::new("grid_svg_scale", Kind::Real, &["Grid", "SVG Scale"]),
+    JsonColumn::new("grid_raster_resolution", Kind::Real, &["Grid", "Raster Resolution"]),
+    JsonColumn::new("grid_obstacles_cells_in_no_cut_zones", Kind::Integer, &["Grid", "Obstacles", "Cells in no-cut zones"]),
+    JsonColumn::new("grid_obstacles_cells_with_virtual_fence", Kind::Integer, &["Grid", "Obstacles", "Cells with virtual fence"]),
+    JsonColumn::new("grid_obstacles_terrain_regions", Kind::Integer, &["Grid", "Obstacles", "Terrain regions"]),
+    JsonColumn::new("grid_obstacles_cells_in_terrain_regions", Kind::Integer, &["Grid", "Obstacles", "Cells in terrain regions"]),
+    JsonColumn::new("stop_conditions_expression", Kind::Text, &["Stop Conditions", "Expression"]),
+    JsonColumn::new("stop_conditions_combined", Kind::Text, &["Stop Conditions", "Combined"]),
+];
+
+/// Columns of the `results` table apart from the model reference
+pub(super) const RESULT_COLUMNS: &[JsonColumn] = &[
+    JsonColumn::new("coverage_bounces", Kind::Integer, &["Coverage", "Bounces"]),
+    JsonColumn::new("coverage_cells", Kind::Integer, &["Coverage", "Cells"]),
+    JsonColumn::new("coverage_max_visited", Kind::Integer, &["Coverage", "Max visited"]),
+    JsonColumn::new("coverage_min_visited", Kind::Integer, &["Coverage", "Min visited"]),
+    JsonColumn::new("coverage_percent", Kind::Real, &["Coverage", "Percent"]),
+    JsonColumn::new("cutter_battery_charge_count", Kind::Integer, &["Cutter", "Battery", "Charge count"]),
+    JsonColumn::new("cutter_battery_charge_left_percent", Kind::Real, &["Cutter", "Battery", "Charge left (%)"]),
+    JsonColumn::new("cutter_battery_charge_time", Kind::Real, &["Cutter", "Battery", "Charge time"]),
+    JsonColumn::new("cutter_battery_run_time", Kind::Real, &["Cutter", "Battery", "Run time"]),
+    JsonColumn::new("cutter_blade_length", Kind::Real, &["Cutter", "Blade Length"]),
+    JsonColumn::new("cutter_cells_under", Kind::Real, &["Cutter", "Cells under"]),
+    JsonColumn::new("cutter_distance", Kind::Real, &["Cutter", "Distance"]),
+    JsonColumn::new("cutter_radius", Kind::Real, &["Cutter", "Radius"]),
+    JsonColumn::new("cutter_radius_in_cells", Kind::Integer, &["Cutter", "Radius in cells"]),
+    JsonColumn::new("cutter_type", Kind::Text, &["Cutter", "Type"]),
+    JsonColumn::new("cutter_velocity", Kind::Real, &["Cutter", "Velocity"]),
+    JsonColumn::new("cutter_wheel_inbalance", Kind::Bool, &["Cutter", "Wheel Inbalance"]),
+    JsonColumn::new("cutter_wheel_slippage", Kind::Bool, &["Cutter", "Wheel Slippage"]),
+    JsonColumn::new("frames_animation", Kind::Bool, &["Frames", "Animation"]),
+    JsonColumn::new("frames_animation_speedup", Kind::Integer, &["Frames", "Animation Speedup"]),
+    JsonColumn::new("frames_animation_file_name", Kind::Text, &["Frames", "Animation file name"]),
+    JsonColumn::new("frames_delete_frames", Kind::Bool, &["Frames", "Delete frames"]),
+    JsonColumn::new("frames_directory", Kind::Text, &["Frames", "Directory"]),
+    JsonColumn::new("frames_enabled", Kind::Bool, &["Frames", "Enabled"]),
+    JsonColumn::new("frames_hw_encoding", Kind::Bool, &["Frames", "HW Encoding"]),
+    JsonColumn::new("frames_rate_fps", Kind::Integer, &["Frames", "Rate (fps)"]),
+    JsonColumn::new("frames_steps_per_frame", Kind::Integer, &["Frames", "Steps per frame"]),
+    JsonColumn::new("grid_cell_side_units", Kind::Real, &["Grid", "Cell side (units)"]),
+    JsonColumn::new("grid_height_units", Kind::Real, &["Grid", "Height (units)"]),
+    JsonColumn::new("grid_hor_cells", Kind::Integer, &["Grid", "Hor.Cells"]),
+    JsonColumn::new("grid_obstacles_numcells", Kind::Integer, &["Grid", "Obstacles", "NumCells"]),
+    JsonColumn::new("grid_obstacles_percent", Kind::Real, &["Grid", "Obstacles", "Percent"]),
+    JsonColumn::new("grid_total_cells", Kind::Integer, &["Grid", "Total cells"]),
+    JsonColumn::new("grid_vert_cells", Kind::Integer, &["Grid", "Vert.Cells"]),
+    JsonColumn::new("grid_width_units", Kind::Real, &["Grid", "Width (units)"]),
+    JsonColumn::new("output_image_color_theme", Kind::Text, &["Output image", "Color Theme"]),
+    JsonColumn::new("output_image_dpi", Kind::Integer, &["Output image", "DPI"]),
+    JsonColumn::new("output_image_file_name", Kind::Text, &["Output image", "File name"]),
+    JsonColumn::new("output_image_paper_size_format", Kind::Text, &["Output image", "Paper size", "format"]),
+    JsonColumn::new("output_image_paper_size_height_mm", Kind::Real, &["Output image", "Paper size", "height_mm"]),
+    JsonColumn::new("output_image_paper_size_width_mm", Kind::Real, &["Output image", "Paper size", "width_mm"]),
+    JsonColumn::new("output_image_pixels_height", Kind::Integer, &["Output image", "Pixels", "height"]),
+    JsonColumn::new("output_image_pixels_width", Kind::Integer, &["Output image", "Pixels", "width"]),
+    JsonColumn::new("output_image_show_gridlines", Kind::Bool, &["Output image", "Show gridlines"]),
+    JsonColumn::new("start_angle_degrees", Kind::Real, &["Start", "Angle (degrees)"]),
+    JsonColumn::new("start_direction_x", Kind::Real, &["Start", "Direction", "X"]),
+    JsonColumn::new("start_direction_y", Kind::Real, &["Start", "Direction", "Y"]),
+    JsonColumn::new("start_position_x", Kind::Real, &["Start", "Position", "X"]),
+    JsonColumn::new("start_position_y", Kind::Real, &["Start", "Position", "Y"]),
+    JsonColumn::new("steps_length_units", Kind::Real, &["Steps", "Length (units)"]),
+    JsonColumn::new("steps_seconds_per_step", Kind::Real, &["Steps", "Seconds/step"]),
+    JsonColumn::new("steps_steps_per_cell", Kind::Real, &["Steps", "Steps/cell"]),
+    JsonColumn::new("steps_steps_per_second", Kind::Integer, &["Steps", "Steps/second"]),
+    JsonColumn::new("steps_total_num", Kind::Integer, &["Steps", "Total #"]),
+    JsonColumn::new("time_cpu_time", Kind::Text, &["Performance", "CPU time"]),
+    JsonColumn::new("time_cpu_time_milliseconds", Kind::Integer, &["Performance", "CPU time (milliseconds)"]),
+    JsonColumn::new("time_cutting_time", Kind::Text, &["Performance", "Cutting time"]),
+    JsonColumn::new("time_cutting_time_seconds", Kind::Integer, &["Performance", "Cutting time (seconds)"]),
+    JsonColumn::new("time_efficiency", Kind::Real, &["Performance", "Efficiency"]),
+    JsonColumn::new("time_ffmpeg_encoding_duration", Kind::Text, &["Performance", "FFmpeg Encoding Duration"]),
+    JsonColumn::new("time_min_cov_time", Kind::Text, &["Performance", "Min.Cov.Time"]),
+    JsonColumn::new("time_min_cov_time_seconds", Kind::Integer, &["Performance", "Min.Cov.Time (seconds)"]),
+    JsonColumn::new("coverage_virtual_fence_bounces", Kind::Integer, &["Coverage", "Virtual fence bounces"]),
+    JsonColumn::new("cutter_time_on_terrain_seconds", Kind::Real, &["Cutter", "Time on terrain (seconds)"]),
+    JsonColumn::new("grid_no_cut_zones_numcells", Kind::Integer, &["Grid", "No-cut zones", "NumCells"]),
+    JsonColumn::new("grid_no_cut_zones_percent", Kind::Real, &["Grid", "No-cut zones", "Percent"]),
+    JsonColumn::new("grid_virtual_fences_numcells", Kind::Integer, &["Grid", "Virtual fences", "NumCells"]),
+    JsonColumn::new("grid_virtual_fences_percent", Kind::Real, &["Grid", "Virtual fences", "Percent"]),
+    JsonColumn::new("grid_terrain_numcells", Kind::Integer, &["Grid", "Terrain", "NumCells"]),
+    JsonColumn::new("grid_terrain_percent", Kind::Real, &["Grid", "Terrain", "Percent"]),
+    JsonColumn::new("stop_reason", Kind::Text, &["Stop", "Reason"]),
+    JsonColumn::new("stop_condition", Kind::Text, &["Stop", "Condition"]),
+];
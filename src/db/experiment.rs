@@ -0,0 +1,278 @@
+//! Grouping of stored runs with the same model parameters into experiments
+
+use super::Database;
+use crate::args::DbFilterArgs;
+use crate::model::SimModel;
+use crate::stats::SampleStats;
+use colored::Colorize;
+use rusqlite::{OptionalExtension, Transaction, params};
+use serde_json::{Value as JsonValue, json};
+use std::collections::HashSet;
+use std::error::Error;
+
+/// Model values, as JSON pointers into the "Model" object, that do not change the outcome of a
+/// simulation and are left out of the parameter set: the random seed, values drawn from it,
+/// output paths and options that only change what is shown or written
+const EXCLUDED_PARAMETERS: &[&str] = &[
+    "/Simulation/Random Seed",
+    "/Simulation/Verbosity",
+    "/Simulation/Quiet",
+    "/Simulation/Show Progress",
+    "/Simulation/Terminal View",
+    "/Cutter/Wheel Inbalance/Radius",
+    "/Grid/Obstacles/Collision Checks",
+    "/Frames",
+    "/Image",
+];
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// The model parameters that decide the outcome of a simulation apart from the random seed. A
+/// start position or direction drawn from the seed is replaced by "Random".
+pub fn parameter_set(model: &SimModel) -> JsonValue {
+    let mut parameters = model.get_model_as_json()["Model"].take();
+    for pointer in EXCLUDED_PARAMETERS {
+        let (parent, key) = pointer.rsplit_once('/').expect("Pointer has a parent");
+        if let Some(object) = parameters.pointer_mut(parent).and_then(JsonValue::as_object_mut) {
+            object.remove(key);
+        }
+    }
+    if let Some(start) = parameters.pointer_mut("/Start").and_then(JsonValue::as_object_mut) {
+        if model.random_start_pos {
+            start.insert("Position".to_string(), json!("Random"));
+        }
+        if model.random_start_dir {
+            start.insert("Direction".to_string(), json!("Random"));
+        }
+    }
+    parameters
+}
+
+/// 64-bit FNV-1a hash, as 16 hex digits, of the parameter set serialized with sorted keys
+pub fn parameter_hash(parameters: &JsonValue) -> String {
+    let hash = parameters
+        .to_string()
+        .bytes()
+        .fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME));
+    format!("{hash:016x}")
+}
+
+/// The experiment a model is stored in and an earlier run it duplicates, if any
+#[derive(Debug, Clone)]
+pub struct ExperimentMatch {
+    pub experiment_id: i64,
+    pub parameter_hash: String,
+    /// Result ID of an earlier run of the experiment with the same random seed
+    pub duplicate_of: Option<i64>,
+}
+
+/// Find the experiment with the parameters of the model, create it if there is none
+pub(super) fn find_or_create_experiment(tx: &Transaction, model: &SimModel) -> Result<ExperimentMatch, Box<dyn Error>> {
+    let parameters = parameter_set(model);
+    let parameter_hash = parameter_hash(&parameters);
+    let existing = tx
+        .query_row("SELECT id FROM experiments WHERE parameter_hash = ?1", [&parameter_hash], |row| row.get(0))
+        .optional()?;
+    let experiment_id = match existing {
+        Some(id) => id,
+        None => {
+            tx.execute(
+                "INSERT INTO experiments (parameter_hash, parameters) VALUES (?1, ?2)",
+                params![parameter_hash, parameters.to_string()],
+            )?;
+            tx.last_insert_rowid()
+        }
+    };
+    let duplicate_of = tx
+        .query_row(
+            "SELECT r.id FROM results r JOIN models m ON m.id = r.model_id
+             WHERE m.experiment_id = ?1 AND m.simulation_random_seed = ?2 ORDER BY r.id LIMIT 1",
+            params![experiment_id, model.random_seed as i64],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(ExperimentMatch {
+        experiment_id,
+        parameter_hash,
+        duplicate_of,
+    })
+}
+
+/// Statistics of the coverage and cutting time of the stored runs of an experiment
+#[derive(Debug, Clone)]
+pub struct ExperimentStats {
+    pub experiment_id: i64,
+    pub parameter_hash: String,
+    pub map_file_name: String,
+    pub cutter_type: String,
+    /// Number of stored runs, including duplicates with the same random seed
+    pub runs: usize,
+    /// Statistics over the runs with different random seeds
+    pub coverage_percent: SampleStats,
+    pub cutting_time_seconds: SampleStats,
+}
+
+impl ExperimentStats {
+    pub fn to_json(&self) -> JsonValue {
+        json!({
+            "Experiment ID": self.experiment_id,
+            "Parameter hash": self.parameter_hash,
+            "Map File Name": self.map_file_name,
+            "Cutter Type": self.cutter_type,
+            "Runs": self.runs,
+            "Coverage (%)": self.coverage_percent.to_json(),
+            "Cutting time (seconds)": self.cutting_time_seconds.to_json(),
+        })
+    }
+}
+
+/// The statistics of the experiments of the runs matching a filter
+#[derive(Debug, Clone)]
+pub struct ExperimentReport {
+    pub experiments: Vec<ExperimentStats>,
+    /// Matching runs stored before experiments were added to the database, which belong to no
+    /// experiment. Their parameter hash can not be computed again from the stored columns as
+    /// these do not tell whether the start position and direction were drawn from the seed.
+    pub runs_without_experiment: usize,
+}
+
+/// One stored run of an experiment, used for the statistics
+struct ExperimentRun {
+    experiment_id: i64,
+    parameter_hash: String,
+    map_file_name: String,
+    cutter_type: String,
+    seed: i64,
+    coverage_percent: f64,
+    cutting_time_seconds: f64,
+}
+
+impl Database {
+    /// The ID of the experiment with the parameter hash, None if no run with its parameters is stored
+    pub fn find_experiment(&self, parameter_hash: &str) -> Result<Option<i64>, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row("SELECT id FROM experiments WHERE parameter_hash = ?1", [parameter_hash], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// Statistics of each experiment over the runs matching the filter. Runs stored before
+    /// experiments were added to the database belong to no experiment and are only counted.
+    pub fn experiment_stats(&self, filter: &DbFilterArgs) -> Result<ExperimentReport, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT m.experiment_id, m.parameter_hash, m.grid_map_file_name, m.cutter_type, m.simulation_random_seed,
+                    r.coverage_percent, r.time_cutting_time_seconds
+             FROM results r JOIN models m ON m.id = r.model_id WHERE r.id = ?1",
+        )?;
+        let mut runs = Vec::new();
+        let mut runs_without_experiment = 0;
+        for id in self.filtered_result_ids(filter)? {
+            let run = stmt
+                .query_row([id], |row| {
+                    let Some(experiment_id) = row.get::<_, Option<i64>>(0)? else {
+                        return Ok(None);
+                    };
+                    Ok(Some(ExperimentRun {
+                        experiment_id,
+                        parameter_hash: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                        map_file_name: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                        cutter_type: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                        seed: row.get::<_, Option<i64>>(4)?.unwrap_or_default(),
+                        coverage_percent: row.get::<_, Option<f64>>(5)?.unwrap_or_default(),
+                        cutting_time_seconds: row.get::<_, Option<f64>>(6)?.unwrap_or_default(),
+                    }))
+                })
+                .optional()?;
+            match run {
+                Some(Some(run)) => runs.push(run),
+                Some(None) => runs_without_experiment += 1,
+                None => {}
+            }
+        }
+        runs.sort_by_key(|run| run.experiment_id);
+        let experiments = runs
+            .chunk_by(|a, b| a.experiment_id == b.experiment_id)
+            .map(|runs| {
+                // Duplicate runs with the same seed have the same result and are only counted once
+                let mut seen = HashSet::new();
+                let unique: Vec<&ExperimentRun> = runs.iter().filter(|run| seen.insert(run.seed)).collect();
+                let coverage: Vec<f64> = unique.iter().map(|run| run.coverage_percent).collect();
+                let time: Vec<f64> = unique.iter().map(|run| run.cutting_time_seconds).collect();
+                ExperimentStats {
+                    experiment_id: runs[0].experiment_id,
+                    parameter_hash: runs[0].parameter_hash.clone(),
+                    map_file_name: runs[0].map_file_name.clone(),
+                    cutter_type: runs[0].cutter_type.clone(),
+                    runs: runs.len(),
+                    coverage_percent: SampleStats::from_values(&coverage),
+                    cutting_time_seconds: SampleStats::from_values(&time),
+                }
+            })
+            .collect();
+        Ok(ExperimentReport {
+            experiments,
+            runs_without_experiment,
+        })
+    }
+}
+
+/// The mean with the 95% confidence interval, e.g. "87.12 ± 1.05"
+fn mean_with_ci(stats: &SampleStats, decimals: usize) -> String {
+    if stats.ci95.is_finite() {
+        format!("{:.decimals$} ± {:.decimals$}", stats.mean, stats.ci95)
+    } else {
+        format!("{:.decimals$}", stats.mean)
+    }
+}
+
+pub(super) fn print_experiment_stats(report: &ExperimentReport, as_json: bool) {
+    let experiments = &report.experiments;
+    // The note goes to stderr with JSON output so the output stays valid JSON
+    let note = (report.runs_without_experiment > 0).then(|| {
+        format!(
+            "Runs without experiment, stored before experiments were added to the database: {}",
+            report.runs_without_experiment
+        )
+    });
+    if as_json {
+        let json = JsonValue::Array(experiments.iter().map(ExperimentStats::to_json).collect());
+        println!("{}", serde_json::to_string_pretty(&json).unwrap_or_default());
+        if let Some(note) = note {
+            eprintln!("{}", note.yellow());
+        }
+        return;
+    }
+    let header = format!(
+        "{:>5}  {:<16}  {:<30} {:<6} {:>5} {:>5}  {:>16} {:>8}  {:>18} {:>8}",
+        "Exp", "Parameter hash", "Map", "Cutter", "Runs", "Seeds", "Coverage (%)", "Std.dev", "Time (s)", "Std.dev"
+    );
+    println!("{}", header.color(colored::Color::Green).bold());
+    for experiment in experiments {
+        let map = std::path::Path::new(&experiment.map_file_name)
+            .file_name()
+            .map_or(experiment.map_file_name.clone(), |name| name.to_string_lossy().to_string());
+        let line = format!(
+            "{:>5}  {:<16}  {:<30} {:<6} {:>5} {:>5}  {:>16} {:>8.2}  {:>18} {:>8.1}",
+            experiment.experiment_id,
+            experiment.parameter_hash,
+            map,
+            experiment.cutter_type,
+            experiment.runs,
+            experiment.coverage_percent.count,
+            mean_with_ci(&experiment.coverage_percent, 2),
+            experiment.coverage_percent.stddev,
+            mean_with_ci(&experiment.cutting_time_seconds, 1),
+            experiment.cutting_time_seconds.stddev,
+        );
+        if experiment.coverage_percent.count < experiment.runs {
+            println!("{}", line.yellow());
+        } else {
+            println!("{line}");
+        }
+    }
+    println!("Experiments: {}", experiments.len());
+    if let Some(note) = note {
+        println!("{}", note.yellow());
+    }
+}
@@ -0,0 +1,386 @@
+//! Reading the simulation runs stored in a results database for the `db` command
+
+use super::Database;
+use super::columns::{MODEL_COLUMNS, RESULT_COLUMNS, columns_to_json};
+use super::experiment::print_experiment_stats;
+use crate::args::{DbArgs, DbCommand, DbExportArgs, DbFilterArgs};
+use crate::model::json_to_console;
+use colored::Colorize;
+use rusqlite::params_from_iter;
+use rusqlite::types::Value as SqlValue;
+use serde_json::{Map, Value as JsonValue, json};
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+
+/// Width of the value columns when two runs are compared
+const COMPARE_COLUMN_WIDTH: usize = 24;
+
+/// The most important values of a stored run, shown by `db list`
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+    pub result_id: i64,
+    pub model_id: i64,
+    /// None for runs stored before experiments were added to the database
+    pub experiment_id: Option<i64>,
+    pub created_at: String,
+    pub map_file_name: String,
+    pub cutter_type: String,
+    pub random_seed: i64,
+    pub coverage_percent: f64,
+    pub cutting_time_seconds: i64,
+    pub stop_reason: String,
+}
+
+/// All stored columns of a run, in table order, with the `id`, `model_id` and `created_at`
+/// bookkeeping columns left out
+#[derive(Debug, Clone)]
+pub struct StoredRun {
+    pub result_id: i64,
+    pub model_id: i64,
+    pub created_at: String,
+    pub model: Vec<(String, JsonValue)>,
+    pub result: Vec<(String, JsonValue)>,
+}
+
+impl StoredRun {
+    /// The run as JSON with the model and result rebuilt in the layout the simulation prints them
+    pub fn to_json(&self) -> JsonValue {
+        let mut run = json!({
+            "Result ID": self.result_id,
+            "Model ID": self.model_id,
+            "Stored": self.created_at,
+        });
+        // Runs stored before experiments were added have no experiment
+        for (column, key) in [("experiment_id", "Experiment ID"), ("parameter_hash", "Parameter hash")] {
+            if let Some((_, value)) = self.model.iter().find(|(c, v)| c == column && !v.is_null()) {
+                run[key] = value.clone();
+            }
+        }
+        json!({
+            "Run": run,
+            "Model": columns_to_json(MODEL_COLUMNS, &self.model),
+            "Result": columns_to_json(RESULT_COLUMNS, &self.result),
+        })
+    }
+}
+
+fn to_json_value(value: SqlValue) -> JsonValue {
+    match value {
+        SqlValue::Null => JsonValue::Null,
+        SqlValue::Integer(i) => json!(i),
+        SqlValue::Real(f) => json!(f),
+        SqlValue::Text(s) => json!(s),
+        SqlValue::Blob(b) => json!(format!("<{} bytes>", b.len())),
+    }
+}
+
+/// The value as shown in a table, without the quotes around strings
+fn display_value(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+impl Database {
+    /// Open a database that must already exist, so a misspelled name does not create an empty one
+    pub fn open_existing(db_path: &str) -> Result<Self, Box<dyn Error>> {
+        if !Path::new(db_path).is_file() {
+            return Err(format!("Database file '{db_path}' does not exist").into());
+        }
+        Database::new(db_path)
+    }
+
+    /// The result IDs of the runs matching the filter, oldest first
+    pub(super) fn filtered_result_ids(&self, filter: &DbFilterArgs) -> Result<Vec<i64>, Box<dyn Error>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<SqlValue> = Vec::new();
+        if let Some(map) = &filter.map {
+            conditions.push("m.grid_map_file_name LIKE ?");
+            values.push(format!("%{map}%").into());
+        }
+        if let Some(cutter_type) = &filter.cutter_type {
+            conditions.push("m.cutter_type = ?");
+            values.push(cutter_type.clone().into());
+        }
+        if let Some(seed) = filter.seed {
+            conditions.push("m.simulation_random_seed = ?");
+            values.push((seed as i64).into());
+        }
+        if let Some(experiment) = filter.experiment {
+            conditions.push("m.experiment_id = ?");
+            values.push(experiment.into());
+        }
+        if let Some(since) = &filter.since {
+            conditions.push("date(r.created_at) >= date(?)");
+            values.push(since.clone().into());
+        }
+        if let Some(until) = &filter.until {
+            conditions.push("date(r.created_at) <= date(?)");
+            values.push(until.clone().into());
+        }
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        let sql = format!("SELECT r.id FROM results r JOIN models m ON m.id = r.model_id {where_clause} ORDER BY r.id");
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut ids = stmt
+            .query_map(params_from_iter(values), |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(limit) = filter.limit {
+            ids.drain(..ids.len().saturating_sub(limit));
+        }
+        Ok(ids)
+    }
+
+    /// Summaries of the runs matching the filter, oldest first
+    pub fn list_runs(&self, filter: &DbFilterArgs) -> Result<Vec<RunSummary>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT r.id, r.model_id, r.created_at, m.grid_map_file_name, m.cutter_type, m.simulation_random_seed,
+                    r.coverage_percent, r.time_cutting_time_seconds, r.stop_reason, m.experiment_id
+             FROM results r JOIN models m ON m.id = r.model_id WHERE r.id = ?1",
+        )?;
+        self.filtered_result_ids(filter)?
+            .into_iter()
+            .map(|id| {
+                Ok(stmt.query_row([id], |row| {
+                    Ok(RunSummary {
+                        result_id: row.get(0)?,
+                        model_id: row.get(1)?,
+                        experiment_id: row.get(9)?,
+                        created_at: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                        map_file_name: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                        cutter_type: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                        random_seed: row.get::<_, Option<i64>>(5)?.unwrap_or_default(),
+                        coverage_percent: row.get::<_, Option<f64>>(6)?.unwrap_or_default(),
+                        cutting_time_seconds: row.get::<_, Option<i64>>(7)?.unwrap_or_default(),
+                        stop_reason: row.get::<_, Option<String>>(8)?.unwrap_or_default(),
+                    })
+                })?)
+            })
+            .collect()
+    }
+
+    /// All columns of a table row as (column name, value)
+    fn row_columns(&self, table: &str, id: i64) -> Result<Vec<(String, JsonValue)>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(&format!("SELECT * FROM {table} WHERE id = ?1"))?;
+        let names: Vec<String> = stmt.column_names().into_iter().map(str::to_string).collect();
+        let values = stmt
+            .query_row([id], |row| (0..names.len()).map(|i| row.get::<_, SqlValue>(i)).collect::<Result<Vec<_>, _>>())
+            .map_err(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => format!("No {table} row with ID {id} in the database").into(),
+                e => Box::<dyn Error>::from(e),
+            })?;
+        Ok(names.into_iter().zip(values.into_iter().map(to_json_value)).collect())
+    }
+
+    /// The stored model and result of the run with the given result ID
+    pub fn get_run(&self, result_id: i64) -> Result<StoredRun, Box<dyn Error>> {
+        let mut result = self.row_columns("results", result_id)?;
+        let column = |columns: &[(String, JsonValue)], name: &str| {
+            columns.iter().find(|(c, _)| c == name).map(|(_, v)| v.clone()).unwrap_or_default()
+        };
+        let model_id = column(&result, "model_id").as_i64().unwrap_or_default();
+        let created_at = display_value(&column(&result, "created_at"));
+        let mut model = self.row_columns("models", model_id)?;
+        let bookkeeping = ["id", "model_id", "created_at"];
+        result.retain(|(c, _)| !bookkeeping.contains(&c.as_str()));
+        model.retain(|(c, _)| !bookkeeping.contains(&c.as_str()));
+        Ok(StoredRun {
+            result_id,
+            model_id,
+            created_at,
+            model,
+            result,
+        })
+    }
+}
+
+/// Run a `db` command on the database
+pub fn run_db_command(db_args: &DbArgs, db_path: &str) -> Result<(), Box<dyn Error>> {
+    let db = Database::open_existing(db_path)?;
+    match &db_args.command {
+        DbCommand::List(filter) => print_runs(&db.list_runs(filter)?),
+        DbCommand::Show { id } => print_run(&db.get_run(*id)?),
+        DbCommand::Compare { first, second, all } => print_comparison(&db.get_run(*first)?, &db.get_run(*second)?, *all),
+        DbCommand::Export(export_args) => export_runs(&db, export_args)?,
+        DbCommand::Stats(stats_args) => {
+            print_experiment_stats(&db.experiment_stats(&stats_args.filter)?, stats_args.json)
+        }
+    }
+    Ok(())
+}
+
+fn print_runs(runs: &[RunSummary]) {
+    let header = format!(
+        "{:>6} {:>6} {:>5}  {:<19}  {:<30} {:<6} {:>20} {:>8} {:>9}  {}",
+        "Result", "Model", "Exp", "Stored", "Map", "Cutter", "Seed", "Coverage", "Time (s)", "Stop reason"
+    );
+    println!("{}", header.color(colored::Color::Green).bold());
+    for run in runs {
+        let map = Path::new(&run.map_file_name)
+            .file_name()
+            .map_or(run.map_file_name.clone(), |name| name.to_string_lossy().to_string());
+        println!(
+            "{:>6} {:>6} {:>5}  {:<19}  {:<30} {:<6} {:>20} {:>7.2}% {:>9}  {}",
+            run.result_id,
+            run.model_id,
+            run.experiment_id.map_or(String::new(), |id| id.to_string()),
+            run.created_at,
+            map,
+            run.cutter_type,
+            run.random_seed,
+            run.coverage_percent,
+            run.cutting_time_seconds,
+            run.stop_reason
+        );
+    }
+    println!("Runs: {}", runs.len());
+}
+
+fn print_run(run: &StoredRun) {
+    let json = run.to_json();
+    for (index, root_key) in ["Run", "Model", "Result"].into_iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!("{}", root_key.color(colored::Color::BrightGreen).bold());
+        println!("{}", "=".repeat(root_key.len()).color(colored::Color::BrightGreen).bold());
+        json_to_console(&json, root_key, 2);
+    }
+}
+
+fn print_comparison(first: &StoredRun, second: &StoredRun, all: bool) {
+    let width = COMPARE_COLUMN_WIDTH;
+    let header = format!(
+        "{:<50} {:>width$} {:>width$} {:>width$}",
+        "",
+        format!("Result {}", first.result_id),
+        format!("Result {}", second.result_id),
+        "Difference"
+    );
+    println!("{}", header.color(colored::Color::Green).bold());
+    for (title, a, b) in [("Model", &first.model, &second.model), ("Result", &first.result, &second.result)] {
+        println!("{}", title.color(colored::Color::BrightGreen).bold());
+        // Columns only one of the runs has, e.g. from an older schema, are shown as empty
+        let mut names: Vec<&String> = a.iter().map(|(c, _)| c).collect();
+        names.extend(b.iter().map(|(c, _)| c).filter(|c| !a.iter().any(|(n, _)| n == *c)));
+        let mut same = 0;
+        for name in names {
+            let value = |columns: &[(String, JsonValue)]| columns.iter().find(|(c, _)| c == name).map(|(_, v)| v.clone());
+            let (va, vb) = (value(a).unwrap_or_default(), value(b).unwrap_or_default());
+            let differs = va != vb;
+            if !differs {
+                same += 1;
+                if !all {
+                    continue;
+                }
+            }
+            let difference = match (&va, &vb) {
+                _ if !differs => String::new(),
+                (JsonValue::Number(x), JsonValue::Number(y)) => match (x.as_i64(), y.as_i64()) {
+                    (Some(x), Some(y)) => y.checked_sub(x).map(|d| format!("{d:+}")).unwrap_or_default(),
+                    _ => format!("{:+}", y.as_f64().unwrap_or_default() - x.as_f64().unwrap_or_default()),
+                },
+                _ => String::new(),
+            };
+            let line = format!(
+                "  {name:<48} {:>width$} {:>width$} {difference:>width$}",
+                display_value(&va),
+                display_value(&vb)
+            );
+            if differs {
+                println!("{}", line.color(colored::Color::Yellow));
+            } else {
+                println!("{line}");
+            }
+        }
+        if !all && same > 0 {
+            println!("  ({same} equal values not shown)");
+        }
+    }
+}
+
+fn export_runs(db: &Database, export_args: &DbExportArgs) -> Result<(), Box<dyn Error>> {
+    let runs = db
+        .filtered_result_ids(&export_args.filter)?
+        .into_iter()
+        .map(|id| db.get_run(id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let content = if export_args.json {
+        let runs: Vec<JsonValue> = runs
+            .iter()
+            .map(|run| {
+                json!({
+                    "result_id": run.result_id,
+                    "model_id": run.model_id,
+                    "created_at": run.created_at,
+                    "model": run.model.iter().cloned().collect::<Map<_, _>>(),
+                    "result": run.result.iter().cloned().collect::<Map<_, _>>(),
+                })
+            })
+            .collect();
+        serde_json::to_string_pretty(&runs)? + "\n"
+    } else {
+        runs_to_csv(&runs)
+    };
+    match &export_args.output {
+        Some(file_name) => std::fs::write(file_name, content)?,
+        None => std::io::stdout().write_all(content.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// One line per run with the model columns prefixed by `model.` and the result columns by
+/// `result.`. Runs from databases with different schemas get empty values for missing columns.
+fn runs_to_csv(runs: &[StoredRun]) -> String {
+    let mut header: Vec<String> = vec!["result_id".into(), "model_id".into(), "created_at".into()];
+    for run in runs {
+        let columns = run
+            .model
+            .iter()
+            .map(|(c, _)| format!("model.{c}"))
+            .chain(run.result.iter().map(|(c, _)| format!("result.{c}")));
+        for column in columns {
+            if !header.contains(&column) {
+                header.push(column);
+            }
+        }
+    }
+    let mut csv = csv_line(header.iter().map(String::as_str));
+    for run in runs {
+        let values: Vec<String> = header
+            .iter()
+            .map(|column| match column.as_str() {
+                "result_id" => run.result_id.to_string(),
+                "model_id" => run.model_id.to_string(),
+                "created_at" => run.created_at.clone(),
+                _ => {
+                    let (table, name) = column.split_once('.').unwrap_or_default();
+                    let columns = if table == "model" { &run.model } else { &run.result };
+                    columns.iter().find(|(c, _)| c == name).map(|(_, v)| display_value(v)).unwrap_or_default()
+                }
+            })
+            .collect();
+        csv.push_str(&csv_line(values.iter().map(String::as_str)));
+    }
+    csv
+}
+
+/// One line of comma separated values, fields with a comma, quote or newline are quoted
+pub fn csv_line<'a>(fields: impl Iterator<Item = &'a str>) -> String {
+    let fields: Vec<String> = fields
+        .map(|field| {
+            if field.contains([',', '"', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect();
+    fields.join(",") + "\n"
+}
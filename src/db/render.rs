@@ -0,0 +1,78 @@
+//! Rendering the image of a stored run from the grid stored with `--db-store-grid`
+
+use super::{Database, blob};
+use crate::args::{Args, RenderArgs};
+use crate::config::SimConfig;
+use crate::image::save_grid_image_with_path;
+use crate::model::SimModel;
+use crate::model::grid::Grid;
+use rusqlite::OptionalExtension;
+use serde_json::Value as JsonValue;
+use std::error::Error;
+
+/// The decoded grid and cutter path stored with a result
+pub struct StoredGrid {
+    pub grid: Grid,
+    pub path: Vec<[f32; 2]>,
+}
+
+impl Database {
+    /// The grid and path stored with the result, an error if the run was stored without them
+    pub fn get_stored_grid(&self, result_id: i64) -> Result<StoredGrid, Box<dyn Error>> {
+        let blobs = self
+            .conn
+            .query_row("SELECT grid, path FROM result_blobs WHERE result_id = ?1", [result_id], |row| {
+                Ok((row.get::<_, Option<Vec<u8>>>(0)?, row.get::<_, Option<Vec<u8>>>(1)?))
+            })
+            .optional()?;
+        let Some((Some(grid), path)) = blobs else {
+            return Err(format!(
+                "No grid is stored for result ID {result_id}, run the simulation with --db-store-grid true to store it"
+            )
+            .into());
+        };
+        Ok(StoredGrid {
+            grid: blob::decode_grid(&grid)?,
+            path: path.as_deref().map(blob::decode_path).transpose()?.unwrap_or_default(),
+        })
+    }
+}
+
+fn column<'a>(columns: &'a [(String, JsonValue)], name: &str) -> Option<&'a JsonValue> {
+    columns.iter().find(|(c, _)| c == name).map(|(_, v)| v)
+}
+
+/// Render the image of a stored run and return the name of the image file. The image options
+/// are taken from the arguments, the color theme from the stored model unless one is given.
+pub fn render_stored_run(args: &Args, render_args: &RenderArgs, db_path: &str) -> Result<String, Box<dyn Error>> {
+    let db = Database::open_existing(db_path)?;
+    let run = db.get_run(render_args.id)?;
+    let stored = db.get_stored_grid(render_args.id)?;
+
+    let config = SimConfig::from(args);
+    let mut model = SimModel::from_config(&config);
+    let Some((width_mm, height_mm)) = config.paper_size.get_size_mm() else {
+        return Err("Unknown paper size.".into());
+    };
+    model.image_width_mm = width_mm as u32;
+    model.image_height_mm = height_mm as u32;
+    model.grid_cells_x = stored.grid.cells_x;
+    model.grid_cells_y = stored.grid.cells_y;
+    model.cell_size = stored.grid.cell_size;
+    model.grid_width = stored.grid.cells_x as f64 * stored.grid.cell_size;
+    model.grid_height = stored.grid.cells_y as f64 * stored.grid.cell_size;
+    model.track_center = column(&run.model, "simulation_track_center").and_then(JsonValue::as_i64) == Some(1);
+    model.sim_time_elapsed =
+        column(&run.result, "time_cutting_time_seconds").and_then(JsonValue::as_f64).unwrap_or_default();
+    if model.color_theme.is_none() {
+        model.color_theme = column(&run.model, "image_color_theme")
+            .and_then(JsonValue::as_str)
+            .filter(|theme| !theme.is_empty() && *theme != "None")
+            .map(str::to_string);
+    }
+    model.grid = Some(stored.grid);
+
+    let file_name = args.image_file_name.clone().unwrap_or_else(|| format!("result_{}.png", render_args.id));
+    save_grid_image_with_path(&model, render_args.path.then_some(stored.path.as_slice()), &file_name)?;
+    Ok(file_name)
+}
@@ -0,0 +1,272 @@
+//! Experiment files declaring a parameter sweep for `grunner`. An experiment file lists the
+//! gridcover options to vary, how their values are combined into design points and how many
+//! random seeds each point is run with.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+
+/// Rounding of generated range values so e.g. 0.1 + 2 * 0.1 is written as 0.3
+const RANGE_DECIMALS: i32 = 10;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExperimentFile {
+    pub name: String,
+    pub description: Option<String>,
+    /// gridcover options used for every run, e.g. "-M garden.yaml -c 90"
+    #[serde(default)]
+    pub base_args: String,
+    /// Number of random seeds each design point is run with
+    #[serde(default = "default_seeds")]
+    pub seeds: u64,
+    /// The first random seed, the seeds are `first_seed..first_seed + seeds`
+    #[serde(default = "default_first_seed")]
+    pub first_seed: u64,
+    /// How the values of the parameters are combined into design points
+    #[serde(default)]
+    pub combination: Combination,
+    #[serde(default)]
+    pub parameters: Vec<Parameter>,
+    #[serde(default)]
+    pub outputs: Outputs,
+}
+
+fn default_seeds() -> u64 {
+    1
+}
+
+fn default_first_seed() -> u64 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Combination {
+    /// Every combination of the parameter values
+    #[default]
+    Cartesian,
+    /// The n:th values of all parameters together, all parameters must have the same number of values
+    Zip,
+}
+
+/// A gridcover option and the values it is swept over. Exactly one of `values`, `range` and
+/// `log_range` is given.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Parameter {
+    /// The gridcover option, e.g. "--radius" or "-r"
+    pub arg: String,
+    /// Column name in the summary, the option without leading dashes if not given
+    pub name: Option<String>,
+    /// Explicit values, numbers or strings such as map file names
+    pub values: Option<Vec<serde_yaml::Value>>,
+    pub range: Option<LinearRange>,
+    pub log_range: Option<LogRange>,
+}
+
+/// Values from `start` to `end`, both included, `step` apart
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinearRange {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+/// `count` values from `start` to `end`, both included, with a constant ratio between them
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogRange {
+    pub start: f64,
+    pub end: f64,
+    pub count: usize,
+}
+
+/// What is written for the runs, relative to the grunner output directory
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Outputs {
+    /// Results database all runs are stored in
+    pub database: String,
+    /// CSV file with the statistics of each design point
+    pub summary: String,
+    /// Save the image of each run as point_<point>_seed_<seed>.png
+    pub images: bool,
+    /// Store the final grid and path of each run in the database
+    pub store_grid: bool,
+}
+
+impl Default for Outputs {
+    fn default() -> Self {
+        Outputs {
+            database: "results.db".to_string(),
+            summary: "summary.csv".to_string(),
+            images: false,
+            store_grid: false,
+        }
+    }
+}
+
+/// One combination of parameter values
+#[derive(Debug, Clone)]
+pub struct DesignPoint {
+    pub index: usize,
+    /// The value of each parameter, in the order of `ExperimentFile::parameters`
+    pub values: Vec<String>,
+}
+
+fn round_range_value(value: f64) -> f64 {
+    let factor = 10f64.powi(RANGE_DECIMALS);
+    (value * factor).round() / factor
+}
+
+impl Parameter {
+    /// Column name of the parameter in the summary
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.arg.trim_start_matches('-').to_string())
+    }
+
+    /// All values of the parameter as command line values
+    pub fn expand(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let name = self.name();
+        let values = match (&self.values, &self.range, &self.log_range) {
+            (Some(values), None, None) => values
+                .iter()
+                .map(|value| match value {
+                    serde_yaml::Value::Number(n) => Ok(n.to_string()),
+                    serde_yaml::Value::String(s) => Ok(s.clone()),
+                    serde_yaml::Value::Bool(b) => Ok(b.to_string()),
+                    _ => Err(format!("Parameter '{name}': values must be numbers, strings or booleans")),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            (None, Some(range), None) => {
+                if range.step <= 0.0 || range.end < range.start {
+                    return Err(format!("Parameter '{name}': range needs step > 0 and end >= start").into());
+                }
+                let count = ((range.end - range.start) / range.step + 1e-9).floor() as usize + 1;
+                (0..count)
+                    .map(|i| round_range_value(range.start + i as f64 * range.step).to_string())
+                    .collect()
+            }
+            (None, None, Some(range)) => {
+                if range.start <= 0.0 || range.end <= 0.0 || range.count == 0 {
+                    return Err(format!("Parameter '{name}': log_range needs start > 0, end > 0 and count > 0").into());
+                }
+                let ratio = if range.count > 1 {
+                    (range.end / range.start).powf(1.0 / (range.count - 1) as f64)
+                } else {
+                    1.0
+                };
+                (0..range.count)
+                    .map(|i| round_range_value(range.start * ratio.powi(i as i32)).to_string())
+                    .collect()
+            }
+            _ => return Err(format!("Parameter '{name}': give exactly one of values, range and log_range").into()),
+        };
+        if values.is_empty() {
+            return Err(format!("Parameter '{name}' has no values").into());
+        }
+        Ok(values)
+    }
+}
+
+/// Options grunner sets itself for each run and that can not be swept
+pub const RESERVED_ARGS: [&str; 8] = ["-S", "--random-seed", "-o", "-Q", "--database-file", "-q", "--quiet", "--db-store-grid"];
+
+/// True if the command line argument is one of `RESERVED_ARGS`, also when it is written as
+/// `--option=value` or as a short option with the value attached, e.g. `-S5`
+pub fn is_reserved_arg(arg: &str) -> bool {
+    let option = if arg.starts_with("--") {
+        arg.split_once('=').map_or(arg, |(option, _)| option)
+    } else if arg.starts_with('-') {
+        arg.get(..2).unwrap_or(arg)
+    } else {
+        return false;
+    };
+    RESERVED_ARGS.contains(&option)
+}
+
+/// Reject gridcover arguments that set an option grunner sets for each run. `source` tells where
+/// the arguments were given, e.g. "base_args".
+pub fn check_reserved_args(args: &[String], source: &str) -> Result<(), Box<dyn Error>> {
+    match args.iter().find(|arg| is_reserved_arg(arg)) {
+        Some(arg) => Err(format!("Option '{arg}' in {source} is set by grunner for each run and can not be given").into()),
+        None => Ok(()),
+    }
+}
+
+impl ExperimentFile {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Cannot read experiment file '{path}': {e}"))?;
+        let experiment: ExperimentFile =
+            serde_yaml::from_str(&content).map_err(|e| format!("Invalid experiment file '{path}': {e}"))?;
+        experiment.check()?;
+        Ok(experiment)
+    }
+
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        if self.seeds == 0 {
+            return Err("The number of seeds must be at least 1".into());
+        }
+        for parameter in &self.parameters {
+            if !parameter.arg.starts_with('-') {
+                return Err(format!("Parameter arg '{}' must be a gridcover option such as --radius", parameter.arg).into());
+            }
+            if is_reserved_arg(&parameter.arg) {
+                return Err(format!("Option '{}' is set by grunner for each run and can not be swept", parameter.arg).into());
+            }
+        }
+        check_reserved_args(&self.base_args()?, "base_args")
+    }
+
+    /// The base options split into command line arguments
+    pub fn base_args(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(shell_words::split(&self.base_args)?)
+    }
+
+    /// The random seeds each design point is run with
+    pub fn seed_values(&self) -> impl Iterator<Item = u64> + use<> {
+        self.first_seed..self.first_seed + self.seeds
+    }
+
+    /// All design points, an experiment without parameters has a single point
+    pub fn design_points(&self) -> Result<Vec<DesignPoint>, Box<dyn Error>> {
+        let values = self.parameters.iter().map(Parameter::expand).collect::<Result<Vec<_>, _>>()?;
+        let points: Vec<Vec<String>> = match self.combination {
+            Combination::Cartesian => values.iter().fold(vec![Vec::new()], |points, parameter_values| {
+                points
+                    .iter()
+                    .flat_map(|point| {
+                        parameter_values.iter().map(move |value| {
+                            let mut point = point.clone();
+                            point.push(value.clone());
+                            point
+                        })
+                    })
+                    .collect()
+            }),
+            Combination::Zip => {
+                let count = values.first().map_or(1, Vec::len);
+                if values.iter().any(|v| v.len() != count) {
+                    return Err("All parameters must have the same number of values when they are zipped".into());
+                }
+                (0..count).map(|i| values.iter().map(|v| v[i].clone()).collect()).collect()
+            }
+        };
+        Ok(points
+            .into_iter()
+            .enumerate()
+            .map(|(index, values)| DesignPoint { index, values })
+            .collect())
+    }
+
+    /// The command line arguments setting the parameter values of a design point
+    pub fn point_args(&self, point: &DesignPoint) -> Vec<String> {
+        self.parameters
+            .iter()
+            .zip(&point.values)
+            .flat_map(|(parameter, value)| [parameter.arg.clone(), value.clone()])
+            .collect()
+    }
+}
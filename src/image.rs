@@ -2,10 +2,10 @@ use std::cmp::min;
 
 use crate::color_theme::{ColorTheme, ColorThemeManager};
 use crate::model::SimModel;
-use crate::model::grid::Cell;
+use crate::model::grid::{Cell, Grid};
 use ab_glyph::{FontArc, PxScale};
 use colored::Colorize;
-use imageproc::drawing::draw_text_mut;
+use imageproc::drawing::{draw_line_segment_mut, draw_text_mut};
 
 mod font_dejavusans;
 mod font_dejavusansbold;
@@ -13,19 +13,69 @@ mod font_dejavusansbold;
 // use crate::image::font_dejavusans::DEJAVUSANS;
 use crate::image::font_dejavusansbold::DEJAVUSANS_BOLD;
 
+/// The image options of the model. An image is drawn from these, the grid and the simulated
+/// time, so an image of a copy of the grid can be drawn on another thread while the simulation
+/// continues.
+#[derive(Debug, Clone)]
+pub struct ImageOptions {
+    pub dpi: u32,
+    pub image_width_mm: u32,
+    pub image_height_mm: u32,
+    pub grid_width: f64,
+    pub grid_height: f64,
+    pub color_theme: Option<String>,
+    pub track_center: bool,
+    pub show_gridlines: bool,
+    pub show_quad_tree: bool,
+    pub show_image_label: bool,
+    pub verbosity: usize,
+}
+
+impl ImageOptions {
+    pub fn from_model(model: &SimModel) -> Self {
+        ImageOptions {
+            dpi: model.dpi,
+            image_width_mm: model.image_width_mm,
+            image_height_mm: model.image_height_mm,
+            grid_width: model.grid_width,
+            grid_height: model.grid_height,
+            color_theme: model.color_theme.clone(),
+            track_center: model.track_center,
+            show_gridlines: model.show_gridlines,
+            show_quad_tree: model.show_quad_tree,
+            show_image_label: model.show_image_label,
+            verbosity: model.verbosity,
+        }
+    }
+}
+
 #[allow(clippy::collapsible_if)]
 pub fn try_save_image(model: &SimModel, override_filename: Option<String>) {
     if model.image_file_name.is_some() || override_filename.is_some() {
         if let Err(err) = save_grid_image(model, override_filename) {
-            eprintln!(
-                "{} {}",
-                "Error saving image:".color(colored::Color::Red).bold(),
-                err
-            );
+            print_image_error(err.as_ref());
         }
     }
 }
 
+/// Save a PNG image of the grid at the simulated time, printing an error if it can not be saved
+pub fn try_save_grid_image(options: &ImageOptions, grid: &Grid, sim_time_elapsed: f64, filename: &str) {
+    let theme_manager = ColorThemeManager::new();
+    let theme = theme_manager.get_theme(options.color_theme.as_deref().unwrap_or("default"));
+    let saved = draw_grid_image(options, grid, sim_time_elapsed, theme).and_then(|img| Ok(img.save(filename)?));
+    if let Err(err) = saved {
+        print_image_error(err.as_ref());
+    }
+}
+
+fn print_image_error(err: &dyn std::error::Error) {
+    eprintln!(
+        "{} {}",
+        "Error saving image:".color(colored::Color::Red).bold(),
+        err
+    );
+}
+
 /// Create a PNG image of the coverage grid with colored squares
 fn save_grid_image(
     model: &crate::model::SimModel,
@@ -54,6 +104,33 @@ fn save_grid_image_with_theme(
     Ok(())
 }
 
+/// Create a PNG image of the coverage grid with the recorded cutter path, if given, drawn on top
+pub fn save_grid_image_with_path(
+    model: &SimModel,
+    path: Option<&[[f32; 2]]>,
+    filename: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let theme_manager = ColorThemeManager::new();
+    let theme = theme_manager.get_theme(model.color_theme.as_deref().unwrap_or("default"));
+    let mut img = create_grid_image_in_memory_with_theme(model, theme)?;
+    if let Some(path) = path {
+        draw_path(&mut img, model, path, image::Rgb(theme.center_color));
+    }
+    img.save(filename)?;
+    Ok(())
+}
+
+/// Draw the cutter path as connected line segments. The image has the same number of pixels
+/// per cell in both directions and the y-axis is inverted.
+fn draw_path(img: &mut image::RgbImage, model: &SimModel, path: &[[f32; 2]], color: image::Rgb<u8>) {
+    let pixels_per_unit = (img.width() / model.grid_cells_x as u32) as f64 / model.cell_size;
+    let height = img.height() as f64;
+    let to_pixel = |[x, y]: [f32; 2]| ((x as f64 * pixels_per_unit) as f32, (height - y as f64 * pixels_per_unit) as f32);
+    for segment in path.windows(2) {
+        draw_line_segment_mut(img, to_pixel(segment[0]), to_pixel(segment[1]), color);
+    }
+}
+
 /// Create an in-memory RGB image of the coverage grid
 #[allow(dead_code)]
 pub fn create_grid_image_in_memory(
@@ -134,48 +211,61 @@ fn create_grid_image_in_memory_with_theme(
     model: &crate::model::SimModel,
     theme: &ColorTheme,
 ) -> Result<image::RgbImage, Box<dyn std::error::Error>> {
+    let grid = model.grid.as_ref().unwrap();
+    draw_grid_image(&ImageOptions::from_model(model), grid, model.sim_time_elapsed, theme)
+}
+
+/// Create an in-memory RGB image of the grid at the simulated time using a specific theme
+fn draw_grid_image(
+    options: &ImageOptions,
+    grid: &Grid,
+    sim_time_elapsed: f64,
+    theme: &ColorTheme,
+) -> Result<image::RgbImage, Box<dyn std::error::Error>> {
+    let (grid_cells_x, grid_cells_y) = (grid.cells_x, grid.cells_y);
+
     // Convert mm to pixels using DPI (Dots Per Inch)
-    let pixels_per_mm = model.dpi as f64 / 25.4;
+    let pixels_per_mm = options.dpi as f64 / 25.4;
 
-    let mut base_img_width_pixels = (model.image_width_mm as f64 * pixels_per_mm).round() as u32;
-    let mut base_img_height_pixels = (model.image_height_mm as f64 * pixels_per_mm).round() as u32;
+    let mut base_img_width_pixels = (options.image_width_mm as f64 * pixels_per_mm).round() as u32;
+    let mut base_img_height_pixels = (options.image_height_mm as f64 * pixels_per_mm).round() as u32;
 
-    if base_img_height_pixels < model.grid_cells_y as u32 {
-        if model.verbosity > 3 {
+    if base_img_height_pixels < grid_cells_y as u32 {
+        if options.verbosity > 3 {
             eprintln!(
                 "{} {} mm",
                 "Notice. Adjusting image height to fit grid height. New height at given DPI:"
                     .yellow()
                     .bold(),
-                (model.grid_cells_y as u32 + 1) / pixels_per_mm as u32
+                (grid_cells_y as u32 + 1) / pixels_per_mm as u32
             );
         }
-        base_img_height_pixels = model.grid_cells_y as u32 + 1;
+        base_img_height_pixels = grid_cells_y as u32 + 1;
     }
 
-    if base_img_width_pixels < model.grid_cells_x as u32 {
-        if model.verbosity > 3 {
+    if base_img_width_pixels < grid_cells_x as u32 {
+        if options.verbosity > 3 {
             eprintln!(
                 "{} {} mm",
                 "Notice.! Adjusting image width to fit grid width. New width at given DPI:"
                     .yellow()
                     .bold(),
-                (model.grid_cells_x as u32 + 1) / pixels_per_mm as u32
+                (grid_cells_x as u32 + 1) / pixels_per_mm as u32
             );
         }
-        base_img_width_pixels = model.grid_cells_x as u32 + 1;
+        base_img_width_pixels = grid_cells_x as u32 + 1;
     }
 
     // Calculate cell size to ensure perfect squares
     // Take the smaller dimension to make sure image fits within requested size
     let pixels_cell_size = std::cmp::min(
-        base_img_width_pixels / model.grid_cells_x as u32,
-        base_img_height_pixels / model.grid_cells_y as u32,
+        base_img_width_pixels / grid_cells_x as u32,
+        base_img_height_pixels / grid_cells_y as u32,
     );
 
     // Recalculate image dimensions using the uniform cell size
-    let img_width = pixels_cell_size * model.grid_cells_x as u32;
-    let img_height = pixels_cell_size * model.grid_cells_y as u32;
+    let img_width = pixels_cell_size * grid_cells_x as u32;
+    let img_height = pixels_cell_size * grid_cells_y as u32;
 
     // Create a new RGB image buffer
     let mut img = image::RgbImage::new(img_width, img_height);
@@ -186,21 +276,23 @@ fn create_grid_image_in_memory_with_theme(
     }
 
     // Draw colored cells for covered areas
-    for y in 0..model.grid_cells_y {
+    for y in 0..grid_cells_y {
         // Convert grid y to image y (invert y axis to match terminal output)
-        let img_y = model.grid_cells_y - 1 - y;
+        let img_y = grid_cells_y - 1 - y;
 
-        for x in 0..model.grid_cells_x {
+        for x in 0..grid_cells_x {
             // Fill the cell with color (using the uniform cell size)
             let start_x = x as u32 * pixels_cell_size;
             let start_y = img_y as u32 * pixels_cell_size;
-            let cell = &model.grid.as_ref().unwrap().cells[x][y];
+            let cell = &grid.cells[x][y];
 
             let color = match cell {
                 Cell::Obstacle => Some(theme.obstacle_color),
+                Cell::NoCut => Some(theme.no_cut_color),
+                Cell::VirtualFence => Some(theme.virtual_fence_color),
                 Cell::Empty => None,
                 Cell::Covered(info) => Some(theme.get_coverage_color(info.times_visited)),
-                Cell::CenterPoint(_) if model.track_center => Some(theme.center_color),
+                Cell::CenterPoint(_) if options.track_center => Some(theme.center_color),
                 _ => None,
             };
 
@@ -216,17 +308,17 @@ fn create_grid_image_in_memory_with_theme(
         }
     }
 
-    if model.show_gridlines {
-        draw_grid_lines(&mut img, model, pixels_cell_size, theme.grid_line_color);
+    if options.show_gridlines {
+        draw_grid_lines(&mut img, options, grid, pixels_cell_size, theme.grid_line_color);
     }
 
-    let show_quad_tree = model.show_quad_tree && model.grid.as_ref().unwrap().quadtree.is_some();
+    let show_quad_tree = options.show_quad_tree && grid.quadtree.is_some();
     if show_quad_tree {
-        draw_quad_tree(&mut img, model, pixels_cell_size);
+        draw_quad_tree(&mut img, grid, pixels_cell_size);
     }
 
-    if model.show_image_label {
-        add_time_and_coverage_to_image(&mut img, model)?;
+    if options.show_image_label {
+        add_time_and_coverage_to_image(&mut img, grid, sim_time_elapsed, theme)?;
     }
 
     Ok(img)
@@ -234,19 +326,18 @@ fn create_grid_image_in_memory_with_theme(
 
 pub fn add_time_and_coverage_to_image(
     img: &mut image::RgbImage,
-    model: &crate::model::SimModel,
+    grid: &Grid,
+    sim_time_elapsed: f64,
+    theme: &ColorTheme,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let theme_manager = ColorThemeManager::new();
-    let theme = theme_manager.get_theme(model.color_theme.as_deref().unwrap_or("default"));
-
     draw_filled_rect(img, 0, 0, 230, 100, theme.text_background_adjustment);
 
-    let total_seconds = model.sim_time_elapsed as u64;
+    let total_seconds = sim_time_elapsed as u64;
     let hours = total_seconds / 3600;
     let minutes = (total_seconds % 3600) / 60;
     let seconds = total_seconds % 60;
     let sim_time = format!("{hours:02}:{minutes:02}:{seconds:02}");
-    let coverage_percent = model.grid.as_ref().unwrap().get_coverage_percent();
+    let coverage_percent = grid.get_coverage_percent();
     let coverage_text = format!("{coverage_percent:.1}%",);
 
     let (x, mut y) = (5, 3);
@@ -259,13 +350,12 @@ pub fn add_time_and_coverage_to_image(
 
 fn draw_grid_lines(
     img: &mut image::RgbImage,
-    model: &crate::model::SimModel,
+    options: &ImageOptions,
+    grid: &Grid,
     pixels_cell_size: u32,
     grid_color: [u8; 3],
 ) {
-    let grid = model.grid.as_ref().unwrap();
-
-    for x in 0..model.grid_width.round() as u32 {
+    for x in 0..options.grid_width.round() as u32 {
         let x_pos = grid.world_coordinate_to_grid_x(x as f64);
         for y in 0..img.height() {
             img.put_pixel(x_pos as u32 * pixels_cell_size, y, image::Rgb(grid_color));
@@ -273,7 +363,7 @@ fn draw_grid_lines(
     }
 
     // Draw horizontal grid lines for each world coordinates
-    for y in 0..model.grid_height.round() as u32 {
+    for y in 0..options.grid_height.round() as u32 {
         let y_pos = grid.world_coordinate_to_grid_y(y as f64);
         for x in 0..img.width() {
             img.put_pixel(x, y_pos as u32 * pixels_cell_size, image::Rgb(grid_color));
@@ -281,14 +371,9 @@ fn draw_grid_lines(
     }
 }
 
-fn draw_quad_tree(
-    img: &mut image::RgbImage,
-    model: &crate::model::SimModel,
-    pixels_cell_size: u32,
-) {
-    let tree = model.grid.as_ref().unwrap().quadtree.as_ref().unwrap();
-    let grid = model.grid.as_ref().unwrap();
-    let grid_cells_y = model.grid_cells_y;
+fn draw_quad_tree(img: &mut image::RgbImage, grid: &Grid, pixels_cell_size: u32) {
+    let tree = grid.quadtree.as_ref().unwrap();
+    let grid_cells_y = grid.cells_y;
     // Draw the quad-tree structure on the image
     if let Some(children) = &tree.root.children {
         for child in children.iter() {
@@ -0,0 +1,39 @@
+//! Grid coverage simulation of an autonomous lawn mower ("cutter").
+//!
+//! The library exposes the complete simulation used by the `gridcover` and `grunner` programs.
+//! A simulation is described by a [`SimConfig`], setup with a [`SimulationBuilder`] and then
+//! either advanced step by step or run to completion, giving a [`SimResult`].
+
+pub mod args;
+pub mod batchmanifest;
+pub mod collision;
+pub mod color_theme;
+pub mod config;
+pub mod db;
+pub mod experimentfile;
+pub mod image;
+pub mod mapfile;
+pub mod memory;
+pub mod metric;
+pub mod model;
+pub mod observer;
+pub mod optimize;
+pub mod optimizefile;
+pub mod serve;
+pub mod sim;
+pub mod simulation;
+pub mod stats;
+pub mod stopcond;
+pub mod strategy;
+pub mod tui;
+pub mod vector;
+pub mod video;
+
+pub use config::SimConfig;
+pub use mapfile::{MapFile, MapImportOptions, ObstacleType, load_map_file};
+pub use model::SimModel;
+pub use observer::{SimObserver, SnapshotMetric, SnapshotObserver, StepCallback};
+pub use sim::StepEvents;
+pub use simulation::{SimResult, Simulation, SimulationBuilder};
+pub use stopcond::StopExpr;
+pub use vector::Vector;
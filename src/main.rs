@@ -1,64 +1,23 @@
-mod args;
-mod collision;
-mod color_theme;
-mod db;
-mod image;
-mod mapfile;
-mod model;
-mod sim;
-mod strategy;
-mod vector;
-mod video;
-
 use std::fs;
 
-use args::{read_args_from_file, write_args_to_file};
 use clap::{CommandFactory, Parser};
 use clap_complete::{
     generate_to,
     shells::{Bash, Zsh},
 };
 use colored::Colorize;
-use db::try_store_result_to_db;
-use image::try_save_image;
-use mapfile::{load_optional_mapfile, try_apply_mapfile_to_model};
-use model::{SimModel, init_model, try_delete_frames_dir};
-use rand::Rng;
-use rand::SeedableRng;
-use sim::{FAILSAFE_TIME_LIMIT, simulation_loop};
-use sysinfo::{Pid, System};
-use vector::Vector;
-use video::try_video_encoding;
-
-fn set_optional_random_start_position(rng: &mut rand::prelude::StdRng, model: &mut SimModel) {
-    // Check if we should randomize the start position
-    if model.start_x < 0.0 || model.start_y < 0.0 {
-        let mut counter = 0;
-        loop {
-            model.start_x = rng.random_range(model.radius..(model.grid_width - model.radius));
-            model.start_y = rng.random_range(model.radius..(model.grid_height - model.radius));
-            let model_start = Vector::new(model.start_x, model.start_y);
-            if !model
-                .grid
-                .as_mut()
-                .unwrap()
-                .collision_with_obstacle(&model_start, model.radius)
-            {
-                break;
-            }
-            counter += 1;
-            if counter > 10_000 {
-                eprintln!(
-                    "{}",
-                    "Failed to find a valid start position after 10,000 attempts, aborting. Set start position manually."
-                        .color(colored::Color::Red)
-                        .bold()
-                );
-                std::process::exit(1);
-            }
-        }
-    }
-}
+use gridcover::args::{self, read_args_from_file, write_args_to_file};
+use gridcover::db::query::run_db_command;
+use gridcover::db::render::render_stored_run;
+use gridcover::db::try_store_result_to_db;
+use gridcover::image::try_save_image;
+use gridcover::mapfile::generate::{GeneratorConfig, generate_map, parse_obstacle_types};
+use gridcover::mapfile::validate::validate_map_file;
+use gridcover::model::{SimModel, try_delete_frames_dir};
+use gridcover::serve::{LiveServer, wait_for_shutdown};
+use gridcover::tui::TuiView;
+use gridcover::video::{self, try_video_encoding};
+use gridcover::{SimConfig, Simulation, SimulationBuilder};
 
 fn generate_completions() {
     let mut cmd = args::Args::command();
@@ -95,6 +54,56 @@ fn check_ffmpeg_installed(args: &args::Args) {
     }
 }
 
+/// Start the live view server if the serve command is used and return its URL
+fn start_optional_live_server(args: &args::Args, sim: &mut Simulation) -> Option<String> {
+    let Some(args::Command::Serve(serve_args)) = &args.command else {
+        return None;
+    };
+    match LiveServer::start(serve_args.clone()) {
+        Ok(server) => {
+            let url = server.url();
+            if !args.quiet {
+                println!(
+                    "{} {}",
+                    "Live view available at".color(colored::Color::Green).bold(),
+                    url.color(colored::Color::Cyan).bold()
+                );
+            }
+            sim.add_observer(server);
+            Some(url)
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {}",
+                "Error: Cannot start live view server:"
+                    .color(colored::Color::Red)
+                    .bold(),
+                err
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn start_optional_tui(sim: &mut Simulation) {
+    if !sim.model().tui {
+        return;
+    }
+    // If the terminal cannot be used we fall back to a plain run
+    match TuiView::new(sim.model()) {
+        Ok(view) => sim.add_observer(view),
+        Err(err) => {
+            eprintln!(
+                "{} {}",
+                "Warning: Cannot start terminal view:"
+                    .color(colored::Color::Yellow)
+                    .bold(),
+                err
+            );
+        }
+    }
+}
+
 fn print_model_and_result(args: &args::Args, model: &SimModel) {
     if args.verbosity > 1 {
         if args.json_output {
@@ -174,6 +183,150 @@ fn check_read_args_from_file(args: &args::Args) -> Option<args::Args> {
     }
 }
 
+/// Validate a map file if the validate-map command is used and exit with a non-zero status if
+/// any errors were found
+fn check_validate_map(args: &args::Args) {
+    let Some(args::Command::ValidateMap(validate_args)) = &args.command else {
+        return;
+    };
+    let Some(map_file) = validate_args.map_file.as_ref().or(args.map_file_name.as_ref()) else {
+        eprintln!(
+            "{}",
+            "Error: No map file given to validate."
+                .color(colored::Color::Red)
+                .bold()
+        );
+        std::process::exit(1);
+    };
+    match validate_map_file(map_file, &SimConfig::from(args)) {
+        Ok(report) => {
+            report.print();
+            std::process::exit(if report.has_errors() { 1 } else { 0 });
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {}",
+                "Error: Cannot validate map file:"
+                    .color(colored::Color::Red)
+                    .bold(),
+                err
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn check_generate_map(args: &args::Args) {
+    let Some(args::Command::GenerateMap(generate_args)) = &args.command else {
+        return;
+    };
+    let seed = if args.random_seed > 0 { args.random_seed } else { rand::random::<u64>() };
+    let defaults = GeneratorConfig::default();
+    let config = GeneratorConfig {
+        seed,
+        name: generate_args.name.clone(),
+        width: if args.grid_width > 0.0 { args.grid_width } else { defaults.width },
+        height: if args.grid_height > 0.0 { args.grid_height } else { defaults.height },
+        cutter_radius: args.radius,
+        num_obstacles: generate_args.obstacles,
+        obstacle_types: parse_obstacle_types(&generate_args.obstacle_types).unwrap_or(defaults.obstacle_types),
+        min_size: generate_args.min_size,
+        max_size: generate_args.max_size,
+        size_distribution: generate_args.size_distribution,
+        clearance: generate_args.clearance,
+        narrow_passages: generate_args.narrow_passages,
+        boundary_complexity: generate_args.boundary_complexity,
+    };
+    let result = generate_map(&config).and_then(|map| {
+        fs::write(&generate_args.output, serde_yaml::to_string(&map)?)?;
+        Ok(map)
+    });
+    match result {
+        Ok(map) => {
+            if !args.quiet {
+                println!(
+                    "Generated map \"{}\" with {} obstacles and seed {seed} in \"{}\"",
+                    map.name,
+                    map.obstacles.len(),
+                    generate_args.output
+                );
+            }
+            std::process::exit(0);
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {}",
+                "Error: Failed to generate map:"
+                    .color(colored::Color::Red)
+                    .bold(),
+                err
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Run a db command on the stored simulation runs if the db command is used
+fn check_db_command(args: &args::Args) {
+    let Some(args::Command::Db(db_args)) = &args.command else {
+        return;
+    };
+    let Some(db_path) = db_args.db.as_ref().or(args.database_file.as_ref()) else {
+        eprintln!(
+            "{}",
+            "Error: No database file given, use --db or --database-file."
+                .color(colored::Color::Red)
+                .bold()
+        );
+        std::process::exit(1);
+    };
+    if let Err(err) = run_db_command(db_args, db_path) {
+        eprintln!(
+            "{} {}",
+            "Error: Database command failed:"
+                .color(colored::Color::Red)
+                .bold(),
+            err
+        );
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+/// Render the image of a stored run if the render command is used
+fn check_render(args: &args::Args) {
+    let Some(args::Command::Render(render_args)) = &args.command else {
+        return;
+    };
+    let Some(db_path) = render_args.db.as_ref().or(args.database_file.as_ref()) else {
+        eprintln!(
+            "{}",
+            "Error: No database file given, use --db or --database-file."
+                .color(colored::Color::Red)
+                .bold()
+        );
+        std::process::exit(1);
+    };
+    match render_stored_run(args, render_args, db_path) {
+        Ok(file_name) => {
+            if !args.quiet {
+                println!("Rendered result {} to \"{file_name}\"", render_args.id);
+            }
+            std::process::exit(0);
+        }
+        Err(err) => {
+            eprintln!(
+                "{} {}",
+                "Error: Failed to render stored run:"
+                    .color(colored::Color::Red)
+                    .bold(),
+                err
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
 fn try_create_animation(model: &mut SimModel) {
     let ffmpeg_encoding_duration = try_video_encoding(model).unwrap_or_else(|err| {
         eprintln!(
@@ -205,21 +358,6 @@ fn try_create_animation(model: &mut SimModel) {
     }
 }
 
-fn get_total_ram_in_gb() -> f64 {
-    let memory = sys_info::mem_info().unwrap();
-    memory.total as f64 / 1024.0 / 1024.0
-}
-
-fn get_process_rss_mb() -> f64 {
-    let sys = System::new_all();
-    let pid = Pid::from(std::process::id() as usize);
-    if let Some(p) = sys.process(pid) {
-        p.memory() as f64 / 1024.0 / 1024.0
-    } else {
-        0.0
-    }
-}
-
 fn main() {
     let mut args = args::Args::parse();
 
@@ -236,21 +374,19 @@ fn main() {
     // Check if we should write all args to file
     check_write_args_to_file(&args);
 
-    // Setup random generator with a possible seed from user
-    let mut rng = if args.random_seed > 0 {
-        rand::rngs::StdRng::seed_from_u64(args.random_seed)
-    } else {
-        let seed = rand::random::<u64>();
-        args.random_seed = seed; // Store the random seed in args for later use
-        rand::rngs::StdRng::seed_from_u64(seed)
-    };
+    // Validating a map file does not run any simulation
+    check_validate_map(&args);
+    check_generate_map(&args);
+    check_db_command(&args);
+    check_render(&args);
 
     // For animation we need to check if ffmpeg is installed
     check_ffmpeg_installed(&args);
 
-    // Initialize the simulation model
-    let mut model = match init_model(&args, &mut rng) {
-        Ok(model) => model,
+    // Initialize the simulation model, load the optional map file with all obstacles and
+    // place the cutter. A random seed is chosen here unless the user specified one.
+    let mut sim = match SimulationBuilder::new(SimConfig::from(&args)).build() {
+        Ok(sim) => sim,
         Err(err) => {
             eprintln!(
                 "{} {}",
@@ -263,39 +399,20 @@ fn main() {
         }
     };
 
-    model.ram_size_gb = get_total_ram_in_gb().round();
-    model.ram_usage_mb = get_process_rss_mb().round();
-
-    // Load the optional specified map file with all obstacles
-    load_optional_mapfile(&args, &mut model);
-
-    // Initialize the grid with the calculated number of cells and cell size
-    model.grid = Some(model::grid::Grid::new(
-        model.grid_cells_x,
-        model.grid_cells_y,
-        model.cell_size,
-    ));
-
-    // As a convenience to avoid passing around the args we also store the quad-tree flag in the model
-    model.grid.as_mut().unwrap().use_quad_tree = args.use_quad_tree;
-
-    // Construct all obstacles and mark them in the model grid
-    try_apply_mapfile_to_model(&mut model);
-
-    // We cannot set a random start position until the map has been loaded
-    // as we need a start position that is not in an obstacle
-    set_optional_random_start_position(&mut rng, &mut model);
+    // Optional live views of the running simulation
+    let live_server_url = start_optional_live_server(&args, &mut sim);
+    start_optional_tui(&mut sim);
 
     // ==============================================================================================
     // ==========  Start the simulation loop. This is where the main simulation happens!  ===========
     // ==============================================================================================
-    let start_time = chrono::Utc::now();
-    simulation_loop(&mut model, &mut rng);
-    let end_time = chrono::Utc::now();
-    model.cpu_time = end_time.signed_duration_since(start_time);
+    sim.run();
+    let result = sim.result();
+    // Dropping the observers also restores the terminal before any results are printed
+    let mut model = sim.into_model();
 
     // Check if the simulation was aborted by our fail-safe
-    if model.sim_time_elapsed >= FAILSAFE_TIME_LIMIT {
+    if result.aborted {
         eprintln!(
             "{}",
             "WARNING: Simulation ABORTED after 604,800 simulation seconds to prevent infinite loop!\n"
@@ -304,11 +421,6 @@ fn main() {
         );
     }
 
-    // Complete the model with some of the results from this simulation run
-    (model.coverage_count, model.coverage_percent) = model.grid.as_ref().unwrap().get_coverage();
-    model.max_visited_number = model.grid.as_ref().unwrap().get_max_visited_number();
-    model.min_visited_number = model.grid.as_ref().unwrap().get_min_visited_number();
-
     // If we have shown progress during the simulation we need a return to start the remainign output on a new line
     if args.show_progress {
         println!();
@@ -328,4 +440,9 @@ fn main() {
 
     // Print the model and results to the console
     print_model_and_result(&args, &model);
+
+    // In serve mode the final state stays available in the browser until the user stops the server
+    if let Some(url) = live_server_url {
+        wait_for_shutdown(&url, args.quiet);
+    }
 }
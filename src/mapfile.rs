@@ -1,25 +1,77 @@
-use crate::args;
-use crate::model::{SimModel, grid::Grid, setup_grid_size};
+use crate::model::{SimModel, grid::Grid, grid::Zone, setup_grid_size, terrain::Terrain, terrain::TerrainMap};
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fs;
 use std::path::Path;
 
 mod circle;
+pub mod generate;
+pub mod geojson;
 mod line;
 mod polygon;
+pub mod prepared;
+pub mod raster;
 mod rectangle;
+pub mod resolve;
+mod shape;
+pub mod svg;
+pub mod validate;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MapFile {
     pub name: String,
     pub description: Option<String>,
     pub grid: Option<GridConfig>,
+    /// Optional outline of the area to cut. All cells outside it are treated as obstacles.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boundary: Option<Vec<[f64; 2]>>,
+    /// Unit of all lengths in the file, meters if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<resolve::Units>,
+    /// Other map files, relative to this file, whose obstacles and templates are added
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Named lists of obstacles that are placed with `instance` obstacles
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<String, Vec<ObstacleType>>,
+    #[serde(default)]
     pub obstacles: Vec<ObstacleType>,
+    /// Areas the cutter can drive over but must not cut, e.g. a gravel path
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_cut_zones: Vec<ObstacleType>,
+    /// Areas the cutter turns away from as from an obstacle but without bumping into anything
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub virtual_fences: Vec<ObstacleType>,
+    /// Regions with a slope or a surface other than normal lawn
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub terrain: Vec<TerrainRegion>,
+}
+
+/// An area of the map with a slope and/or a surface with more or less grip than normal lawn. Where
+/// regions overlap the one given last is used.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TerrainRegion {
+    pub name: Option<String>,
+    /// Slope in degrees from the horizontal plane
+    #[serde(default)]
+    pub slope: f64,
+    /// Direction the ground falls towards in degrees counter-clockwise from the x-axis
+    #[serde(default)]
+    pub slope_direction: f64,
+    /// Grip of the surface relative to normal lawn, e.g. 0.5 for wet clay
+    #[serde(default = "default_friction")]
+    pub friction: f64,
+    /// The area of the region, any obstacle shape
+    pub area: ObstacleType,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+fn default_friction() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GridConfig {
     pub width: Option<f64>,
     pub height: Option<f64>,
@@ -49,6 +101,9 @@ pub enum ObstacleType {
     #[serde(rename = "polygon")]
     Polygon {
         points: Vec<[f64; 2]>,
+        /// Polygons inside the outline that are not part of the obstacle
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        holes: Vec<Vec<[f64; 2]>>,
         name: Option<String>,
     },
 
@@ -58,78 +113,400 @@ pub enum ObstacleType {
         width: f64,
         name: Option<String>,
     },
+
+    /// An ellipse rotated counter-clockwise by `rotation` degrees around its center
+    #[serde(rename = "ellipse")]
+    Ellipse {
+        x: f64,
+        y: f64,
+        radius_x: f64,
+        radius_y: f64,
+        #[serde(default)]
+        rotation: f64,
+        name: Option<String>,
+    },
+
+    /// A rectangle with the lower left corner at `x`, `y` rotated counter-clockwise by `rotation`
+    /// degrees around that corner
+    #[serde(rename = "rotated_rectangle")]
+    RotatedRectangle {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+        rotation: f64,
+        name: Option<String>,
+    },
+
+    /// A rectangle with rounded corners, optionally rotated as a rotated rectangle
+    #[serde(rename = "rounded_rectangle")]
+    RoundedRectangle {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+        corner_radius: f64,
+        #[serde(default)]
+        rotation: f64,
+        name: Option<String>,
+    },
+
+    /// A ring between two radii. With start and end angles, in degrees counter-clockwise from
+    /// the x-axis, only the arc from the start angle counter-clockwise to the end angle is used.
+    #[serde(rename = "ring", alias = "arc")]
+    Ring {
+        x: f64,
+        y: f64,
+        inner_radius: f64,
+        outer_radius: f64,
+        start_angle: Option<f64>,
+        end_angle: Option<f64>,
+        name: Option<String>,
+    },
+
+    /// Obstacles combined into one. Cells with their center inside the combined shape are
+    /// obstacles, raster obstacles can not be part of a group.
+    #[serde(rename = "group")]
+    Group {
+        operation: GroupOperation,
+        children: Vec<ObstacleType>,
+        name: Option<String>,
+    },
+
+    /// A copy of a template that is scaled, rotated counter-clockwise by `rotate` degrees around
+    /// the origin and then translated. Instances are replaced by the obstacles of the template
+    /// when the map file is loaded.
+    #[serde(rename = "instance")]
+    Instance {
+        template: String,
+        #[serde(default)]
+        translate: [f64; 2],
+        #[serde(default)]
+        rotate: f64,
+        #[serde(default = "default_instance_scale")]
+        scale: f64,
+        name: Option<String>,
+    },
+
+    /// A PNG/BMP mask where each pixel is lawn, obstacle or outside the area. The file name is
+    /// relative to the map file. Without a palette dark pixels are obstacles.
+    #[serde(rename = "raster")]
+    Raster {
+        file: String,
+        #[serde(default)]
+        x: f64,
+        #[serde(default)]
+        y: f64,
+        /// Size of a pixel in units (meters per pixel)
+        resolution: f64,
+        threshold: Option<u8>,
+        #[serde(default)]
+        invert: bool,
+        /// Map from "#rrggbb" colors to the class of the pixel
+        palette: Option<BTreeMap<String, raster::RasterClass>>,
+        name: Option<String>,
+    },
 }
 
-fn load_map_file<P: AsRef<Path>>(path: P) -> Result<MapFile, Box<dyn Error>> {
-    let content = fs::read_to_string(path)?;
-    let map_file: MapFile = serde_yaml::from_str(&content)?;
-    Ok(map_file)
-}
-
-fn apply_obstacles_to_grid(grid: &mut Grid, map: &MapFile) {
-    for obstacle in &map.obstacles {
-        match obstacle {
-            ObstacleType::Rectangle {
-                x,
-                y,
-                width,
-                height,
-                ..
-            } => {
-                rectangle::apply_rectangle_obstacle(grid, *x, *y, *width, *height);
-            }
-            ObstacleType::Circle { x, y, radius, .. } => {
-                circle::apply_circle_obstacle(grid, *x, *y, *radius);
-            }
-            ObstacleType::Polygon { points, .. } => {
-                polygon::apply_polygon_obstacle(grid, points);
-            }
-            ObstacleType::Line { points, width, .. } => {
-                line::apply_line_obstacle(grid, points.as_slice(), *width);
-            }
+fn default_instance_scale() -> f64 {
+    1.0
+}
+
+/// How the children of a group obstacle are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupOperation {
+    /// Inside any of the children
+    Union,
+    /// Inside the first child but not inside any of the others
+    Difference,
+    /// Inside all of the children
+    Intersection,
+}
+
+/// Options used when a map is imported from a format other than YAML
+#[derive(Debug, Clone, Copy)]
+pub struct MapImportOptions {
+    /// Number of simulation units per SVG unit
+    pub svg_scale: f64,
+    /// Size in simulation units of a pixel in a raster map
+    pub raster_resolution: f64,
+}
+
+impl Default for MapImportOptions {
+    fn default() -> Self {
+        MapImportOptions {
+            svg_scale: 1.0,
+            raster_resolution: 0.05,
         }
     }
 }
 
-pub fn load_optional_mapfile(args: &args::Args, model: &mut SimModel) {
-    // Load map file if specified
-    if let Some(map_path) = args.map_file_name.clone() {
-        match load_map_file(&map_path) {
-            Ok(map) => {
-                // Apply grid overrides if specified in map
-                if let Some(grid_config) = &map.grid {
-                    if let Some(width) = grid_config.width {
-                        model.grid_width = width;
-                    }
-                    if let Some(height) = grid_config.height {
-                        model.grid_height = height;
-                    }
-                }
-                if let Err(e) = setup_grid_size(model) {
-                    eprintln!(
-                        "{}",
-                        format!("Failed to setup grid size after map loading: {e}")
-                            .color(colored::Color::Red)
-                            .bold()
-                    );
-                    std::process::exit(1);
+/// Read and parse a map file. Files with the extension `.geojson` are imported as GeoJSON,
+/// `.svg` files as SVG drawings, `.png` and `.bmp` files as raster masks and all other files
+/// are read as YAML. Units, includes and templates in YAML files are resolved so the returned
+/// map has all lengths in meters and no template instances. The default import options are used.
+pub fn load_map_file<P: AsRef<Path>>(path: P) -> Result<MapFile, Box<dyn Error>> {
+    load_map_file_with_options(path, &MapImportOptions::default())
+}
+
+/// Same as `load_map_file()` but with the given options for imported formats
+pub fn load_map_file_with_options<P: AsRef<Path>>(
+    path: P,
+    options: &MapImportOptions,
+) -> Result<MapFile, Box<dyn Error>> {
+    load_map_file_with_sources(path, options).map(|resolved| resolved.map)
+}
+
+/// Same as `load_map_file_with_options()` but also returns the templates and, for each obstacle,
+/// the index of the obstacle in the file it was expanded from or `None` if it comes from an
+/// included file
+pub(crate) fn load_map_file_with_sources<P: AsRef<Path>>(
+    path: P,
+    options: &MapImportOptions,
+) -> Result<resolve::ResolvedMap, Box<dyn Error>> {
+    let path = path.as_ref();
+    let extension = path.extension().unwrap_or_default().to_ascii_lowercase();
+    let default_name = path.file_stem().unwrap_or_default().to_string_lossy();
+    let with_sources = |map: MapFile| resolve::ResolvedMap {
+        sources: (0..map.obstacles.len()).map(Some).collect(),
+        templates: BTreeMap::new(),
+        map,
+    };
+    if extension == "png" || extension == "bmp" {
+        return raster_map(&path.to_string_lossy(), &default_name, options.raster_resolution).map(with_sources);
+    }
+    let content = fs::read_to_string(path)?;
+    if extension == "geojson" {
+        return geojson::parse_geojson(&content, &default_name).map(with_sources);
+    }
+    if extension == "svg" {
+        return svg::parse_svg(&content, &default_name, options.svg_scale).map(with_sources);
+    }
+    resolve::resolve_yaml_map(&content, path)
+}
+
+/// A map consisting of a single raster mask that covers the whole grid
+fn raster_map(file: &str, name: &str, resolution: f64) -> Result<MapFile, Box<dyn Error>> {
+    if resolution <= 0.0 {
+        return Err(format!("Raster resolution must be positive, got {resolution}").into());
+    }
+    let (width, height) = raster::raster_dimensions(file)?;
+    Ok(MapFile {
+        name: name.to_string(),
+        description: Some(format!("Raster map {width}x{height} pixels")),
+        grid: Some(GridConfig {
+            width: Some(width as f64 * resolution),
+            height: Some(height as f64 * resolution),
+        }),
+        boundary: None,
+        units: None,
+        include: Vec::new(),
+        templates: BTreeMap::new(),
+        obstacles: vec![ObstacleType::Raster {
+            file: file.to_string(),
+            x: 0.0,
+            y: 0.0,
+            resolution,
+            threshold: None,
+            invert: false,
+            palette: None,
+            name: Some(name.to_string()),
+        }],
+        no_cut_zones: Vec::new(),
+        virtual_fences: Vec::new(),
+        terrain: Vec::new(),
+    })
+}
+
+/// Mark the obstacles, no-cut zones and virtual fences of the map in the grid
+pub(crate) fn apply_obstacles_to_grid(grid: &mut Grid, map: &MapFile) -> Result<(), Box<dyn Error>> {
+    if let Some(boundary) = &map.boundary {
+        polygon::apply_boundary(grid, boundary);
+    }
+    let zones = [
+        (Zone::Obstacle, &map.obstacles),
+        (Zone::NoCut, &map.no_cut_zones),
+        (Zone::VirtualFence, &map.virtual_fences),
+    ];
+    for (zone, obstacles) in zones {
+        grid.zone = zone;
+        let result = obstacles.iter().try_for_each(|obstacle| apply_obstacle(grid, obstacle));
+        grid.zone = Zone::Obstacle;
+        result?;
+    }
+    Ok(())
+}
+
+/// Build the terrain map of the grid from the terrain regions of the map, if it has any
+pub(crate) fn apply_terrain_to_grid(grid: &mut Grid, map: &MapFile) -> Result<(), Box<dyn Error>> {
+    if map.terrain.is_empty() {
+        return Ok(());
+    }
+    let mut terrain = TerrainMap::new(grid.cells_x, grid.cells_y);
+    for region in &map.terrain {
+        let name = region.name.as_deref().unwrap_or("unnamed");
+        if !(0.0..90.0).contains(&region.slope) {
+            return Err(format!("Terrain region '{name}' must have a slope of at least 0 and less than 90 degrees, got {}", region.slope).into());
+        }
+        if !(region.friction.is_finite() && region.friction > 0.0) {
+            return Err(format!("Terrain region '{name}' must have a positive friction, got {}", region.friction).into());
+        }
+        let region_terrain = Terrain::new(region.slope, region.slope_direction, region.friction);
+        if region_terrain.max_drift_per_step() >= 1.0 {
+            return Err(format!(
+                "Terrain region '{name}' has a slope of {} degrees and friction {} that make the cutter slide further than it drives, increase the friction",
+                region.slope, region.friction
+            )
+            .into());
+        }
+        // Rasterize the area as an obstacle in an empty grid to find the cells of the region
+        let mut area = Grid::new(grid.cells_x, grid.cells_y, grid.cell_size);
+        apply_obstacle(&mut area, &region.area)?;
+        let index = terrain.add_region(region_terrain);
+        for (x, column) in area.cells.iter().enumerate() {
+            for (y, cell) in column.iter().enumerate() {
+                if cell.is_obstacle() {
+                    terrain.set_cell(x, y, index);
                 }
-                model.map_file = Some(map);
-            }
-            Err(e) => {
-                eprintln!("Failed to load map file: {e}");
-                std::process::exit(1);
             }
         }
     }
+    grid.terrain = Some(terrain);
+    Ok(())
 }
 
-pub fn try_apply_mapfile_to_model(model: &mut SimModel) {
+/// Mark the cells of one obstacle as the current zone of the grid
+fn apply_obstacle(grid: &mut Grid, obstacle: &ObstacleType) -> Result<(), Box<dyn Error>> {
+    match obstacle {
+        ObstacleType::Rectangle {
+            x,
+            y,
+            width,
+            height,
+            ..
+        } => {
+            rectangle::apply_rectangle_obstacle(grid, *x, *y, *width, *height);
+        }
+        ObstacleType::Circle { x, y, radius, .. } => {
+            circle::apply_circle_obstacle(grid, *x, *y, *radius);
+        }
+        ObstacleType::Polygon { points, holes, .. } => {
+            polygon::apply_polygon_with_holes_obstacle(grid, points, holes);
+        }
+        ObstacleType::Line { points, width, .. } => {
+            line::apply_line_obstacle(grid, points.as_slice(), *width);
+        }
+        ObstacleType::Ellipse {
+            x,
+            y,
+            radius_x,
+            radius_y,
+            rotation,
+            ..
+        } => {
+            shape::apply_ellipse_obstacle(grid, *x, *y, *radius_x, *radius_y, *rotation);
+        }
+        ObstacleType::RotatedRectangle {
+            x,
+            y,
+            width,
+            height,
+            rotation,
+            ..
+        } => {
+            shape::apply_rotated_rectangle_obstacle(grid, *x, *y, *width, *height, *rotation);
+        }
+        ObstacleType::RoundedRectangle {
+            x,
+            y,
+            width,
+            height,
+            corner_radius,
+            rotation,
+            ..
+        } => {
+            shape::apply_rounded_rectangle_obstacle(grid, *x, *y, *width, *height, *corner_radius, *rotation);
+        }
+        ObstacleType::Ring {
+            x,
+            y,
+            inner_radius,
+            outer_radius,
+            start_angle,
+            end_angle,
+            ..
+        } => {
+            shape::apply_ring_obstacle(grid, *x, *y, *inner_radius, *outer_radius, *start_angle, *end_angle);
+        }
+        ObstacleType::Group { .. } => {
+            shape::apply_group_obstacle(grid, obstacle);
+        }
+        ObstacleType::Instance { template, .. } => {
+            return Err(format!("Instance of template '{template}' must be resolved when the map is loaded").into());
+        }
+        ObstacleType::Raster {
+            file,
+            x,
+            y,
+            resolution,
+            threshold,
+            invert,
+            palette,
+            ..
+        } => {
+            let classifier = raster::RasterClassifier::new(*threshold, *invert, palette.as_ref())?;
+            raster::apply_raster_obstacle(grid, file, *x, *y, *resolution, &classifier)?;
+        }
+    }
+    Ok(())
+}
+
+/// Load the map file named in the model, if any, and adjust the grid size to the map
+pub fn load_optional_mapfile(model: &mut SimModel) -> Result<(), Box<dyn Error>> {
+    if let Some(map_path) = model.map_file_name.clone() {
+        let options = MapImportOptions {
+            svg_scale: model.svg_scale,
+            raster_resolution: model.raster_resolution,
+        };
+        let map = load_map_file_with_options(&map_path, &options).map_err(|e| format!("Failed to load map file: {e}"))?;
+        set_model_map(model, map)?;
+    }
+    Ok(())
+}
+
+/// Use the given map for the model. Any grid size given in the map overrides the model grid size.
+pub fn set_model_map(model: &mut SimModel, map: MapFile) -> Result<(), Box<dyn Error>> {
+    if let Some(grid_config) = &map.grid {
+        if let Some(width) = grid_config.width {
+            model.grid_width = width;
+        }
+        if let Some(height) = grid_config.height {
+            model.grid_height = height;
+        }
+    }
+    setup_grid_size(model).map_err(|e| format!("Failed to setup grid size after map loading: {e}"))?;
+    model.map_file = Some(map);
+    Ok(())
+}
+
+/// Copy the number of obstacles and of obstacle, zone and terrain cells of the grid to the model
+fn update_model_map_counts(model: &mut SimModel) {
+    let grid = model.grid.as_ref().unwrap();
+    model.grid_cells_obstacles_count = grid.cells_obstacles_count;
+    model.grid_cells_no_cut_count = grid.cells_no_cut_count;
+    model.grid_cells_virtual_fence_count = grid.cells_virtual_fence_count;
+    model.grid_cells_terrain_count = grid.terrain.as_ref().map_or(0, |terrain| terrain.get_cell_count());
+    model.num_obstacles = model.map_file.as_ref().map_or(0, |map| map.obstacles.len());
+}
+
+pub fn try_apply_mapfile_to_model(model: &mut SimModel) -> Result<(), Box<dyn Error>> {
     // Load map file if specified
     if let Some(map_file) = &model.map_file {
-        apply_obstacles_to_grid(model.grid.as_mut().unwrap(), map_file);
+        apply_obstacles_to_grid(model.grid.as_mut().unwrap(), map_file)?;
+        apply_terrain_to_grid(model.grid.as_mut().unwrap(), map_file)?;
         model.grid.as_mut().unwrap().update_obstacle_cells_count();
-        model.num_obstacles = map_file.obstacles.len();
+        update_model_map_counts(model);
 
         // Build the spatial index which is a quad-tree
         if model.verbosity > 1 && !model.quiet {
@@ -161,9 +538,15 @@ pub fn try_apply_mapfile_to_model(model: &mut SimModel) {
             );
         }
 
-        if model.save_quad_tree {
+        // A map given directly through the library has no file name to derive the index name from
+        if model.save_quad_tree
+            && let Some(map_file_name) = &model.map_file_name
+        {
             // Save the quad-tree to a file
-            let index_file = model.map_file_name.as_ref().unwrap().replace(".yaml", "_index.json");
+            let index_file = format!(
+                "{}_index.json",
+                Path::new(map_file_name).with_extension("").display()
+            );
             if model.verbosity > 1 && !model.quiet {
                 print!(
                     "{}",
@@ -182,4 +565,5 @@ pub fn try_apply_mapfile_to_model(model: &mut SimModel) {
             }
         }
     }
+    Ok(())
 }
@@ -33,9 +33,7 @@ pub fn apply_circle_obstacle(grid: &mut Grid, center_x: f64, center_y: f64, radi
                 center_y,
                 radius,
             ) {
-                if let Some(cell) = grid.get_cell_mut(grid_x as usize, grid_y as usize) {
-                    cell.set_as_obstacle();
-                }
+                grid.mark_zone_cell(grid_x as usize, grid_y as usize);
             }
         }
     }
@@ -0,0 +1,607 @@
+//! Procedural generation of random maps from a seed, used to benchmark strategies on many
+//! different gardens. The same seed and settings always give the same map.
+//!
+//! A map is built in three steps
+//! 1. An optional star shaped `boundary` around the center of the grid. The boundary complexity
+//!    sets the number of vertices and how much they are pulled in from an ellipse.
+//! 2. Narrow passages. Each is a straight wall across the whole lawn with a single gap that is
+//!    only a little wider than the cutter. All walls are parallel so the lawn is split into
+//!    strips that are joined by the gaps.
+//! 3. Obstacles of random type and size. Each obstacle keeps at least the clearance to the other
+//!    obstacles, the walls and the lawn edge.
+//!
+//! After each addition the map is rasterized and the cells where the cutter center fits are
+//! checked to form a single connected area. Additions that would split the lawn are discarded
+//! and a new one is tried, so every part of the lawn stays reachable for the given cutter radius.
+
+use super::validate::count_cutter_areas;
+use super::{GridConfig, MapFile, ObstacleType, polygon};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::f64::consts::PI;
+
+/// Largest number of cells along a side of the grid used to check the connectivity
+const MAX_CHECK_CELLS: f64 = 1000.0;
+
+/// Number of random placements tried for each obstacle, wall or boundary
+const MAX_ATTEMPTS: usize = 200;
+
+/// Highest boundary complexity
+pub const MAX_BOUNDARY_COMPLEXITY: u32 = 10;
+
+/// The shapes of generated obstacles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeneratedShape {
+    Circle,
+    Rectangle,
+    RotatedRectangle,
+    Ellipse,
+    Polygon,
+    Line,
+}
+
+impl GeneratedShape {
+    const ALL: [GeneratedShape; 6] = [
+        GeneratedShape::Circle,
+        GeneratedShape::Rectangle,
+        GeneratedShape::RotatedRectangle,
+        GeneratedShape::Ellipse,
+        GeneratedShape::Polygon,
+        GeneratedShape::Line,
+    ];
+
+    /// Name of the shape as used in the map file
+    pub fn name(&self) -> &'static str {
+        match self {
+            GeneratedShape::Circle => "circle",
+            GeneratedShape::Rectangle => "rectangle",
+            GeneratedShape::RotatedRectangle => "rotated_rectangle",
+            GeneratedShape::Ellipse => "ellipse",
+            GeneratedShape::Polygon => "polygon",
+            GeneratedShape::Line => "line",
+        }
+    }
+}
+
+/// How the sizes of the obstacles are distributed between the smallest and largest size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizeDistribution {
+    /// All sizes are equally likely
+    Uniform,
+    /// The logarithm of the size is uniform, so small obstacles are more common
+    LogUniform,
+}
+
+/// Parse a comma separated list of obstacle shapes, each with an optional relative weight given
+/// as `shape:weight`, e.g. "circle:3,rectangle,line:0.5"
+pub fn parse_obstacle_types(s: &str) -> Result<Vec<(GeneratedShape, f64)>, String> {
+    let mut types = Vec::new();
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (name, weight) = match item.split_once(':') {
+            Some((name, weight)) => (
+                name.trim(),
+                weight
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid weight '{weight}' for obstacle type '{name}'"))?,
+            ),
+            None => (item, 1.0),
+        };
+        let shape = GeneratedShape::ALL
+            .into_iter()
+            .find(|shape| shape.name() == name.to_lowercase())
+            .ok_or_else(|| {
+                let names: Vec<&str> = GeneratedShape::ALL.iter().map(|shape| shape.name()).collect();
+                format!("Unknown obstacle type '{name}', expected one of: {}", names.join(", "))
+            })?;
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(format!("Weight for obstacle type '{name}' must not be negative, got {weight}"));
+        }
+        types.push((shape, weight));
+    }
+    if types.iter().map(|(_, weight)| weight).sum::<f64>() <= 0.0 {
+        return Err("At least one obstacle type with a positive weight must be given".to_string());
+    }
+    Ok(types)
+}
+
+/// Settings for a generated map
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub name: Option<String>,
+    pub width: f64,
+    pub height: f64,
+    /// Radius of the cutter that must be able to reach the whole lawn
+    pub cutter_radius: f64,
+    pub num_obstacles: usize,
+    /// Obstacle shapes with their relative weights
+    pub obstacle_types: Vec<(GeneratedShape, f64)>,
+    /// Smallest and largest obstacle size, i.e. the diameter or longest side
+    pub min_size: f64,
+    pub max_size: f64,
+    pub size_distribution: SizeDistribution,
+    /// Smallest distance between two obstacles and between an obstacle and the lawn edge
+    pub clearance: f64,
+    pub narrow_passages: usize,
+    /// From 0, a rectangular lawn, to `MAX_BOUNDARY_COMPLEXITY`
+    pub boundary_complexity: u32,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            seed: 0,
+            name: None,
+            width: 30.0,
+            height: 20.0,
+            cutter_radius: 0.15,
+            num_obstacles: 10,
+            obstacle_types: GeneratedShape::ALL.iter().map(|shape| (*shape, 1.0)).collect(),
+            min_size: 0.5,
+            max_size: 3.0,
+            size_distribution: SizeDistribution::Uniform,
+            clearance: 0.5,
+            narrow_passages: 0,
+            boundary_complexity: 0,
+        }
+    }
+}
+
+/// Distance from a point to a line segment
+fn segment_distance(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length2 = dx * dx + dy * dy;
+    let t = if length2 > 0.0 {
+        (((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / length2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((p[0] - a[0] - t * dx).powi(2) + (p[1] - a[1] - t * dy).powi(2)).sqrt()
+}
+
+/// Distance from a point to the closest edge of a closed polygon
+fn outline_distance(p: [f64; 2], points: &[[f64; 2]]) -> f64 {
+    (0..points.len())
+        .map(|i| segment_distance(p, points[i], points[(i + 1) % points.len()]))
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Generated values are rounded to millimeters to keep the map file readable
+fn round(v: f64) -> f64 {
+    (v * 1000.0).round() / 1000.0
+}
+
+fn round_point(p: [f64; 2]) -> [f64; 2] {
+    [round(p[0]), round(p[1])]
+}
+
+fn round_points(points: Vec<[f64; 2]>) -> Vec<[f64; 2]> {
+    points.into_iter().map(round_point).collect()
+}
+
+/// Round all values of a generated obstacle
+fn round_obstacle(obstacle: ObstacleType) -> ObstacleType {
+    match obstacle {
+        ObstacleType::Circle { x, y, radius, name } => ObstacleType::Circle {
+            x: round(x),
+            y: round(y),
+            radius: round(radius),
+            name,
+        },
+        ObstacleType::Rectangle {
+            x,
+            y,
+            width,
+            height,
+            name,
+        } => ObstacleType::Rectangle {
+            x: round(x),
+            y: round(y),
+            width: round(width),
+            height: round(height),
+            name,
+        },
+        ObstacleType::RotatedRectangle {
+            x,
+            y,
+            width,
+            height,
+            rotation,
+            name,
+        } => ObstacleType::RotatedRectangle {
+            x: round(x),
+            y: round(y),
+            width: round(width),
+            height: round(height),
+            rotation: round(rotation),
+            name,
+        },
+        ObstacleType::Ellipse {
+            x,
+            y,
+            radius_x,
+            radius_y,
+            rotation,
+            name,
+        } => ObstacleType::Ellipse {
+            x: round(x),
+            y: round(y),
+            radius_x: round(radius_x),
+            radius_y: round(radius_y),
+            rotation: round(rotation),
+            name,
+        },
+        ObstacleType::Polygon { points, holes, name } => ObstacleType::Polygon {
+            points: round_points(points),
+            holes: holes.into_iter().map(round_points).collect(),
+            name,
+        },
+        ObstacleType::Line { points, width, name } => ObstacleType::Line {
+            points: round_points(points),
+            width: round(width),
+            name,
+        },
+        other => other,
+    }
+}
+
+/// An obstacle together with a circle that encloses it, used to keep the clearance
+struct Placed {
+    center: [f64; 2],
+    radius: f64,
+}
+
+struct Generator<'a> {
+    config: &'a GeneratorConfig,
+    rng: StdRng,
+    map: MapFile,
+    check_cell_size: f64,
+    placed: Vec<Placed>,
+    walls: Vec<([f64; 2], [f64; 2])>,
+}
+
+impl Generator<'_> {
+    /// True if the cutter can still reach the whole lawn
+    fn is_connected(&self) -> Result<bool, Box<dyn Error>> {
+        let areas = count_cutter_areas(
+            &self.map,
+            self.config.cutter_radius,
+            self.config.width,
+            self.config.height,
+            self.check_cell_size,
+        )?;
+        Ok(areas == 1)
+    }
+
+    /// True if the point is on the lawn at least `margin` from its edge
+    fn on_lawn(&self, p: [f64; 2], margin: f64) -> bool {
+        let (width, height) = (self.config.width, self.config.height);
+        if p[0] < margin || p[1] < margin || p[0] > width - margin || p[1] > height - margin {
+            return false;
+        }
+        match &self.map.boundary {
+            Some(boundary) => polygon::point_in_polygon(p[0], p[1], boundary) && outline_distance(p, boundary) >= margin,
+            None => true,
+        }
+    }
+
+    fn random_point(&mut self) -> [f64; 2] {
+        [
+            self.rng.random_range(0.0..self.config.width),
+            self.rng.random_range(0.0..self.config.height),
+        ]
+    }
+
+    fn generate_boundary(&mut self) -> Result<(), Box<dyn Error>> {
+        let complexity = self.config.boundary_complexity.min(MAX_BOUNDARY_COMPLEXITY);
+        if complexity == 0 {
+            return Ok(());
+        }
+        let vertices = 6 + 4 * complexity as usize;
+        let indent = 0.05 * complexity as f64;
+        let center = [self.config.width / 2.0, self.config.height / 2.0];
+        let margin = self.config.cutter_radius;
+        let (radius_x, radius_y) = (center[0] - margin, center[1] - margin);
+        for _ in 0..MAX_ATTEMPTS {
+            // The vertices are at increasing angles around the center, so the outline can not
+            // intersect itself
+            let offset = self.rng.random_range(0.0..2.0 * PI);
+            let points = (0..vertices)
+                .map(|i| {
+                    let angle = offset + 2.0 * PI * i as f64 / vertices as f64;
+                    let scale = 1.0 - indent * self.rng.random::<f64>();
+                    [
+                        center[0] + radius_x * scale * angle.cos(),
+                        center[1] + radius_y * scale * angle.sin(),
+                    ]
+                })
+                .map(round_point)
+                .collect();
+            self.map.boundary = Some(points);
+            if self.is_connected()? {
+                return Ok(());
+            }
+        }
+        Err("Failed to generate a lawn boundary where the cutter can reach the whole lawn".into())
+    }
+
+    fn generate_passages(&mut self) -> Result<(), Box<dyn Error>> {
+        let (width, height) = (self.config.width, self.config.height);
+        let radius = self.config.cutter_radius;
+        let wall_width = (2.0 * self.check_cell_size).max(0.1);
+        // Wide enough for the cutter also with the coarser cells used for the check
+        let gap = 2.0 * radius * 1.25 + 2.0 * self.check_cell_size + wall_width;
+        let horizontal = self.rng.random_bool(0.5);
+        let min_spacing = 4.0 * radius + wall_width;
+
+        for index in 0..self.config.narrow_passages {
+            let mut added = false;
+            for _ in 0..MAX_ATTEMPTS {
+                // The wall spans the grid, the gap must be on the lawn
+                let p = self.random_point();
+                let position = if horizontal { p[1] } else { p[0] };
+                if self.walls.iter().any(|(a, _)| {
+                    let other = if horizontal { a[1] } else { a[0] };
+                    (other - position).abs() < min_spacing
+                }) || !self.on_lawn(p, gap)
+                {
+                    continue;
+                }
+                let along = if horizontal { p[0] } else { p[1] };
+                let end = if horizontal { width } else { height };
+                let to_point = |t: f64| if horizontal { [t, position] } else { [position, t] };
+                let segments = [
+                    (to_point(wall_width / 2.0), to_point(along - gap / 2.0)),
+                    (to_point(along + gap / 2.0), to_point(end - wall_width / 2.0)),
+                ]
+                .map(|(a, b)| (round_point(a), round_point(b)));
+                for (part, (a, b)) in segments.iter().enumerate() {
+                    self.map.obstacles.push(ObstacleType::Line {
+                        points: vec![*a, *b],
+                        width: round(wall_width),
+                        name: Some(format!("Passage {} wall {}", index + 1, part + 1)),
+                    });
+                }
+                if self.is_connected()? {
+                    self.walls.extend(segments);
+                    added = true;
+                    break;
+                }
+                self.map.obstacles.truncate(self.map.obstacles.len() - 2);
+            }
+            if !added {
+                return Err(format!(
+                    "Failed to add narrow passage {} of {}, the lawn is too small for this many passages",
+                    index + 1,
+                    self.config.narrow_passages
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn random_size(&mut self) -> f64 {
+        let (min, max) = (self.config.min_size, self.config.max_size);
+        if min >= max {
+            return min;
+        }
+        match self.config.size_distribution {
+            SizeDistribution::Uniform => self.rng.random_range(min..max),
+            SizeDistribution::LogUniform => self.rng.random_range(min.ln()..max.ln()).exp(),
+        }
+    }
+
+    fn random_shape(&mut self) -> GeneratedShape {
+        let total: f64 = self.config.obstacle_types.iter().map(|(_, weight)| weight).sum();
+        let mut pick = self.rng.random_range(0.0..total);
+        for (shape, weight) in &self.config.obstacle_types {
+            if pick < *weight {
+                return *shape;
+            }
+            pick -= weight;
+        }
+        self.config.obstacle_types[0].0
+    }
+
+    /// An obstacle of the given shape and size centered at `c`
+    fn make_obstacle(&mut self, shape: GeneratedShape, size: f64, c: [f64; 2], name: String) -> ObstacleType {
+        let name = Some(name);
+        match shape {
+            GeneratedShape::Circle => ObstacleType::Circle {
+                x: c[0],
+                y: c[1],
+                radius: size / 2.0,
+                name,
+            },
+            GeneratedShape::Rectangle => {
+                let height = size * self.rng.random_range(0.4..1.0);
+                ObstacleType::Rectangle {
+                    x: c[0] - size / 2.0,
+                    y: c[1] - height / 2.0,
+                    width: size,
+                    height,
+                    name,
+                }
+            }
+            GeneratedShape::RotatedRectangle => {
+                let height = size * self.rng.random_range(0.4..1.0);
+                let rotation: f64 = self.rng.random_range(0.0..180.0);
+                // Rotated around the lower left corner, so place that corner to keep the center
+                let (sin, cos) = rotation.to_radians().sin_cos();
+                let (hx, hy) = (size / 2.0, height / 2.0);
+                ObstacleType::RotatedRectangle {
+                    x: c[0] - (hx * cos - hy * sin),
+                    y: c[1] - (hx * sin + hy * cos),
+                    width: size,
+                    height,
+                    rotation,
+                    name,
+                }
+            }
+            GeneratedShape::Ellipse => ObstacleType::Ellipse {
+                x: c[0],
+                y: c[1],
+                radius_x: size / 2.0,
+                radius_y: size / 2.0 * self.rng.random_range(0.3..1.0),
+                rotation: self.rng.random_range(0.0..180.0),
+                name,
+            },
+            GeneratedShape::Polygon => {
+                let vertices = self.rng.random_range(5..10);
+                let offset = self.rng.random_range(0.0..2.0 * PI);
+                let points = (0..vertices)
+                    .map(|i| {
+                        let angle = offset + 2.0 * PI * i as f64 / vertices as f64;
+                        let r = size / 2.0 * self.rng.random_range(0.6..1.0);
+                        [c[0] + r * angle.cos(), c[1] + r * angle.sin()]
+                    })
+                    .collect();
+                ObstacleType::Polygon {
+                    points,
+                    holes: Vec::new(),
+                    name,
+                }
+            }
+            GeneratedShape::Line => {
+                // A fence or edging, straight or with a bend
+                let angle = self.rng.random_range(0.0..PI);
+                let (dx, dy) = (size / 2.0 * angle.cos(), size / 2.0 * angle.sin());
+                let mut points = vec![[c[0] - dx, c[1] - dy], [c[0] + dx, c[1] + dy]];
+                if self.rng.random_bool(0.5) {
+                    let bend = angle + self.rng.random_range(-PI / 2.0..PI / 2.0);
+                    points[1] = c;
+                    points.push([c[0] + size / 2.0 * bend.cos(), c[1] + size / 2.0 * bend.sin()]);
+                }
+                ObstacleType::Line {
+                    points,
+                    width: (size * 0.05).max(2.0 * self.check_cell_size).max(0.05),
+                    name,
+                }
+            }
+        }
+    }
+
+    fn generate_obstacles(&mut self) -> Result<(), Box<dyn Error>> {
+        let clearance = self.config.clearance;
+        let mut attempts = 0;
+        while self.placed.len() < self.config.num_obstacles {
+            if attempts >= MAX_ATTEMPTS * self.config.num_obstacles.max(1) {
+                return Err(format!(
+                    "Could only place {} of {} obstacles, use fewer or smaller obstacles, a smaller clearance or a larger lawn",
+                    self.placed.len(),
+                    self.config.num_obstacles
+                )
+                .into());
+            }
+            attempts += 1;
+
+            let shape = self.random_shape();
+            let size = self.random_size();
+            let center = self.random_point();
+            let name = format!("{} {}", shape.name().replace('_', " "), self.placed.len() + 1);
+            let obstacle = round_obstacle(self.make_obstacle(shape, size, center, name));
+            // The circle around the bounding box encloses the obstacle
+            let Some([x0, y0, x1, y1]) = obstacle.bounds() else {
+                continue;
+            };
+            let radius = [[x0, y0], [x1, y0], [x0, y1], [x1, y1]]
+                .iter()
+                .map(|p| ((p[0] - center[0]).powi(2) + (p[1] - center[1]).powi(2)).sqrt())
+                .fold(0.0, f64::max);
+            let fits = self.on_lawn(center, radius + clearance)
+                && self.placed.iter().all(|other| {
+                    let d = ((center[0] - other.center[0]).powi(2) + (center[1] - other.center[1]).powi(2)).sqrt();
+                    d >= radius + other.radius + clearance
+                })
+                && self
+                    .walls
+                    .iter()
+                    .all(|(a, b)| segment_distance(center, *a, *b) >= radius + clearance);
+            if !fits {
+                continue;
+            }
+
+            self.map.obstacles.push(obstacle);
+            if self.is_connected()? {
+                self.placed.push(Placed { center, radius });
+            } else {
+                self.map.obstacles.pop();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Generate a random map. Fails if the obstacles or passages do not fit on the lawn while
+/// keeping the whole lawn reachable.
+pub fn generate_map(config: &GeneratorConfig) -> Result<MapFile, Box<dyn Error>> {
+    if !(config.width > 0.0 && config.height > 0.0) {
+        return Err(format!("Lawn size must be positive, got {} x {}", config.width, config.height).into());
+    }
+    if config.cutter_radius <= 0.0 || config.cutter_radius.is_nan() {
+        return Err(format!("Cutter radius must be positive, got {}", config.cutter_radius).into());
+    }
+    if !(config.min_size > 0.0 && config.max_size >= config.min_size) {
+        return Err(format!(
+            "Obstacle sizes must satisfy 0 < min size <= max size, got {} and {}",
+            config.min_size, config.max_size
+        )
+        .into());
+    }
+    if config.clearance < 0.0 {
+        return Err(format!("Clearance must not be negative, got {}", config.clearance).into());
+    }
+    if config.obstacle_types.iter().map(|(_, weight)| weight).sum::<f64>() <= 0.0 {
+        return Err("At least one obstacle type with a positive weight must be given".into());
+    }
+
+    let mut generator = Generator {
+        config,
+        rng: StdRng::seed_from_u64(config.seed),
+        map: MapFile {
+            name: config.name.clone().unwrap_or_else(|| format!("Generated map {}", config.seed)),
+            description: Some(format!(
+                "Generated with seed {}: {} obstacles of size {}-{} ({}), clearance {}, {} narrow passages, boundary complexity {}, cutter radius {}",
+                config.seed,
+                config.num_obstacles,
+                config.min_size,
+                config.max_size,
+                match config.size_distribution {
+                    SizeDistribution::Uniform => "uniform",
+                    SizeDistribution::LogUniform => "log-uniform",
+                },
+                config.clearance,
+                config.narrow_passages,
+                config.boundary_complexity,
+                config.cutter_radius
+            )),
+            grid: Some(GridConfig {
+                width: Some(config.width),
+                height: Some(config.height),
+            }),
+            boundary: None,
+            units: None,
+            include: Vec::new(),
+            templates: BTreeMap::new(),
+            obstacles: Vec::new(),
+            no_cut_zones: Vec::new(),
+            virtual_fences: Vec::new(),
+            terrain: Vec::new(),
+        },
+        check_cell_size: (config.cutter_radius / 3.0).max(config.width.max(config.height) / MAX_CHECK_CELLS),
+        placed: Vec::new(),
+        walls: Vec::new(),
+    };
+    if !generator.is_connected()? {
+        return Err("The cutter does not fit on the lawn".into());
+    }
+    generator.generate_boundary()?;
+    generator.generate_passages()?;
+    generator.generate_obstacles()?;
+    Ok(generator.map)
+}
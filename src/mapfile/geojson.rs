@@ -0,0 +1,304 @@
+//! Import of maps given as GeoJSON (RFC 7946) with WGS84 longitude/latitude coordinates.
+//!
+//! The coordinates are projected to a local plane in meters using an equirectangular projection
+//! around the center of the map. This is accurate to well below a centimeter over the size of a
+//! garden. The x-axis points east and the y-axis north and the origin is placed at the south-west
+//! corner of the lawn (or of all features if no lawn is designated).
+//!
+//! Features are mapped as follows
+//! - The polygon feature with the property `"role": "lawn"` is the lawn area. Everything outside
+//!   it is treated as an obstacle and any holes in it become polygon obstacles.
+//! - Other `Polygon` and `MultiPolygon` features become polygon obstacles, keeping their holes
+//! - `LineString` and `MultiLineString` features become line obstacles with the width in meters
+//!   given by the `width` property (default `DEFAULT_LINE_WIDTH`)
+//! - `Point` and `MultiPoint` features become circle obstacles with the radius in meters given by
+//!   the `radius` property (default `DEFAULT_POINT_RADIUS`)
+//! - Features with `"role": "no-cut"` become no-cut zones and features with
+//!   `"role": "virtual-fence"` virtual fences instead of obstacles
+//! - Features with `"role": "ignore"` are skipped
+//!
+//! The `name` property of a feature is kept as the obstacle name.
+
+use super::{GridConfig, MapFile, ObstacleType};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Mean earth radius in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Width in meters of line obstacles without a `width` property
+pub const DEFAULT_LINE_WIDTH: f64 = 0.1;
+
+/// Radius in meters of point obstacles without a `radius` property
+pub const DEFAULT_POINT_RADIUS: f64 = 0.5;
+
+/// A geometry with coordinates still given as longitude/latitude
+enum Geometry {
+    Polygon(Vec<Vec<[f64; 2]>>),
+    Line(Vec<[f64; 2]>),
+    Point([f64; 2]),
+}
+
+struct Feature {
+    name: Option<String>,
+    role: Option<String>,
+    width: Option<f64>,
+    radius: Option<f64>,
+    geometries: Vec<Geometry>,
+}
+
+fn parse_position(value: &Value) -> Result<[f64; 2], Box<dyn Error>> {
+    let lon = value.get(0).and_then(Value::as_f64);
+    let lat = value.get(1).and_then(Value::as_f64);
+    match (lon, lat) {
+        (Some(lon), Some(lat)) => Ok([lon, lat]),
+        _ => Err(format!("Invalid GeoJSON position: {value}").into()),
+    }
+}
+
+fn parse_positions(value: &Value) -> Result<Vec<[f64; 2]>, Box<dyn Error>> {
+    value
+        .as_array()
+        .ok_or_else(|| format!("Expected a list of GeoJSON positions, got: {value}"))?
+        .iter()
+        .map(parse_position)
+        .collect()
+}
+
+/// Parse the rings of a polygon, the first ring is the outer boundary and the rest are holes.
+/// The closing position, which repeats the first, is removed since polygons auto-close.
+fn parse_rings(value: &Value) -> Result<Vec<Vec<[f64; 2]>>, Box<dyn Error>> {
+    let rings = value
+        .as_array()
+        .ok_or_else(|| format!("Expected a list of GeoJSON polygon rings, got: {value}"))?;
+    let mut result = Vec::new();
+    for ring in rings {
+        let mut points = parse_positions(ring)?;
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        if points.len() < 3 {
+            return Err("GeoJSON polygon ring must have at least 3 distinct positions".into());
+        }
+        result.push(points);
+    }
+    if result.is_empty() {
+        return Err("GeoJSON polygon without any rings".into());
+    }
+    Ok(result)
+}
+
+fn parse_geometry(value: &Value, geometries: &mut Vec<Geometry>) -> Result<(), Box<dyn Error>> {
+    let coordinates = &value["coordinates"];
+    match value["type"].as_str().unwrap_or_default() {
+        "Polygon" => geometries.push(Geometry::Polygon(parse_rings(coordinates)?)),
+        "MultiPolygon" => {
+            for polygon in coordinates.as_array().into_iter().flatten() {
+                geometries.push(Geometry::Polygon(parse_rings(polygon)?));
+            }
+        }
+        "LineString" => geometries.push(Geometry::Line(parse_positions(coordinates)?)),
+        "MultiLineString" => {
+            for line in coordinates.as_array().into_iter().flatten() {
+                geometries.push(Geometry::Line(parse_positions(line)?));
+            }
+        }
+        "Point" => geometries.push(Geometry::Point(parse_position(coordinates)?)),
+        "MultiPoint" => {
+            for point in parse_positions(coordinates)? {
+                geometries.push(Geometry::Point(point));
+            }
+        }
+        "GeometryCollection" => {
+            for geometry in value["geometries"].as_array().into_iter().flatten() {
+                parse_geometry(geometry, geometries)?;
+            }
+        }
+        other => return Err(format!("Unsupported GeoJSON geometry type '{other}'").into()),
+    }
+    Ok(())
+}
+
+fn parse_feature(value: &Value) -> Result<Feature, Box<dyn Error>> {
+    let properties = &value["properties"];
+    let mut geometries = Vec::new();
+    if !value["geometry"].is_null() {
+        parse_geometry(&value["geometry"], &mut geometries)?;
+    }
+    Ok(Feature {
+        name: properties["name"].as_str().map(str::to_string),
+        role: properties["role"].as_str().map(str::to_lowercase),
+        width: properties["width"].as_f64(),
+        radius: properties["radius"].as_f64(),
+        geometries,
+    })
+}
+
+/// Equirectangular projection of longitude/latitude to meters around a reference point
+struct Projection {
+    lon0: f64,
+    lat0: f64,
+    cos_lat0: f64,
+    offset_x: f64,
+    offset_y: f64,
+}
+
+impl Projection {
+    fn project(&self, [lon, lat]: [f64; 2]) -> [f64; 2] {
+        [
+            EARTH_RADIUS * (lon - self.lon0).to_radians() * self.cos_lat0 - self.offset_x,
+            EARTH_RADIUS * (lat - self.lat0).to_radians() - self.offset_y,
+        ]
+    }
+
+    fn project_all(&self, points: &[[f64; 2]]) -> Vec<[f64; 2]> {
+        points.iter().map(|p| self.project(*p)).collect()
+    }
+}
+
+/// Parse a GeoJSON `FeatureCollection`, `Feature` or bare geometry into a map
+pub fn parse_geojson(content: &str, default_name: &str) -> Result<MapFile, Box<dyn Error>> {
+    let root: Value = serde_json::from_str(content)?;
+
+    let features = match root["type"].as_str().unwrap_or_default() {
+        "FeatureCollection" => root["features"]
+            .as_array()
+            .ok_or("GeoJSON FeatureCollection without a list of features")?
+            .iter()
+            .map(parse_feature)
+            .collect::<Result<Vec<_>, _>>()?,
+        "Feature" => vec![parse_feature(&root)?],
+        _ => {
+            let mut geometries = Vec::new();
+            parse_geometry(&root, &mut geometries)?;
+            vec![Feature {
+                name: None,
+                role: None,
+                width: None,
+                radius: None,
+                geometries,
+            }]
+        }
+    };
+    let features: Vec<Feature> = features
+        .into_iter()
+        .filter(|f| f.role.as_deref() != Some("ignore"))
+        .collect();
+
+    let mut lawns = features.iter().filter(|f| f.role.as_deref() == Some("lawn"));
+    let lawn = lawns.next();
+    if lawns.next().is_some() {
+        return Err("More than one GeoJSON feature has the role 'lawn'".into());
+    }
+    let lawn_rings = match lawn.map(|f| f.geometries.as_slice()) {
+        None => None,
+        Some([Geometry::Polygon(rings)]) => Some(rings),
+        Some(_) => return Err("The GeoJSON lawn feature must be a single Polygon".into()),
+    };
+
+    // The extent of the map, in longitude/latitude, is the lawn or else all features
+    let extent: Vec<[f64; 2]> = match lawn_rings {
+        Some(rings) => rings[0].clone(),
+        None => features
+            .iter()
+            .flat_map(|f| f.geometries.iter())
+            .flat_map(|g| match g {
+                Geometry::Polygon(rings) => rings[0].clone(),
+                Geometry::Line(points) => points.clone(),
+                Geometry::Point(point) => vec![*point],
+            })
+            .collect(),
+    };
+    if extent.is_empty() {
+        return Err("GeoJSON map does not contain any features".into());
+    }
+
+    let (min_lon, max_lon, min_lat, max_lat) = extent.iter().fold(
+        (f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY),
+        |(x0, x1, y0, y1), p| (x0.min(p[0]), x1.max(p[0]), y0.min(p[1]), y1.max(p[1])),
+    );
+    let mut projection = Projection {
+        lon0: (min_lon + max_lon) / 2.0,
+        lat0: (min_lat + max_lat) / 2.0,
+        cos_lat0: ((min_lat + max_lat) / 2.0).to_radians().cos(),
+        offset_x: 0.0,
+        offset_y: 0.0,
+    };
+    [projection.offset_x, projection.offset_y] = projection.project([min_lon, min_lat]);
+    let [width, height] = projection.project([max_lon, max_lat]);
+
+    let mut obstacles = Vec::new();
+    let mut no_cut_zones = Vec::new();
+    let mut virtual_fences = Vec::new();
+    let mut boundary = None;
+    for feature in &features {
+        if let Some(rings) = lawn_rings
+            && feature.role.as_deref() == Some("lawn")
+        {
+            boundary = Some(projection.project_all(&rings[0]));
+            for (i, hole) in rings[1..].iter().enumerate() {
+                obstacles.push(ObstacleType::Polygon {
+                    points: projection.project_all(hole),
+                    holes: Vec::new(),
+                    name: Some(match &feature.name {
+                        Some(name) => format!("{name} hole {}", i + 1),
+                        None => format!("Hole {}", i + 1),
+                    }),
+                });
+            }
+            continue;
+        }
+        let target = match feature.role.as_deref() {
+            Some("no-cut") => &mut no_cut_zones,
+            Some("virtual-fence") => &mut virtual_fences,
+            _ => &mut obstacles,
+        };
+        for geometry in &feature.geometries {
+            target.push(match geometry {
+                Geometry::Polygon(rings) => ObstacleType::Polygon {
+                    points: projection.project_all(&rings[0]),
+                    holes: rings[1..].iter().map(|hole| projection.project_all(hole)).collect(),
+                    name: feature.name.clone(),
+                },
+                Geometry::Line(points) => ObstacleType::Line {
+                    points: projection.project_all(points),
+                    width: feature.width.unwrap_or(DEFAULT_LINE_WIDTH),
+                    name: feature.name.clone(),
+                },
+                Geometry::Point(point) => {
+                    let [x, y] = projection.project(*point);
+                    ObstacleType::Circle {
+                        x,
+                        y,
+                        radius: feature.radius.unwrap_or(DEFAULT_POINT_RADIUS),
+                        name: feature.name.clone(),
+                    }
+                }
+            });
+        }
+    }
+
+    Ok(MapFile {
+        name: root["name"]
+            .as_str()
+            .or(lawn.and_then(|f| f.name.as_deref()))
+            .unwrap_or(default_name)
+            .to_string(),
+        description: Some(format!(
+            "Imported from GeoJSON, south-west corner at lon {min_lon:.7}, lat {min_lat:.7}"
+        )),
+        grid: Some(GridConfig {
+            width: Some(width),
+            height: Some(height),
+        }),
+        boundary,
+        units: None,
+        include: Vec::new(),
+        templates: BTreeMap::new(),
+        obstacles,
+        no_cut_zones,
+        virtual_fences,
+        terrain: Vec::new(),
+    })
+}
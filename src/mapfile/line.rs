@@ -22,7 +22,7 @@ pub fn apply_line_obstacle(grid: &mut Grid, points: &[[f64; 2]], width: f64) {
 }
 
 /// Create a polygon representing a line segment with specified width
-fn create_line_polygon(
+pub(crate) fn create_line_polygon(
     start: [f64; 2],
     end: [f64; 2],
     half_width: f64,
@@ -1,8 +1,13 @@
 use crate::model::grid::Grid;
 
 /// Apply polygon obstacle using point-in-polygon algorithm
-#[allow(clippy::collapsible_if)]
 pub fn apply_polygon_obstacle(grid: &mut Grid, points: &[[f64; 2]]) {
+    apply_polygon_with_holes_obstacle(grid, points, &[]);
+}
+
+/// Apply polygon obstacle where cells inside any of the holes are left as they are
+#[allow(clippy::collapsible_if)]
+pub fn apply_polygon_with_holes_obstacle(grid: &mut Grid, points: &[[f64; 2]], holes: &[Vec<[f64; 2]>]) {
     if points.len() < 3 {
         eprintln!("Polygon must have at least 3 points");
         return;
@@ -20,10 +25,30 @@ pub fn apply_polygon_obstacle(grid: &mut Grid, points: &[[f64; 2]]) {
     // Check each cell in the bounding box
     for grid_y in grid_min_y..grid_max_y {
         for grid_x in grid_min_x..grid_max_x {
-            if is_cell_in_polygon(grid, grid_x, grid_y, points) {
-                if let Some(cell) = grid.get_cell_mut(grid_x, grid_y) {
-                    cell.set_as_obstacle();
-                }
+            if is_cell_in_polygon(grid, grid_x, grid_y, points)
+                && !holes
+                    .iter()
+                    .any(|hole| hole.len() >= 3 && is_cell_in_polygon(grid, grid_x, grid_y, hole))
+            {
+                grid.mark_zone_cell(grid_x, grid_y);
+            }
+        }
+    }
+}
+
+/// Mark all cells outside the boundary polygon as obstacles
+pub fn apply_boundary(grid: &mut Grid, points: &[[f64; 2]]) {
+    if points.len() < 3 {
+        eprintln!("Boundary polygon must have at least 3 points");
+        return;
+    }
+
+    for grid_y in 0..grid.cells_y {
+        for grid_x in 0..grid.cells_x {
+            if !is_cell_in_polygon(grid, grid_x, grid_y, points)
+                && let Some(cell) = grid.get_cell_mut(grid_x, grid_y)
+            {
+                cell.set_as_obstacle();
             }
         }
     }
@@ -42,7 +67,7 @@ fn is_cell_in_polygon(grid: &Grid, grid_x: usize, grid_y: usize, points: &[[f64;
 }
 
 /// Ray casting algorithm to determine if a point is inside a polygon
-fn point_in_polygon(x: f64, y: f64, polygon: &[[f64; 2]]) -> bool {
+pub(crate) fn point_in_polygon(x: f64, y: f64, polygon: &[[f64; 2]]) -> bool {
     let n = polygon.len();
     let mut inside = false;
 
@@ -0,0 +1,103 @@
+//! A map that is loaded, rasterised and indexed once and then shared by many simulations, e.g.
+//! all the runs of a `grunner` sweep that only differ in the seed or the cutter strategy
+
+use super::{MapFile, load_optional_mapfile, try_apply_mapfile_to_model, update_model_map_counts};
+use crate::config::SimConfig;
+use crate::model::{SimModel, grid::Grid, init_model};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::error::Error;
+
+/// The model values the rasterised grid depends on. Two simulations with the same key can share
+/// a prepared map. Floating point values are kept as bits so the key can be hashed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PreparedMapKey {
+    map_file_name: Option<String>,
+    svg_scale: u64,
+    raster_resolution: u64,
+    grid_width: u64,
+    grid_height: u64,
+    cell_size: u64,
+    radius: u64,
+    min_qnode_size: u64,
+    use_quad_tree: bool,
+}
+
+impl PreparedMapKey {
+    /// The key of a model set up by `init_model()`, before the map is loaded
+    fn from_model(model: &SimModel) -> Self {
+        PreparedMapKey {
+            map_file_name: model.map_file_name.clone(),
+            svg_scale: model.svg_scale.to_bits(),
+            raster_resolution: model.raster_resolution.to_bits(),
+            grid_width: model.grid_width.to_bits(),
+            grid_height: model.grid_height.to_bits(),
+            cell_size: model.cell_size.to_bits(),
+            radius: model.radius.to_bits(),
+            min_qnode_size: model.min_qnode_size.to_bits(),
+            use_quad_tree: model.use_quad_tree,
+        }
+    }
+
+    /// The key of the simulation with the given configuration
+    pub fn new(config: &SimConfig) -> Result<Self, Box<dyn Error>> {
+        Ok(PreparedMapKey::from_model(&init_map_model(config)?))
+    }
+}
+
+/// Setup a model for preparing the map. The random generator is only used for the start
+/// direction which the map does not depend on. Frames are turned off since `init_model()` would
+/// otherwise create the frames directory before the simulation itself is setup.
+fn init_map_model(config: &SimConfig) -> Result<SimModel, Box<dyn Error>> {
+    let mut config = config.clone();
+    config.generate_frames = false;
+    config.create_animation = false;
+    init_model(&config, &mut StdRng::seed_from_u64(0))
+}
+
+/// The loaded map file and the grid with all obstacles, zones and terrain marked and the
+/// quad-tree built, but nothing covered
+#[derive(Debug, Clone)]
+pub struct PreparedMap {
+    key: PreparedMapKey,
+    map: Option<MapFile>,
+    grid: Grid,
+}
+
+impl PreparedMap {
+    /// Load and rasterise the map of the configuration, the grid is empty if there is no map file
+    pub fn new(config: &SimConfig) -> Result<Self, Box<dyn Error>> {
+        let mut model = init_map_model(config)?;
+        let key = PreparedMapKey::from_model(&model);
+        load_optional_mapfile(&mut model)?;
+        let mut grid = Grid::new(model.grid_cells_x, model.grid_cells_y, model.cell_size);
+        grid.use_quad_tree = model.use_quad_tree;
+        model.grid = Some(grid);
+        try_apply_mapfile_to_model(&mut model)?;
+        Ok(PreparedMap {
+            key,
+            map: model.map_file,
+            grid: model.grid.expect("Grid was created above"),
+        })
+    }
+
+    pub fn key(&self) -> &PreparedMapKey {
+        &self.key
+    }
+
+    /// Use a copy of the map and grid for a model set up by `init_model()` with a configuration
+    /// that has the same key
+    pub fn apply_to_model(&self, model: &mut SimModel) -> Result<(), Box<dyn Error>> {
+        if PreparedMapKey::from_model(model) != self.key {
+            return Err("The prepared map was made for another map, grid or cutter size".into());
+        }
+        if let Some(map) = &self.map {
+            super::set_model_map(model, map.clone())?;
+        }
+        model.grid = Some(self.grid.clone());
+        if model.map_file.is_some() {
+            update_model_map_counts(model);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use crate::model::grid::Grid;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Pixels with an alpha value below this are always outside the area
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// Default gray level below which a pixel is an obstacle
+pub const DEFAULT_THRESHOLD: u8 = 128;
+
+/// What a pixel in a raster map represents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RasterClass {
+    Lawn,
+    Obstacle,
+    Outside,
+}
+
+/// How the pixels of a raster map are classified
+pub struct RasterClassifier {
+    threshold: u8,
+    invert: bool,
+    palette: Vec<([u8; 3], RasterClass)>,
+}
+
+impl RasterClassifier {
+    pub fn new(
+        threshold: Option<u8>,
+        invert: bool,
+        palette: Option<&BTreeMap<String, RasterClass>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let palette = palette
+            .into_iter()
+            .flatten()
+            .map(|(color, class)| Ok((parse_color(color)?, *class)))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        Ok(RasterClassifier {
+            threshold: threshold.unwrap_or(DEFAULT_THRESHOLD),
+            invert,
+            palette,
+        })
+    }
+
+    /// Classify a pixel. With a palette the class of the nearest palette color is used, otherwise
+    /// dark pixels are obstacles and light pixels lawn (the reverse if inverted).
+    fn classify(&self, [r, g, b, a]: [u8; 4]) -> RasterClass {
+        if a < ALPHA_THRESHOLD {
+            return RasterClass::Outside;
+        }
+        if !self.palette.is_empty() {
+            let distance = |c: &[u8; 3]| {
+                [r, g, b]
+                    .iter()
+                    .zip(c)
+                    .map(|(p, q)| (*p as i32 - *q as i32).pow(2))
+                    .sum::<i32>()
+            };
+            return self
+                .palette
+                .iter()
+                .min_by_key(|(color, _)| distance(color))
+                .map(|(_, class)| *class)
+                .unwrap_or(RasterClass::Lawn);
+        }
+        let gray = (0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64).round() as u8;
+        if (gray < self.threshold) != self.invert {
+            RasterClass::Obstacle
+        } else {
+            RasterClass::Lawn
+        }
+    }
+}
+
+/// Parse a color given as "#rrggbb"
+fn parse_color(color: &str) -> Result<[u8; 3], Box<dyn Error>> {
+    let hex = color.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("Invalid palette color '{color}', expected \"#rrggbb\"").into());
+    }
+    let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16)
+            .map_err(|_| format!("Invalid palette color '{color}', expected \"#rrggbb\""))
+    };
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Size in pixels of a raster image, used to check the image before the simulation starts
+pub fn raster_dimensions(file: &str) -> Result<(u32, u32), Box<dyn Error>> {
+    ::image::image_dimensions(file).map_err(|e| format!("Failed to read raster map '{file}': {e}").into())
+}
+
+/// Resample a raster image onto the grid. The lower left corner of the image is placed at `x`, `y`
+/// and each pixel is `resolution` units wide. Each cell takes the class of the pixel at its center
+/// and cells classified as obstacle or outside the area are marked as obstacles.
+pub fn apply_raster_obstacle(
+    grid: &mut Grid,
+    file: &str,
+    x: f64,
+    y: f64,
+    resolution: f64,
+    classifier: &RasterClassifier,
+) -> Result<(), Box<dyn Error>> {
+    let img = ::image::open(file)
+        .map_err(|e| format!("Failed to read raster map '{file}': {e}"))?
+        .into_rgba8();
+    let (width, height) = img.dimensions();
+    let cell_size = grid.get_cell_size();
+
+    // Only the cells covered by the image need to be checked
+    let grid_min_x = grid.world_coordinate_to_grid_x(x.max(0.0));
+    let grid_min_y = grid.world_coordinate_to_grid_y(y.max(0.0));
+    let grid_max_x = (grid.world_coordinate_to_grid_x(x + width as f64 * resolution) + 1).min(grid.cells_x);
+    let grid_max_y = (grid.world_coordinate_to_grid_y(y + height as f64 * resolution) + 1).min(grid.cells_y);
+
+    for grid_y in grid_min_y..grid_max_y {
+        for grid_x in grid_min_x..grid_max_x {
+            let (cell_x, cell_y) = grid.grid_to_world_coordinate(grid_x, grid_y);
+            let px = ((cell_x + cell_size / 2.0 - x) / resolution).floor();
+            let py = ((cell_y + cell_size / 2.0 - y) / resolution).floor();
+            if px < 0.0 || py < 0.0 || px >= width as f64 || py >= height as f64 {
+                continue;
+            }
+            // Image rows go from the top down
+            let pixel = img.get_pixel(px as u32, height - 1 - py as u32).0;
+            if classifier.classify(pixel) != RasterClass::Lawn {
+                grid.mark_zone_cell(grid_x, grid_y);
+            }
+        }
+    }
+    Ok(())
+}
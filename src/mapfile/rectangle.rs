@@ -11,9 +11,7 @@ pub fn apply_rectangle_obstacle(grid: &mut Grid, x: f64, y: f64, width: f64, hei
     // Mark cells as obstacles
     for dx in 0..grid_width {
         for dy in 0..grid_height {
-            if let Some(cell) = grid.get_cell_mut(grid_x + dx, grid_y + dy) {
-                cell.set_as_obstacle();
-            }
+            grid.mark_zone_cell(grid_x + dx, grid_y + dy);
         }
     }
 }
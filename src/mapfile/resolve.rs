@@ -0,0 +1,507 @@
+//! Resolution of YAML map files that use `units`, `include` and `templates` into a plain map with
+//! all lengths in meters and only the basic obstacle types, so the rasterization does not need
+//! to know about them.
+//!
+//! - `units` gives the unit of all lengths in the file, one of m (default), cm, mm, ft and in
+//! - `include` lists other map files, relative to the including file, whose obstacles are added
+//!   after the obstacles of the including file and whose templates can be instantiated. The
+//!   `grid` and `boundary` of an included file are used when the including file has none.
+//! - `templates` are named lists of obstacles. An `instance` obstacle places a copy of a template
+//!   that is first scaled, then rotated counter-clockwise around the origin and last translated.
+
+use super::{GridConfig, GroupOperation, MapFile, ObstacleType, TerrainRegion, raster};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Length units that can be used in a map file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    M,
+    Cm,
+    Mm,
+    Ft,
+    In,
+}
+
+impl Units {
+    /// Length of the unit in meters
+    pub fn meters(&self) -> f64 {
+        match self {
+            Units::M => 1.0,
+            Units::Cm => 0.01,
+            Units::Mm => 0.001,
+            Units::Ft => 0.3048,
+            Units::In => 0.0254,
+        }
+    }
+}
+
+/// Scale, then rotate counter-clockwise by `rotate` degrees around the origin and then translate
+#[derive(Debug, Clone, Copy)]
+struct Transform {
+    scale: f64,
+    rotate: f64,
+    translate: [f64; 2],
+}
+
+impl Transform {
+    fn scaling(scale: f64) -> Self {
+        Transform {
+            scale,
+            rotate: 0.0,
+            translate: [0.0, 0.0],
+        }
+    }
+
+    fn point(&self, [x, y]: [f64; 2]) -> [f64; 2] {
+        let (sin, cos) = self.rotate.to_radians().sin_cos();
+        let (x, y) = (x * self.scale, y * self.scale);
+        [
+            x * cos - y * sin + self.translate[0],
+            x * sin + y * cos + self.translate[1],
+        ]
+    }
+
+    fn points(&self, points: &[[f64; 2]]) -> Vec<[f64; 2]> {
+        points.iter().map(|p| self.point(*p)).collect()
+    }
+
+    fn is_rotated(&self) -> bool {
+        self.rotate.rem_euclid(360.0) != 0.0
+    }
+
+    /// Apply the transform to an obstacle. Rectangles that are rotated become rotated rectangles.
+    fn obstacle(&self, obstacle: &ObstacleType) -> Result<ObstacleType, Box<dyn Error>> {
+        let s = self.scale;
+        Ok(match obstacle.clone() {
+            ObstacleType::Rectangle {
+                x,
+                y,
+                width,
+                height,
+                name,
+            } => {
+                let [x, y] = self.point([x, y]);
+                if self.is_rotated() {
+                    ObstacleType::RotatedRectangle {
+                        x,
+                        y,
+                        width: width * s,
+                        height: height * s,
+                        rotation: self.rotate,
+                        name,
+                    }
+                } else {
+                    ObstacleType::Rectangle {
+                        x,
+                        y,
+                        width: width * s,
+                        height: height * s,
+                        name,
+                    }
+                }
+            }
+            ObstacleType::Circle { x, y, radius, name } => {
+                let [x, y] = self.point([x, y]);
+                ObstacleType::Circle {
+                    x,
+                    y,
+                    radius: radius * s,
+                    name,
+                }
+            }
+            ObstacleType::Polygon { points, holes, name } => ObstacleType::Polygon {
+                points: self.points(&points),
+                holes: holes.iter().map(|hole| self.points(hole)).collect(),
+                name,
+            },
+            ObstacleType::Line { points, width, name } => ObstacleType::Line {
+                points: self.points(&points),
+                width: width * s,
+                name,
+            },
+            ObstacleType::Ellipse {
+                x,
+                y,
+                radius_x,
+                radius_y,
+                rotation,
+                name,
+            } => {
+                let [x, y] = self.point([x, y]);
+                ObstacleType::Ellipse {
+                    x,
+                    y,
+                    radius_x: radius_x * s,
+                    radius_y: radius_y * s,
+                    rotation: rotation + self.rotate,
+                    name,
+                }
+            }
+            ObstacleType::RotatedRectangle {
+                x,
+                y,
+                width,
+                height,
+                rotation,
+                name,
+            } => {
+                let [x, y] = self.point([x, y]);
+                ObstacleType::RotatedRectangle {
+                    x,
+                    y,
+                    width: width * s,
+                    height: height * s,
+                    rotation: rotation + self.rotate,
+                    name,
+                }
+            }
+            ObstacleType::RoundedRectangle {
+                x,
+                y,
+                width,
+                height,
+                corner_radius,
+                rotation,
+                name,
+            } => {
+                let [x, y] = self.point([x, y]);
+                ObstacleType::RoundedRectangle {
+                    x,
+                    y,
+                    width: width * s,
+                    height: height * s,
+                    corner_radius: corner_radius * s,
+                    rotation: rotation + self.rotate,
+                    name,
+                }
+            }
+            ObstacleType::Ring {
+                x,
+                y,
+                inner_radius,
+                outer_radius,
+                start_angle,
+                end_angle,
+                name,
+            } => {
+                let [x, y] = self.point([x, y]);
+                ObstacleType::Ring {
+                    x,
+                    y,
+                    inner_radius: inner_radius * s,
+                    outer_radius: outer_radius * s,
+                    start_angle: start_angle.map(|a| a + self.rotate),
+                    end_angle: end_angle.map(|a| a + self.rotate),
+                    name,
+                }
+            }
+            ObstacleType::Group {
+                operation,
+                children,
+                name,
+            } => ObstacleType::Group {
+                operation,
+                children: children
+                    .iter()
+                    .map(|child| self.obstacle(child))
+                    .collect::<Result<_, _>>()?,
+                name,
+            },
+            // Templates are kept in meters, so only the placement of an instance is transformed
+            ObstacleType::Instance {
+                template,
+                translate,
+                rotate,
+                scale,
+                name,
+            } => ObstacleType::Instance {
+                template,
+                translate: self.point(translate),
+                rotate: rotate + self.rotate,
+                scale,
+                name,
+            },
+            ObstacleType::Raster {
+                file,
+                x,
+                y,
+                resolution,
+                threshold,
+                invert,
+                palette,
+                name,
+            } => {
+                if self.is_rotated() {
+                    return Err(format!("Raster obstacle '{file}' can not be rotated").into());
+                }
+                let [x, y] = self.point([x, y]);
+                ObstacleType::Raster {
+                    file,
+                    x,
+                    y,
+                    resolution: resolution * s,
+                    threshold,
+                    invert,
+                    palette,
+                    name,
+                }
+            }
+        })
+    }
+}
+
+/// Name of an obstacle placed by an instance. A single obstacle takes the name of the instance.
+fn instance_child_name(instance: Option<&str>, child: Option<&str>, index: usize, count: usize) -> Option<String> {
+    match (instance, child) {
+        (Some(instance), _) if count == 1 => Some(instance.to_string()),
+        (Some(instance), Some(child)) => Some(format!("{instance} {child}")),
+        (Some(instance), None) => Some(format!("{instance} {}", index + 1)),
+        (None, child) => child.map(str::to_string),
+    }
+}
+
+fn set_name(obstacle: &mut ObstacleType, new_name: Option<String>) {
+    match obstacle {
+        ObstacleType::Rectangle { name, .. }
+        | ObstacleType::Circle { name, .. }
+        | ObstacleType::Polygon { name, .. }
+        | ObstacleType::Line { name, .. }
+        | ObstacleType::Ellipse { name, .. }
+        | ObstacleType::RotatedRectangle { name, .. }
+        | ObstacleType::RoundedRectangle { name, .. }
+        | ObstacleType::Ring { name, .. }
+        | ObstacleType::Group { name, .. }
+        | ObstacleType::Instance { name, .. }
+        | ObstacleType::Raster { name, .. } => *name = new_name,
+    }
+}
+
+fn get_name(obstacle: &ObstacleType) -> Option<&str> {
+    match obstacle {
+        ObstacleType::Rectangle { name, .. }
+        | ObstacleType::Circle { name, .. }
+        | ObstacleType::Polygon { name, .. }
+        | ObstacleType::Line { name, .. }
+        | ObstacleType::Ellipse { name, .. }
+        | ObstacleType::RotatedRectangle { name, .. }
+        | ObstacleType::RoundedRectangle { name, .. }
+        | ObstacleType::Ring { name, .. }
+        | ObstacleType::Group { name, .. }
+        | ObstacleType::Instance { name, .. }
+        | ObstacleType::Raster { name, .. } => name.as_deref(),
+    }
+}
+
+/// Replace template instances, also inside groups, by the transformed obstacles of the template.
+/// `active` holds the templates being expanded to find templates that instantiate themselves.
+fn expand(
+    obstacle: &ObstacleType,
+    templates: &BTreeMap<String, Vec<ObstacleType>>,
+    active: &mut Vec<String>,
+) -> Result<Vec<ObstacleType>, Box<dyn Error>> {
+    match obstacle {
+        ObstacleType::Instance {
+            template,
+            translate,
+            rotate,
+            scale,
+            name,
+        } => {
+            if *scale <= 0.0 || !scale.is_finite() {
+                return Err(format!("Instance of template '{template}' must have a positive scale, got {scale}").into());
+            }
+            if active.contains(template) {
+                return Err(format!("Template '{template}' instantiates itself").into());
+            }
+            let children = templates
+                .get(template)
+                .ok_or_else(|| format!("Unknown template '{template}'"))?;
+            let transform = Transform {
+                scale: *scale,
+                rotate: *rotate,
+                translate: *translate,
+            };
+            active.push(template.clone());
+            let mut expanded = Vec::new();
+            for child in children {
+                expanded.extend(expand(child, templates, active)?);
+            }
+            active.pop();
+            let count = expanded.len();
+            expanded
+                .iter()
+                .enumerate()
+                .map(|(index, child)| {
+                    let mut placed = transform.obstacle(child)?;
+                    set_name(
+                        &mut placed,
+                        instance_child_name(name.as_deref(), get_name(child), index, count),
+                    );
+                    Ok(placed)
+                })
+                .collect()
+        }
+        ObstacleType::Group {
+            operation,
+            children,
+            name,
+        } => {
+            let mut expanded = Vec::new();
+            for child in children {
+                expanded.extend(expand(child, templates, active)?);
+            }
+            Ok(vec![ObstacleType::Group {
+                operation: *operation,
+                children: expanded,
+                name: name.clone(),
+            }])
+        }
+        other => Ok(vec![other.clone()]),
+    }
+}
+
+/// Make raster file names relative to the map file and check the rasters already when loading
+fn resolve_raster_files(obstacle: &mut ObstacleType, map_dir: &Path) -> Result<(), Box<dyn Error>> {
+    match obstacle {
+        ObstacleType::Raster { file, resolution, .. } => {
+            *file = map_dir.join(&file).to_string_lossy().to_string();
+            if *resolution <= 0.0 {
+                return Err(format!("Raster map '{file}' must have a positive resolution").into());
+            }
+            raster::raster_dimensions(file)?;
+        }
+        ObstacleType::Group { children, .. } => {
+            for child in children {
+                resolve_raster_files(child, map_dir)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// A map file with all lengths in meters, its templates and, for each obstacle, the index of
+/// the obstacle in the file it was expanded from (`None` for obstacles from included files)
+pub(crate) struct ResolvedMap {
+    pub map: MapFile,
+    pub templates: BTreeMap<String, Vec<ObstacleType>>,
+    pub sources: Vec<Option<usize>>,
+}
+
+/// Parse the content of a YAML map file found at `path` and resolve units, includes and templates
+pub(crate) fn resolve_yaml_map(content: &str, path: &Path) -> Result<ResolvedMap, Box<dyn Error>> {
+    let mut stack = vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())];
+    resolve(content, path, &mut stack)
+}
+
+fn resolve(content: &str, path: &Path, stack: &mut Vec<PathBuf>) -> Result<ResolvedMap, Box<dyn Error>> {
+    let mut raw: MapFile = serde_yaml::from_str(content)?;
+    let map_dir = path.parent().unwrap_or(Path::new(""));
+    let to_meters = Transform::scaling(raw.units.map_or(1.0, |units| units.meters()));
+
+    // Templates and obstacles of included files are already in meters
+    let mut templates = BTreeMap::new();
+    let mut included = Vec::new();
+    let mut included_no_cut_zones = Vec::new();
+    let mut included_virtual_fences = Vec::new();
+    let mut terrain = Vec::new();
+    let mut included_grid = None;
+    let mut included_boundary = None;
+    for include in &raw.include {
+        let include_path = map_dir.join(include);
+        let canonical = include_path.canonicalize().unwrap_or_else(|_| include_path.clone());
+        if stack.contains(&canonical) {
+            return Err(format!("Map file '{}' includes itself", include_path.display()).into());
+        }
+        let include_content = fs::read_to_string(&include_path)
+            .map_err(|e| format!("Cannot read included map file '{}': {e}", include_path.display()))?;
+        stack.push(canonical);
+        let resolved = resolve(&include_content, &include_path, stack)
+            .map_err(|e| format!("In included map file '{}': {e}", include_path.display()))?;
+        stack.pop();
+        templates.extend(resolved.templates);
+        included.extend(resolved.map.obstacles);
+        included_no_cut_zones.extend(resolved.map.no_cut_zones);
+        included_virtual_fences.extend(resolved.map.virtual_fences);
+        terrain.extend(resolved.map.terrain);
+        // A later include replaces the grid and boundary of an earlier one, like templates
+        included_grid = resolved.map.grid.or(included_grid);
+        included_boundary = resolved.map.boundary.or(included_boundary);
+    }
+
+    // Templates of this file replace included templates with the same name
+    for (name, obstacles) in std::mem::take(&mut raw.templates) {
+        let mut converted = Vec::new();
+        for mut obstacle in obstacles {
+            resolve_raster_files(&mut obstacle, map_dir)?;
+            converted.push(to_meters.obstacle(&obstacle)?);
+        }
+        templates.insert(name, converted);
+    }
+
+    let mut obstacles = Vec::new();
+    let mut sources = Vec::new();
+    for (index, obstacle) in raw.obstacles.iter_mut().enumerate() {
+        resolve_raster_files(obstacle, map_dir)?;
+        let expanded = expand(&to_meters.obstacle(obstacle)?, &templates, &mut Vec::new())?;
+        sources.extend(std::iter::repeat_n(Some(index), expanded.len()));
+        obstacles.extend(expanded);
+    }
+    sources.extend(std::iter::repeat_n(None, included.len()));
+    obstacles.extend(included);
+
+    // No-cut zones and virtual fences can use the same shapes and templates as obstacles
+    let zones = |own: &mut Vec<ObstacleType>, included: Vec<ObstacleType>| -> Result<_, Box<dyn Error>> {
+        let mut resolved = Vec::new();
+        for zone in own.iter_mut() {
+            resolve_raster_files(zone, map_dir)?;
+            resolved.extend(expand(&to_meters.obstacle(zone)?, &templates, &mut Vec::new())?);
+        }
+        resolved.extend(included);
+        Ok(resolved)
+    };
+    let no_cut_zones = zones(&mut raw.no_cut_zones, included_no_cut_zones)?;
+    let virtual_fences = zones(&mut raw.virtual_fences, included_virtual_fences)?;
+
+    // Terrain regions of this file come after the included ones so they take precedence
+    for mut region in std::mem::take(&mut raw.terrain) {
+        resolve_raster_files(&mut region.area, map_dir)?;
+        let mut expanded = expand(&to_meters.obstacle(&region.area)?, &templates, &mut Vec::new())?;
+        let area = if expanded.len() == 1 {
+            expanded.remove(0)
+        } else {
+            ObstacleType::Group {
+                operation: GroupOperation::Union,
+                children: expanded,
+                name: None,
+            }
+        };
+        terrain.push(TerrainRegion { area, ..region });
+    }
+
+    let map = MapFile {
+        name: raw.name,
+        description: raw.description,
+        grid: raw
+            .grid
+            .map(|grid| GridConfig {
+                width: grid.width.map(|w| w * to_meters.scale),
+                height: grid.height.map(|h| h * to_meters.scale),
+            })
+            .or(included_grid),
+        boundary: raw.boundary.map(|boundary| to_meters.points(&boundary)).or(included_boundary),
+        units: None,
+        include: Vec::new(),
+        templates: BTreeMap::new(),
+        obstacles,
+        no_cut_zones,
+        virtual_fences,
+        terrain,
+    };
+    Ok(ResolvedMap { map, templates, sources })
+}
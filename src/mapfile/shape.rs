@@ -0,0 +1,352 @@
+use crate::{
+    mapfile::{GroupOperation, ObstacleType, line, polygon, raster},
+    model::grid::Grid,
+};
+
+/// Number of segments used to approximate a quarter circle in a rounded corner
+const CORNER_SEGMENTS: usize = 12;
+
+/// Rotate a point counter-clockwise by `angle` degrees around `origin`
+fn rotate(point: [f64; 2], origin: [f64; 2], angle: f64) -> [f64; 2] {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let dx = point[0] - origin[0];
+    let dy = point[1] - origin[1];
+    [origin[0] + dx * cos - dy * sin, origin[1] + dx * sin + dy * cos]
+}
+
+/// Corners of a rectangle with the lower left corner at `x`, `y` rotated counter-clockwise by
+/// `rotation` degrees around that corner
+pub(crate) fn rotated_rectangle_polygon(x: f64, y: f64, width: f64, height: f64, rotation: f64) -> Vec<[f64; 2]> {
+    [[x, y], [x + width, y], [x + width, y + height], [x, y + height]]
+        .into_iter()
+        .map(|p| rotate(p, [x, y], rotation))
+        .collect()
+}
+
+/// Outline of a rectangle with rounded corners. The corner radius is limited to half of the
+/// shortest side and the rectangle is rotated around its lower left corner as for a rotated
+/// rectangle.
+pub(crate) fn rounded_rectangle_polygon(
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    corner_radius: f64,
+    rotation: f64,
+) -> Vec<[f64; 2]> {
+    let r = corner_radius.clamp(0.0, width.min(height) / 2.0);
+    if r <= 0.0 {
+        return rotated_rectangle_polygon(x, y, width, height, rotation);
+    }
+    // Centers of the corner arcs, counter-clockwise from the lower left, with the start angle
+    let corners = [
+        ([x + r, y + r], 180.0),
+        ([x + width - r, y + r], 270.0),
+        ([x + width - r, y + height - r], 0.0),
+        ([x + r, y + height - r], 90.0),
+    ];
+    let mut points = Vec::with_capacity(4 * (CORNER_SEGMENTS + 1));
+    for (center, start) in corners {
+        for i in 0..=CORNER_SEGMENTS {
+            let angle = (start + 90.0 * i as f64 / CORNER_SEGMENTS as f64).to_radians();
+            let p = [center[0] + r * angle.cos(), center[1] + r * angle.sin()];
+            points.push(rotate(p, [x, y], rotation));
+        }
+    }
+    points
+}
+
+/// Check if a point is inside an ellipse with the given radii rotated counter-clockwise by
+/// `rotation` degrees around its center
+pub(crate) fn point_in_ellipse(
+    px: f64,
+    py: f64,
+    center_x: f64,
+    center_y: f64,
+    radius_x: f64,
+    radius_y: f64,
+    rotation: f64,
+) -> bool {
+    if radius_x <= 0.0 || radius_y <= 0.0 {
+        return false;
+    }
+    let [u, v] = rotate([px, py], [center_x, center_y], -rotation);
+    let dx = (u - center_x) / radius_x;
+    let dy = (v - center_y) / radius_y;
+    dx * dx + dy * dy <= 1.0
+}
+
+/// Half the width and height of the bounding box of a rotated ellipse
+pub(crate) fn ellipse_extent(radius_x: f64, radius_y: f64, rotation: f64) -> (f64, f64) {
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    (
+        ((radius_x * cos).powi(2) + (radius_y * sin).powi(2)).sqrt(),
+        ((radius_x * sin).powi(2) + (radius_y * cos).powi(2)).sqrt(),
+    )
+}
+
+/// Check if a point is inside a ring, or the part of it between the start and end angle. The
+/// angles are in degrees counter-clockwise from the x-axis and the arc goes counter-clockwise
+/// from the start to the end angle. Without angles the ring is closed.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn point_in_ring(
+    px: f64,
+    py: f64,
+    center_x: f64,
+    center_y: f64,
+    inner_radius: f64,
+    outer_radius: f64,
+    start_angle: Option<f64>,
+    end_angle: Option<f64>,
+) -> bool {
+    let dx = px - center_x;
+    let dy = py - center_y;
+    let distance = (dx * dx + dy * dy).sqrt();
+    if distance < inner_radius || distance > outer_radius {
+        return false;
+    }
+    let start = start_angle.unwrap_or(0.0);
+    let end = end_angle.unwrap_or(start + 360.0);
+    let sweep = end - start;
+    if sweep >= 360.0 {
+        return true;
+    }
+    let angle = dy.atan2(dx).to_degrees();
+    (angle - start).rem_euclid(360.0) <= sweep.rem_euclid(360.0)
+}
+
+/// Mark all cells within the bounding box where the test is true for the center of the cell or,
+/// if `corners` is set and as for circles, for any of its corners
+fn apply_shape_obstacle(grid: &mut Grid, bounds: [f64; 4], corners: bool, inside: impl Fn(f64, f64) -> bool) {
+    let [min_x, min_y, max_x, max_y] = bounds;
+    let grid_min_x = grid.world_coordinate_to_grid_x(min_x.max(0.0)).saturating_sub(1);
+    let grid_max_x = (grid.world_coordinate_to_grid_x(max_x.max(0.0)) + 1).min(grid.cells_x);
+    let grid_min_y = grid.world_coordinate_to_grid_y(min_y.max(0.0)).saturating_sub(1);
+    let grid_max_y = (grid.world_coordinate_to_grid_y(max_y.max(0.0)) + 1).min(grid.cells_y);
+    let cell_size = grid.get_cell_size();
+
+    for grid_y in grid_min_y..grid_max_y {
+        for grid_x in grid_min_x..grid_max_x {
+            let (x, y) = grid.grid_to_world_coordinate(grid_x, grid_y);
+            let hit = inside(x + cell_size / 2.0, y + cell_size / 2.0)
+                || corners
+                    && [(x, y), (x + cell_size, y), (x + cell_size, y + cell_size), (x, y + cell_size)]
+                    .iter()
+                    .any(|(cx, cy)| inside(*cx, *cy));
+            if hit {
+                grid.mark_zone_cell(grid_x, grid_y);
+            }
+        }
+    }
+}
+
+pub fn apply_ellipse_obstacle(
+    grid: &mut Grid,
+    center_x: f64,
+    center_y: f64,
+    radius_x: f64,
+    radius_y: f64,
+    rotation: f64,
+) {
+    let (extent_x, extent_y) = ellipse_extent(radius_x, radius_y, rotation);
+    let bounds = [center_x - extent_x, center_y - extent_y, center_x + extent_x, center_y + extent_y];
+    apply_shape_obstacle(grid, bounds, true, |px, py| {
+        point_in_ellipse(px, py, center_x, center_y, radius_x, radius_y, rotation)
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn apply_ring_obstacle(
+    grid: &mut Grid,
+    center_x: f64,
+    center_y: f64,
+    inner_radius: f64,
+    outer_radius: f64,
+    start_angle: Option<f64>,
+    end_angle: Option<f64>,
+) {
+    let bounds = [
+        center_x - outer_radius,
+        center_y - outer_radius,
+        center_x + outer_radius,
+        center_y + outer_radius,
+    ];
+    apply_shape_obstacle(grid, bounds, true, |px, py| {
+        point_in_ring(px, py, center_x, center_y, inner_radius, outer_radius, start_angle, end_angle)
+    });
+}
+
+pub fn apply_rotated_rectangle_obstacle(grid: &mut Grid, x: f64, y: f64, width: f64, height: f64, rotation: f64) {
+    polygon::apply_polygon_obstacle(grid, &rotated_rectangle_polygon(x, y, width, height, rotation));
+}
+
+pub fn apply_rounded_rectangle_obstacle(
+    grid: &mut Grid,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    corner_radius: f64,
+    rotation: f64,
+) {
+    polygon::apply_polygon_obstacle(
+        grid,
+        &rounded_rectangle_polygon(x, y, width, height, corner_radius, rotation),
+    );
+}
+
+/// Mark the cells with their center inside the combined shape of a group
+pub fn apply_group_obstacle(grid: &mut Grid, group: &ObstacleType) {
+    if let Some(bounds) = group.bounds() {
+        apply_shape_obstacle(grid, bounds, false, |px, py| group.contains(px, py));
+    }
+}
+
+/// Bounding box of a list of points extended by a margin
+fn points_bounds(points: &[[f64; 2]], margin: f64) -> Option<[f64; 4]> {
+    points
+        .iter()
+        .fold(None, |acc: Option<[f64; 4]>, p| {
+            let [x0, y0, x1, y1] = acc.unwrap_or([p[0], p[1], p[0], p[1]]);
+            Some([x0.min(p[0]), y0.min(p[1]), x1.max(p[0]), y1.max(p[1])])
+        })
+        .map(|[x0, y0, x1, y1]| [x0 - margin, y0 - margin, x1 + margin, y1 + margin])
+}
+
+impl ObstacleType {
+    /// Bounding box (min x, min y, max x, max y) of the obstacle
+    pub(crate) fn bounds(&self) -> Option<[f64; 4]> {
+        match self {
+            ObstacleType::Rectangle {
+                x, y, width, height, ..
+            } => Some([*x, *y, x + width, y + height]),
+            ObstacleType::Circle { x, y, radius, .. } => Some([x - radius, y - radius, x + radius, y + radius]),
+            ObstacleType::Polygon { points, .. } => points_bounds(points, 0.0),
+            ObstacleType::Line { points, width, .. } => points_bounds(points, width / 2.0),
+            ObstacleType::Ellipse {
+                x,
+                y,
+                radius_x,
+                radius_y,
+                rotation,
+                ..
+            } => {
+                let (extent_x, extent_y) = ellipse_extent(*radius_x, *radius_y, *rotation);
+                Some([x - extent_x, y - extent_y, x + extent_x, y + extent_y])
+            }
+            ObstacleType::RotatedRectangle {
+                x,
+                y,
+                width,
+                height,
+                rotation,
+                ..
+            }
+            | ObstacleType::RoundedRectangle {
+                x,
+                y,
+                width,
+                height,
+                rotation,
+                ..
+            } => points_bounds(&rotated_rectangle_polygon(*x, *y, *width, *height, *rotation), 0.0),
+            ObstacleType::Ring {
+                x, y, outer_radius, ..
+            } => Some([x - outer_radius, y - outer_radius, x + outer_radius, y + outer_radius]),
+            ObstacleType::Group {
+                operation, children, ..
+            } => {
+                let mut child_bounds = children.iter().filter_map(ObstacleType::bounds);
+                let first = child_bounds.next()?;
+                match operation {
+                    // Nothing outside the first child is part of a difference
+                    GroupOperation::Difference => Some(first),
+                    GroupOperation::Union => Some(child_bounds.fold(first, |[a0, b0, a1, b1], [x0, y0, x1, y1]| {
+                        [a0.min(x0), b0.min(y0), a1.max(x1), b1.max(y1)]
+                    })),
+                    GroupOperation::Intersection => {
+                        Some(child_bounds.fold(first, |[a0, b0, a1, b1], [x0, y0, x1, y1]| {
+                            [a0.max(x0), b0.max(y0), a1.min(x1), b1.min(y1)]
+                        }))
+                    }
+                }
+            }
+            // Instances are replaced by their template when the map is loaded
+            ObstacleType::Instance { .. } => None,
+            ObstacleType::Raster {
+                file, x, y, resolution, ..
+            } => raster::raster_dimensions(file)
+                .ok()
+                .map(|(w, h)| [*x, *y, x + w as f64 * resolution, y + h as f64 * resolution]),
+        }
+    }
+
+    /// True if the point is inside the obstacle, rasters are not considered
+    pub(crate) fn contains(&self, px: f64, py: f64) -> bool {
+        match self {
+            ObstacleType::Rectangle {
+                x, y, width, height, ..
+            } => px >= *x && px <= x + width && py >= *y && py <= y + height,
+            ObstacleType::Circle { x, y, radius, .. } => (px - x).powi(2) + (py - y).powi(2) <= radius * radius,
+            ObstacleType::Polygon { points, holes, .. } => {
+                points.len() >= 3
+                    && polygon::point_in_polygon(px, py, points)
+                    && !holes
+                        .iter()
+                        .any(|hole| hole.len() >= 3 && polygon::point_in_polygon(px, py, hole))
+            }
+            ObstacleType::Line { points, width, .. } => points
+                .windows(2)
+                .any(|w| polygon::point_in_polygon(px, py, &line::create_line_polygon(w[0], w[1], width / 2.0))),
+            ObstacleType::Ellipse {
+                x,
+                y,
+                radius_x,
+                radius_y,
+                rotation,
+                ..
+            } => point_in_ellipse(px, py, *x, *y, *radius_x, *radius_y, *rotation),
+            ObstacleType::RotatedRectangle {
+                x,
+                y,
+                width,
+                height,
+                rotation,
+                ..
+            } => polygon::point_in_polygon(px, py, &rotated_rectangle_polygon(*x, *y, *width, *height, *rotation)),
+            ObstacleType::RoundedRectangle {
+                x,
+                y,
+                width,
+                height,
+                corner_radius,
+                rotation,
+                ..
+            } => polygon::point_in_polygon(
+                px,
+                py,
+                &rounded_rectangle_polygon(*x, *y, *width, *height, *corner_radius, *rotation),
+            ),
+            ObstacleType::Ring {
+                x,
+                y,
+                inner_radius,
+                outer_radius,
+                start_angle,
+                end_angle,
+                ..
+            } => point_in_ring(px, py, *x, *y, *inner_radius, *outer_radius, *start_angle, *end_angle),
+            ObstacleType::Group {
+                operation, children, ..
+            } => match operation {
+                GroupOperation::Union => children.iter().any(|c| c.contains(px, py)),
+                GroupOperation::Intersection => !children.is_empty() && children.iter().all(|c| c.contains(px, py)),
+                GroupOperation::Difference => match children.split_first() {
+                    Some((first, rest)) => first.contains(px, py) && !rest.iter().any(|c| c.contains(px, py)),
+                    None => false,
+                },
+            },
+            ObstacleType::Instance { .. } | ObstacleType::Raster { .. } => false,
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! Import of obstacle maps drawn as SVG.
+//!
+//! The elements `rect`, `circle`, `ellipse`, `polygon`, `polyline`, `line` and `path` are
+//! converted to obstacles with all transforms, including those of enclosing groups, applied.
+//! Closed shapes become filled obstacles while `line`, `polyline` and open paths become line
+//! obstacles with the width given by their `stroke-width`. Curves are flattened to straight
+//! segments and the element `id` is used as obstacle name.
+//!
+//! SVG coordinates have the y-axis pointing down, they are flipped so that the drawing appears
+//! the same way in the simulation image. The grid size is taken from the `viewBox`, or the
+//! `width` and `height` of the drawing, multiplied by the scale.
+
+use super::{GridConfig, MapFile, ObstacleType};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::str::FromStr;
+use svgtypes::{PointsParser, SimplePathSegment, SimplifyingPathParser, Transform, ViewBox};
+
+/// Number of straight segments used for each curve segment in a path
+const CURVE_SEGMENTS: usize = 16;
+
+/// Number of points used when an ellipse, or a transformed circle, is converted to a polygon
+const ELLIPSE_POINTS: usize = 72;
+
+/// Stroke width used for lines without a `stroke-width`, in SVG units
+const DEFAULT_STROKE_WIDTH: f64 = 1.0;
+
+/// Elements whose content is not drawn directly
+const SKIPPED_ELEMENTS: [&str; 8] = [
+    "defs",
+    "clipPath",
+    "mask",
+    "marker",
+    "pattern",
+    "symbol",
+    "metadata",
+    "title",
+];
+
+/// Maps SVG user coordinates to simulation coordinates
+struct Mapping {
+    scale: f64,
+    min_x: f64,
+    min_y: f64,
+    height: f64,
+}
+
+impl Mapping {
+    fn point(&self, ts: &Transform, x: f64, y: f64) -> [f64; 2] {
+        let (x, y) = apply(ts, x, y);
+        [
+            (x - self.min_x) * self.scale,
+            (self.height - (y - self.min_y)) * self.scale,
+        ]
+    }
+
+    fn points(&self, ts: &Transform, points: &[(f64, f64)]) -> Vec<[f64; 2]> {
+        points.iter().map(|&(x, y)| self.point(ts, x, y)).collect()
+    }
+}
+
+fn apply(ts: &Transform, x: f64, y: f64) -> (f64, f64) {
+    (ts.a * x + ts.c * y + ts.e, ts.b * x + ts.d * y + ts.f)
+}
+
+/// The transform `parent` applied after `child`
+fn concat(parent: &Transform, child: &Transform) -> Transform {
+    Transform::new(
+        parent.a * child.a + parent.c * child.b,
+        parent.b * child.a + parent.d * child.b,
+        parent.a * child.c + parent.c * child.d,
+        parent.b * child.c + parent.d * child.d,
+        parent.a * child.e + parent.c * child.f + parent.e,
+        parent.b * child.e + parent.d * child.f + parent.f,
+    )
+}
+
+/// Uniform scale factor if the transform is a combination of translation and uniform scaling
+fn uniform_scale(ts: &Transform) -> Option<f64> {
+    (ts.b == 0.0 && ts.c == 0.0 && ts.a.abs() == ts.d.abs()).then_some(ts.a.abs())
+}
+
+/// Mean scale factor of the transform, used for stroke widths
+fn mean_scale(ts: &Transform) -> f64 {
+    (ts.a * ts.d - ts.b * ts.c).abs().sqrt()
+}
+
+/// A presentation attribute given either directly or in the `style` attribute
+fn style_value<'a>(node: &roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    let from_style = node.attribute("style").and_then(|style| {
+        style.split(';').find_map(|decl| {
+            let (key, value) = decl.split_once(':')?;
+            (key.trim() == name).then(|| value.trim())
+        })
+    });
+    from_style.or_else(|| node.attribute(name))
+}
+
+/// A length attribute in user units. Units other than user units are not converted.
+fn length(node: &roxmltree::Node, name: &str) -> Result<f64, Box<dyn Error>> {
+    match node.attribute(name) {
+        None => Ok(0.0),
+        Some(value) => Ok(svgtypes::Length::from_str(value)
+            .map_err(|e| format!("Invalid SVG attribute {name}=\"{value}\": {e}"))?
+            .number),
+    }
+}
+
+fn stroke_width(node: &roxmltree::Node) -> f64 {
+    // Inherited stroke widths are looked up in the ancestors
+    node.ancestors()
+        .find_map(|n| style_value(&n, "stroke-width"))
+        .and_then(|value| svgtypes::Length::from_str(value).ok())
+        .map_or(DEFAULT_STROKE_WIDTH, |l| l.number)
+}
+
+fn parse_points(node: &roxmltree::Node) -> Vec<(f64, f64)> {
+    PointsParser::from(node.attribute("points").unwrap_or_default()).collect()
+}
+
+fn ellipse_points(cx: f64, cy: f64, rx: f64, ry: f64) -> Vec<(f64, f64)> {
+    (0..ELLIPSE_POINTS)
+        .map(|i| {
+            let angle = i as f64 * std::f64::consts::TAU / ELLIPSE_POINTS as f64;
+            (cx + rx * angle.cos(), cy + ry * angle.sin())
+        })
+        .collect()
+}
+
+/// A flattened sub-path together with a flag for whether it is closed
+type SubPath = (Vec<(f64, f64)>, bool);
+
+/// Flatten the path data into sub-paths of points
+fn flatten_path(data: &str) -> Result<Vec<SubPath>, Box<dyn Error>> {
+    let mut subpaths = Vec::new();
+    let mut current: Vec<(f64, f64)> = Vec::new();
+    for segment in SimplifyingPathParser::from(data) {
+        let segment = segment.map_err(|e| format!("Invalid SVG path data: {e}"))?;
+        let last = current.last().copied().unwrap_or((0.0, 0.0));
+        match segment {
+            SimplePathSegment::MoveTo { x, y } => {
+                if current.len() > 1 {
+                    subpaths.push((std::mem::take(&mut current), false));
+                }
+                current = vec![(x, y)];
+            }
+            SimplePathSegment::LineTo { x, y } => current.push((x, y)),
+            SimplePathSegment::CurveTo {
+                x1,
+                y1,
+                x2,
+                y2,
+                x,
+                y,
+            } => {
+                for i in 1..=CURVE_SEGMENTS {
+                    let t = i as f64 / CURVE_SEGMENTS as f64;
+                    let u = 1.0 - t;
+                    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+                    current.push((
+                        a * last.0 + b * x1 + c * x2 + d * x,
+                        a * last.1 + b * y1 + c * y2 + d * y,
+                    ));
+                }
+            }
+            SimplePathSegment::Quadratic { x1, y1, x, y } => {
+                for i in 1..=CURVE_SEGMENTS {
+                    let t = i as f64 / CURVE_SEGMENTS as f64;
+                    let u = 1.0 - t;
+                    current.push((
+                        u * u * last.0 + 2.0 * u * t * x1 + t * t * x,
+                        u * u * last.1 + 2.0 * u * t * y1 + t * t * y,
+                    ));
+                }
+            }
+            SimplePathSegment::ClosePath => {
+                if current.first() == current.last() && current.len() > 1 {
+                    current.pop();
+                }
+                if current.len() > 1 {
+                    // The implicit MoveTo after a ClosePath starts at the same point
+                    let start = current[0];
+                    subpaths.push((std::mem::replace(&mut current, vec![start]), true));
+                }
+            }
+        }
+    }
+    if current.len() > 1 {
+        subpaths.push((current, false));
+    }
+    Ok(subpaths)
+}
+
+fn convert_element(
+    node: &roxmltree::Node,
+    ts: &Transform,
+    map: &Mapping,
+    obstacles: &mut Vec<ObstacleType>,
+) -> Result<(), Box<dyn Error>> {
+    let name = node.attribute("id").map(str::to_string);
+    let line_width = || stroke_width(node) * mean_scale(ts) * map.scale;
+
+    match node.tag_name().name() {
+        "rect" => {
+            let (x, y) = (length(node, "x")?, length(node, "y")?);
+            let (width, height) = (length(node, "width")?, length(node, "height")?);
+            if width <= 0.0 || height <= 0.0 {
+                return Ok(());
+            }
+            let corners = [(x, y), (x + width, y), (x + width, y + height), (x, y + height)];
+            if ts.b == 0.0 && ts.c == 0.0 {
+                // Still axis aligned, find the lower left corner after the y-axis is flipped
+                let points = map.points(ts, &corners);
+                let min_x = points.iter().map(|p| p[0]).fold(f64::INFINITY, f64::min);
+                let min_y = points.iter().map(|p| p[1]).fold(f64::INFINITY, f64::min);
+                obstacles.push(ObstacleType::Rectangle {
+                    x: min_x,
+                    y: min_y,
+                    width: width * ts.a.abs() * map.scale,
+                    height: height * ts.d.abs() * map.scale,
+                    name,
+                });
+            } else {
+                obstacles.push(ObstacleType::Polygon {
+                    points: map.points(ts, &corners),
+                    holes: Vec::new(),
+                    name,
+                });
+            }
+        }
+        "circle" | "ellipse" => {
+            let (cx, cy) = (length(node, "cx")?, length(node, "cy")?);
+            let (rx, ry) = if node.tag_name().name() == "circle" {
+                let r = length(node, "r")?;
+                (r, r)
+            } else {
+                (length(node, "rx")?, length(node, "ry")?)
+            };
+            if rx <= 0.0 || ry <= 0.0 {
+                return Ok(());
+            }
+            match uniform_scale(ts) {
+                Some(scale) if rx == ry => {
+                    let [x, y] = map.point(ts, cx, cy);
+                    obstacles.push(ObstacleType::Circle {
+                        x,
+                        y,
+                        radius: rx * scale * map.scale,
+                        name,
+                    });
+                }
+                _ => obstacles.push(ObstacleType::Polygon {
+                    points: map.points(ts, &ellipse_points(cx, cy, rx, ry)),
+                    holes: Vec::new(),
+                    name,
+                }),
+            }
+        }
+        "polygon" => {
+            let points = parse_points(node);
+            if points.len() >= 3 {
+                obstacles.push(ObstacleType::Polygon {
+                    points: map.points(ts, &points),
+                    holes: Vec::new(),
+                    name,
+                });
+            }
+        }
+        "polyline" => {
+            let points = parse_points(node);
+            if points.len() >= 2 {
+                obstacles.push(ObstacleType::Line {
+                    points: map.points(ts, &points),
+                    width: line_width(),
+                    name,
+                });
+            }
+        }
+        "line" => {
+            let points = [
+                (length(node, "x1")?, length(node, "y1")?),
+                (length(node, "x2")?, length(node, "y2")?),
+            ];
+            obstacles.push(ObstacleType::Line {
+                points: map.points(ts, &points),
+                width: line_width(),
+                name,
+            });
+        }
+        "path" => {
+            for (points, closed) in flatten_path(node.attribute("d").unwrap_or_default())? {
+                obstacles.push(if closed && points.len() >= 3 {
+                    ObstacleType::Polygon {
+                        points: map.points(ts, &points),
+                        holes: Vec::new(),
+                        name: name.clone(),
+                    }
+                } else {
+                    ObstacleType::Line {
+                        points: map.points(ts, &points),
+                        width: line_width(),
+                        name: name.clone(),
+                    }
+                });
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn convert_children(
+    parent: &roxmltree::Node,
+    parent_ts: &Transform,
+    map: &Mapping,
+    obstacles: &mut Vec<ObstacleType>,
+) -> Result<(), Box<dyn Error>> {
+    for node in parent.children().filter(|n| n.is_element()) {
+        let tag = node.tag_name().name();
+        if SKIPPED_ELEMENTS.contains(&tag) || style_value(&node, "display") == Some("none") {
+            continue;
+        }
+        let ts = match node.attribute("transform") {
+            Some(value) => concat(
+                parent_ts,
+                &Transform::from_str(value)
+                    .map_err(|e| format!("Invalid SVG transform \"{value}\": {e}"))?,
+            ),
+            None => *parent_ts,
+        };
+        if tag == "g" || tag == "a" || tag == "switch" {
+            convert_children(&node, &ts, map, obstacles)?;
+        } else {
+            convert_element(&node, &ts, map, obstacles)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse an SVG drawing into a map where `scale` is the number of simulation units per SVG unit
+pub fn parse_svg(content: &str, default_name: &str, scale: f64) -> Result<MapFile, Box<dyn Error>> {
+    if scale <= 0.0 {
+        return Err(format!("SVG scale must be positive, got {scale}").into());
+    }
+    let doc = roxmltree::Document::parse(content)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "svg" {
+        return Err("Not an SVG file, the root element must be <svg>".into());
+    }
+
+    let (min_x, min_y, width, height) = match root.attribute("viewBox") {
+        Some(value) => {
+            let vb = ViewBox::from_str(value)
+                .map_err(|e| format!("Invalid SVG viewBox \"{value}\": {e}"))?;
+            (vb.x, vb.y, vb.w, vb.h)
+        }
+        None => (0.0, 0.0, length(&root, "width")?, length(&root, "height")?),
+    };
+    if width <= 0.0 || height <= 0.0 {
+        return Err("SVG drawing must have a viewBox or a width and height".into());
+    }
+
+    let map = Mapping {
+        scale,
+        min_x,
+        min_y,
+        height,
+    };
+    let mut obstacles = Vec::new();
+    convert_children(&root, &Transform::default(), &map, &mut obstacles)?;
+
+    let title = root
+        .children()
+        .find(|n| n.has_tag_name("title"))
+        .and_then(|n| n.text())
+        .map(str::trim);
+    let description = root
+        .children()
+        .find(|n| n.has_tag_name("desc"))
+        .and_then(|n| n.text())
+        .map(|s| s.trim().to_string());
+
+    Ok(MapFile {
+        name: title.unwrap_or(default_name).to_string(),
+        description,
+        grid: Some(GridConfig {
+            width: Some(width * scale),
+            height: Some(height * scale),
+        }),
+        boundary: None,
+        units: None,
+        include: Vec::new(),
+        templates: BTreeMap::new(),
+        obstacles,
+        no_cut_zones: Vec::new(),
+        virtual_fences: Vec::new(),
+        terrain: Vec::new(),
+    })
+}
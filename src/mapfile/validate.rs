@@ -0,0 +1,1050 @@
+//! Validation of map files.
+//!
+//! Checks the map against the schema, validates the geometry of each obstacle, checks that
+//! obstacles are inside the grid and do not overlap, and finds the parts of the lawn that a cutter
+//! of the given radius cannot reach. Problems are reported as diagnostics with the name of the
+//! obstacle and, for YAML maps, the line in the file where it starts.
+
+use super::{GroupOperation, MapFile, MapImportOptions, ObstacleType, apply_obstacles_to_grid, load_map_file_with_sources, polygon, raster};
+use crate::config::SimConfig;
+use crate::model::cuttertype::CutterType;
+use crate::model::grid::{Cell, Grid};
+use crate::model::terrain::Terrain;
+use colored::Colorize;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+/// Maximum number of cells along each side of the grid used for the overlap and reachability
+/// checks. The cell size of the simulation is used unless the grid would be larger.
+const MAX_CHECK_CELLS: f64 = 2000.0;
+
+/// Maximum number of unreachable regions listed individually
+const MAX_LISTED_REGIONS: usize = 10;
+
+const TOP_LEVEL_KEYS: [&str; 11] = [
+    "name",
+    "description",
+    "grid",
+    "boundary",
+    "units",
+    "include",
+    "templates",
+    "obstacles",
+    "no_cut_zones",
+    "virtual_fences",
+    "terrain",
+];
+const GRID_KEYS: [&str; 2] = ["width", "height"];
+const TERRAIN_KEYS: [&str; 5] = ["name", "slope", "slope_direction", "friction", "area"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "{}", "error".color(colored::Color::Red).bold()),
+            Severity::Warning => write!(f, "{}", "warning".color(colored::Color::Yellow).bold()),
+            Severity::Note => write!(f, "{}", "note".color(colored::Color::Cyan).bold()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Line in the map file, only known for YAML maps
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// The result of validating a map file
+#[derive(Debug, Clone)]
+pub struct ValidationReport {
+    pub file: String,
+    pub map_name: Option<String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ValidationReport {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity == severity).count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    /// Print all diagnostics, in the order of the map file, followed by a summary
+    pub fn print(&self) {
+        for d in &self.diagnostics {
+            match d.line {
+                Some(line) => println!("{}: {}:{}: {}", d.severity, self.file, line, d.message),
+                None => println!("{}: {}: {}", d.severity, self.file, d.message),
+            }
+        }
+        let (errors, warnings) = (self.count(Severity::Error), self.count(Severity::Warning));
+        let summary = format!(
+            "Map {}({}): {} error{}, {} warning{}",
+            self.map_name
+                .as_ref()
+                .map(|n| format!("\"{n}\" "))
+                .unwrap_or_default(),
+            self.file,
+            errors,
+            if errors == 1 { "" } else { "s" },
+            warnings,
+            if warnings == 1 { "" } else { "s" },
+        );
+        let color = if errors > 0 {
+            colored::Color::Red
+        } else if warnings > 0 {
+            colored::Color::Yellow
+        } else {
+            colored::Color::Green
+        };
+        println!("{}", summary.color(color).bold());
+    }
+}
+
+struct Checker<'a> {
+    diagnostics: Vec<Diagnostic>,
+    /// Line of each obstacle, obstacles from included files have no line
+    obstacle_lines: &'a [Option<usize>],
+}
+
+impl Checker<'_> {
+    fn report(&mut self, severity: Severity, line: Option<usize>, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line,
+            message,
+        });
+    }
+
+    fn obstacle(&mut self, severity: Severity, index: usize, obstacle: &ObstacleType, message: &str) {
+        let line = self.obstacle_lines.get(index).copied().flatten();
+        self.report(severity, line, format!("{}: {message}", label(index, obstacle)));
+    }
+}
+
+fn obstacle_name(obstacle: &ObstacleType) -> (&'static str, Option<&str>) {
+    match obstacle {
+        ObstacleType::Rectangle { name, .. } => ("rectangle", name.as_deref()),
+        ObstacleType::Circle { name, .. } => ("circle", name.as_deref()),
+        ObstacleType::Polygon { name, .. } => ("polygon", name.as_deref()),
+        ObstacleType::Line { name, .. } => ("line", name.as_deref()),
+        ObstacleType::Ellipse { name, .. } => ("ellipse", name.as_deref()),
+        ObstacleType::RotatedRectangle { name, .. } => ("rotated rectangle", name.as_deref()),
+        ObstacleType::RoundedRectangle { name, .. } => ("rounded rectangle", name.as_deref()),
+        ObstacleType::Ring { name, .. } => ("ring", name.as_deref()),
+        ObstacleType::Group { name, .. } => ("group", name.as_deref()),
+        ObstacleType::Instance { name, .. } => ("instance", name.as_deref()),
+        ObstacleType::Raster { name, .. } => ("raster", name.as_deref()),
+    }
+}
+
+/// Obstacles are numbered from 1 in the order of the map file
+fn label(index: usize, obstacle: &ObstacleType) -> String {
+    match obstacle_name(obstacle) {
+        (kind, Some(name)) => format!("{kind} #{} \"{name}\"", index + 1),
+        (kind, None) => format!("{kind} #{}", index + 1),
+    }
+}
+
+/// Find the line where each item of the top level `obstacles` list starts. Only block style
+/// lists are recognized, for other styles no lines are returned.
+fn find_obstacle_lines(content: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut in_obstacles = false;
+    let mut item_indent = None;
+    for (number, text) in content.lines().enumerate() {
+        let trimmed = text.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = text.len() - trimmed.len();
+        if indent == 0 && !trimmed.starts_with('-') {
+            in_obstacles = trimmed.trim_end() == "obstacles:";
+            continue;
+        }
+        if in_obstacles && trimmed.starts_with('-') {
+            if item_indent.is_none() {
+                item_indent = Some(indent);
+            }
+            if item_indent == Some(indent) {
+                lines.push(number + 1);
+            }
+        }
+    }
+    lines
+}
+
+/// Report keys that are not part of the schema, e.g. misspelled optional keys which are
+/// otherwise silently ignored
+fn check_unknown_keys(checker: &mut Checker, content: &str) {
+    let Ok(root) = serde_yaml::from_str::<serde_yaml::Value>(content) else {
+        return;
+    };
+    let unknown = |value: &serde_yaml::Value, known: &[&str]| -> Vec<String> {
+        value
+            .as_mapping()
+            .into_iter()
+            .flat_map(|m| m.keys())
+            .filter_map(|k| k.as_str())
+            .filter(|k| !known.contains(k))
+            .map(str::to_string)
+            .collect()
+    };
+    for key in unknown(&root, &TOP_LEVEL_KEYS) {
+        checker.report(Severity::Warning, None, format!("unknown top level key '{key}' is ignored"));
+    }
+    for key in unknown(&root["grid"], &GRID_KEYS) {
+        checker.report(Severity::Warning, None, format!("unknown grid key '{key}' is ignored"));
+    }
+    for (index, obstacle) in root["obstacles"].as_sequence().into_iter().flatten().enumerate() {
+        let Some(known) = known_obstacle_keys(obstacle["type"].as_str()) else {
+            continue;
+        };
+        let line = checker.obstacle_lines.get(index).copied().flatten();
+        for key in unknown(obstacle, known) {
+            checker.report(
+                Severity::Warning,
+                line,
+                format!("obstacle #{}: unknown key '{key}' is ignored", index + 1),
+            );
+        }
+    }
+    for (list, kind) in [("no_cut_zones", "no-cut zone"), ("virtual_fences", "virtual fence")] {
+        for (index, zone) in root[list].as_sequence().into_iter().flatten().enumerate() {
+            let Some(known) = known_obstacle_keys(zone["type"].as_str()) else {
+                continue;
+            };
+            for key in unknown(zone, known) {
+                checker.report(
+                    Severity::Warning,
+                    None,
+                    format!("{kind} #{}: unknown key '{key}' is ignored", index + 1),
+                );
+            }
+        }
+    }
+    for (index, region) in root["terrain"].as_sequence().into_iter().flatten().enumerate() {
+        let mut keys = unknown(region, &TERRAIN_KEYS);
+        if let Some(known) = known_obstacle_keys(region["area"]["type"].as_str()) {
+            keys.extend(unknown(&region["area"], known).into_iter().map(|key| format!("area.{key}")));
+        }
+        for key in keys {
+            checker.report(
+                Severity::Warning,
+                None,
+                format!("terrain region #{}: unknown key '{key}' is ignored", index + 1),
+            );
+        }
+    }
+}
+
+/// The keys of each obstacle type, None for unknown types which are reported when parsing
+fn known_obstacle_keys(obstacle_type: Option<&str>) -> Option<&'static [&'static str]> {
+    Some(match obstacle_type {
+        Some("rectangle") => &["type", "x", "y", "width", "height", "name"],
+        Some("circle") => &["type", "x", "y", "radius", "name"],
+        Some("polygon") => &["type", "points", "holes", "name"],
+        Some("group") => &["type", "operation", "children", "name"],
+        Some("line") => &["type", "points", "width", "name"],
+        Some("ellipse") => &["type", "x", "y", "radius_x", "radius_y", "rotation", "name"],
+        Some("rotated_rectangle") => &["type", "x", "y", "width", "height", "rotation", "name"],
+        Some("rounded_rectangle") => &["type", "x", "y", "width", "height", "corner_radius", "rotation", "name"],
+        Some("ring") | Some("arc") => {
+            &["type", "x", "y", "inner_radius", "outer_radius", "start_angle", "end_angle", "name"]
+        }
+        Some("instance") => &["type", "template", "translate", "rotate", "scale", "name"],
+        Some("raster") => &["type", "file", "x", "y", "resolution", "threshold", "invert", "palette", "name"],
+        _ => return None,
+    })
+}
+
+fn polygon_area(points: &[[f64; 2]]) -> f64 {
+    let n = points.len();
+    (0..n)
+        .map(|i| {
+            let (p, q) = (points[i], points[(i + 1) % n]);
+            p[0] * q[1] - q[0] * p[1]
+        })
+        .sum::<f64>()
+        .abs()
+        / 2.0
+}
+
+fn cross(o: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+}
+
+/// True if the two segments cross or touch
+fn segments_intersect(p1: [f64; 2], p2: [f64; 2], q1: [f64; 2], q2: [f64; 2]) -> bool {
+    let on_segment = |p: [f64; 2], q: [f64; 2], r: [f64; 2]| {
+        r[0] >= p[0].min(q[0]) && r[0] <= p[0].max(q[0]) && r[1] >= p[1].min(q[1]) && r[1] <= p[1].max(q[1])
+    };
+    let (d1, d2) = (cross(q1, q2, p1), cross(q1, q2, p2));
+    let (d3, d4) = (cross(p1, p2, q1), cross(p1, p2, q2));
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0)) && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0)) {
+        return true;
+    }
+    (d1 == 0.0 && on_segment(q1, q2, p1))
+        || (d2 == 0.0 && on_segment(q1, q2, p2))
+        || (d3 == 0.0 && on_segment(p1, p2, q1))
+        || (d4 == 0.0 && on_segment(p1, p2, q2))
+}
+
+/// The first pair of non-adjacent edges of the closed polygon that intersect
+fn find_self_intersection(points: &[[f64; 2]]) -> Option<(usize, usize)> {
+    let n = points.len();
+    for i in 0..n {
+        for j in i + 2..n {
+            if i == 0 && j == n - 1 {
+                continue; // Adjacent through the closing edge
+            }
+            if segments_intersect(points[i], points[(i + 1) % n], points[j], points[(j + 1) % n]) {
+                return Some((i, j));
+            }
+        }
+    }
+    None
+}
+
+/// Check a polygon outline, used both for polygon obstacles and the map boundary
+fn check_outline(points: &[[f64; 2]]) -> Vec<(Severity, String)> {
+    let mut problems = Vec::new();
+    if points.iter().flatten().any(|v| !v.is_finite()) {
+        problems.push((Severity::Error, "coordinates must be finite numbers".to_string()));
+        return problems;
+    }
+    if points.len() < 3 {
+        problems.push((Severity::Error, format!("polygon needs at least 3 points, got {}", points.len())));
+        return problems;
+    }
+    if points.len() > 3 && points.first() == points.last() {
+        problems.push((
+            Severity::Warning,
+            "last point repeats the first, polygons are closed automatically".to_string(),
+        ));
+    }
+    if points.windows(2).any(|w| w[0] == w[1]) {
+        problems.push((Severity::Warning, "polygon has repeated consecutive points".to_string()));
+    }
+    // Repeated points only give zero length edges which would otherwise be reported as crossings
+    let mut points = points.to_vec();
+    points.dedup();
+    if points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    // A self-intersecting polygon such as a bowtie can have a zero signed area, so the crossing
+    // is checked first to report the actual problem
+    if let Some((i, j)) = find_self_intersection(&points) {
+        let n = points.len();
+        problems.push((
+            Severity::Error,
+            format!(
+                "polygon intersects itself, edge {:?}-{:?} crosses edge {:?}-{:?}",
+                points[i],
+                points[(i + 1) % n],
+                points[j],
+                points[(j + 1) % n]
+            ),
+        ));
+    } else if points.len() < 3 || polygon_area(&points) <= f64::EPSILON {
+        problems.push((Severity::Error, "polygon is degenerate, its area is zero".to_string()));
+    }
+    problems
+}
+
+fn check_obstacle(checker: &mut Checker, index: usize, obstacle: &ObstacleType, cell_size: f64) {
+    for (severity, message) in obstacle_problems(obstacle, cell_size) {
+        checker.obstacle(severity, index, obstacle, &message);
+    }
+}
+
+/// Problems with the geometry of a single obstacle, the children of a group are checked as well
+fn obstacle_problems(obstacle: &ObstacleType, cell_size: f64) -> Vec<(Severity, String)> {
+    use Severity::{Error, Warning};
+    let mut problems: Vec<(Severity, String)> = Vec::new();
+    match obstacle {
+        ObstacleType::Rectangle {
+            x, y, width, height, ..
+        } => {
+            if ![x, y, width, height].iter().all(|v| v.is_finite()) {
+                problems.push((Error, "values must be finite numbers".to_string()));
+            } else if *width <= 0.0 || *height <= 0.0 {
+                problems.push((Error, format!("width and height must be positive, got {width} x {height}")));
+            }
+        }
+        ObstacleType::Circle { x, y, radius, .. } => {
+            if ![x, y, radius].iter().all(|v| v.is_finite()) {
+                problems.push((Error, "values must be finite numbers".to_string()));
+            } else if *radius <= 0.0 {
+                problems.push((Error, format!("radius must be positive, got {radius}")));
+            }
+        }
+        ObstacleType::Polygon { points, holes, .. } => {
+            problems.extend(check_outline(points));
+            for (i, hole) in holes.iter().enumerate() {
+                problems.extend(
+                    check_outline(hole)
+                        .into_iter()
+                        .map(|(severity, message)| (severity, format!("hole {}: {message}", i + 1))),
+                );
+                let outside = points.len() >= 3
+                    && hole
+                        .iter()
+                        .any(|p| p.iter().all(|v| v.is_finite()) && !polygon::point_in_polygon(p[0], p[1], points));
+                if outside {
+                    problems.push((Warning, format!("hole {} is not completely inside the polygon", i + 1)));
+                }
+            }
+        }
+        ObstacleType::Group {
+            operation, children, ..
+        } => {
+            if children.is_empty() {
+                problems.push((Error, "group has no children".to_string()));
+            } else if *operation != GroupOperation::Union && children.len() < 2 {
+                problems.push((
+                    Warning,
+                    format!("{} of a single child is the child itself", format!("{operation:?}").to_lowercase()),
+                ));
+            }
+            for (i, child) in children.iter().enumerate() {
+                if matches!(child, ObstacleType::Raster { .. }) {
+                    problems.push((Error, format!("child #{}: raster obstacles can not be part of a group", i + 1)));
+                    continue;
+                }
+                let (kind, _) = obstacle_name(child);
+                problems.extend(
+                    obstacle_problems(child, cell_size)
+                        .into_iter()
+                        .map(|(severity, message)| (severity, format!("child #{} ({kind}): {message}", i + 1))),
+                );
+            }
+        }
+        ObstacleType::Line { points, width, .. } => {
+            if points.iter().flatten().any(|v| !v.is_finite()) || !width.is_finite() {
+                problems.push((Error, "values must be finite numbers".to_string()));
+            } else if points.len() < 2 {
+                problems.push((Error, format!("line needs at least 2 points, got {}", points.len())));
+            } else if *width <= 0.0 {
+                problems.push((Error, format!("line has zero width ({width}) and blocks nothing")));
+            } else if *width < cell_size {
+                problems.push((
+                    Warning,
+                    format!("line width {width} is less than the cell size {cell_size}, the cutter may pass through gaps"),
+                ));
+            }
+            if points.windows(2).any(|w| w[0] == w[1]) {
+                problems.push((Warning, "line has a segment of zero length".to_string()));
+            }
+        }
+        ObstacleType::Ellipse {
+            x,
+            y,
+            radius_x,
+            radius_y,
+            rotation,
+            ..
+        } => {
+            if ![x, y, radius_x, radius_y, rotation].iter().all(|v| v.is_finite()) {
+                problems.push((Error, "values must be finite numbers".to_string()));
+            } else if *radius_x <= 0.0 || *radius_y <= 0.0 {
+                problems.push((Error, format!("radii must be positive, got {radius_x} x {radius_y}")));
+            }
+        }
+        ObstacleType::RotatedRectangle {
+            x,
+            y,
+            width,
+            height,
+            rotation,
+            ..
+        } => {
+            if ![x, y, width, height, rotation].iter().all(|v| v.is_finite()) {
+                problems.push((Error, "values must be finite numbers".to_string()));
+            } else if *width <= 0.0 || *height <= 0.0 {
+                problems.push((Error, format!("width and height must be positive, got {width} x {height}")));
+            }
+        }
+        ObstacleType::RoundedRectangle {
+            x,
+            y,
+            width,
+            height,
+            corner_radius,
+            rotation,
+            ..
+        } => {
+            if ![x, y, width, height, corner_radius, rotation].iter().all(|v| v.is_finite()) {
+                problems.push((Error, "values must be finite numbers".to_string()));
+            } else if *width <= 0.0 || *height <= 0.0 {
+                problems.push((Error, format!("width and height must be positive, got {width} x {height}")));
+            } else if *corner_radius < 0.0 {
+                problems.push((Error, format!("corner radius must not be negative, got {corner_radius}")));
+            } else if *corner_radius > width.min(*height) / 2.0 {
+                problems.push((
+                    Warning,
+                    format!(
+                        "corner radius {corner_radius} is larger than half the shortest side and is reduced to {}",
+                        width.min(*height) / 2.0
+                    ),
+                ));
+            }
+        }
+        ObstacleType::Ring {
+            x,
+            y,
+            inner_radius,
+            outer_radius,
+            start_angle,
+            end_angle,
+            ..
+        } => {
+            let angles = [start_angle, end_angle].into_iter().flatten();
+            if ![x, y, inner_radius, outer_radius].iter().all(|v| v.is_finite())
+                || angles.clone().any(|v| !v.is_finite())
+            {
+                problems.push((Error, "values must be finite numbers".to_string()));
+            } else if *inner_radius < 0.0 || *outer_radius <= *inner_radius {
+                problems.push((
+                    Error,
+                    format!("radii must satisfy 0 <= inner radius < outer radius, got {inner_radius} and {outer_radius}"),
+                ));
+            } else if outer_radius - inner_radius < cell_size {
+                problems.push((
+                    Warning,
+                    format!(
+                        "ring width {} is less than the cell size {cell_size}, the cutter may pass through gaps",
+                        outer_radius - inner_radius
+                    ),
+                ));
+            }
+            if start_angle.is_some() != end_angle.is_some() {
+                problems.push((
+                    Warning,
+                    "only one of start_angle and end_angle is given, the ring is closed".to_string(),
+                ));
+            }
+        }
+        ObstacleType::Raster {
+            threshold,
+            invert,
+            palette,
+            ..
+        } => {
+            if let Err(e) = raster::RasterClassifier::new(*threshold, *invert, palette.as_ref()) {
+                problems.push((Error, e.to_string()));
+            }
+        }
+        // Instances are replaced by their template when the map is loaded
+        ObstacleType::Instance { .. } => {}
+    }
+    problems
+}
+
+fn check_bounds(checker: &mut Checker, index: usize, obstacle: &ObstacleType, width: f64, height: f64) {
+    let Some([x0, y0, x1, y1]) = obstacle.bounds() else {
+        return;
+    };
+    if !(x0.is_finite() && y0.is_finite() && x1.is_finite() && y1.is_finite()) {
+        return;
+    }
+    if x1 <= 0.0 || y1 <= 0.0 || x0 >= width || y0 >= height {
+        checker.obstacle(
+            Severity::Error,
+            index,
+            obstacle,
+            &format!(
+                "obstacle is completely outside the {width} x {height} grid, its extent is ({x0:.2}, {y0:.2})-({x1:.2}, {y1:.2})"
+            ),
+        );
+    } else if x0 < 0.0 || y0 < 0.0 || x1 > width || y1 > height {
+        checker.obstacle(
+            Severity::Warning,
+            index,
+            obstacle,
+            &format!(
+                "obstacle extends outside the {width} x {height} grid, its extent is ({x0:.2}, {y0:.2})-({x1:.2}, {y1:.2})"
+            ),
+        );
+    }
+}
+
+/// A grid of flags, one per cell, stored row by row
+struct Mask {
+    cells_x: usize,
+    cells_y: usize,
+    cell_size: f64,
+    data: Vec<bool>,
+}
+
+impl Mask {
+    fn center(&self, i: usize) -> (f64, f64) {
+        (
+            ((i % self.cells_x) as f64 + 0.5) * self.cell_size,
+            ((i / self.cells_x) as f64 + 0.5) * self.cell_size,
+        )
+    }
+}
+
+/// Report pairs of obstacles that cover the same cells
+fn check_overlaps(checker: &mut Checker, map: &MapFile, cells_x: usize, cells_y: usize, cell_size: f64) {
+    let mut owner = vec![0usize; cells_x * cells_y];
+    let mut overlaps: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    for (index, obstacle) in map.obstacles.iter().enumerate() {
+        if matches!(obstacle, ObstacleType::Raster { .. }) {
+            continue;
+        }
+        let Some([x0, y0, x1, y1]) = obstacle.bounds() else {
+            continue;
+        };
+        if ![x0, y0, x1, y1].iter().all(|v| v.is_finite()) {
+            continue;
+        }
+        let to_cell = |v: f64, max: usize| ((v / cell_size).floor().max(0.0) as usize).min(max);
+        for gy in to_cell(y0, cells_y)..to_cell(y1, cells_y - 1) + 1 {
+            for gx in to_cell(x0, cells_x)..to_cell(x1, cells_x - 1) + 1 {
+                let (px, py) = ((gx as f64 + 0.5) * cell_size, (gy as f64 + 0.5) * cell_size);
+                if obstacle.contains(px, py) {
+                    let cell = &mut owner[gy * cells_x + gx];
+                    if *cell == 0 {
+                        *cell = index + 1;
+                    } else if *cell != index + 1 {
+                        *overlaps.entry((*cell - 1, index)).or_default() += 1;
+                    }
+                }
+            }
+        }
+    }
+    for ((first, second), count) in overlaps {
+        let area = count as f64 * cell_size * cell_size;
+        let message = format!(
+            "overlaps {} by about {area:.3} square units",
+            label(first, &map.obstacles[first])
+        );
+        checker.obstacle(Severity::Warning, second, &map.obstacles[second], &message);
+    }
+}
+
+/// Squared euclidean distance transform along one line (Felzenszwalb & Huttenlocher)
+fn distance_transform_1d(f: &[f64], d: &mut [f64]) {
+    let n = f.len();
+    let mut v = vec![0usize; n];
+    let mut z = vec![0.0; n + 1];
+    let mut k = 0;
+    z[0] = f64::NEG_INFINITY;
+    z[1] = f64::INFINITY;
+    for q in 1..n {
+        loop {
+            let p = v[k];
+            let s = ((f[q] + (q * q) as f64) - (f[p] + (p * p) as f64)) / (2.0 * (q as f64 - p as f64));
+            if s <= z[k] && k > 0 {
+                k -= 1;
+            } else {
+                k += 1;
+                v[k] = q;
+                z[k] = s;
+                z[k + 1] = f64::INFINITY;
+                break;
+            }
+        }
+    }
+    k = 0;
+    for (q, dq) in d.iter_mut().enumerate() {
+        while z[k + 1] < q as f64 {
+            k += 1;
+        }
+        let p = v[k];
+        *dq = (q as f64 - p as f64).powi(2) + f[p];
+    }
+}
+
+/// Euclidean distance, in cells, from each cell to the nearest cell that is set in the mask
+fn distance_transform(mask: &Mask) -> Vec<f64> {
+    const FAR: f64 = 1e20;
+    let (w, h) = (mask.cells_x, mask.cells_y);
+    let mut dist: Vec<f64> = mask.data.iter().map(|&set| if set { 0.0 } else { FAR }).collect();
+    let mut column = vec![0.0; h];
+    let mut result = vec![0.0; h.max(w)];
+    for x in 0..w {
+        for (y, c) in column.iter_mut().enumerate() {
+            *c = dist[y * w + x];
+        }
+        distance_transform_1d(&column, &mut result[..h]);
+        for y in 0..h {
+            dist[y * w + x] = result[y];
+        }
+    }
+    for y in 0..h {
+        let row = dist[y * w..(y + 1) * w].to_vec();
+        distance_transform_1d(&row, &mut result[..w]);
+        dist[y * w..(y + 1) * w].copy_from_slice(&result[..w]);
+    }
+    dist.into_iter().map(f64::sqrt).collect()
+}
+
+/// Connected components of the set cells. Returns the component of each cell (usize::MAX if not
+/// set) and the size of each component.
+fn components(mask: &Mask, diagonal: bool) -> (Vec<usize>, Vec<usize>) {
+    let (w, h) = (mask.cells_x as isize, mask.cells_y as isize);
+    let mut component = vec![usize::MAX; mask.data.len()];
+    let mut sizes = Vec::new();
+    let neighbours: &[(isize, isize)] = if diagonal {
+        &[(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)]
+    } else {
+        &[(1, 0), (-1, 0), (0, 1), (0, -1)]
+    };
+    for start in 0..mask.data.len() {
+        if !mask.data[start] || component[start] != usize::MAX {
+            continue;
+        }
+        let id = sizes.len();
+        let mut size = 0;
+        let mut queue = VecDeque::from([start]);
+        component[start] = id;
+        while let Some(i) = queue.pop_front() {
+            size += 1;
+            let (x, y) = ((i as isize) % w, (i as isize) / w);
+            for (dx, dy) in neighbours {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx >= 0 && ny >= 0 && nx < w && ny < h {
+                    let j = (ny * w + nx) as usize;
+                    if mask.data[j] && component[j] == usize::MAX {
+                        component[j] = id;
+                        queue.push_back(j);
+                    }
+                }
+            }
+        }
+        sizes.push(size);
+    }
+    (component, sizes)
+}
+
+/// The cells of a map that are not lawn, i.e. obstacles, no-cut zones and virtual fences, and the
+/// cells where the center of a cutter with the given radius can be without touching an obstacle,
+/// a virtual fence or the grid edge
+fn cutter_positions(
+    map: &MapFile,
+    radius: f64,
+    width: f64,
+    height: f64,
+    cell_size: f64,
+) -> Result<(Mask, Mask), Box<dyn Error>> {
+    let cells_x = (width / cell_size).ceil() as usize;
+    let cells_y = (height / cell_size).ceil() as usize;
+    let mut grid = Grid::new(cells_x, cells_y, cell_size);
+    apply_obstacles_to_grid(&mut grid, map)?;
+    let mask = |include: fn(&Cell) -> bool| Mask {
+        cells_x,
+        cells_y,
+        cell_size,
+        data: (0..cells_x * cells_y)
+            .map(|i| grid.get_cell(i % cells_x, i / cells_x).is_some_and(include))
+            .collect(),
+    };
+    let blocking = mask(Cell::is_blocking);
+    let not_lawn = mask(|c| c.is_blocking() || c.is_no_cut());
+
+    let clearance = distance_transform(&blocking);
+    let allowed = Mask {
+        data: (0..blocking.data.len())
+            .map(|i| {
+                let (x, y) = blocking.center(i);
+                clearance[i] * cell_size >= radius
+                    && x >= radius
+                    && y >= radius
+                    && x <= width - radius
+                    && y <= height - radius
+            })
+            .collect(),
+        cells_x,
+        cells_y,
+        cell_size,
+    };
+    Ok((not_lawn, allowed))
+}
+
+/// Number of separate areas a cutter with the given radius can move around in. On a map where
+/// the cutter can get everywhere there is exactly one.
+pub(crate) fn count_cutter_areas(
+    map: &MapFile,
+    radius: f64,
+    width: f64,
+    height: f64,
+    cell_size: f64,
+) -> Result<usize, Box<dyn Error>> {
+    let (_, allowed) = cutter_positions(map, radius, width, height, cell_size)?;
+    Ok(components(&allowed, false).1.len())
+}
+
+/// Find the parts of the lawn that a cutter of the given radius can not reach from the main area,
+/// i.e. the area containing the start position or else the largest area.
+fn check_reachability(checker: &mut Checker, map: &MapFile, config: &SimConfig, width: f64, height: f64, cell_size: f64) {
+    let radius = config.radius;
+    let (not_lawn, allowed) = match cutter_positions(map, radius, width, height, cell_size) {
+        Ok(masks) => masks,
+        Err(e) => {
+            checker.report(Severity::Error, None, e.to_string());
+            return;
+        }
+    };
+    let (cells_x, cells_y) = (not_lawn.cells_x, not_lawn.cells_y);
+    let (component, sizes) = components(&allowed, false);
+    if sizes.is_empty() {
+        checker.report(
+            Severity::Error,
+            None,
+            format!("a cutter with radius {radius} does not fit anywhere on the lawn"),
+        );
+        return;
+    }
+
+    let largest = (0..sizes.len()).max_by_key(|&c| sizes[c]).unwrap_or(0);
+    let main = if config.start_x >= 0.0 && config.start_y >= 0.0 {
+        let gx = ((config.start_x / cell_size) as usize).min(cells_x - 1);
+        let gy = ((config.start_y / cell_size) as usize).min(cells_y - 1);
+        match component[gy * cells_x + gx] {
+            usize::MAX => {
+                checker.report(
+                    Severity::Error,
+                    None,
+                    format!(
+                        "start position ({}, {}) is inside an obstacle or closer than the cutter radius {radius} to one",
+                        config.start_x, config.start_y
+                    ),
+                );
+                largest
+            }
+            c => c,
+        }
+    } else {
+        largest
+    };
+
+    // Cells within the cutter radius of a reachable cutter position can be cut
+    let reachable_centers = Mask {
+        data: component.iter().map(|&c| c == main).collect(),
+        cells_x,
+        cells_y,
+        cell_size,
+    };
+    let reach = distance_transform(&reachable_centers);
+    let unreachable = Mask {
+        data: (0..reach.len())
+            .map(|i| !not_lawn.data[i] && reach[i] * cell_size > radius)
+            .collect(),
+        ..reachable_centers
+    };
+    let lawn_cells = not_lawn.data.iter().filter(|&&o| !o).count();
+    let unreachable_cells = unreachable.data.iter().filter(|&&u| u).count();
+    if unreachable_cells == 0 || lawn_cells == 0 {
+        return;
+    }
+
+    // Only regions larger than the cutter itself are listed, narrow strips along edges and in
+    // corners can never be reached by a round cutter
+    let (region, region_sizes) = components(&unreachable, true);
+    let cell_area = cell_size * cell_size;
+    let min_cells = (std::f64::consts::PI * radius * radius / cell_area).ceil() as usize;
+    let mut regions: Vec<usize> = (0..region_sizes.len()).filter(|&r| region_sizes[r] >= min_cells).collect();
+    regions.sort_by_key(|&r| std::cmp::Reverse(region_sizes[r]));
+    let mut centroids = vec![(0.0, 0.0); region_sizes.len()];
+    for (i, &r) in region.iter().enumerate() {
+        if r != usize::MAX {
+            let (x, y) = unreachable.center(i);
+            centroids[r].0 += x / region_sizes[r] as f64;
+            centroids[r].1 += y / region_sizes[r] as f64;
+        }
+    }
+    for &r in regions.iter().take(MAX_LISTED_REGIONS) {
+        checker.report(
+            Severity::Warning,
+            None,
+            format!(
+                "lawn region of about {:.2} square units around ({:.2}, {:.2}) can not be reached by a cutter with radius {radius}",
+                region_sizes[r] as f64 * cell_area,
+                centroids[r].0,
+                centroids[r].1
+            ),
+        );
+    }
+    if regions.len() > MAX_LISTED_REGIONS {
+        checker.report(
+            Severity::Warning,
+            None,
+            format!("{} more unreachable lawn regions are not listed", regions.len() - MAX_LISTED_REGIONS),
+        );
+    }
+    checker.report(
+        Severity::Note,
+        None,
+        format!(
+            "{:.2} of {:.2} square units ({:.2}%) of the lawn can not be reached by a cutter with radius {radius}",
+            unreachable_cells as f64 * cell_area,
+            lawn_cells as f64 * cell_area,
+            unreachable_cells as f64 / lawn_cells as f64 * 100.0
+        ),
+    );
+}
+
+/// Validate an already loaded map. The grid size, cell size, cutter radius and start position are
+/// taken from the configuration unless the map overrides the grid size.
+pub fn validate_map(map: &MapFile, config: &SimConfig) -> Vec<Diagnostic> {
+    validate_map_with_lines(map, config, &[])
+}
+
+fn validate_map_with_lines(map: &MapFile, config: &SimConfig, obstacle_lines: &[Option<usize>]) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        diagnostics: Vec::new(),
+        obstacle_lines,
+    };
+    let grid = map.grid.as_ref();
+    let mut width = grid.and_then(|g| g.width).unwrap_or(config.grid_width);
+    let mut height = grid.and_then(|g| g.height).unwrap_or(config.grid_height);
+    // Unset sizes get the same defaults as when the simulation model is set up
+    if width == 0.0 && height == 0.0 {
+        width = config.radius * 50.0;
+        height = config.radius * 50.0;
+    } else if width == 0.0 {
+        width = height;
+    } else if height == 0.0 {
+        height = width;
+    }
+    let cell_size = if config.cell_size > 0.0 {
+        config.cell_size
+    } else if config.cutter_type == CutterType::Blade {
+        config.blade_len / 2.5
+    } else {
+        config.radius / 3.0
+    };
+    if !(width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0) {
+        checker.report(Severity::Error, None, format!("grid size must be positive, got {width} x {height}"));
+        return checker.diagnostics;
+    }
+
+    if let Some(boundary) = &map.boundary {
+        for (severity, message) in check_outline(boundary) {
+            checker.report(severity, None, format!("boundary: {message}"));
+        }
+    }
+    for (index, obstacle) in map.obstacles.iter().enumerate() {
+        check_obstacle(&mut checker, index, obstacle, cell_size);
+        check_bounds(&mut checker, index, obstacle, width, height);
+    }
+    for (kind, zones) in [("no-cut zone", &map.no_cut_zones), ("virtual fence", &map.virtual_fences)] {
+        for (index, zone) in zones.iter().enumerate() {
+            let (shape, _) = obstacle_name(zone);
+            for (severity, message) in obstacle_problems(zone, cell_size) {
+                checker.report(severity, None, format!("{kind} #{} ({shape}): {message}", index + 1));
+            }
+        }
+    }
+
+    for (index, region) in map.terrain.iter().enumerate() {
+        let (shape, _) = obstacle_name(&region.area);
+        let kind = format!("terrain region #{} ({shape})", index + 1);
+        if !(0.0..90.0).contains(&region.slope) {
+            checker.report(Severity::Error, None, format!("{kind}: slope must be at least 0 and less than 90 degrees, got {}", region.slope));
+        }
+        if !(region.friction.is_finite() && region.friction > 0.0) {
+            checker.report(Severity::Error, None, format!("{kind}: friction must be positive, got {}", region.friction));
+        } else if Terrain::new(region.slope, region.slope_direction, region.friction).max_drift_per_step() >= 1.0 {
+            checker.report(
+                Severity::Error,
+                None,
+                format!(
+                    "{kind}: slope {} degrees with friction {} makes the cutter slide further than it drives, increase the friction",
+                    region.slope, region.friction
+                ),
+            );
+        }
+        for (severity, message) in obstacle_problems(&region.area, cell_size) {
+            checker.report(severity, None, format!("{kind}: {message}"));
+        }
+    }
+
+    // The remaining checks need valid geometry
+    if checker.diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        return checker.diagnostics;
+    }
+    let cell_size = cell_size.max(width.max(height) / MAX_CHECK_CELLS);
+    let (cells_x, cells_y) = ((width / cell_size).ceil() as usize, (height / cell_size).ceil() as usize);
+    check_overlaps(&mut checker, map, cells_x, cells_y, cell_size);
+    check_reachability(&mut checker, map, config, width, height, cell_size);
+    checker.diagnostics
+}
+
+/// Load and validate a map file. Only failing to read the file is returned as an error, all
+/// problems with the content of the file are reported as diagnostics.
+pub fn validate_map_file(path: &str, config: &SimConfig) -> Result<ValidationReport, Box<dyn Error>> {
+    let mut report = ValidationReport {
+        file: path.to_string(),
+        map_name: None,
+        diagnostics: Vec::new(),
+    };
+    let is_yaml = !["geojson", "svg", "png", "bmp"].iter().any(|ext| {
+        std::path::Path::new(path)
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case(ext))
+    });
+    let (obstacle_lines, mut diagnostics) = if is_yaml {
+        let content = std::fs::read_to_string(path)?;
+        let lines: Vec<Option<usize>> = find_obstacle_lines(&content).into_iter().map(Some).collect();
+        let mut checker = Checker {
+            diagnostics: Vec::new(),
+            obstacle_lines: &lines,
+        };
+        check_unknown_keys(&mut checker, &content);
+        (lines.clone(), checker.diagnostics)
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
+    let options = MapImportOptions {
+        svg_scale: config.svg_scale,
+        raster_resolution: config.raster_resolution,
+    };
+    match load_map_file_with_sources(path, &options) {
+        Ok(resolved) => {
+            let map = resolved.map;
+            report.map_name = Some(map.name.clone());
+            // Obstacles expanded from a template instance get the line of the instance
+            let lines: Vec<Option<usize>> = resolved
+                .sources
+                .iter()
+                .map(|source| source.and_then(|i| obstacle_lines.get(i).copied().flatten()))
+                .collect();
+            diagnostics.extend(validate_map_with_lines(&map, config, &lines));
+        }
+        Err(e) => {
+            if let Some(io_error) = e.downcast_ref::<std::io::Error>() {
+                return Err(format!("Cannot read map file '{path}': {io_error}").into());
+            }
+            let line = e
+                .downcast_ref::<serde_yaml::Error>()
+                .and_then(|e| e.location())
+                .map(|l| l.line());
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                line,
+                message: e.to_string(),
+            });
+        }
+    }
+
+    // Diagnostics with a line come first in the order of the file
+    diagnostics.sort_by_key(|d| (d.line.is_none(), d.line, d.severity));
+    report.diagnostics = diagnostics;
+    Ok(report)
+}
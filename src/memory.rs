@@ -0,0 +1,18 @@
+use sysinfo::{Pid, System};
+
+/// Total amount of physical memory in the machine in GB
+pub fn get_total_ram_in_gb() -> f64 {
+    let memory = sys_info::mem_info().unwrap();
+    memory.total as f64 / 1024.0 / 1024.0
+}
+
+/// Resident set size of the current process in MB
+pub fn get_process_rss_mb() -> f64 {
+    let sys = System::new_all();
+    let pid = Pid::from(std::process::id() as usize);
+    if let Some(p) = sys.process(pid) {
+        p.memory() as f64 / 1024.0 / 1024.0
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,125 @@
+//! Metrics of a single simulation run that `grunner` estimates and optimises over many seeds
+
+use crate::model::SimModel;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::str::FromStr;
+
+/// A metric of a run together with the stop condition that ends the run when the metric is known.
+/// In files it is written as a map with one of the keys `time_to_coverage` and `coverage_at_time`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "MetricSpec", into = "MetricSpec")]
+pub enum RunMetric {
+    /// Simulated cutting time in seconds to reach the coverage percent. The value of a run stopped
+    /// by another stop condition before the coverage is reached is the time it was stopped at.
+    TimeToCoverage(f64),
+    /// Coverage percent after the simulated cutting time in seconds. The value of a run stopped
+    /// by another stop condition before that time is the coverage it was stopped at.
+    CoverageAtTime(f64),
+}
+
+/// How a metric is written in a file, exactly one of the fields is given
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct MetricSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_to_coverage: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coverage_at_time: Option<f64>,
+}
+
+impl TryFrom<MetricSpec> for RunMetric {
+    type Error = String;
+
+    fn try_from(spec: MetricSpec) -> Result<Self, Self::Error> {
+        match (spec.time_to_coverage, spec.coverage_at_time) {
+            (Some(percent), None) => Ok(RunMetric::TimeToCoverage(percent)),
+            (None, Some(seconds)) => Ok(RunMetric::CoverageAtTime(seconds)),
+            _ => Err("give exactly one of time_to_coverage and coverage_at_time".to_string()),
+        }
+    }
+}
+
+impl From<RunMetric> for MetricSpec {
+    fn from(metric: RunMetric) -> Self {
+        match metric {
+            RunMetric::TimeToCoverage(percent) => MetricSpec { time_to_coverage: Some(percent), ..Default::default() },
+            RunMetric::CoverageAtTime(seconds) => MetricSpec { coverage_at_time: Some(seconds), ..Default::default() },
+        }
+    }
+}
+
+/// Parse a metric from the command line, e.g. "time_to_coverage=95" or "coverage_at_time=3600"
+impl FromStr for RunMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Metric '{s}' must be written as <name>=<value>, e.g. time_to_coverage=95"))?;
+        let value: f64 = value.trim().parse().map_err(|_| format!("Metric '{s}' needs a number after '='"))?;
+        let metric = match name.trim() {
+            "time_to_coverage" => RunMetric::TimeToCoverage(value),
+            "coverage_at_time" => RunMetric::CoverageAtTime(value),
+            other => return Err(format!("Unknown metric '{other}', use time_to_coverage or coverage_at_time")),
+        };
+        metric.check().map_err(|e| e.to_string())?;
+        Ok(metric)
+    }
+}
+
+impl RunMetric {
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        match *self {
+            RunMetric::TimeToCoverage(percent) if !(percent > 0.0 && percent <= 99.9) => {
+                Err(format!("time_to_coverage must be a coverage percent between 0 and 99.9, got {percent}").into())
+            }
+            RunMetric::CoverageAtTime(seconds) if seconds <= 0.0 => {
+                Err(format!("coverage_at_time must be a time in seconds > 0, got {seconds}").into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The gridcover stop option that ends a run when the metric is known
+    pub fn stop_args(&self) -> Vec<String> {
+        match *self {
+            RunMetric::TimeToCoverage(percent) => vec!["-c".to_string(), percent.to_string()],
+            RunMetric::CoverageAtTime(seconds) => vec!["-t".to_string(), seconds.to_string()],
+        }
+    }
+
+    /// The value of the metric for a finished run
+    pub fn value(&self, model: &SimModel) -> f64 {
+        match self {
+            RunMetric::TimeToCoverage(_) => model.sim_time_elapsed,
+            RunMetric::CoverageAtTime(_) => coverage_percent(model),
+        }
+    }
+
+    /// True if the run was stopped by another stop condition before the coverage or time of the
+    /// metric was reached, so its value is only a bound of the metric
+    pub fn is_censored(&self, model: &SimModel) -> bool {
+        match *self {
+            RunMetric::TimeToCoverage(percent) => coverage_percent(model) < percent,
+            RunMetric::CoverageAtTime(seconds) => model.sim_time_elapsed < seconds,
+        }
+    }
+
+    /// True if a smaller value is better
+    pub fn minimize(&self) -> bool {
+        matches!(self, RunMetric::TimeToCoverage(_))
+    }
+
+    /// Short description, e.g. "time to 95% coverage (s)"
+    pub fn description(&self) -> String {
+        match self {
+            RunMetric::TimeToCoverage(percent) => format!("time to {percent}% coverage (s)"),
+            RunMetric::CoverageAtTime(seconds) => format!("coverage after {seconds} s (%)"),
+        }
+    }
+}
+
+fn coverage_percent(model: &SimModel) -> f64 {
+    model.grid.as_ref().map_or(0.0, |grid| grid.get_coverage_percent())
+}
@@ -1,8 +1,10 @@
 use std::error::Error;
 use std::fs;
 
+use crate::config::SimConfig;
 use crate::model::grid::Grid;
-use crate::{args, color_theme, mapfile};
+use crate::stopcond::{self, Metric, StopExpr};
+use crate::{color_theme, mapfile};
 use chrono::Duration;
 use colored::Colorize;
 use rand::Rng;
@@ -17,6 +19,7 @@ pub mod cuttertype;
 pub mod grid;
 pub mod papersize;
 pub mod quadtree;
+pub mod terrain;
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -39,6 +42,13 @@ pub struct SimModel {
     pub coverage_percent: f64,
     pub coverage_count: usize,
     pub grid_cells_obstacles_count: usize,
+    pub grid_cells_no_cut_count: usize,
+    pub grid_cells_virtual_fence_count: usize,
+    /// Number of bounces that were caused by a virtual fence, included in the total bounces
+    pub virtual_fence_bounces: usize,
+    /// Number of cells in terrain regions of the map and the simulated seconds spent on them
+    pub grid_cells_terrain_count: usize,
+    pub terrain_time: f64,
     pub num_obstacles: usize,
     pub max_visited_number: usize,
     pub min_visited_number: usize,
@@ -51,16 +61,25 @@ pub struct SimModel {
     pub stop_bounces: usize,
     pub stop_simsteps: u64,
     pub stop_distance: f64,
+    /// Expression given with `--stop-when`, if any
+    pub stop_when: Option<String>,
+    /// All stop conditions combined into one expression. Set by `init_model()`.
+    pub stop_condition: Option<StopExpr>,
+    /// The condition that stopped the simulation
+    pub stop_reason: Option<String>,
     pub parallel: bool,
     pub image_width_mm: u32,
     pub image_height_mm: u32,
     pub image_file_name: Option<String>,
     pub map_file_name: Option<String>,
+    pub svg_scale: f64,
+    pub raster_resolution: f64,
     pub show_gridlines: bool,
     pub color_theme: Option<String>,
     pub verbosity: usize,
     pub track_center: bool,
     pub show_progress: bool,
+    pub tui: bool,
     pub blade_len: f64,
     pub cutter_type: cuttertype::CutterType,
     pub dpi: u32,
@@ -85,6 +104,11 @@ pub struct SimModel {
     pub delete_frames: bool,
     pub ffmpeg_encoding_duration: Option<Duration>,
     pub animation_speedup: u64,
+    /// Save an image every n:th step in `snapshot_dir`, 0 for none
+    pub snapshot_steps: u64,
+    /// Save an image each time the coverage has increased this many tenths of a percent, 0 for none
+    pub snapshot_coverage_tenths: u64,
+    pub snapshot_dir: String,
     pub wheel_slippage: bool,
     pub slippage_probability: f64,
     pub slippage_min_distance: f64,
@@ -105,6 +129,15 @@ pub struct SimModel {
     pub save_quad_tree: bool,
     pub show_image_label: bool,
     pub generate_json_files: bool,
+    /// Store the final grid and the cutter path in the database together with the result
+    pub db_store_grid: bool,
+    /// Positions of the cutter center recorded at every bounce and every `sim::PATH_SAMPLE_DISTANCE`
+    /// units travelled when the grid is stored in the database
+    pub cutter_path: Vec<[f32; 2]>,
+    /// The start position was drawn from the random seed and not given as an option
+    pub random_start_pos: bool,
+    /// The start direction was drawn from the random seed and not given as an option
+    pub random_start_dir: bool,
     pub ram_size_gb: f64,
     pub ram_usage_mb: f64,
 }
@@ -141,6 +174,7 @@ impl SimModel {
         verbosity: usize,
         track_center: bool,
         show_progress: bool,
+        tui: bool,
         blade_len: f64,
         cutter_type: cuttertype::CutterType,
         dpi: u32,
@@ -150,6 +184,8 @@ impl SimModel {
         battery_charge_time: f64,
         paper_size: papersize::PaperSize,
         map_file_name: Option<String>,
+        svg_scale: f64,
+        raster_resolution: f64,
         quiet: bool,
         generate_frames: bool,
         frames_dir: String,
@@ -203,6 +239,11 @@ impl SimModel {
             max_visited_number: 0,
             min_visited_number: 0,
             grid_cells_obstacles_count: 0,
+            grid_cells_no_cut_count: 0,
+            grid_cells_virtual_fence_count: 0,
+            virtual_fence_bounces: 0,
+            grid_cells_terrain_count: 0,
+            terrain_time: 0.0,
             perturb: true,
             cpu_time: Duration::zero(),
             sim_steps: 0,
@@ -212,16 +253,29 @@ impl SimModel {
             stop_bounces,
             stop_simsteps,
             stop_distance,
+            stop_when: None,
+            db_store_grid: false,
+            snapshot_steps: 0,
+            snapshot_coverage_tenths: 0,
+            snapshot_dir: String::new(),
+            cutter_path: Vec::new(),
+            random_start_pos: false,
+            random_start_dir: false,
+            stop_condition: None,
+            stop_reason: None,
             parallel,
             image_width_mm,
             image_height_mm,
             image_file_name,
             map_file_name,
+            svg_scale,
+            raster_resolution,
             show_gridlines,
             color_theme,
             verbosity,
             track_center,
             show_progress,
+            tui,
             blade_len,
             cutter_type,
             dpi,
@@ -272,70 +326,75 @@ impl SimModel {
         }
     }
 
-    pub fn init(args: &crate::args::Args) -> Self {
+    /// Create a model from the given configuration. The model still needs to be validated
+    /// and completed by `init_model()` before it can be simulated.
+    pub fn from_config(config: &SimConfig) -> Self {
         Self::new(
-            args.start_x,
-            args.start_y,
-            args.start_dir_x,
-            args.start_dir_y,
+            config.start_x,
+            config.start_y,
+            config.start_dir_x,
+            config.start_dir_y,
             0.0,
-            args.step_size,
-            args.radius,
-            args.grid_width,
-            args.grid_height,
-            args.cell_size,
-            args.velocity,
-            args.stop_coverage,
-            args.stop_time,
-            args.stop_bounces,
-            args.stop_simsteps,
-            args.stop_distance,
-            args.parallel,
-            args.image_width_mm,
-            args.image_height_mm,
-            args.image_file_name.clone(),
-            args.show_gridlines,
-            args.verbosity,
-            args.track_center,
-            args.show_progress,
-            args.blade_len,
-            args.cutter_type,
-            args.dpi,
-            args.perturb_segment,
-            args.perturb_segment_percent / 100.0,
-            args.battery_run_time,
-            args.battery_charge_time,
-            args.paper_size,
-            args.map_file_name.clone(),
-            args.quiet,
-            args.generate_frames,
-            args.frames_dir.clone(),
-            args.frame_rate,
-            args.create_animation,
-            args.animation_file_name.clone(),
-            args.hw_encoding,
-            args.delete_frames,
-            args.animation_speedup,
-            args.color_theme.clone(),
-            args.wheel_slippage,
-            args.slippage_probability,
-            args.slippage_min_distance,
-            args.slippage_max_distance,
-            args.slippage_radius_min,
-            args.slippage_radius_max,
-            args.slippage_check_activation_distance,
-            args.slippage_adjustment_step,
-            args.wheel_inbalance,
-            args.wheel_inbalance_radius_min,
-            args.wheel_inbalance_radius_max,
-            args.wheel_inbalance_adjustment_step,
-            args.random_seed,
-            args.show_quad_tree,
-            args.min_qnode_size,
-            args.use_quad_tree,
-            args.save_quad_tree,
-            args.show_image_label,
-            args.generate_json_files,
+            config.step_size,
+            config.radius,
+            config.grid_width,
+            config.grid_height,
+            config.cell_size,
+            config.velocity,
+            config.stop_coverage,
+            config.stop_time,
+            config.stop_bounces,
+            config.stop_simsteps,
+            config.stop_distance,
+            config.parallel,
+            config.image_width_mm,
+            config.image_height_mm,
+            config.image_file_name.clone(),
+            config.show_gridlines,
+            config.verbosity,
+            config.track_center,
+            config.show_progress,
+            config.tui,
+            config.blade_len,
+            config.cutter_type,
+            config.dpi,
+            config.perturb_segment,
+            config.perturb_segment_percent / 100.0,
+            config.battery_run_time,
+            config.battery_charge_time,
+            config.paper_size,
+            config.map_file_name.clone(),
+            config.svg_scale,
+            config.raster_resolution,
+            config.quiet,
+            config.generate_frames,
+            config.frames_dir.clone(),
+            config.frame_rate,
+            config.create_animation,
+            config.animation_file_name.clone(),
+            config.hw_encoding,
+            config.delete_frames,
+            config.animation_speedup,
+            config.color_theme.clone(),
+            config.wheel_slippage,
+            config.slippage_probability,
+            config.slippage_min_distance,
+            config.slippage_max_distance,
+            config.slippage_radius_min,
+            config.slippage_radius_max,
+            config.slippage_check_activation_distance,
+            config.slippage_adjustment_step,
+            config.wheel_inbalance,
+            config.wheel_inbalance_radius_min,
+            config.wheel_inbalance_radius_max,
+            config.wheel_inbalance_adjustment_step,
+            config.random_seed,
+            config.show_quad_tree,
+            config.min_qnode_size,
+            config.use_quad_tree,
+            config.save_quad_tree,
+            config.show_image_label,
+            config.generate_json_files,
             0.0,
             0.0,
         )
@@ -377,6 +436,7 @@ impl SimModel {
                     "Verbosity": self.verbosity,
                     "Quiet": self.quiet,
                     "Show Progress": self.show_progress,
+                    "Terminal View": self.tui,
                     "Track Center": self.track_center,
                     "Step Size": self.step_size,
                     "Perturb at Bounces": self.perturb,
@@ -413,12 +473,18 @@ impl SimModel {
                     "Height (units)": self.grid_height,
                     "Cell Size": self.cell_size,
                     "Map File Name": self.map_file_name.as_ref().unwrap_or(&"None".to_string()),
+                    "SVG Scale": self.svg_scale,
+                    "Raster Resolution": self.raster_resolution,
                     "Obstacles": {
                         "Num obstacles": self.num_obstacles,
                         "Cells with obstacle": self.grid_cells_obstacles_count,
                         "Percent": (self.grid_cells_obstacles_count as f64
                             / (self.grid_cells_x * self.grid_cells_y) as f64)
                             * 100.0,
+                        "Cells in no-cut zones": self.grid_cells_no_cut_count,
+                        "Cells with virtual fence": self.grid_cells_virtual_fence_count,
+                        "Terrain regions": self.map_file.as_ref().map_or(0, |map| map.terrain.len()),
+                        "Cells in terrain regions": self.grid_cells_terrain_count,
                         "Min Quad Node Size": self.min_qnode_size,
                         "Collision Checks": self.grid.as_ref().unwrap().num_detailed_collision_checks,
                     },
@@ -440,18 +506,28 @@ impl SimModel {
                     "Coverage (%)": self.stop_coverage,
                     "Simulation Steps": self.stop_simsteps,
                     "Distance (units)": self.stop_distance,
+                    "Expression": self.stop_when,
+                    "Combined": self.stop_condition.as_ref().map(|c| c.to_string()),
                 },
             }
         });
         json
     }
 
+    /// Number of cells that should be cut, i.e. all cells except obstacles, no-cut zones and
+    /// virtual fences
+    pub fn get_lawn_cell_count(&self) -> usize {
+        self.grid_cells_x * self.grid_cells_y
+            - self.grid_cells_obstacles_count
+            - self.grid_cells_no_cut_count
+            - self.grid_cells_virtual_fence_count
+    }
+
     pub fn get_theorethical_minimum_cutting_time(&self) -> (usize, usize, usize, f64) {
         let mut theoretical_minimum_time_seconds = 0;
         if self.stop_coverage > 0.0 {
             // Theoretical minimum time is calculated based on the grid size, radius, and velocity
-            theoretical_minimum_time_seconds =
-                self.grid_cells_x * self.grid_cells_y - self.grid_cells_obstacles_count;
+            theoretical_minimum_time_seconds = self.get_lawn_cell_count();
 
             theoretical_minimum_time_seconds /=
                 (2.0 * self.radius / self.cell_size).floor() as usize;
@@ -496,9 +572,14 @@ impl SimModel {
                     "Percent": self.coverage_percent,
                     "Cells": self.coverage_count,
                     "Bounces": self.segment_number,
+                    "Virtual fence bounces": self.virtual_fence_bounces,
                     "Max visited": self.max_visited_number,
                     "Min visited": self.min_visited_number,
                 },
+                "Stop": {
+                    "Reason": self.stop_reason,
+                    "Condition": self.stop_condition.as_ref().map(|c| c.to_string()),
+                },
                 "Cutter": {
                     "Type": self.cutter_type.as_str(),
                     "Blade Length": self.blade_len,
@@ -515,6 +596,7 @@ impl SimModel {
                         "Charge left (%)": self.battery_charge_left,
                     },
                     "Wheel Slippage": self.wheel_slippage,
+                    "Time on terrain (seconds)": self.terrain_time,
                 },
                 "Performance": {
                      "CPU time": format!("{:02}:{:02}:{:02}.{:03}",
@@ -559,6 +641,24 @@ impl SimModel {
                             / (self.grid_cells_x * self.grid_cells_y) as f64)
                             * 100.0,
                     },
+                    "No-cut zones": {
+                        "NumCells": self.grid_cells_no_cut_count,
+                        "Percent": (self.grid_cells_no_cut_count as f64
+                            / (self.grid_cells_x * self.grid_cells_y) as f64)
+                            * 100.0,
+                    },
+                    "Virtual fences": {
+                        "NumCells": self.grid_cells_virtual_fence_count,
+                        "Percent": (self.grid_cells_virtual_fence_count as f64
+                            / (self.grid_cells_x * self.grid_cells_y) as f64)
+                            * 100.0,
+                    },
+                    "Terrain": {
+                        "NumCells": self.grid_cells_terrain_count,
+                        "Percent": (self.grid_cells_terrain_count as f64
+                            / (self.grid_cells_x * self.grid_cells_y) as f64)
+                            * 100.0,
+                    },
                     "Cell side (units)": self.cell_size,
                     "Width (units)": self.grid_width,
                     "Height (units)": self.grid_height,
@@ -584,11 +684,7 @@ impl SimModel {
                 "Output image": {
                     "Paper size": self.paper_size.get_json(),
                     "Show gridlines": self.show_gridlines,
-                    "File name": if self.image_file_name.is_none() {
-                        "".to_string()
-                    } else {
-                        self.image_file_name.as_ref().unwrap().clone()
-                    },
+                    "File name": self.image_file_name.clone().unwrap_or_default(),
                     "DPI": self.dpi,
                     "Pixels": {
                         "width": (self.image_width_mm as f64 * self.dpi as f64 / 25.4).round() as u32,
@@ -611,7 +707,9 @@ impl SimModel {
                 "Coverage": {
                     "Percent": self.coverage_percent,
                     "Bounces": self.segment_number,
+                    "Virtual fence bounces": self.virtual_fence_bounces,
                     "Distance": self.distance_covered,
+                    "Stop reason": self.stop_reason,
                 },
                 "Time": {
                     "CPU": format!("{:02}:{:02}:{:02}.{:03}",
@@ -623,6 +721,10 @@ impl SimModel {
                         self.sim_time_elapsed as u64 / 3600,
                         (self.sim_time_elapsed as u64 % 3600) / 60,
                         self.sim_time_elapsed as u64 % 60),
+                    "On terrain": format!("{:02}:{:02}:{:02}",
+                        self.terrain_time as u64 / 3600,
+                        (self.terrain_time as u64 % 3600) / 60,
+                        self.terrain_time as u64 % 60),
                 },
                 "Cutter": {
                     "Type": self.cutter_type.as_str(),
@@ -726,7 +828,7 @@ impl SimModel {
     }
 }
 
-fn json_to_console(json: &serde_json::Value, root_key: &str, indent: usize) {
+pub(crate) fn json_to_console(json: &serde_json::Value, root_key: &str, indent: usize) {
     let column1 = 45;
     let column2 = column1 - indent;
     let column3 = column2 - indent;
@@ -802,9 +904,9 @@ pub fn try_delete_frames_dir(model: &mut SimModel) -> Result<(), Box<dyn Error>>
     Ok(())
 }
 
-fn set_initial_direction(args: &args::Args, rng: &mut impl Rng) -> (f64, f64, f64) {
-    let mut current_dir_x = args.start_dir_x;
-    let mut current_dir_y = args.start_dir_y;
+fn set_initial_direction(config: &SimConfig, rng: &mut impl Rng) -> (f64, f64, f64) {
+    let mut current_dir_x = config.start_dir_x;
+    let mut current_dir_y = config.start_dir_y;
     let angle_deg: f64;
 
     // If both directions are zero randomize them in range -1 to 1
@@ -827,11 +929,11 @@ fn set_initial_direction(args: &args::Args, rng: &mut impl Rng) -> (f64, f64, f6
 }
 
 pub fn init_model(
-    args: &args::Args,
+    config: &SimConfig,
     rng: &mut impl Rng,
 ) -> Result<SimModel, Box<dyn std::error::Error>> {
     // Initialize simulation configuration
-    let mut model = SimModel::init(args);
+    let mut model = SimModel::from_config(config);
 
     // Check that min inbalance radius is less than max inbalance radius
     if model.wheel_inbalance_radius_min >= model.wheel_inbalance_radius_max {
@@ -874,15 +976,35 @@ pub fn init_model(
         }
     }
 
-    // Make sure one of the stopping conditions is set
-    if args.stop_bounces == 0
-        && args.stop_time == 0.0
-        && args.stop_coverage == 0.0
-        && args.stop_simsteps == 0
-        && args.stop_distance == 0.0
-    {
+    // Combine all stopping conditions into one expression where the first condition reached
+    // stops the simulation. Make sure at least one of them is set.
+    model.stop_when = config.stop_when.clone();
+    model.db_store_grid = config.db_store_grid;
+    let mut conditions = Vec::new();
+    if config.stop_bounces > 0 {
+        conditions.push(StopExpr::at_least(Metric::Bounces, config.stop_bounces as f64));
+    }
+    if config.stop_time > 0.0 {
+        conditions.push(StopExpr::at_least(Metric::Time, config.stop_time));
+    }
+    if config.stop_coverage > 0.0 {
+        conditions.push(StopExpr::at_least(Metric::Coverage, config.stop_coverage));
+    }
+    if config.stop_simsteps > 0 {
+        conditions.push(StopExpr::at_least(Metric::Steps, config.stop_simsteps as f64));
+    }
+    if config.stop_distance > 0.0 {
+        conditions.push(StopExpr::at_least(Metric::Distance, config.stop_distance));
+    }
+    if let Some(expr) = &config.stop_when {
+        conditions.push(
+            stopcond::parse(expr).map_err(|e| format!("Invalid stop condition '{expr}': {e}"))?,
+        );
+    }
+    model.stop_condition = StopExpr::any_of(conditions);
+    if model.stop_condition.is_none() {
         return Err(
-            "No stopping condition set (use bounces, sim_time, sim_step, coverage, or distance)."
+            "No stopping condition set (use bounces, sim_time, sim_step, coverage, distance or stop_when)."
                 .into(),
         );
     }
@@ -952,6 +1074,11 @@ pub fn init_model(
         model.generate_frames = true;
     }
 
+    // The terminal view replaces the single line progress output
+    if model.tui {
+        model.show_progress = false;
+    }
+
     // If frame generation is enabled we must adjust step_size so it corresponds to the frame rate
 
     if model.generate_frames && model.frame_rate > 0 {
@@ -1007,18 +1134,37 @@ pub fn init_model(
         })?;
     }
 
+    // Snapshot images are named by the step or by the coverage in tenths of a percent
+    if config.snapshot_coverage != 0.0 {
+        let tenths = (config.snapshot_coverage * 10.0).round();
+        if !(1.0..=1000.0).contains(&tenths) || (tenths / 10.0 - config.snapshot_coverage).abs() > 1e-9 {
+            return Err(format!(
+                "Snapshot coverage must be a multiple of 0.1 between 0.1 and 100, got {}",
+                config.snapshot_coverage
+            )
+            .color(colored::Color::Red)
+            .bold()
+            .into());
+        }
+        model.snapshot_coverage_tenths = tenths as u64;
+    }
+    model.snapshot_steps = config.snapshot_steps;
+    model.snapshot_dir = config.snapshot_dir.clone();
+
     // Use the user-defined start position
-    model.start_x = args.start_x;
-    model.start_y = args.start_y;
+    model.start_x = config.start_x;
+    model.start_y = config.start_y;
+    model.random_start_pos = config.start_x < 0.0 || config.start_y < 0.0;
 
     // Setup the initial direction of movement based on user input or randomize it
-    let (current_dir_x, current_dir_y, angle_deg) = set_initial_direction(args, rng);
+    let (current_dir_x, current_dir_y, angle_deg) = set_initial_direction(config, rng);
     model.start_dir_x = current_dir_x;
     model.start_dir_y = current_dir_y;
     model.start_angle_deg = angle_deg;
+    model.random_start_dir = config.start_dir_x == 0.0 && config.start_dir_y == 0.0;
 
     // Setup the paper size in mm
-    if let Some((width_mm, height_mm)) = args.paper_size.get_size_mm() {
+    if let Some((width_mm, height_mm)) = config.paper_size.get_size_mm() {
         model.image_width_mm = width_mm as u32;
         model.image_height_mm = height_mm as u32;
     } else {
@@ -1,4 +1,4 @@
-use crate::model::{coverageinfo::CoverageInfo, cuttertype::CutterType, quadtree::QuadTree};
+use crate::model::{coverageinfo::CoverageInfo, cuttertype::CutterType, quadtree::QuadTree, terrain::Terrain, terrain::TerrainMap};
 use crate::vector::Vector;
 
 #[derive(Debug, Clone)]
@@ -7,6 +7,20 @@ pub enum Cell {
     Covered(CoverageInfo),
     CenterPoint(CoverageInfo),
     Obstacle,
+    /// The cutter can drive over the cell but does not cut it, e.g. a gravel path
+    NoCut,
+    /// The cutter turns before entering the cell as if it was an obstacle but there is nothing
+    /// physical to bump into
+    VirtualFence,
+}
+
+/// The kind of area that the map rasterization marks cells as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Zone {
+    #[default]
+    Obstacle,
+    NoCut,
+    VirtualFence,
 }
 
 impl Cell {
@@ -24,10 +38,34 @@ impl Cell {
         matches!(self, Cell::Obstacle)
     }
 
+    pub fn is_no_cut(&self) -> bool {
+        matches!(self, Cell::NoCut)
+    }
+
+    pub fn is_virtual_fence(&self) -> bool {
+        matches!(self, Cell::VirtualFence)
+    }
+
+    /// True if the cutter can not enter the cell, i.e. an obstacle or a virtual fence
+    pub fn is_blocking(&self) -> bool {
+        matches!(self, Cell::Obstacle | Cell::VirtualFence)
+    }
+
     pub fn set_as_obstacle(&mut self) {
         *self = Cell::Obstacle;
     }
 
+    /// Mark the cell as part of a zone. Obstacles take precedence over virtual fences which in
+    /// turn take precedence over no-cut zones.
+    pub fn set_as_zone(&mut self, zone: Zone) {
+        match zone {
+            Zone::Obstacle => *self = Cell::Obstacle,
+            Zone::VirtualFence if !self.is_obstacle() => *self = Cell::VirtualFence,
+            Zone::NoCut if !self.is_blocking() => *self = Cell::NoCut,
+            _ => {}
+        }
+    }
+
     pub fn set_as_covered(&mut self, segment_number: usize) {
         *self = Cell::Covered(CoverageInfo::new(segment_number, 1));
     }
@@ -41,9 +79,17 @@ pub struct Grid {
     pub cells_y: usize,
     pub covered_cells: usize,
     pub cells_obstacles_count: usize,
+    pub cells_no_cut_count: usize,
+    pub cells_virtual_fence_count: usize,
+    /// The kind of cell that obstacles applied from a map are marked as
+    pub zone: Zone,
     pub quadtree: Option<QuadTree>,
     pub num_detailed_collision_checks: usize,
     pub use_quad_tree: bool,
+    /// When set, every cell that becomes covered for the first time is logged here (used by the live view)
+    pub newly_covered: Option<Vec<(usize, usize)>>,
+    /// Slope and surface of the cells if the map has terrain regions
+    pub terrain: Option<TerrainMap>,
 }
 
 impl Grid {
@@ -57,9 +103,14 @@ impl Grid {
             cells_y: grid_cells_y,
             covered_cells: 0,
             cells_obstacles_count: 0,
+            cells_no_cut_count: 0,
+            cells_virtual_fence_count: 0,
+            zone: Zone::Obstacle,
             quadtree: None,
             num_detailed_collision_checks: 0,
             use_quad_tree: false,
+            newly_covered: None,
+            terrain: None,
         }
     }
 
@@ -105,14 +156,40 @@ impl Grid {
         }
     }
 
-    // Count the number of cells with an obstacle
+    // Count the number of cells with an obstacle, in a no-cut zone and with a virtual fence
     pub fn update_obstacle_cells_count(&mut self) {
         self.cells_obstacles_count = self
-            .cells
-            .iter()
-            .flat_map(|row| row.iter())
+            .get_cell_iter()
             .filter(|cell| cell.is_obstacle())
             .count();
+        self.cells_no_cut_count = self.get_cell_iter().filter(|cell| cell.is_no_cut()).count();
+        self.cells_virtual_fence_count = self
+            .get_cell_iter()
+            .filter(|cell| cell.is_virtual_fence())
+            .count();
+    }
+
+    /// Mark the cell at the given grid coordinates as the current zone, see `Cell::set_as_zone()`
+    pub fn mark_zone_cell(&mut self, x: usize, y: usize) {
+        let zone = self.zone;
+        if let Some(cell) = self.get_cell_mut(x, y) {
+            cell.set_as_zone(zone);
+        }
+    }
+
+    /// Number of cells that should be cut, i.e. all cells except obstacles, no-cut zones and
+    /// virtual fences
+    pub fn get_lawn_cell_count(&self) -> usize {
+        self.cells_x * self.cells_y
+            - self.cells_obstacles_count
+            - self.cells_no_cut_count
+            - self.cells_virtual_fence_count
+    }
+
+    /// The terrain at the given world coordinates, None on flat lawn
+    pub fn get_terrain(&self, pos: &Vector) -> Option<&Terrain> {
+        let (x, y) = self.world_coordinate_to_grid(pos.x, pos.y);
+        self.terrain.as_ref()?.get(x, y)
     }
 
     pub fn get_cell_iter(&self) -> impl Iterator<Item = &Cell> {
@@ -196,14 +273,20 @@ impl Grid {
     //     false
     // }
 
-    /// Check if there is an obstacle in a bounding box determined by the given radius around the given center point.
-    #[allow(clippy::collapsible_if)]
+    /// Check if there is an obstacle or virtual fence in a bounding box determined by the given radius around the given center point.
     pub fn collision_with_obstacle(&mut self, center: &Vector, radius: f64) -> bool {
+        self.find_collision(center, radius).is_some()
+    }
+
+    /// Find what the cutter collides with in a bounding box determined by the given radius around
+    /// the given center point. An obstacle is reported before a virtual fence.
+    #[allow(clippy::collapsible_if)]
+    pub fn find_collision(&mut self, center: &Vector, radius: f64) -> Option<Zone> {
         // First check the spatial index if available
         if self.use_quad_tree {
             if let Some(quad_tree) = &self.quadtree {
                 if !quad_tree.might_have_collision(center.x, center.y, radius) {
-                    return false; // No collision possible in this area
+                    return None; // No collision possible in this area
                 }
             }
         }
@@ -216,6 +299,7 @@ impl Grid {
         let grid_center_x = self.world_coordinate_to_grid_x(center.x) as i32;
         let grid_center_y = self.world_coordinate_to_grid_y(center.y) as i32;
 
+        let mut collision = None;
         for dx in -grid_radius..=grid_radius {
             for dy in -grid_radius..=grid_radius {
                 let grid_cell_x = grid_center_x + dx;
@@ -225,12 +309,15 @@ impl Grid {
                 }
                 if let Some(cell) = self.get_cell(grid_cell_x as usize, grid_cell_y as usize) {
                     if cell.is_obstacle() {
-                        return true;
+                        return Some(Zone::Obstacle);
+                    }
+                    if cell.is_virtual_fence() {
+                        collision = Some(Zone::VirtualFence);
                     }
                 }
             }
         }
-        false
+        collision
     }
 
     // pub fn get_cell_at_coordinates(&self, x: f64, y: f64) -> Option<&Cell> {
@@ -258,9 +345,7 @@ impl Grid {
         if self.cells_x == 0 || self.cells_y == 0 {
             return 0.0;
         }
-        (self.covered_cells as f64
-            / (self.cells_x * self.cells_y - self.cells_obstacles_count) as f64)
-            * 100.0
+        (self.covered_cells as f64 / self.get_lawn_cell_count() as f64) * 100.0
     }
 
     pub fn get_coverage(&self) -> (usize, f64) {
@@ -337,6 +422,7 @@ impl Grid {
                     continue;
                 }
 
+                let mut newly_covered = false;
                 #[allow(clippy::collapsible_if)]
                 if self.is_cell_covered(
                     center,
@@ -353,6 +439,7 @@ impl Grid {
                             Cell::Empty => {
                                 cell.set_as_covered(segment_number);
                                 self.covered_cells += 1;
+                                newly_covered = true;
                             }
                             Cell::Covered(existing_coverage) => {
                                 if segment_number != existing_coverage.segment_number {
@@ -374,9 +461,15 @@ impl Grid {
                                 );
                                 panic!("Attempted to cover a cell marked as an obstacle");
                             }
+                            // The cutter passes over no-cut zones and the edge of virtual fences without cutting
+                            Cell::NoCut | Cell::VirtualFence => {}
                         }
                     }
                 }
+
+                if newly_covered && let Some(log) = self.newly_covered.as_mut() {
+                    log.push((grid_cell_x as usize, grid_cell_y as usize));
+                }
             }
         }
 
@@ -403,6 +496,7 @@ impl Grid {
                     Cell::Obstacle => {
                         panic!("Attempted to mark center point in a cell marked as an obstacle");
                     }
+                    Cell::NoCut | Cell::VirtualFence => {}
                 }
             }
         }
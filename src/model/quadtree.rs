@@ -154,7 +154,7 @@ impl QuadTree {
             for x in start_x.saturating_sub(margin)..end_x.saturating_add(margin).min(grid.cells_x)
             {
                 if let Some(cell) = grid.get_cell(x, y)
-                    && cell.is_obstacle()
+                    && cell.is_blocking()
                 {
                     has_obstacle = true;
                     break;
@@ -0,0 +1,109 @@
+use crate::vector::Vector;
+
+/// How much the slippage probability grows on a slope, per unit of sin(slope)
+const SLOPE_SLIPPAGE_FACTOR: f64 = 4.0;
+
+/// How far the cutter slides downhill sideways, per unit travelled and unit of sin(slope), when
+/// driving across a slope with normal friction
+const SLOPE_DRIFT_FACTOR: f64 = 0.15;
+
+/// The slowest the cutter moves on steep or slippery ground relative to its nominal velocity
+const MIN_VELOCITY_FACTOR: f64 = 0.1;
+
+/// Slope and surface of a part of the map
+#[derive(Debug, Clone, Copy)]
+pub struct Terrain {
+    /// sin() of the slope angle
+    pub sin_slope: f64,
+    /// Unit vector pointing downhill
+    pub downhill: Vector,
+    /// Grip of the surface relative to normal lawn
+    pub friction: f64,
+}
+
+impl Terrain {
+    /// A terrain with the slope in degrees falling towards `slope_direction` degrees
+    /// counter-clockwise from the x-axis
+    pub fn new(slope: f64, slope_direction: f64, friction: f64) -> Self {
+        let (sin, cos) = slope_direction.to_radians().sin_cos();
+        Terrain {
+            sin_slope: slope.to_radians().sin(),
+            downhill: Vector::new(cos, sin),
+            friction,
+        }
+    }
+
+    /// The factor the velocity is multiplied with when driving in the given direction. The
+    /// cutter keeps its speed downhill but slows down uphill and on surfaces with less grip.
+    pub fn velocity_factor(&self, dir: &Vector) -> f64 {
+        let uphill = -(dir.x * self.downhill.x + dir.y * self.downhill.y);
+        let factor = self.friction.min(1.0) * (1.0 - self.sin_slope * uphill.max(0.0));
+        factor.max(MIN_VELOCITY_FACTOR)
+    }
+
+    /// The probability of wheel slippage on this terrain given the probability on flat lawn
+    pub fn slippage_probability(&self, probability: f64) -> f64 {
+        (probability * (1.0 + SLOPE_SLIPPAGE_FACTOR * self.sin_slope) / self.friction).min(1.0)
+    }
+
+    /// The sideways movement downhill when moving `step` units in the given direction. Only the
+    /// part of the downhill direction across the direction of travel makes the cutter slide.
+    pub fn drift(&self, dir: &Vector, step: f64) -> Vector {
+        let along = dir.x * self.downhill.x + dir.y * self.downhill.y;
+        let across = self.downhill - *dir * along;
+        across * (SLOPE_DRIFT_FACTOR * self.sin_slope * step / self.friction)
+    }
+
+    /// The longest drift relative to the step, when driving straight across the slope
+    pub fn max_drift_per_step(&self) -> f64 {
+        SLOPE_DRIFT_FACTOR * self.sin_slope / self.friction
+    }
+
+    /// +1.0 if turning counter-clockwise from the given direction turns the cutter downhill,
+    /// otherwise -1.0
+    pub fn downhill_turn(&self, dir: &Vector) -> f64 {
+        if dir.x * self.downhill.y - dir.y * self.downhill.x >= 0.0 { 1.0 } else { -1.0 }
+    }
+}
+
+/// The terrain of each cell of the grid. Cells outside all terrain regions are flat lawn.
+#[derive(Debug, Clone)]
+pub struct TerrainMap {
+    pub regions: Vec<Terrain>,
+    /// Index into `regions` plus one for each cell, 0 for flat lawn
+    cells: Vec<Vec<u16>>,
+}
+
+impl TerrainMap {
+    pub fn new(cells_x: usize, cells_y: usize) -> Self {
+        TerrainMap {
+            regions: Vec::new(),
+            cells: vec![vec![0; cells_y]; cells_x],
+        }
+    }
+
+    /// Add a region, returns the value to mark its cells with
+    pub fn add_region(&mut self, terrain: Terrain) -> u16 {
+        self.regions.push(terrain);
+        self.regions.len() as u16
+    }
+
+    /// Mark the cell as part of the region returned by `add_region()`. Later regions replace
+    /// earlier ones where they overlap.
+    pub fn set_cell(&mut self, x: usize, y: usize, region: u16) {
+        if let Some(cell) = self.cells.get_mut(x).and_then(|column| column.get_mut(y)) {
+            *cell = region;
+        }
+    }
+
+    /// The terrain of the cell at the given grid coordinates, None for flat lawn
+    pub fn get(&self, x: usize, y: usize) -> Option<&Terrain> {
+        let region = *self.cells.get(x)?.get(y)?;
+        if region == 0 { None } else { self.regions.get(region as usize - 1) }
+    }
+
+    /// Number of cells that are not flat lawn
+    pub fn get_cell_count(&self) -> usize {
+        self.cells.iter().flatten().filter(|&&region| region != 0).count()
+    }
+}
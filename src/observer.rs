@@ -0,0 +1,345 @@
+use crate::image::{ImageOptions, try_save_grid_image, try_save_image};
+use crate::memory::get_process_rss_mb;
+use crate::model::SimModel;
+use crate::sim::StepEvents;
+use crate::vector::Vector;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::time::Duration;
+use thousands::Separable;
+
+/// Hooks into a running simulation.
+///
+/// All methods have empty default implementations so an observer only needs to implement the
+/// events it is interested in. For each step the specific events (bounce, collision, battery
+/// and coverage milestones) are reported before `on_step()`.
+pub trait SimObserver {
+    /// Called once before the first step
+    fn on_start(&mut self, _model: &mut SimModel, _cutter_center: &Vector) {}
+
+    /// Called after every step. Returning `ControlFlow::Break` stops the simulation.
+    fn on_step(
+        &mut self,
+        _model: &mut SimModel,
+        _cutter_center: &Vector,
+        _events: &StepEvents,
+    ) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// The cutter bounced against the edge of the grid
+    fn on_bounce(&mut self, _model: &SimModel, _cutter_center: &Vector) {}
+
+    /// The cutter collided with an obstacle
+    fn on_collision(&mut self, _model: &SimModel, _cutter_center: &Vector) {}
+
+    /// The cutter turned at a virtual fence
+    fn on_virtual_fence(&mut self, _model: &SimModel, _cutter_center: &Vector) {}
+
+    /// The battery ran out in this step. The time to find the charging station and recharge is
+    /// added before the next step, which reports `on_battery_recharged()`.
+    fn on_battery_empty(&mut self, _model: &SimModel) {}
+
+    /// The battery has been recharged and the cutter continues with this step
+    fn on_battery_recharged(&mut self, _model: &SimModel) {}
+
+    /// The coverage reached another whole percent
+    fn on_coverage_milestone(&mut self, _model: &SimModel, _percent: u32) {}
+
+    /// Called once when the simulation has ended, either by a stop condition or by an observer
+    fn on_end(&mut self, _model: &mut SimModel, _cutter_center: &Vector) {}
+}
+
+/// Observer calling a closure after every step
+pub struct StepCallback<F>(pub F);
+
+impl<F> SimObserver for StepCallback<F>
+where
+    F: FnMut(&mut SimModel, &Vector) -> ControlFlow<()>,
+{
+    fn on_step(
+        &mut self,
+        model: &mut SimModel,
+        cutter_center: &Vector,
+        _events: &StepEvents,
+    ) -> ControlFlow<()> {
+        (self.0)(model, cutter_center)
+    }
+}
+
+/// Prints a single progress line that is updated in place while the simulation runs
+pub struct ProgressObserver;
+
+impl SimObserver for ProgressObserver {
+    fn on_step(
+        &mut self,
+        model: &mut SimModel,
+        _cutter_center: &Vector,
+        events: &StepEvents,
+    ) -> ControlFlow<()> {
+        if events.coverage_updated {
+            let (coverage_cell_count, current_coverage_percent) = model
+                .grid
+                .as_ref()
+                .expect("Failed to get grid. Internal BUG!")
+                .get_coverage();
+            // Number of frames saved before this step
+            let frame_number = if model.generate_frames {
+                ((model.sim_steps - 1) / model.steps_per_frame).div_ceil(model.animation_speedup)
+            } else {
+                0
+            };
+            print_progress(
+                model,
+                frame_number,
+                current_coverage_percent,
+                coverage_cell_count,
+            );
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+fn print_progress(
+    model: &SimModel,
+    frame_counter: u64,
+    current_coverage_percent: f64,
+    coverage_cell_count: usize,
+) {
+    if model.show_progress {
+        if model.battery_run_time > 0.0 {
+            if model.generate_frames {
+                print!(
+                    "\rFrame: {:>06}, Coverage: {:>6.2}% ({:>7}/{:>7} cells covered), Distance: {:>6.2}, Bounces: {:>4}, Sim-Time: {:02}:{:02}:{:02}, Battery capacity left: {:>5.1}%",
+                    frame_counter,
+                    current_coverage_percent,
+                    coverage_cell_count,
+                    model.get_lawn_cell_count(),
+                    model.distance_covered,
+                    model.segment_number,
+                    model.sim_time_elapsed as u64 / 3600,
+                    (model.sim_time_elapsed as u64 % 3600) / 60,
+                    model.sim_time_elapsed as u64 % 60,
+                    model.battery_charge_left
+                );
+            } else {
+                print!(
+                    "\rCoverage: {:>6.2}% ({:>7}/{:>7} cells covered), Distance: {:>6.2}, Bounces: {:>4}, Sim-Time: {:02}:{:02}:{:02}, Battery capacity left: {:>5.1}%",
+                    current_coverage_percent,
+                    coverage_cell_count,
+                    model.get_lawn_cell_count(),
+                    model.distance_covered,
+                    model.segment_number,
+                    model.sim_time_elapsed as u64 / 3600,
+                    (model.sim_time_elapsed as u64 % 3600) / 60,
+                    model.sim_time_elapsed as u64 % 60,
+                    model.battery_charge_left
+                );
+            }
+        } else if model.generate_frames {
+            print!(
+                "\rFrame: {:>06}, Coverage: {:>6.2}% ({:>7}/{:>7} cells covered), Distance: {:>6.2}, Bounces: {:>4}, Sim-Time: {:02}:{:02}:{:02}",
+                frame_counter,
+                current_coverage_percent,
+                coverage_cell_count,
+                model.get_lawn_cell_count(),
+                model.distance_covered,
+                model.segment_number,
+                model.sim_time_elapsed as u64 / 3600,
+                (model.sim_time_elapsed as u64 % 3600) / 60,
+                model.sim_time_elapsed as u64 % 60,
+            );
+        } else {
+            print!(
+                "\rCoverage: {:>6.2}% ({:>7}/{:>7} cells covered), Distance: {:>6.2}, Bounces: {:>4}, Sim-Time: {:02}:{:02}:{:02}",
+                current_coverage_percent,
+                coverage_cell_count,
+                model.get_lawn_cell_count(),
+                model.distance_covered,
+                model.segment_number,
+                model.sim_time_elapsed as u64 / 3600,
+                (model.sim_time_elapsed as u64 % 3600) / 60,
+                model.sim_time_elapsed as u64 % 60,
+            );
+        }
+        if model.verbosity > 1 {
+            print!(
+                ", Memory: {:>5} MB/{:>3} GB",
+                model.ram_usage_mb.separate_with_commas(),
+                model.ram_size_gb
+            );
+        }
+        std::io::stdout().flush().unwrap();
+    }
+}
+
+/// Samples the memory used by the process every 1000 steps
+pub struct MemoryObserver;
+
+impl SimObserver for MemoryObserver {
+    fn on_step(
+        &mut self,
+        model: &mut SimModel,
+        _cutter_center: &Vector,
+        _events: &StepEvents,
+    ) -> ControlFlow<()> {
+        if model.sim_steps == 1 || model.sim_steps.is_multiple_of(1000) {
+            model.ram_usage_mb = get_process_rss_mb().round();
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Saves the grid as an image in the frames directory for the animation
+#[derive(Default)]
+pub struct FrameObserver {
+    frame_counter: u64,
+    frame_image_numbering: u64,
+}
+
+impl SimObserver for FrameObserver {
+    fn on_step(
+        &mut self,
+        model: &mut SimModel,
+        _cutter_center: &Vector,
+        _events: &StepEvents,
+    ) -> ControlFlow<()> {
+        if model.sim_steps.is_multiple_of(model.steps_per_frame) {
+            if self.frame_counter.is_multiple_of(model.animation_speedup) {
+                let frame_filename = format!(
+                    "{}/frame_{:07}.png",
+                    model.frames_dir, self.frame_image_numbering
+                );
+                self.frame_image_numbering += 1;
+                try_save_image(model, Some(frame_filename));
+            }
+            self.frame_counter += 1;
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// What the milestones of a `SnapshotObserver` count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotMetric {
+    /// Simulation steps, the images are named `frame_<step:08>.png`
+    Steps,
+    /// Coverage in tenths of a percent, the images are named `frame_<tenths:04>.png`
+    Coverage,
+}
+
+impl SnapshotMetric {
+    /// File name of the image saved at the milestone
+    pub fn file_name(&self, dir: &str, milestone: u64) -> String {
+        match self {
+            SnapshotMetric::Steps => format!("{dir}/frame_{milestone:08}.png"),
+            SnapshotMetric::Coverage => format!("{dir}/frame_{milestone:04}.png"),
+        }
+    }
+}
+
+/// Saves an image of the grid each time the simulation reaches a milestone, so a series of
+/// images at increasing step counts or coverage is made in a single simulation pass.
+///
+/// The milestones are `first`, `first + interval`, ... up to `last`. An image is saved as soon
+/// as the number of steps, or the coverage, reaches a milestone, which is the same grid a
+/// simulation stopped at the milestone would end with. If `last` is given the series is always
+/// complete; milestones not reached when the simulation ends are saved with the final grid.
+///
+/// The grid is copied at each milestone and the image is encoded on the rayon pool while the
+/// simulation continues. All images are saved when the simulation has ended.
+pub struct SnapshotObserver {
+    dir: String,
+    metric: SnapshotMetric,
+    next: u64,
+    interval: u64,
+    last: Option<u64>,
+    /// Images being encoded, each sends on `saved_tx` when it has been saved
+    pending: usize,
+    saved_tx: Sender<()>,
+    saved_rx: Receiver<()>,
+}
+
+impl SnapshotObserver {
+    pub fn new(dir: &str, metric: SnapshotMetric, first: u64, interval: u64, last: Option<u64>) -> Self {
+        let (saved_tx, saved_rx) = channel();
+        SnapshotObserver {
+            dir: dir.to_string(),
+            metric,
+            next: first,
+            interval: interval.max(1),
+            last,
+            pending: 0,
+            saved_tx,
+            saved_rx,
+        }
+    }
+
+    fn in_series(&self) -> bool {
+        self.last.is_none_or(|last| self.next <= last)
+    }
+
+    fn reached(&self, model: &SimModel) -> bool {
+        match self.metric {
+            SnapshotMetric::Steps => model.sim_steps >= self.next,
+            SnapshotMetric::Coverage => {
+                let grid = model.grid.as_ref().expect("Failed to get grid. Internal BUG!");
+                grid.get_coverage_percent() >= self.next as f64 / 10.0
+            }
+        }
+    }
+
+    fn save_next(&mut self, model: &SimModel) {
+        let file_name = self.metric.file_name(&self.dir, self.next);
+        // Each image being encoded holds a copy of the grid, limit them to what the pool can encode
+        self.wait_for_images(2 * rayon::current_num_threads());
+        let options = ImageOptions::from_model(model);
+        let grid = model.grid.clone().expect("Failed to get grid. Internal BUG!");
+        let sim_time_elapsed = model.sim_time_elapsed;
+        let saved_tx = self.saved_tx.clone();
+        self.pending += 1;
+        rayon::spawn(move || {
+            try_save_grid_image(&options, &grid, sim_time_elapsed, &file_name);
+            let _ = saved_tx.send(());
+        });
+        self.next += self.interval;
+    }
+
+    /// Wait until at most `max_pending` images are being encoded. On a thread of the rayon pool,
+    /// such as a run of `grunner`, other jobs of the pool are run while waiting so that the
+    /// images are encoded even if all threads of the pool are waiting.
+    fn wait_for_images(&mut self, max_pending: usize) {
+        while self.pending > max_pending {
+            let saved = match rayon::yield_now() {
+                Some(rayon::Yield::Executed) => self.saved_rx.try_recv().is_ok(),
+                Some(rayon::Yield::Idle) => self.saved_rx.recv_timeout(Duration::from_millis(1)).is_ok(),
+                None => self.saved_rx.recv().is_ok(),
+            };
+            if saved {
+                self.pending -= 1;
+            }
+        }
+    }
+}
+
+impl SimObserver for SnapshotObserver {
+    fn on_step(
+        &mut self,
+        model: &mut SimModel,
+        _cutter_center: &Vector,
+        _events: &StepEvents,
+    ) -> ControlFlow<()> {
+        while self.in_series() && self.reached(model) {
+            self.save_next(model);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn on_end(&mut self, model: &mut SimModel, _cutter_center: &Vector) {
+        while self.last.is_some() && self.in_series() {
+            self.save_next(model);
+        }
+        self.wait_for_images(0);
+    }
+}
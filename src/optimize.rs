@@ -0,0 +1,162 @@
+//! Black-box optimisers for noisy objectives of a few continuous parameters, used by `grunner`
+//! to search for the best gridcover settings. All points are in the unit cube `[0, 1]^n`, the
+//! caller maps them to the bounds of the parameters.
+
+use rand::Rng;
+
+/// Initial step size of the CMA-ES search relative to the unit cube
+pub const INITIAL_SIGMA: f64 = 0.3;
+
+/// A uniformly distributed point in the unit cube
+pub fn random_point(dim: usize, rng: &mut impl Rng) -> Vec<f64> {
+    (0..dim).map(|_| rng.random_range(0.0..=1.0)).collect()
+}
+
+/// The point clipped to the unit cube
+pub fn clip_to_unit_cube(point: &[f64]) -> Vec<f64> {
+    point.iter().map(|x| x.clamp(0.0, 1.0)).collect()
+}
+
+/// A standard normally distributed value (Box-Muller transform)
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = rng.random_range(f64::EPSILON..1.0);
+    let u2: f64 = rng.random_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+}
+
+/// The default CMA-ES population size for the dimension, `4 + 3 ln(n)`
+pub fn default_population(dim: usize) -> usize {
+    4 + (3.0 * (dim.max(1) as f64).ln()).floor() as usize
+}
+
+/// Separable CMA-ES (Ros and Hansen, 2008), the covariance evolution strategy restricted to a
+/// diagonal covariance matrix. Each generation `ask()` samples a population around the mean and
+/// `tell()` moves the mean, step size and per-parameter variances towards the best half of it.
+/// The sampled points can lie outside the unit cube. The caller evaluates them clipped to it with
+/// `clip_to_unit_cube()` but tells the points as sampled, so the update follows the steps that
+/// were drawn and the mean is not pulled onto the bounds.
+#[derive(Debug, Clone)]
+pub struct SepCmaEs {
+    dim: usize,
+    population: usize,
+    mean: Vec<f64>,
+    sigma: f64,
+    /// Diagonal of the covariance matrix
+    variances: Vec<f64>,
+    path_c: Vec<f64>,
+    path_sigma: Vec<f64>,
+    weights: Vec<f64>,
+    mu_eff: f64,
+    c_c: f64,
+    c_sigma: f64,
+    c_1: f64,
+    c_mu: f64,
+    damping: f64,
+    /// Expected length of a standard normally distributed vector
+    chi_n: f64,
+    generation: u32,
+}
+
+impl SepCmaEs {
+    pub fn new(mean: Vec<f64>, sigma: f64, population: usize) -> Self {
+        let dim = mean.len();
+        let n = dim as f64;
+        let population = population.max(2);
+        let mu = population / 2;
+        let raw_weights: Vec<f64> = (1..=mu).map(|i| (mu as f64 + 0.5).ln() - (i as f64).ln()).collect();
+        let weight_sum: f64 = raw_weights.iter().sum();
+        let weights: Vec<f64> = raw_weights.iter().map(|w| w / weight_sum).collect();
+        let mu_eff = 1.0 / weights.iter().map(|w| w * w).sum::<f64>();
+
+        let c_sigma = (mu_eff + 2.0) / (n + mu_eff + 5.0);
+        let damping = 1.0 + 2.0 * (((mu_eff - 1.0) / (n + 1.0)).sqrt() - 1.0).max(0.0) + c_sigma;
+        let c_c = (4.0 + mu_eff / n) / (n + 4.0 + 2.0 * mu_eff / n);
+        // The learning rates of the full CMA-ES, scaled up as only the diagonal is learned
+        let c_1 = 2.0 / ((n + 1.3).powi(2) + mu_eff);
+        let c_mu = (2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((n + 2.0).powi(2) + mu_eff)).min(1.0 - c_1);
+        let scale = (n + 2.0) / 3.0;
+        let (c_1, c_mu) = if (c_1 + c_mu) * scale < 1.0 {
+            (c_1 * scale, c_mu * scale)
+        } else {
+            (c_1 / (c_1 + c_mu), c_mu / (c_1 + c_mu))
+        };
+
+        SepCmaEs {
+            dim,
+            population,
+            mean,
+            sigma,
+            variances: vec![1.0; dim],
+            path_c: vec![0.0; dim],
+            path_sigma: vec![0.0; dim],
+            weights,
+            mu_eff,
+            c_c,
+            c_sigma,
+            c_1,
+            c_mu,
+            damping,
+            chi_n: n.sqrt() * (1.0 - 1.0 / (4.0 * n) + 1.0 / (21.0 * n * n)),
+            generation: 0,
+        }
+    }
+
+    /// Sample the points of the next generation, they are not clipped to the unit cube
+    pub fn ask(&self, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+        (0..self.population)
+            .map(|_| {
+                (0..self.dim)
+                    .map(|i| self.mean[i] + self.sigma * self.variances[i].sqrt() * standard_normal(rng))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Update the search distribution with the objective values of the points of a generation as
+    /// returned by `ask()`, smaller values are better
+    pub fn tell(&mut self, points: &[Vec<f64>], values: &[f64]) {
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+
+        // Weighted mean of the steps of the best points, in units of sigma
+        let steps: Vec<Vec<f64>> = order
+            .iter()
+            .take(self.weights.len())
+            .map(|&i| (0..self.dim).map(|d| (points[i][d] - self.mean[d]) / self.sigma).collect())
+            .collect();
+        let step_mean: Vec<f64> = (0..self.dim)
+            .map(|d| steps.iter().zip(&self.weights).map(|(step, w)| w * step[d]).sum())
+            .collect();
+        for (mean, step) in self.mean.iter_mut().zip(&step_mean) {
+            *mean += self.sigma * step;
+        }
+
+        // Evolution paths of the step size and the covariance
+        let norm_sigma = (self.c_sigma * (2.0 - self.c_sigma) * self.mu_eff).sqrt();
+        for ((path, step), variance) in self.path_sigma.iter_mut().zip(&step_mean).zip(&self.variances) {
+            *path = (1.0 - self.c_sigma) * *path + norm_sigma * step / variance.sqrt();
+        }
+        let path_sigma_len = self.path_sigma.iter().map(|p| p * p).sum::<f64>().sqrt();
+        self.generation += 1;
+        let stalled = path_sigma_len / (1.0 - (1.0 - self.c_sigma).powi(2 * self.generation as i32)).sqrt()
+            >= (1.4 + 2.0 / (self.dim as f64 + 1.0)) * self.chi_n;
+        let h_sigma = if stalled { 0.0 } else { 1.0 };
+        let norm_c = (self.c_c * (2.0 - self.c_c) * self.mu_eff).sqrt();
+        for (path, step) in self.path_c.iter_mut().zip(&step_mean) {
+            *path = (1.0 - self.c_c) * *path + h_sigma * norm_c * step;
+        }
+
+        // Rank-one and rank-mu update of the variances
+        for d in 0..self.dim {
+            let rank_mu: f64 = steps.iter().zip(&self.weights).map(|(step, w)| w * step[d] * step[d]).sum();
+            let rank_one = self.path_c[d] * self.path_c[d]
+                + (1.0 - h_sigma) * self.c_c * (2.0 - self.c_c) * self.variances[d];
+            self.variances[d] =
+                (1.0 - self.c_1 - self.c_mu) * self.variances[d] + self.c_1 * rank_one + self.c_mu * rank_mu;
+        }
+
+        self.sigma *= ((self.c_sigma / self.damping) * (path_sigma_len / self.chi_n - 1.0)).exp();
+        // A step size larger than the unit cube only samples its corners
+        self.sigma = self.sigma.min(1.0);
+    }
+}
@@ -0,0 +1,194 @@
+//! Optimisation files declaring a search for the best gridcover settings with `grunner`. An
+//! optimisation file gives the objective, measured over a number of random seeds, and the
+//! gridcover options to search together with their bounds.
+
+use crate::experimentfile::{check_reserved_args, is_reserved_arg};
+use crate::metric::RunMetric;
+use crate::optimize::default_population;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+
+/// Decimals of the parameter values tried, so they are short enough to read in the history
+const VALUE_DECIMALS: i32 = 6;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OptimizeFile {
+    pub name: String,
+    pub description: Option<String>,
+    /// gridcover options used for every run, e.g. "-M garden.yaml -t 36000"
+    #[serde(default)]
+    pub base_args: String,
+    /// Number of random seeds each candidate is run with, all candidates use the same seeds
+    #[serde(default = "default_seeds")]
+    pub seeds: u64,
+    /// The first random seed, the seeds are `first_seed..first_seed + seeds`
+    #[serde(default = "default_first_seed")]
+    pub first_seed: u64,
+    /// The metric whose mean over the seeds is minimised (time) or maximised (coverage)
+    pub objective: RunMetric,
+    pub parameters: Vec<BoundedParameter>,
+    #[serde(default)]
+    pub search: Search,
+    #[serde(default)]
+    pub outputs: OptimizeOutputs,
+}
+
+fn default_seeds() -> u64 {
+    5
+}
+
+fn default_first_seed() -> u64 {
+    1
+}
+
+/// A gridcover option searched between `min` and `max`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoundedParameter {
+    /// The gridcover option, e.g. "--velocity"
+    pub arg: String,
+    /// Column name in the history, the option without leading dashes if not given
+    pub name: Option<String>,
+    pub min: f64,
+    pub max: f64,
+    /// Only whole numbers are tried
+    #[serde(default)]
+    pub integer: bool,
+}
+
+/// How many candidates are tried
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Search {
+    /// Candidates drawn uniformly within the bounds before the CMA-ES search starts from the best
+    pub random_samples: usize,
+    /// Generations of the CMA-ES search
+    pub generations: usize,
+    /// Candidates per CMA-ES generation, 4 + 3 ln(number of parameters) if not given
+    pub population: Option<usize>,
+    /// Random seed of the search itself, so a search can be repeated
+    pub seed: u64,
+    /// Number of new seeds the best candidate is run with at the end, for an unbiased estimate
+    pub validation_seeds: u64,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Search {
+            random_samples: 10,
+            generations: 10,
+            population: None,
+            seed: 1,
+            validation_seeds: 20,
+        }
+    }
+}
+
+/// What is written, relative to the grunner output directory
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct OptimizeOutputs {
+    /// CSV file with every candidate tried and its objective statistics
+    pub history: String,
+    /// Results database all runs are stored in, none if not given
+    pub database: Option<String>,
+}
+
+impl Default for OptimizeOutputs {
+    fn default() -> Self {
+        OptimizeOutputs {
+            history: "history.csv".to_string(),
+            database: None,
+        }
+    }
+}
+
+impl BoundedParameter {
+    /// Column name of the parameter in the history
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.arg.trim_start_matches('-').to_string())
+    }
+
+    /// The parameter value at the relative position `unit` (0 to 1) between the bounds
+    pub fn value(&self, unit: f64) -> f64 {
+        let value = self.min + unit.clamp(0.0, 1.0) * (self.max - self.min);
+        if self.integer {
+            value.round()
+        } else {
+            let factor = 10f64.powi(VALUE_DECIMALS);
+            (value * factor).round() / factor
+        }
+    }
+}
+
+impl OptimizeFile {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Cannot read optimisation file '{path}': {e}"))?;
+        let optimize: OptimizeFile =
+            serde_yaml::from_str(&content).map_err(|e| format!("Invalid optimisation file '{path}': {e}"))?;
+        optimize.check()?;
+        Ok(optimize)
+    }
+
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        if self.seeds == 0 {
+            return Err("The number of seeds must be at least 1".into());
+        }
+        if self.parameters.is_empty() {
+            return Err("At least one parameter to optimise must be given".into());
+        }
+        if self.search.random_samples == 0 && self.search.generations == 0 {
+            return Err("Give random_samples or generations, or both, so that candidates are tried".into());
+        }
+        self.objective.check()?;
+        for parameter in &self.parameters {
+            if !parameter.arg.starts_with('-') {
+                return Err(format!("Parameter arg '{}' must be a gridcover option such as --velocity", parameter.arg).into());
+            }
+            if is_reserved_arg(&parameter.arg) {
+                return Err(format!("Option '{}' is set by grunner for each run and can not be optimised", parameter.arg).into());
+            }
+            if parameter.min >= parameter.max || parameter.min.is_nan() || parameter.max.is_nan() {
+                return Err(format!("Parameter '{}': min must be less than max", parameter.name()).into());
+            }
+        }
+        check_reserved_args(&self.base_args()?, "base_args")
+    }
+
+    /// The base options split into command line arguments
+    pub fn base_args(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(shell_words::split(&self.base_args)?)
+    }
+
+    /// The random seeds each candidate is run with
+    pub fn seed_values(&self) -> impl Iterator<Item = u64> + use<> {
+        self.first_seed..self.first_seed + self.seeds
+    }
+
+    /// The seeds the best candidate is validated with, following the search seeds
+    pub fn validation_seed_values(&self) -> impl Iterator<Item = u64> + use<> {
+        let first = self.first_seed + self.seeds;
+        first..first + self.search.validation_seeds
+    }
+
+    /// Candidates per CMA-ES generation
+    pub fn population(&self) -> usize {
+        self.search.population.unwrap_or_else(|| default_population(self.parameters.len()))
+    }
+
+    /// The parameter values of a point in the unit cube
+    pub fn parameter_values(&self, point: &[f64]) -> Vec<f64> {
+        self.parameters.iter().zip(point).map(|(parameter, &unit)| parameter.value(unit)).collect()
+    }
+
+    /// The command line arguments setting the parameter values
+    pub fn point_args(&self, values: &[f64]) -> Vec<String> {
+        self.parameters
+            .iter()
+            .zip(values)
+            .flat_map(|(parameter, value)| [parameter.arg.clone(), value.to_string()])
+            .collect()
+    }
+}
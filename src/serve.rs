@@ -0,0 +1,448 @@
+use crate::args::ServeArgs;
+use crate::color_theme::ColorThemeManager;
+use crate::image::create_grid_image_in_memory;
+use crate::model::SimModel;
+use crate::observer::SimObserver;
+use crate::sim::StepEvents;
+use crate::vector::Vector;
+use base64::Engine;
+use colored::Colorize;
+use serde_json::json;
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::ControlFlow;
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Interval in real time between two incremental updates sent to the browser
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Largest side in pixels of the snapshot images sent to the browser
+const MAX_SNAPSHOT_SIDE: u32 = 1000;
+
+/// Clients that do not accept data within this time are dropped
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Connections that do not send their request within this time are closed
+const CLIENT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Events queued for a client before it is considered too slow and dropped
+const CLIENT_QUEUE_LENGTH: usize = 64;
+
+/// State shared between the simulation thread and the connection threads
+#[derive(Default)]
+struct SharedState {
+    /// Queues of the connected event stream clients, each drained by its own writer thread
+    clients: Vec<SyncSender<Arc<str>>>,
+    init_event: Option<String>,
+    snapshot_event: Option<String>,
+    stats_event: Option<String>,
+    snapshot_png: Option<Vec<u8>>,
+    /// Cells covered since the last snapshot, flattened as x, y pairs
+    cells_since_snapshot: Vec<usize>,
+    end_event: Option<String>,
+}
+
+/// Small HTTP server on localhost that streams the running simulation to a browser
+/// using server-sent events. The page itself is self-contained and needs no external resources.
+pub struct LiveServer {
+    config: ServeArgs,
+    shared: Arc<Mutex<SharedState>>,
+    started: Instant,
+    last_publish: Instant,
+    last_snapshot: Instant,
+}
+
+impl LiveServer {
+    /// Bind to localhost on the configured port and start accepting connections in the background
+    pub fn start(config: ServeArgs) -> Result<Self, Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind(("127.0.0.1", config.port))?;
+        let shared = Arc::new(Mutex::new(SharedState::default()));
+
+        let accept_shared = Arc::clone(&shared);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let conn_shared = Arc::clone(&accept_shared);
+                std::thread::spawn(move || handle_connection(stream, conn_shared));
+            }
+        });
+
+        let now = Instant::now();
+        Ok(LiveServer {
+            config,
+            shared,
+            started: now,
+            last_publish: now,
+            last_snapshot: now,
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/", self.config.port)
+    }
+
+    /// Send the static grid information and a first snapshot. Must be called before the
+    /// simulation starts so that the grid starts logging newly covered cells.
+    fn begin(&mut self, model: &mut SimModel) {
+        let grid = model.grid.as_mut().expect("Failed to get grid. Internal BUG!");
+        grid.newly_covered = Some(Vec::new());
+
+        let theme_manager = ColorThemeManager::new();
+        let theme = theme_manager.get_theme(model.color_theme.as_deref().unwrap_or("default"));
+        let init = json!({
+            "cells_x": grid.cells_x,
+            "cells_y": grid.cells_y,
+            "cell_size": grid.cell_size,
+            "radius": model.radius,
+            "cover_color": theme.get_coverage_color(1),
+            "background_color": theme.grid_background_color,
+        });
+        let event = sse_event("init", &init.to_string());
+        self.shared.lock().unwrap().init_event = Some(event.clone());
+        self.broadcast(&event);
+
+        self.publish_snapshot(model);
+        self.started = Instant::now();
+    }
+
+    /// Called every simulation step. Publishes incremental updates at a fixed real time interval,
+    /// full snapshots at the configured interval and throttles the simulation to the realtime factor.
+    fn update(&mut self, model: &mut SimModel, cutter_center: &Vector) {
+        if self.last_publish.elapsed() < PUBLISH_INTERVAL {
+            return;
+        }
+        self.last_publish = Instant::now();
+        self.publish_progress(model, cutter_center);
+
+        if self.last_snapshot.elapsed().as_secs_f64() >= self.config.snapshot_interval {
+            self.publish_snapshot(model);
+        }
+
+        // Slow down so that one real second corresponds to `realtime_factor` simulated seconds
+        if self.config.realtime_factor > 0.0 {
+            let target = Duration::from_secs_f64(model.sim_time_elapsed / self.config.realtime_factor);
+            let elapsed = self.started.elapsed();
+            if target > elapsed {
+                std::thread::sleep((target - elapsed).min(Duration::from_secs(1)));
+            }
+        }
+    }
+
+    /// Send the final state of the simulation
+    fn finish(&mut self, model: &mut SimModel, cutter_center: &Vector) {
+        self.publish_progress(model, cutter_center);
+        self.publish_snapshot(model);
+        let result = model.get_simulation_result_short_as_json();
+        let event = sse_event("end", &result.to_string());
+        self.shared.lock().unwrap().end_event = Some(event.clone());
+        self.broadcast(&event);
+    }
+
+    fn publish_progress(&mut self, model: &mut SimModel, cutter_center: &Vector) {
+        let grid = model.grid.as_mut().expect("Failed to get grid. Internal BUG!");
+        if let Some(cells) = grid.newly_covered.as_mut()
+            && !cells.is_empty()
+        {
+            let flat: Vec<usize> = cells.iter().flat_map(|&(x, y)| [x, y]).collect();
+            cells.clear();
+            let event = sse_event("cells", &json!(flat).to_string());
+            self.shared.lock().unwrap().cells_since_snapshot.extend_from_slice(&flat);
+            self.broadcast(&event);
+        }
+
+        let (covered, percent) = grid.get_coverage();
+        let stats = json!({
+            "x": cutter_center.x,
+            "y": cutter_center.y,
+            "coverage_percent": percent,
+            "coverage_cells": covered,
+            "bounces": model.segment_number,
+            "distance": model.distance_covered,
+            "sim_time": model.sim_time_elapsed,
+            "sim_steps": model.sim_steps,
+            "battery_charge_left": if model.battery_run_time > 0.0 { Some(model.battery_charge_left) } else { None },
+            "battery_charge_count": model.battery_charge_count,
+        });
+        let event = sse_event("stats", &stats.to_string());
+        self.shared.lock().unwrap().stats_event = Some(event.clone());
+        self.broadcast(&event);
+    }
+
+    fn publish_snapshot(&mut self, model: &SimModel) {
+        self.last_snapshot = Instant::now();
+        let png = match encode_snapshot(model) {
+            Ok(png) => png,
+            Err(err) => {
+                eprintln!(
+                    "{} {}",
+                    "Warning: Failed to create live view snapshot:"
+                        .color(colored::Color::Yellow)
+                        .bold(),
+                    err
+                );
+                return;
+            }
+        };
+        let data = json!({
+            "png": base64::engine::general_purpose::STANDARD.encode(&png),
+        });
+        let event = sse_event("snapshot", &data.to_string());
+        {
+            let mut shared = self.shared.lock().unwrap();
+            shared.snapshot_event = Some(event.clone());
+            shared.snapshot_png = Some(png);
+            shared.cells_since_snapshot.clear();
+        }
+        self.broadcast(&event);
+    }
+
+    /// Queue the event for every client. The writing happens on the writer thread of each client,
+    /// so a slow browser never blocks the simulation.
+    fn broadcast(&self, event: &str) {
+        let event: Arc<str> = Arc::from(event);
+        let mut shared = self.shared.lock().unwrap();
+        shared.clients.retain(|client| client.try_send(Arc::clone(&event)).is_ok());
+    }
+}
+
+impl SimObserver for LiveServer {
+    fn on_start(&mut self, model: &mut SimModel, _cutter_center: &Vector) {
+        self.begin(model);
+    }
+
+    fn on_step(
+        &mut self,
+        model: &mut SimModel,
+        cutter_center: &Vector,
+        _events: &StepEvents,
+    ) -> ControlFlow<()> {
+        self.update(model, cutter_center);
+        ControlFlow::Continue(())
+    }
+
+    fn on_end(&mut self, model: &mut SimModel, cutter_center: &Vector) {
+        self.finish(model, cutter_center);
+    }
+}
+
+/// Keep serving the final state until the user presses Enter. The server keeps running in the
+/// background after the simulation has ended and the `LiveServer` has been dropped.
+pub fn wait_for_shutdown(url: &str, quiet: bool) {
+    if !quiet {
+        println!(
+            "{} {}",
+            "Simulation finished, live view still available at".color(colored::Color::Green).bold(),
+            url.color(colored::Color::Cyan).bold()
+        );
+        println!("Press Enter to stop the server.");
+    }
+    let mut line = String::new();
+    let _ = std::io::stdin().read_line(&mut line);
+}
+
+/// Render the grid with the same routine as the PNG output, scaled down to a size suitable for a browser
+fn encode_snapshot(model: &SimModel) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut img = create_grid_image_in_memory(model)?;
+    let largest_side = img.width().max(img.height());
+    if largest_side > MAX_SNAPSHOT_SIDE {
+        let factor = MAX_SNAPSHOT_SIDE as f64 / largest_side as f64;
+        img = ::image::imageops::resize(
+            &img,
+            ((img.width() as f64 * factor) as u32).max(1),
+            ((img.height() as f64 * factor) as u32).max(1),
+            ::image::imageops::FilterType::Triangle,
+        );
+    }
+    let mut png = Vec::new();
+    img.write_to(&mut Cursor::new(&mut png), ::image::ImageFormat::Png)?;
+    Ok(png)
+}
+
+fn sse_event(name: &str, data: &str) -> String {
+    format!("event: {name}\ndata: {data}\n\n")
+}
+
+fn handle_connection(mut stream: TcpStream, shared: Arc<Mutex<SharedState>>) {
+    if stream.set_read_timeout(Some(CLIENT_READ_TIMEOUT)).is_err() {
+        return;
+    }
+    let mut request_line = String::new();
+    {
+        let mut reader = BufReader::new(&stream);
+        if reader.read_line(&mut request_line).is_err() {
+            return;
+        }
+        // Consume the request headers, we have no use for them
+        let mut header = String::new();
+        while reader.read_line(&mut header).map(|n| n > 2).unwrap_or(false) {
+            header.clear();
+        }
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+    let _ = stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT));
+    match path {
+        "/" | "/index.html" => {
+            let _ = write_response(&mut stream, "200 OK", "text/html; charset=utf-8", INDEX_HTML.as_bytes());
+        }
+        "/snapshot.png" => {
+            let png = shared.lock().unwrap().snapshot_png.clone();
+            let _ = match png {
+                Some(png) => write_response(&mut stream, "200 OK", "image/png", &png),
+                None => write_response(&mut stream, "404 Not Found", "text/plain", b"No snapshot yet"),
+            };
+        }
+        "/events" => {
+            let header = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n";
+            let (sender, receiver) = sync_channel(CLIENT_QUEUE_LENGTH);
+            let mut shared = shared.lock().unwrap();
+            // Bring a newly connected browser up to date before it starts receiving incremental updates:
+            // the last snapshot, the cells covered since and the end of the run if it is over
+            let cells_event = (!shared.cells_since_snapshot.is_empty())
+                .then(|| sse_event("cells", &json!(shared.cells_since_snapshot).to_string()));
+            let catch_up: String = [
+                &shared.init_event,
+                &shared.snapshot_event,
+                &cells_event,
+                &shared.stats_event,
+                &shared.end_event,
+            ]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+            // Register the client before releasing the lock so that no event between the catch up
+            // and the first queued event is lost, but write outside the lock
+            shared.clients.push(sender);
+            drop(shared);
+            if stream.write_all(header.as_bytes()).is_ok() && stream.write_all(catch_up.as_bytes()).is_ok() {
+                stream_events(stream, receiver);
+            }
+        }
+        _ => {
+            let _ = write_response(&mut stream, "404 Not Found", "text/plain", b"Not found");
+        }
+    }
+}
+
+/// Write queued events to the client until it disconnects or the server drops its queue
+fn stream_events(mut stream: TcpStream, receiver: Receiver<Arc<str>>) {
+    for event in receiver {
+        if stream.write_all(event.as_bytes()).is_err() || stream.flush().is_err() {
+            return;
+        }
+    }
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>gridcover live view</title>
+<style>
+  body { font-family: sans-serif; background: #202020; color: #e0e0e0; margin: 1em; }
+  #view { position: relative; display: inline-block; }
+  #view canvas { position: absolute; left: 0; top: 0; }
+  #stats { font-family: monospace; margin-top: 0.5em; white-space: pre; }
+  #state { color: #ffd000; }
+</style>
+</head>
+<body>
+<h2>gridcover live view <span id="state">connecting ...</span></h2>
+<div id="view"><canvas id="grid"></canvas><canvas id="cutter"></canvas></div>
+<div id="stats"></div>
+<script>
+const gridCanvas = document.getElementById('grid');
+const cutterCanvas = document.getElementById('cutter');
+const view = document.getElementById('view');
+const g = gridCanvas.getContext('2d');
+const c = cutterCanvas.getContext('2d');
+let info = null;
+let scale = 1;
+
+function rgb(a) { return 'rgb(' + a[0] + ',' + a[1] + ',' + a[2] + ')'; }
+function hms(t) {
+  t = Math.floor(t);
+  const p = n => String(n).padStart(2, '0');
+  return p(Math.floor(t / 3600)) + ':' + p(Math.floor(t % 3600 / 60)) + ':' + p(t % 60);
+}
+
+const es = new EventSource('/events');
+es.onopen = () => document.getElementById('state').textContent = 'running';
+es.onerror = () => document.getElementById('state').textContent = 'disconnected';
+
+es.addEventListener('init', e => {
+  info = JSON.parse(e.data);
+  const maxSide = Math.min(window.innerWidth - 40, (window.innerHeight - 150) * info.cells_x / info.cells_y);
+  scale = Math.max(maxSide, 100) / info.cells_x;
+  for (const cv of [gridCanvas, cutterCanvas]) {
+    cv.width = Math.round(info.cells_x * scale);
+    cv.height = Math.round(info.cells_y * scale);
+  }
+  view.style.width = gridCanvas.width + 'px';
+  view.style.height = gridCanvas.height + 'px';
+  g.fillStyle = rgb(info.background_color);
+  g.fillRect(0, 0, gridCanvas.width, gridCanvas.height);
+});
+
+es.addEventListener('snapshot', e => {
+  const img = new Image();
+  img.onload = () => g.drawImage(img, 0, 0, gridCanvas.width, gridCanvas.height);
+  img.src = 'data:image/png;base64,' + JSON.parse(e.data).png;
+});
+
+es.addEventListener('cells', e => {
+  if (!info) return;
+  const cells = JSON.parse(e.data);
+  g.fillStyle = rgb(info.cover_color);
+  const s = Math.max(scale, 1);
+  for (let i = 0; i < cells.length; i += 2) {
+    g.fillRect(cells[i] * scale, (info.cells_y - 1 - cells[i + 1]) * scale, s, s);
+  }
+});
+
+es.addEventListener('stats', e => {
+  const s = JSON.parse(e.data);
+  if (info) {
+    c.clearRect(0, 0, cutterCanvas.width, cutterCanvas.height);
+    const k = scale / info.cell_size;
+    c.strokeStyle = '#ffd000';
+    c.lineWidth = 2;
+    c.beginPath();
+    c.arc(s.x * k, cutterCanvas.height - s.y * k, Math.max(info.radius * k, 3), 0, 2 * Math.PI);
+    c.stroke();
+  }
+  let text = 'Coverage : ' + s.coverage_percent.toFixed(2) + '% (' + s.coverage_cells + ' cells)\n'
+    + 'Sim-Time : ' + hms(s.sim_time) + '\n'
+    + 'Distance : ' + s.distance.toFixed(2) + '\n'
+    + 'Bounces  : ' + s.bounces + '\n'
+    + 'Steps    : ' + s.sim_steps;
+  if (s.battery_charge_left !== null) {
+    text += '\nBattery  : ' + s.battery_charge_left.toFixed(1) + '% (charges: ' + s.battery_charge_count + ')';
+  }
+  document.getElementById('stats').textContent = text;
+});
+
+es.addEventListener('end', () => {
+  document.getElementById('state').textContent = 'finished';
+});
+</script>
+</body>
+</html>
+"#;
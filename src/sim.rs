@@ -1,16 +1,27 @@
 // use crate::cells::{calc_grid_coverage, mark_covered_cells};
 use crate::collision::is_grid_edge;
-use crate::image::try_save_image;
 use crate::model::SimModel;
+use crate::model::grid::Zone;
+use crate::model::terrain::Terrain;
+use crate::stopcond::StopMetrics;
 use crate::strategy::cutter_strategy;
 use crate::vector::Vector;
-// use colored::Colorize;
 use rand::Rng;
-use std::io::Write;
+use std::collections::VecDeque;
 use thousands::Separable;
 
 pub const FAILSAFE_TIME_LIMIT: f64 = 7.0 * 24.0 * 3600.0; // 7 days in simulated time to prevent infinite loop
 
+/// Simulated seconds between the coverage samples used for the coverage gain per hour
+const COVERAGE_SAMPLE_INTERVAL: f64 = 60.0;
+
+/// Distance travelled between two recorded positions of the cutter path
+pub const PATH_SAMPLE_DISTANCE: f64 = 0.1;
+
+/// Largest movement in one step, step and terrain drift together, relative to the cell size. Cells
+/// are covered before the collision check, so the cutter must never move a whole cell in a step.
+const MAX_DISPLACEMENT_FRACTION_OF_CELL: f64 = 0.95;
+
 fn fast_inv_sqrt(x: f64) -> f64 {
     let i = 0x5FE6EB50C7B537A9 - (x.to_bits() >> 1);
     let y = f64::from_bits(i);
@@ -23,84 +34,8 @@ fn normalize_vector(v: &mut Vector) {
     v.y *= inv_length;
 }
 
-fn print_progress(
-    model: &SimModel,
-    frame_counter: u64,
-    current_coverage_percent: f64,
-    coverage_cell_count: usize,
-) {
-    if model.show_progress {
-        if model.battery_run_time > 0.0 {
-            if model.generate_frames {
-                print!(
-                    "\rFrame: {:>06}, Coverage: {:>6.2}% ({:>7}/{:>7} cells covered), Distance: {:>6.2}, Bounces: {:>4}, Sim-Time: {:02}:{:02}:{:02}, Battery capacity left: {:>5.1}%",
-                    frame_counter,
-                    current_coverage_percent,
-                    coverage_cell_count,
-                    model.grid_cells_x * model.grid_cells_y - model.grid_cells_obstacles_count,
-                    model.distance_covered,
-                    model.segment_number,
-                    model.sim_time_elapsed as u64 / 3600,
-                    (model.sim_time_elapsed as u64 % 3600) / 60,
-                    model.sim_time_elapsed as u64 % 60,
-                    model.battery_charge_left
-                );
-            } else {
-                print!(
-                    "\rCoverage: {:>6.2}% ({:>7}/{:>7} cells covered), Distance: {:>6.2}, Bounces: {:>4}, Sim-Time: {:02}:{:02}:{:02}, Battery capacity left: {:>5.1}%",
-                    current_coverage_percent,
-                    coverage_cell_count,
-                    model.grid_cells_x * model.grid_cells_y - model.grid_cells_obstacles_count,
-                    model.distance_covered,
-                    model.segment_number,
-                    model.sim_time_elapsed as u64 / 3600,
-                    (model.sim_time_elapsed as u64 % 3600) / 60,
-                    model.sim_time_elapsed as u64 % 60,
-                    model.battery_charge_left
-                );
-            }
-        } else if model.generate_frames {
-            print!(
-                "\rFrame: {:>06}, Coverage: {:>6.2}% ({:>7}/{:>7} cells covered), Distance: {:>6.2}, Bounces: {:>4}, Sim-Time: {:02}:{:02}:{:02}",
-                frame_counter,
-                current_coverage_percent,
-                coverage_cell_count,
-                model.grid_cells_x * model.grid_cells_y - model.grid_cells_obstacles_count,
-                model.distance_covered,
-                model.segment_number,
-                model.sim_time_elapsed as u64 / 3600,
-                (model.sim_time_elapsed as u64 % 3600) / 60,
-                model.sim_time_elapsed as u64 % 60,
-            );
-        } else {
-            print!(
-                "\rCoverage: {:>6.2}% ({:>7}/{:>7} cells covered), Distance: {:>6.2}, Bounces: {:>4}, Sim-Time: {:02}:{:02}:{:02}",
-                current_coverage_percent,
-                coverage_cell_count,
-                model.grid_cells_x * model.grid_cells_y - model.grid_cells_obstacles_count,
-                model.distance_covered,
-                model.segment_number,
-                model.sim_time_elapsed as u64 / 3600,
-                (model.sim_time_elapsed as u64 % 3600) / 60,
-                model.sim_time_elapsed as u64 % 60,
-            );
-        }
-        if model.verbosity > 1 {
-            print!(
-                ", Memory: {:>5} MB/{:>3} GB",
-                model.ram_usage_mb.separate_with_commas(),
-                model.ram_size_gb
-            );
-        }
-        std::io::stdout().flush().unwrap();
-    }
-}
-
-fn handle_battery_charge(
-    model: &mut SimModel,
-    mut time_elapsed_since_last_charge: f64,
-    rng: &mut impl Rng,
-) -> f64 {
+/// Update the charge left and return true if the battery has run out
+fn battery_ran_out(model: &mut SimModel, time_elapsed_since_last_charge: f64) -> bool {
     // Check if we should consider battery run-time
     if model.battery_run_time > 0.0 {
         // If the battery run time is set, we need to check if we have reached it
@@ -109,23 +44,24 @@ fn handle_battery_charge(
         model.battery_charge_left =
             100.0 - (time_elapsed_since_last_charge / (model.battery_run_time * 60.0)) * 100.0;
 
-        if time_elapsed_since_last_charge > model.battery_run_time * 60.0 {
-            // If we have reached or exceeded the battery run time, we stop the simulation
-            // We add a random time between 3 and 15 minutes to simulate time for the cutter to find its way back to the charging station
-            let random_time = rng.random_range(180.0..=900.0);
-            model.sim_time_elapsed += random_time;
-            if model.show_progress && model.verbosity > 1 {
-                println!(
-                    "\nBattery run time reached. Time to find charging station: {:.1} minutes",
-                    random_time / 60.0
-                );
-            }
-            time_elapsed_since_last_charge = 0.0;
-            model.sim_time_elapsed += model.battery_charge_time * 60.0; // Add the charging time in seconds
-            model.battery_charge_count += 1;
-        }
+        return time_elapsed_since_last_charge > model.battery_run_time * 60.0;
+    }
+    false
+}
+
+/// Add the time to find the charging station and recharge an empty battery
+fn recharge_battery(model: &mut SimModel, rng: &mut impl Rng) {
+    // We add a random time between 3 and 15 minutes to simulate time for the cutter to find its way back to the charging station
+    let random_time = rng.random_range(180.0..=900.0);
+    model.sim_time_elapsed += random_time;
+    if model.show_progress && model.verbosity > 1 {
+        println!(
+            "\nBattery run time reached. Time to find charging station: {:.1} minutes",
+            random_time / 60.0
+        );
     }
-    time_elapsed_since_last_charge
+    model.sim_time_elapsed += model.battery_charge_time * 60.0; // Add the charging time in seconds
+    model.battery_charge_count += 1;
 }
 
 struct SlippageModel {
@@ -152,6 +88,7 @@ impl SlippageModel {
 
 fn handle_wheel_slippage(
     model: &mut SimModel,
+    terrain: Option<&Terrain>,
     slippage_model: &mut SlippageModel,
     current_dir: &mut Vector,
     rng: &mut impl Rng,
@@ -179,11 +116,18 @@ fn handle_wheel_slippage(
             >= model.slippage_check_activation_distance
         {
             slippage_model.last_activation_check = model.distance_covered;
-            // If not within slippage range, we randomly decide to enter a slippage state
-            if rng.random_range(0.0..1.0) < model.slippage_probability {
+            // If not within slippage range, we randomly decide to enter a slippage state. Slopes and
+            // surfaces with less grip make slippage more likely.
+            let probability = terrain.map_or(model.slippage_probability, |t| t.slippage_probability(model.slippage_probability));
+            if rng.random_range(0.0..1.0) < probability {
                 slippage_model.within_slippage = true;
 
-                let slippage_sign = if rng.random_bool(0.5) { -1.0 } else { 1.0 };
+                // On a slope the cutter always slips downhill
+                let slippage_sign = match terrain {
+                    Some(t) if t.sin_slope > 0.0 => t.downhill_turn(current_dir),
+                    _ if rng.random_bool(0.5) => -1.0,
+                    _ => 1.0,
+                };
                 let slippage_radius = rng
                     .random_range(model.slippage_radius_min..=model.slippage_radius_max)
                     * slippage_sign;
@@ -267,7 +211,155 @@ fn handle_wheel_inbalance(
     }
 }
 
-pub fn simulation_loop(model: &mut SimModel, rng: &mut impl Rng) {
+/// The state of a running simulation that is not part of the model, i.e. the cutter position and
+/// direction together with the internal state of the wheel models and the output counters.
+pub struct SimState {
+    pub cutter_center: Vector,
+    pub current_dir: Vector,
+    pub current_coverage_percent: f64,
+    pub coverage_cell_count: usize,
+    time_since_last_charge: f64,
+    /// The battery ran out in the last step and is recharged before the next one
+    battery_empty: bool,
+    steps_per_20th_percent: u64,
+    slippage_model: SlippageModel,
+    inbalance_model: InbalanceModel,
+    /// (simulated time, coverage percent) samples covering at least the last simulated hour
+    coverage_history: VecDeque<(f64, f64)>,
+    /// Distance covered when the cutter path was last recorded
+    last_path_sample: f64,
+}
+
+impl SimState {
+    /// Setup the start state for a fully initialized model
+    pub fn new(model: &mut SimModel, rng: &mut impl Rng) -> Self {
+        // Initialize the circle position binding it to the bounding box
+        let cutter_center = Vector::new(
+            model.bb.limit_x(model.start_x),
+            model.bb.limit_y(model.start_y),
+        );
+
+        let current_dir = Vector::new(model.start_dir_x, model.start_dir_y);
+
+        let total_cells = model.grid_cells_x * model.grid_cells_y;
+        let one_percent_cells = total_cells / 100;
+        let steps_per_cell = (model.cell_size / model.step_size).ceil() as usize;
+        let steps_per_20th_percent = (((one_percent_cells * steps_per_cell) / 20) as u64).max(1);
+
+        if model.verbosity > 2 {
+            println!(" --> Total Cells: {}", total_cells.separate_with_commas());
+            println!(
+                " --> One Percent Cells: {}",
+                one_percent_cells.separate_with_commas()
+            );
+            println!(
+                " --> Steps per Cell: {}",
+                steps_per_cell.separate_with_commas()
+            );
+            println!(
+                " --> Steps per Tenth Percent: {}",
+                steps_per_20th_percent.separate_with_commas()
+            );
+            println!(
+                " --> Steps per frame: {}",
+                model.steps_per_frame.separate_with_commas()
+            );
+            println!(" --> Model velocity: {}", model.velocity);
+            println!(" --> Model step size: {}", model.step_size);
+            println!(" --> Model frame rate: {}", model.frame_rate);
+        }
+
+        let mut inbalance_model = InbalanceModel::new();
+        inbalance_model.init(model, rng);
+
+        SimState {
+            cutter_center,
+            current_dir,
+            current_coverage_percent: 0.0,
+            coverage_cell_count: 0,
+            time_since_last_charge: 0.0,
+            battery_empty: false,
+            steps_per_20th_percent,
+            slippage_model: SlippageModel::new(),
+            inbalance_model,
+            coverage_history: VecDeque::from([(0.0, 0.0)]),
+            last_path_sample: 0.0,
+        }
+    }
+}
+
+/// The current value of all metrics that can be used in a stop condition
+pub fn stop_metrics(model: &SimModel, state: &SimState) -> StopMetrics {
+    let coverage = model
+        .grid
+        .as_ref()
+        .expect("Failed to get grid. Internal BUG!")
+        .get_coverage_percent();
+
+    // The oldest sample is at least one hour old once an hour has been simulated. The window is
+    // longer than an hour by up to a sample interval, or more after a battery recharge, so the
+    // gain is scaled to one hour.
+    let coverage_gain_per_hour = match state.coverage_history.front() {
+        Some(&(time, percent)) if model.sim_time_elapsed - time >= 3600.0 => {
+            (coverage - percent) * 3600.0 / (model.sim_time_elapsed - time)
+        }
+        _ => f64::INFINITY,
+    };
+
+    StopMetrics {
+        coverage,
+        time: model.sim_time_elapsed,
+        bounces: model.segment_number as f64,
+        steps: model.sim_steps as f64,
+        distance: model.distance_covered,
+        battery_cycles: model.battery_charge_count as f64,
+        coverage_gain_per_hour,
+    }
+}
+
+/// Check if the combined stop condition of the model is met, i.e. the first of the stop options
+/// and the `--stop-when` expression that is reached.
+///
+/// In addition we have a hard limit of `FAILSAFE_TIME_LIMIT` seconds to prevent infinite loops.
+/// This is a safety measure in case of misconfiguration
+pub fn stop_condition_reached(model: &SimModel, state: &SimState) -> bool {
+    model.sim_time_elapsed >= FAILSAFE_TIME_LIMIT
+        || model
+            .stop_condition
+            .as_ref()
+            .is_some_and(|condition| condition.evaluate(&stop_metrics(model, state)))
+}
+
+/// A description of why the simulation stopped, None if it has not stopped
+pub fn stop_reason(model: &SimModel, state: &SimState) -> Option<String> {
+    if model.sim_time_elapsed >= FAILSAFE_TIME_LIMIT {
+        return Some("fail-safe time limit".to_string());
+    }
+    model
+        .stop_condition
+        .as_ref()
+        .and_then(|condition| condition.trigger(&stop_metrics(model, state)))
+}
+
+/// What happened during one simulation step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StepEvents {
+    /// The cutter bounced against the edge of the grid
+    pub bounce: bool,
+    /// The cutter collided with an obstacle
+    pub collision: bool,
+    /// The cutter turned at a virtual fence
+    pub virtual_fence: bool,
+    /// The battery ran out at the end of this step
+    pub battery_empty: bool,
+    /// The battery that ran out in the previous step was recharged before this step
+    pub battery_recharged: bool,
+    /// The coverage used by the stop condition was re-calculated in this step
+    pub coverage_updated: bool,
+}
+
+/// Advance the simulation one step
+pub fn simulation_step(model: &mut SimModel, state: &mut SimState, rng: &mut impl Rng) -> StepEvents {
     // Both wheel slippage and inbalance are modelled as a slight change in the direction vector
     // we model this by multiplying the direction vector with a rotation matrix
     // and using the optimization that sin(a) = a , cos(a) = 1-(a^2)/2  for small angles.
@@ -282,164 +374,151 @@ pub fn simulation_loop(model: &mut SimModel, rng: &mut impl Rng) {
     // which is faster than the standard sqrt function.
     const ERROR_MSG: &str = "Failed to get grid. Internal BUG!";
 
-    let mut current_coverage_percent = 0.0;
-    let mut coverage_cell_count;
-    let mut time_since_last_charge = 0.0;
-
-    // Initialize the circle position binding it to the bounding box
-    let mut cutter_center = Vector::new(
-        model.bb.limit_x(model.start_x),
-        model.bb.limit_y(model.start_y),
-    );
-
-    let mut current_dir = Vector::new(model.start_dir_x, model.start_dir_y);
-
-    let total_cells = model.grid_cells_x * model.grid_cells_y;
-    let one_percent_cells = total_cells / 100;
-    let steps_per_cell = (model.cell_size / model.step_size).ceil() as usize;
-    let steps_per_20th_percent = (((one_percent_cells * steps_per_cell) / 20) as u64).max(1);
+    let mut events = StepEvents::default();
 
-    if model.verbosity > 2 {
-        println!(" --> Total Cells: {}", total_cells.separate_with_commas());
-        println!(
-            " --> One Percent Cells: {}",
-            one_percent_cells.separate_with_commas()
-        );
-        println!(
-            " --> Steps per Cell: {}",
-            steps_per_cell.separate_with_commas()
-        );
-        println!(
-            " --> Steps per Tenth Percent: {}",
-            steps_per_20th_percent.separate_with_commas()
-        );
-        println!(
-            " --> Steps per frame: {}",
-            model.steps_per_frame.separate_with_commas()
-        );
-        println!(" --> Model velocity: {}", model.velocity);
-        println!(" --> Model step size: {}", model.step_size);
-        println!(" --> Model frame rate: {}", model.frame_rate);
+    // A battery that ran out in the previous step is recharged before the cutter moves on
+    if state.battery_empty {
+        recharge_battery(model, rng);
+        state.time_since_last_charge = 0.0;
+        state.battery_empty = false;
+        events.battery_recharged = true;
     }
 
+    model.sim_steps += 1;
 
-    let mut frame_counter: u64 = 0;
-    let mut frame_image_numbering = 0;
-
-    let mut slippage_model = SlippageModel::new();
-    let mut inbalance_model = InbalanceModel::new();
-    inbalance_model.init(model, rng);
-
-    // Run simulation until the first of the stopping conditions is met
-    // - either the specified number of bounces is reached
-    // - or the specified simulation time is reached
-    // - or the specified coverage limit is reached
-    // - or simulations steps
-    // In addition we have a hard limit of 1_000_000.0 seconds to prevent infinite loops
-    // This is a safety measure in case of misconfiguration
-    while (model.stop_bounces == 0
-        || (model.stop_bounces > 0 && model.segment_number < model.stop_bounces))
-        && (model.stop_time == 0.0
-            || (model.stop_time > 0.0 && model.sim_time_elapsed < model.stop_time))
-        && (model.stop_coverage == 0.0
-            || model.stop_coverage > 0.0 && current_coverage_percent < model.stop_coverage)
-        && (model.stop_simsteps == 0
-            || model.stop_simsteps > 0 && model.sim_steps < model.stop_simsteps)
-        && (model.stop_distance == 0.0
-            || model.stop_distance > 0.0 && model.distance_covered < model.stop_distance)
-        && model.sim_time_elapsed < FAILSAFE_TIME_LIMIT
-    {
-        model.sim_steps += 1;
-
-        // Keep track of how far we have moved
-        model.distance_covered += model.step_size;
-
-        // Calculate the next position of the cutter center based on the current direction and step size
-        cutter_center += current_dir * model.step_size;
-
-        // Model an inbalance between the wheels on either side. We model this as a random turning radius
-        handle_wheel_inbalance(model, &mut inbalance_model, &mut current_dir);
-
-        // Simulate one side wheel slippage that will cause the cuttter to slightly alter its course
-        // This is a simple model of slippage, where we randomly change the direction slightly
-        // This is done to simulate a more realistic movement of the cutter
-        handle_wheel_slippage(model, &mut slippage_model, &mut current_dir, rng);
-
-        // Find and mark all grid cells that are fully covered by the circle at the current position
-        model.grid.as_mut().expect(ERROR_MSG).mark_covered_cells(
-            &cutter_center,
-            model.radius,
-            model.segment_number,
-            model.blade_len,
-            model.cutter_type,
-            model.track_center,
-        );
+    // Keep track of how far we have moved
+    model.distance_covered += model.step_size;
 
-        // Check for collisions with boundaries
-        let mut collision_detected = is_grid_edge(&cutter_center, &model.bb, &mut current_dir);
+    // The slope and surface under the cutter changes its speed, makes it slide sideways downhill
+    // and makes wheel slippage more likely
+    let terrain = model.grid.as_ref().expect(ERROR_MSG).get_terrain(&state.cutter_center).copied();
+    let step_dir = state.current_dir;
 
-        // Check if we are colliding with an obstacle
-        if !collision_detected
-            && model
-                .grid
-                .as_mut()
-                .expect(ERROR_MSG)
-                .collision_with_obstacle(&cutter_center, model.radius)
-        {
-            current_dir = -current_dir; // Reverse direction if we hit an obstacle
-            collision_detected = true; // Mark as collision detected 
+    // Calculate the next position of the cutter center based on the current direction and step
+    // size plus the sideways drift on a slope, limited so that the step stays below a cell
+    let mut displacement = state.current_dir * model.step_size;
+    if let Some(terrain) = &terrain {
+        let drift = terrain.drift(&step_dir, model.step_size);
+        let drift_len = (drift.x * drift.x + drift.y * drift.y).sqrt();
+        let max_drift = (model.cell_size * MAX_DISPLACEMENT_FRACTION_OF_CELL - model.step_size).max(0.0);
+        displacement += if drift_len > max_drift { drift * (max_drift / drift_len) } else { drift };
+    }
+    state.cutter_center += displacement;
+
+    // Model an inbalance between the wheels on either side. We model this as a random turning radius
+    handle_wheel_inbalance(model, &mut state.inbalance_model, &mut state.current_dir);
+
+    // Simulate one side wheel slippage that will cause the cuttter to slightly alter its course
+    // This is a simple model of slippage, where we randomly change the direction slightly
+    // This is done to simulate a more realistic movement of the cutter
+    handle_wheel_slippage(model, terrain.as_ref(), &mut state.slippage_model, &mut state.current_dir, rng);
+
+    // Find and mark all grid cells that are fully covered by the circle at the current position
+    model.grid.as_mut().expect(ERROR_MSG).mark_covered_cells(
+        &state.cutter_center,
+        model.radius,
+        model.segment_number,
+        model.blade_len,
+        model.cutter_type,
+        model.track_center,
+    );
 
-            // Make the position un-collided by moving one step in reverse direction
-            // This is to ensure we don't get stuck in the obstacle
-            cutter_center += current_dir * model.step_size;
+    // Check for collisions with boundaries
+    let mut collision_detected = is_grid_edge(&state.cutter_center, &model.bb, &mut state.current_dir);
+    events.bounce = collision_detected;
+
+    // Check if we are colliding with an obstacle or a virtual fence. This is also checked after a
+    // bounce, as drift on a slope can make the cutter slide along the edge into an obstacle.
+    if let Some(zone) = model
+        .grid
+        .as_mut()
+        .expect(ERROR_MSG)
+        .find_collision(&state.cutter_center, model.radius)
+    {
+        // Reverse direction if we hit an obstacle. After a bounce the direction has already been
+        // turned at the edge, so we turn back the way we came.
+        state.current_dir = if collision_detected { -step_dir } else { -state.current_dir };
+        collision_detected = true; // Mark as collision detected 
+        if zone == Zone::VirtualFence {
+            events.virtual_fence = true;
+            model.virtual_fence_bounces += 1;
+        } else {
+            events.collision = true;
         }
 
-        if collision_detected {
-            model.segment_number += 1;
-            // Get the position un-collided
-            cutter_center = Vector {
-                x: model.bb.limit_x(cutter_center.x),
-                y: model.bb.limit_y(cutter_center.y),
-            };
-        }
+        // Make the position un-collided by undoing the whole movement of this step, including
+        // any drift. This is to ensure we don't get stuck in the obstacle
+        state.cutter_center += -displacement;
+    }
 
-        current_dir = cutter_strategy(&current_dir, &cutter_center, collision_detected, model, rng);
+    if collision_detected {
+        model.segment_number += 1;
+        // Get the position un-collided
+        state.cutter_center = Vector {
+            x: model.bb.limit_x(state.cutter_center.x),
+            y: model.bb.limit_y(state.cutter_center.y),
+        };
+    }
 
-        // Update time in the simulation
-        // sim_time_elapsed is in seconds, so we divide the step size by the velocity to get the time for this step
-        // This assumes velocity is in units/second
-        model.sim_time_elapsed += model.step_size / model.velocity;
-        time_since_last_charge += model.step_size / model.velocity;
+    // Record the path of the cutter when it is stored in the database
+    if model.db_store_grid
+        && (collision_detected
+            || model.sim_steps == 1
+            || model.distance_covered - state.last_path_sample >= PATH_SAMPLE_DISTANCE)
+    {
+        state.last_path_sample = model.distance_covered;
+        model.cutter_path.push([state.cutter_center.x as f32, state.cutter_center.y as f32]);
+    }
 
-        time_since_last_charge = handle_battery_charge(model, time_since_last_charge, rng);
+    state.current_dir = cutter_strategy(&state.current_dir, &state.cutter_center, collision_detected, model, rng);
 
-        if model.sim_steps == 1 || 
-            model.sim_steps % steps_per_20th_percent == 0 || (frame_counter>0 && frame_counter % 50 == 0) {
-            (coverage_cell_count, current_coverage_percent) = model.grid.as_ref().expect(ERROR_MSG).get_coverage();
+    // Update time in the simulation
+    // sim_time_elapsed is in seconds, so we divide the step size by the velocity to get the time for this step
+    // This assumes velocity is in units/second
+    let velocity = terrain.map_or(model.velocity, |t| model.velocity * t.velocity_factor(&step_dir));
+    model.sim_time_elapsed += model.step_size / velocity;
+    state.time_since_last_charge += model.step_size / velocity;
+    if terrain.is_some() {
+        model.terrain_time += model.step_size / velocity;
+    }
 
-            print_progress(
-                model,
-                frame_image_numbering,
-                current_coverage_percent,
-                coverage_cell_count,
-            );
-        }
+    if battery_ran_out(model, state.time_since_last_charge) {
+        state.battery_empty = true;
+        events.battery_empty = true;
+    }
 
-        if model.sim_steps == 1 || model.sim_steps % 1000 == 0 {
-            // Update RAM usage every 1000 steps
-            model.ram_usage_mb = crate::get_process_rss_mb().round();
+    // Keep coverage samples for the last hour, plus the one just before it
+    if state
+        .coverage_history
+        .back()
+        .is_some_and(|&(time, _)| model.sim_time_elapsed - time >= COVERAGE_SAMPLE_INTERVAL)
+    {
+        let coverage = model.grid.as_ref().expect(ERROR_MSG).get_coverage_percent();
+        state.coverage_history.push_back((model.sim_time_elapsed, coverage));
+        while state
+            .coverage_history
+            .get(1)
+            .is_some_and(|&(time, _)| model.sim_time_elapsed - time >= 3600.0)
+        {
+            state.coverage_history.pop_front();
         }
+    }
 
-        if model.generate_frames && (model.sim_steps % model.steps_per_frame) == 0 {
-            if frame_counter % model.animation_speedup == 0 {
-                let frame_filename = format!(
-                    "{}/frame_{:07}.png",
-                    model.frames_dir, frame_image_numbering
-                );
-                frame_image_numbering += 1;
-                try_save_image(model, Some(frame_filename));
-            }
-            frame_counter += 1;
-        }
+    // The number of frames generated so far. Coverage is also sampled every 50 frames so that the
+    // progress shown follows the animation.
+    let frame_counter = if model.generate_frames {
+        (model.sim_steps - 1) / model.steps_per_frame
+    } else {
+        0
+    };
+
+    if model.sim_steps == 1
+        || model.sim_steps.is_multiple_of(state.steps_per_20th_percent)
+        || (frame_counter > 0 && frame_counter.is_multiple_of(50))
+    {
+        (state.coverage_cell_count, state.current_coverage_percent) = model.grid.as_ref().expect(ERROR_MSG).get_coverage();
+        events.coverage_updated = true;
     }
+
+    events
 }
@@ -0,0 +1,376 @@
+use crate::config::SimConfig;
+use crate::mapfile::prepared::PreparedMap;
+use crate::mapfile::{MapFile, load_optional_mapfile, set_model_map, try_apply_mapfile_to_model};
+use crate::memory::{get_process_rss_mb, get_total_ram_in_gb};
+use crate::model::{SimModel, grid::Grid, init_model};
+use crate::observer::{FrameObserver, MemoryObserver, ProgressObserver, SimObserver, SnapshotMetric, SnapshotObserver};
+use crate::sim::{FAILSAFE_TIME_LIMIT, SimState, simulation_step, stop_condition_reached, stop_reason};
+use crate::vector::Vector;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::sync::Arc;
+
+/// Maximum number of attempts to find a random start position outside all obstacles
+const MAX_START_POSITION_ATTEMPTS: usize = 10_000;
+
+/// Builder for a `Simulation`.
+///
+/// # Example
+///
+/// ```no_run
+/// use gridcover::{SimConfig, SimulationBuilder};
+///
+/// let mut config = SimConfig::default();
+/// config.stop_coverage = 90.0;
+/// let mut sim = SimulationBuilder::new(config).seed(42).build().unwrap();
+/// sim.run();
+/// println!("{:?}", sim.result());
+/// ```
+pub struct SimulationBuilder {
+    config: SimConfig,
+    map: Option<MapFile>,
+    prepared_map: Option<Arc<PreparedMap>>,
+    observers: Vec<Box<dyn SimObserver>>,
+}
+
+impl SimulationBuilder {
+    pub fn new(config: SimConfig) -> Self {
+        SimulationBuilder {
+            config,
+            map: None,
+            prepared_map: None,
+            observers: Vec::new(),
+        }
+    }
+
+    /// Register an observer. Observers are called in the order they are added, after the
+    /// built-in observers for progress, memory usage and animation frames.
+    pub fn observer(mut self, observer: impl SimObserver + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Seed for the random generator. A seed of 0 means a random seed is used.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.random_seed = seed;
+        self
+    }
+
+    /// Read obstacles from the given YAML map file
+    pub fn map_file(mut self, path: &str) -> Self {
+        self.config.map_file_name = Some(path.to_string());
+        self.map = None;
+        self
+    }
+
+    /// Use an already loaded (or programmatically created) map
+    pub fn map(mut self, map: MapFile) -> Self {
+        self.map = Some(map);
+        self
+    }
+
+    /// Use a map that has already been loaded and rasterised, see `PreparedMap`. It replaces the
+    /// map file and map given to the builder and must have been prepared for the same map, grid
+    /// and cutter size as the configuration.
+    pub fn prepared_map(mut self, prepared_map: Arc<PreparedMap>) -> Self {
+        self.prepared_map = Some(prepared_map);
+        self
+    }
+
+    /// Validate the configuration, setup the grid with all obstacles and place the cutter
+    pub fn build(mut self) -> Result<Simulation, Box<dyn Error>> {
+        if self.config.random_seed == 0 {
+            self.config.random_seed = rand::random::<u64>();
+        }
+        let mut rng = StdRng::seed_from_u64(self.config.random_seed);
+
+        let mut model = init_model(&self.config, &mut rng)?;
+        model.ram_size_gb = get_total_ram_in_gb().round();
+        model.ram_usage_mb = get_process_rss_mb().round();
+
+        if let Some(prepared_map) = &self.prepared_map {
+            prepared_map.apply_to_model(&mut model)?;
+        } else {
+            match self.map {
+                Some(map) => set_model_map(&mut model, map)?,
+                None => load_optional_mapfile(&mut model)?,
+            }
+
+            let mut grid = Grid::new(model.grid_cells_x, model.grid_cells_y, model.cell_size);
+            grid.use_quad_tree = self.config.use_quad_tree;
+            model.grid = Some(grid);
+
+            // Construct all obstacles and mark them in the model grid
+            try_apply_mapfile_to_model(&mut model)?;
+        }
+
+        // We cannot set a random start position until the map has been loaded
+        // as we need a start position that is not in an obstacle
+        set_optional_random_start_position(&mut rng, &mut model)?;
+
+        let state = SimState::new(&mut model, &mut rng);
+
+        // The standard outputs enabled in the model are themselves observers
+        let mut observers: Vec<Box<dyn SimObserver>> = Vec::new();
+        if model.show_progress {
+            observers.push(Box::new(ProgressObserver));
+        }
+        observers.push(Box::new(MemoryObserver));
+        if model.generate_frames {
+            observers.push(Box::new(FrameObserver::default()));
+        }
+        if model.snapshot_steps > 0 || model.snapshot_coverage_tenths > 0 {
+            fs::create_dir_all(&model.snapshot_dir)
+                .map_err(|e| format!("Failed to create snapshot directory '{}': {e}", model.snapshot_dir))?;
+        }
+        if model.snapshot_steps > 0 {
+            let steps = model.snapshot_steps;
+            observers.push(Box::new(SnapshotObserver::new(&model.snapshot_dir, SnapshotMetric::Steps, steps, steps, None)));
+        }
+        if model.snapshot_coverage_tenths > 0 {
+            let tenths = model.snapshot_coverage_tenths;
+            observers.push(Box::new(SnapshotObserver::new(&model.snapshot_dir, SnapshotMetric::Coverage, tenths, tenths, None)));
+        }
+        observers.extend(self.observers);
+
+        Ok(Simulation {
+            model,
+            rng,
+            state,
+            observers,
+            start_time: None,
+            stopped: false,
+            ended: false,
+            coverage_milestone: 0,
+        })
+    }
+}
+
+fn set_optional_random_start_position(rng: &mut StdRng, model: &mut SimModel) -> Result<(), Box<dyn Error>> {
+    // Check if we should randomize the start position
+    if model.start_x < 0.0 || model.start_y < 0.0 {
+        for _ in 0..MAX_START_POSITION_ATTEMPTS {
+            model.start_x = rng.random_range(model.radius..(model.grid_width - model.radius));
+            model.start_y = rng.random_range(model.radius..(model.grid_height - model.radius));
+            let model_start = Vector::new(model.start_x, model.start_y);
+            if !model
+                .grid
+                .as_mut()
+                .unwrap()
+                .collision_with_obstacle(&model_start, model.radius)
+            {
+                return Ok(());
+            }
+        }
+        return Err("Failed to find a valid start position after 10,000 attempts, aborting. Set start position manually.".into());
+    }
+    Ok(())
+}
+
+/// Summary of a simulation run
+#[derive(Debug, Clone, Serialize)]
+pub struct SimResult {
+    pub coverage_percent: f64,
+    pub coverage_count: usize,
+    pub bounces: usize,
+    /// Bounces caused by a virtual fence, included in `bounces`
+    pub virtual_fence_bounces: usize,
+    pub distance: f64,
+    pub sim_time: f64,
+    pub sim_steps: u64,
+    pub battery_charge_count: usize,
+    pub max_visited_number: usize,
+    pub min_visited_number: usize,
+    pub cpu_time_ms: i64,
+    pub random_seed: u64,
+    /// True if the simulation was stopped by the fail-safe time limit rather than a stop condition
+    pub aborted: bool,
+    /// The stop condition that ended the simulation, None if it has not ended
+    pub stop_reason: Option<String>,
+}
+
+/// A fully initialized simulation that can be advanced step by step or run until one of the
+/// stop conditions is met
+pub struct Simulation {
+    model: SimModel,
+    rng: StdRng,
+    state: SimState,
+    observers: Vec<Box<dyn SimObserver>>,
+    start_time: Option<chrono::DateTime<chrono::Utc>>,
+    stopped: bool,
+    ended: bool,
+    coverage_milestone: u32,
+}
+
+impl Simulation {
+    pub fn model(&self) -> &SimModel {
+        &self.model
+    }
+
+    pub fn model_mut(&mut self) -> &mut SimModel {
+        &mut self.model
+    }
+
+    /// Give up the simulation and keep the model, e.g. for storing or rendering it.
+    /// All observers are dropped.
+    pub fn into_model(self) -> SimModel {
+        self.model
+    }
+
+    /// Register an observer on an already built simulation
+    pub fn add_observer(&mut self, observer: impl SimObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Current position of the cutter center
+    pub fn cutter_position(&self) -> Vector {
+        self.state.cutter_center
+    }
+
+    /// Current (unit) direction of the cutter
+    pub fn cutter_direction(&self) -> Vector {
+        self.state.current_dir
+    }
+
+    /// True if a stop condition has been reached or an observer stopped the simulation
+    pub fn is_finished(&self) -> bool {
+        self.stopped || stop_condition_reached(&self.model, &self.state)
+    }
+
+    /// Advance the simulation one step. Returns false, without stepping, if the simulation is
+    /// already finished.
+    pub fn step(&mut self) -> bool {
+        if self.is_finished() {
+            self.end();
+            return false;
+        }
+        self.start();
+        self.step_and_notify();
+        self.update_cpu_time();
+        if self.is_finished() {
+            self.end();
+        }
+        true
+    }
+
+    /// Run until the simulation is finished
+    pub fn run(&mut self) -> &mut Self {
+        self.run_until(|_| false)
+    }
+
+    /// Run until the simulation is finished or the condition becomes true. The condition is
+    /// checked after every step. The simulation can be continued after the condition is met.
+    pub fn run_until<F>(&mut self, mut condition: F) -> &mut Self
+    where
+        F: FnMut(&SimModel) -> bool,
+    {
+        self.start();
+        while !self.is_finished() {
+            self.step_and_notify();
+            if condition(&self.model) {
+                break;
+            }
+        }
+        self.update_cpu_time();
+        if self.is_finished() {
+            self.end();
+        }
+        self
+    }
+
+    fn start(&mut self) {
+        if self.start_time.is_none() {
+            self.start_time = Some(chrono::Utc::now());
+            for observer in self.observers.iter_mut() {
+                observer.on_start(&mut self.model, &self.state.cutter_center);
+            }
+        }
+    }
+
+    fn end(&mut self) {
+        if !self.ended {
+            self.ended = true;
+            self.model.stop_reason = stop_reason(&self.model, &self.state)
+                .or_else(|| self.stopped.then(|| "stopped by observer".to_string()));
+            self.complete_model_results();
+            for observer in self.observers.iter_mut() {
+                observer.on_end(&mut self.model, &self.state.cutter_center);
+            }
+        }
+    }
+
+    fn step_and_notify(&mut self) {
+        let events = simulation_step(&mut self.model, &mut self.state, &mut self.rng);
+        let model = &mut self.model;
+        let cutter_center = &self.state.cutter_center;
+
+        let percent = model
+            .grid
+            .as_ref()
+            .expect("Failed to get grid. Internal BUG!")
+            .get_coverage_percent();
+        let first_milestone = self.coverage_milestone + 1;
+        self.coverage_milestone = self.coverage_milestone.max(percent.floor() as u32);
+
+        for observer in self.observers.iter_mut() {
+            if events.battery_recharged {
+                observer.on_battery_recharged(model);
+            }
+            if events.bounce {
+                observer.on_bounce(model, cutter_center);
+            }
+            if events.collision {
+                observer.on_collision(model, cutter_center);
+            }
+            if events.virtual_fence {
+                observer.on_virtual_fence(model, cutter_center);
+            }
+            if events.battery_empty {
+                observer.on_battery_empty(model);
+            }
+            for milestone in first_milestone..=self.coverage_milestone {
+                observer.on_coverage_milestone(model, milestone);
+            }
+            if observer.on_step(model, cutter_center, &events).is_break() {
+                self.stopped = true;
+            }
+        }
+    }
+
+    /// Complete the model with the results so far and return a summary of them
+    pub fn result(&mut self) -> SimResult {
+        self.complete_model_results();
+        SimResult {
+            coverage_percent: self.model.coverage_percent,
+            coverage_count: self.model.coverage_count,
+            bounces: self.model.segment_number,
+            virtual_fence_bounces: self.model.virtual_fence_bounces,
+            distance: self.model.distance_covered,
+            sim_time: self.model.sim_time_elapsed,
+            sim_steps: self.model.sim_steps,
+            battery_charge_count: self.model.battery_charge_count,
+            max_visited_number: self.model.max_visited_number,
+            min_visited_number: self.model.min_visited_number,
+            cpu_time_ms: self.model.cpu_time.num_milliseconds(),
+            random_seed: self.model.random_seed,
+            aborted: self.model.sim_time_elapsed >= FAILSAFE_TIME_LIMIT,
+            stop_reason: self.model.stop_reason.clone(),
+        }
+    }
+
+    fn update_cpu_time(&mut self) {
+        if let Some(start_time) = self.start_time {
+            self.model.cpu_time = chrono::Utc::now().signed_duration_since(start_time);
+        }
+    }
+
+    fn complete_model_results(&mut self) {
+        let grid = self.model.grid.as_ref().expect("Failed to get grid. Internal BUG!");
+        (self.model.coverage_count, self.model.coverage_percent) = grid.get_coverage();
+        self.model.max_visited_number = grid.get_max_visited_number();
+        self.model.min_visited_number = grid.get_min_visited_number();
+    }
+}
@@ -0,0 +1,68 @@
+//! Summary statistics of repeated simulation runs
+
+use serde_json::{Value, json};
+
+/// Two-sided 95% critical values of Student's t-distribution for 1 to 30 degrees of freedom
+const T_CRITICAL_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160, 2.145, 2.131, 2.120,
+    2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+/// Two-sided 95% critical value of the normal distribution
+const Z_CRITICAL_95: f64 = 1.959964;
+
+/// The two-sided 95% critical value of Student's t-distribution. Above 30 degrees of freedom the
+/// first term of the Cornish-Fisher expansion around the normal distribution is used.
+pub fn t_critical_95(degrees_of_freedom: usize) -> f64 {
+    match degrees_of_freedom {
+        0 => f64::INFINITY,
+        df if df <= T_CRITICAL_95.len() => T_CRITICAL_95[df - 1],
+        df => Z_CRITICAL_95 + (Z_CRITICAL_95.powi(3) + Z_CRITICAL_95) / (4.0 * df as f64),
+    }
+}
+
+/// Mean, sample standard deviation and 95% confidence interval of the mean of a sample
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SampleStats {
+    pub count: usize,
+    pub mean: f64,
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Half width of the 95% confidence interval of the mean, infinite for a single value
+    pub ci95: f64,
+}
+
+impl SampleStats {
+    pub fn from_values(values: &[f64]) -> Self {
+        if values.is_empty() {
+            return SampleStats::default();
+        }
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let stddev = if count > 1 {
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+        SampleStats {
+            count,
+            mean,
+            stddev,
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            ci95: if count > 1 { t_critical_95(count - 1) * stddev / (count as f64).sqrt() } else { f64::INFINITY },
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "Count": self.count,
+            "Mean": self.mean,
+            "Std.dev": self.stddev,
+            "Min": self.min,
+            "Max": self.max,
+            "CI95": if self.ci95.is_finite() { json!(self.ci95) } else { Value::Null },
+        })
+    }
+}
@@ -0,0 +1,617 @@
+//! A small expression language for stop conditions.
+//!
+//! An expression compares simulation metrics with values and combines the comparisons with
+//! `and`, `or`, `not` and parentheses, for example
+//!
+//! ```text
+//! coverage>=95 or time>=4h
+//! (bounces>200 and coverage_gain_per_hour<0.5) or battery_cycles>=3
+//! ```
+//!
+//! Available metrics are `coverage` (percent), `time` (simulated seconds, the units `s`, `min`
+//! and `h` may be used), `bounces`, `steps`, `distance`, `battery_cycles` and
+//! `coverage_gain_per_hour` (coverage percent gained during the last simulated hour).
+
+use std::fmt;
+
+/// A metric that can be used in a stop condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Coverage,
+    Time,
+    Bounces,
+    Steps,
+    Distance,
+    BatteryCycles,
+    CoverageGainPerHour,
+}
+
+impl Metric {
+    const ALL: [Metric; 7] = [
+        Metric::Coverage,
+        Metric::Time,
+        Metric::Bounces,
+        Metric::Steps,
+        Metric::Distance,
+        Metric::BatteryCycles,
+        Metric::CoverageGainPerHour,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Metric::Coverage => "coverage",
+            Metric::Time => "time",
+            Metric::Bounces => "bounces",
+            Metric::Steps => "steps",
+            Metric::Distance => "distance",
+            Metric::BatteryCycles => "battery_cycles",
+            Metric::CoverageGainPerHour => "coverage_gain_per_hour",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Metric> {
+        Metric::ALL.into_iter().find(|m| m.as_str() == name)
+    }
+
+    /// Scale factor for a unit given after a value, None if the unit is not valid for the metric
+    fn unit_factor(&self, unit: &str) -> Option<f64> {
+        match (self, unit) {
+            (_, "") => Some(1.0),
+            (Metric::Time, "s") => Some(1.0),
+            (Metric::Time, "min") => Some(60.0),
+            (Metric::Time, "h") => Some(3600.0),
+            (Metric::Coverage | Metric::CoverageGainPerHour, "%") => Some(1.0),
+            _ => None,
+        }
+    }
+}
+
+/// Comparison operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    Eq,
+    Ne,
+}
+
+impl CmpOp {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CmpOp::Ge => ">=",
+            CmpOp::Gt => ">",
+            CmpOp::Le => "<=",
+            CmpOp::Lt => "<",
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+        }
+    }
+
+    fn apply(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            CmpOp::Ge => lhs >= rhs,
+            CmpOp::Gt => lhs > rhs,
+            CmpOp::Le => lhs <= rhs,
+            CmpOp::Lt => lhs < rhs,
+            CmpOp::Eq => lhs == rhs,
+            CmpOp::Ne => lhs != rhs,
+        }
+    }
+}
+
+/// Current values of all metrics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StopMetrics {
+    pub coverage: f64,
+    pub time: f64,
+    pub bounces: f64,
+    pub steps: f64,
+    pub distance: f64,
+    pub battery_cycles: f64,
+    /// Infinite until one simulated hour has passed
+    pub coverage_gain_per_hour: f64,
+}
+
+impl StopMetrics {
+    fn get(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::Coverage => self.coverage,
+            Metric::Time => self.time,
+            Metric::Bounces => self.bounces,
+            Metric::Steps => self.steps,
+            Metric::Distance => self.distance,
+            Metric::BatteryCycles => self.battery_cycles,
+            Metric::CoverageGainPerHour => self.coverage_gain_per_hour,
+        }
+    }
+}
+
+/// A parsed stop condition
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopExpr {
+    Or(Box<StopExpr>, Box<StopExpr>),
+    And(Box<StopExpr>, Box<StopExpr>),
+    Not(Box<StopExpr>),
+    Compare {
+        metric: Metric,
+        op: CmpOp,
+        value: f64,
+        /// The value as written, including any unit, used when the condition is displayed
+        text: String,
+    },
+}
+
+impl StopExpr {
+    /// A comparison `metric >= value` as used for the fixed stop options
+    pub fn at_least(metric: Metric, value: f64) -> StopExpr {
+        StopExpr::Compare {
+            metric,
+            op: CmpOp::Ge,
+            value,
+            text: value.to_string(),
+        }
+    }
+
+    /// Combine a list of conditions so that the first one reached stops the simulation
+    pub fn any_of(exprs: Vec<StopExpr>) -> Option<StopExpr> {
+        exprs
+            .into_iter()
+            .reduce(|a, b| StopExpr::Or(Box::new(a), Box::new(b)))
+    }
+
+    pub fn evaluate(&self, metrics: &StopMetrics) -> bool {
+        match self {
+            StopExpr::Or(a, b) => a.evaluate(metrics) || b.evaluate(metrics),
+            StopExpr::And(a, b) => a.evaluate(metrics) && b.evaluate(metrics),
+            StopExpr::Not(a) => !a.evaluate(metrics),
+            StopExpr::Compare {
+                metric, op, value, ..
+            } => op.apply(metrics.get(*metric), *value),
+        }
+    }
+
+    /// The part of the expression that made it true, e.g. the branch of an `or` that was
+    /// reached. Returns None if the expression is false.
+    pub fn trigger(&self, metrics: &StopMetrics) -> Option<String> {
+        match self {
+            StopExpr::Or(a, b) => a.trigger(metrics).or_else(|| b.trigger(metrics)),
+            StopExpr::And(a, b) => match (a.trigger(metrics), b.trigger(metrics)) {
+                (Some(ta), Some(tb)) => Some(format!("{ta} and {tb}")),
+                _ => None,
+            },
+            _ => self.evaluate(metrics).then(|| self.to_string()),
+        }
+    }
+
+    /// True if the metric is used anywhere in the expression
+    pub fn uses(&self, metric: Metric) -> bool {
+        match self {
+            StopExpr::Or(a, b) | StopExpr::And(a, b) => a.uses(metric) || b.uses(metric),
+            StopExpr::Not(a) => a.uses(metric),
+            StopExpr::Compare { metric: m, .. } => *m == metric,
+        }
+    }
+}
+
+impl fmt::Display for StopExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Parentheses are only needed around an `or` inside an `and` or a `not`
+        fn wrapped(expr: &StopExpr, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match expr {
+                StopExpr::Or(..) => write!(f, "({expr})"),
+                _ => write!(f, "{expr}"),
+            }
+        }
+        match self {
+            StopExpr::Or(a, b) => write!(f, "{a} or {b}"),
+            StopExpr::And(a, b) => {
+                wrapped(a, f)?;
+                write!(f, " and ")?;
+                wrapped(b, f)
+            }
+            StopExpr::Not(a) => match a.as_ref() {
+                StopExpr::Compare { .. } => write!(f, "not {a}"),
+                _ => write!(f, "not ({a})"),
+            },
+            StopExpr::Compare {
+                metric, op, text, ..
+            } => write!(f, "{}{}{}", metric.as_str(), op.as_str(), text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64, String),
+    Op(CmpOp),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+/// A token with the source text it was read from and its position (1-based, in characters)
+#[derive(Debug, Clone)]
+struct SpannedToken {
+    token: Token,
+    text: String,
+    position: usize,
+}
+
+impl fmt::Display for SpannedToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' at position {}", self.text, self.position)
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<SpannedToken>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let start = i;
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                i += 1;
+                Token::LParen
+            }
+            ')' => {
+                i += 1;
+                Token::RParen
+            }
+            '>' | '<' | '=' | '!' if next == Some('=') => {
+                i += 2;
+                Token::Op(match c {
+                    '>' => CmpOp::Ge,
+                    '<' => CmpOp::Le,
+                    '=' => CmpOp::Eq,
+                    _ => CmpOp::Ne,
+                })
+            }
+            '>' => {
+                i += 1;
+                Token::Op(CmpOp::Gt)
+            }
+            '<' => {
+                i += 1;
+                Token::Op(CmpOp::Lt)
+            }
+            '=' => {
+                i += 1;
+                Token::Op(CmpOp::Eq)
+            }
+            '!' => {
+                i += 1;
+                Token::Not
+            }
+            '&' | '|' if next == Some(c) => {
+                i += 2;
+                if c == '&' { Token::And } else { Token::Or }
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                let value = number
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid number '{number}'"))?;
+                let unit_start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphabetic() || chars[i] == '%') {
+                    i += 1;
+                }
+                let unit: String = chars[unit_start..i].iter().collect();
+                Token::Number(value, unit)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect::<String>().to_lowercase();
+                match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Ident(word),
+                }
+            }
+            _ => return Err(format!("Unexpected character '{c}' at position {}", i + 1)),
+        };
+        tokens.push(SpannedToken {
+            token,
+            text: chars[start..i].iter().collect(),
+            position: start + 1,
+        });
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<SpannedToken>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|t| t.token.clone());
+        self.pos += 1;
+        token
+    }
+
+    /// The token last returned by `next`
+    fn previous(&self) -> &SpannedToken {
+        &self.tokens[self.pos - 1]
+    }
+
+    fn parse_or(&mut self) -> Result<StopExpr, String> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = StopExpr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<StopExpr, String> {
+        let mut expr = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = StopExpr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<StopExpr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(StopExpr::Not(Box::new(self.parse_unary()?))),
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err("Missing closing parenthesis".to_string()),
+                }
+            }
+            Some(Token::Ident(name)) => {
+                let metric = Metric::from_name(&name).ok_or_else(|| {
+                    format!(
+                        "Unknown metric '{name}'. Available metrics: {}",
+                        Metric::ALL.map(|m| m.as_str()).join(", ")
+                    )
+                })?;
+                let Some(Token::Op(op)) = self.next() else {
+                    return Err(format!("Expected a comparison after '{name}'"));
+                };
+                let Some(Token::Number(value, unit)) = self.next() else {
+                    return Err(format!("Expected a number after '{name}{}'", op.as_str()));
+                };
+                // A bare `m` reads as meters as easily as minutes
+                if metric == Metric::Time && unit == "m" {
+                    return Err(format!("Unit 'm' is ambiguous for '{name}', use 'min' for minutes"));
+                }
+                let factor = metric.unit_factor(&unit).ok_or_else(|| {
+                    format!("Unit '{unit}' cannot be used with metric '{name}'")
+                })?;
+                Ok(StopExpr::Compare {
+                    metric,
+                    op,
+                    value: value * factor,
+                    text: format!("{value}{unit}"),
+                })
+            }
+            Some(_) => Err(format!("Unexpected {} in stop condition", self.previous())),
+            None => Err("Unexpected end of stop condition".to_string()),
+        }
+    }
+}
+
+/// Parse a stop condition expression
+pub fn parse(input: &str) -> Result<StopExpr, String> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Err("Empty stop condition".to_string());
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_or()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(format!("Unexpected {token} after end of stop condition"));
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(metric: Metric, op: CmpOp, value: f64, text: &str) -> StopExpr {
+        StopExpr::Compare {
+            metric,
+            op,
+            value,
+            text: text.to_string(),
+        }
+    }
+
+    fn parse_err(input: &str) -> String {
+        parse(input).expect_err("stop condition should not parse")
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expr = parse("coverage>=95 or time>=1h and bounces>10").unwrap();
+        assert_eq!(
+            expr,
+            StopExpr::Or(
+                Box::new(compare(Metric::Coverage, CmpOp::Ge, 95.0, "95")),
+                Box::new(StopExpr::And(
+                    Box::new(compare(Metric::Time, CmpOp::Ge, 3600.0, "1h")),
+                    Box::new(compare(Metric::Bounces, CmpOp::Gt, 10.0, "10")),
+                )),
+            )
+        );
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let expr = parse("(coverage>=95 || time>=1h) && bounces>10").unwrap();
+        assert_eq!(
+            expr,
+            StopExpr::And(
+                Box::new(StopExpr::Or(
+                    Box::new(compare(Metric::Coverage, CmpOp::Ge, 95.0, "95")),
+                    Box::new(compare(Metric::Time, CmpOp::Ge, 3600.0, "1h")),
+                )),
+                Box::new(compare(Metric::Bounces, CmpOp::Gt, 10.0, "10")),
+            )
+        );
+        assert_eq!(
+            expr.to_string(),
+            "(coverage>=95 or time>=1h) and bounces>10"
+        );
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        let expr = parse("not coverage<50 and !(steps!=3)").unwrap();
+        assert_eq!(
+            expr,
+            StopExpr::And(
+                Box::new(StopExpr::Not(Box::new(compare(
+                    Metric::Coverage,
+                    CmpOp::Lt,
+                    50.0,
+                    "50"
+                )))),
+                Box::new(StopExpr::Not(Box::new(compare(
+                    Metric::Steps,
+                    CmpOp::Ne,
+                    3.0,
+                    "3"
+                )))),
+            )
+        );
+    }
+
+    #[test]
+    fn time_units_are_converted_to_seconds() {
+        for (input, seconds) in [
+            ("time>=4h", 14400.0),
+            ("time>=30min", 1800.0),
+            ("time>=90s", 90.0),
+            ("time>=90", 90.0),
+        ] {
+            match parse(input).unwrap() {
+                StopExpr::Compare { metric, value, .. } => {
+                    assert_eq!(metric, Metric::Time);
+                    assert_eq!(value, seconds, "{input}");
+                }
+                expr => panic!("Expected a comparison for '{input}', got {expr:?}"),
+            }
+        }
+        assert_eq!(parse("time>=30min").unwrap().to_string(), "time>=30min");
+    }
+
+    #[test]
+    fn percent_is_accepted_for_coverage() {
+        assert_eq!(
+            parse("coverage>=95%").unwrap(),
+            compare(Metric::Coverage, CmpOp::Ge, 95.0, "95%")
+        );
+    }
+
+    #[test]
+    fn metrics_refuse_units_that_do_not_fit() {
+        assert_eq!(
+            parse_err("bounces>5h"),
+            "Unit 'h' cannot be used with metric 'bounces'"
+        );
+        assert_eq!(
+            parse_err("coverage>=30min"),
+            "Unit 'min' cannot be used with metric 'coverage'"
+        );
+        assert_eq!(
+            parse_err("time>=5%"),
+            "Unit '%' cannot be used with metric 'time'"
+        );
+    }
+
+    #[test]
+    fn m_is_ambiguous_for_time() {
+        assert_eq!(
+            parse_err("time>=30m"),
+            "Unit 'm' is ambiguous for 'time', use 'min' for minutes"
+        );
+    }
+
+    #[test]
+    fn unexpected_tokens_are_reported_with_position() {
+        assert_eq!(
+            parse_err("coverage >= 5 0"),
+            "Unexpected '0' at position 15 after end of stop condition"
+        );
+        assert_eq!(
+            parse_err("and coverage>5"),
+            "Unexpected 'and' at position 1 in stop condition"
+        );
+        assert_eq!(
+            parse_err("coverage>5 or )"),
+            "Unexpected ')' at position 15 in stop condition"
+        );
+        assert_eq!(
+            parse_err("coverage>=5é"),
+            "Unexpected character 'é' at position 12"
+        );
+    }
+
+    #[test]
+    fn incomplete_conditions_are_rejected() {
+        assert_eq!(parse_err("  "), "Empty stop condition");
+        assert_eq!(parse_err("(coverage>5"), "Missing closing parenthesis");
+        assert_eq!(
+            parse_err("coverage>5 and"),
+            "Unexpected end of stop condition"
+        );
+        assert_eq!(
+            parse_err("coverage 5"),
+            "Expected a comparison after 'coverage'"
+        );
+        assert_eq!(
+            parse_err("coverage>="),
+            "Expected a number after 'coverage>='"
+        );
+        assert!(
+            parse_err("lawn>5")
+                .starts_with("Unknown metric 'lawn'. Available metrics: coverage, time")
+        );
+    }
+
+    #[test]
+    fn trigger_names_the_branch_that_was_reached() {
+        let expr = parse("coverage>=95 or (time>=1h and bounces>10)").unwrap();
+        let mut metrics = StopMetrics {
+            coverage: 50.0,
+            time: 7200.0,
+            bounces: 5.0,
+            ..Default::default()
+        };
+        assert_eq!(expr.trigger(&metrics), None);
+        metrics.bounces = 11.0;
+        assert_eq!(
+            expr.trigger(&metrics).as_deref(),
+            Some("time>=1h and bounces>10")
+        );
+    }
+}
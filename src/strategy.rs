@@ -54,7 +54,7 @@ pub fn cutter_strategy(
                 let sim_steps_per_cell =
                     (model.cell_size / model.step_size).ceil() as u64;
 
-                if model.sim_steps % sim_steps_per_cell == 0
+                if model.sim_steps.is_multiple_of(sim_steps_per_cell)
                     && rng.random_bool(model.perturb_segment_percent)
                 {
                     // Perturb the direction randomly +/- PI radians
@@ -0,0 +1,382 @@
+use crate::color_theme::{ColorTheme, ColorThemeManager};
+use crate::model::SimModel;
+use crate::model::grid::Cell;
+use crate::observer::SimObserver;
+use crate::sim::StepEvents;
+use crate::vector::Vector;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Colors, Print, ResetColor, SetColors};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::{BufWriter, IsTerminal, Stdout, Write};
+use std::ops::ControlFlow;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Unicode upper half block. Foreground paints the upper pixel and background the lower pixel
+/// so every character cell on the terminal shows two vertically stacked grid pixels.
+const HALF_BLOCK: char = '\u{2580}';
+
+/// Color used to mark the current cutter position in the view
+const CUTTER_COLOR: [u8; 3] = [255, 220, 0];
+
+/// Number of text lines reserved for the status area below the map
+const STATUS_LINES: u16 = 3;
+
+/// Target time between two rendered frames. At low speed levels we sleep to keep this pace
+/// so that the movement of the cutter can actually be followed on screen.
+const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+/// Speed is expressed as 2^level simulation steps between two rendered frames
+const MAX_SPEED_LEVEL: u32 = 20;
+const DEFAULT_SPEED_LEVEL: u32 = 6;
+
+/// Maximum number of grid cells sampled in each direction when downsampling a block of cells
+/// into one terminal pixel. This keeps the rendering time bounded for very large grids.
+const MAX_SAMPLES_PER_AXIS: usize = 4;
+
+/// Live terminal view of a running simulation.
+///
+/// The view owns the terminal while it is alive (raw mode and alternate screen) and restores
+/// it when dropped.
+pub struct TuiView {
+    out: BufWriter<Stdout>,
+    theme: ColorTheme,
+    paused: bool,
+    quit: bool,
+    speed_level: u32,
+    steps_since_render: u64,
+    last_render: Instant,
+    last_key_check: Instant,
+}
+
+impl TuiView {
+    /// Take over the terminal and prepare for rendering. Fails if stdout is not a terminal.
+    pub fn new(model: &SimModel) -> Result<Self, Box<dyn std::error::Error>> {
+        if !std::io::stdout().is_terminal() {
+            return Err("Terminal view requires stdout to be a terminal".into());
+        }
+
+        let theme_manager = ColorThemeManager::new();
+        let theme = theme_manager
+            .get_theme(model.color_theme.as_deref().unwrap_or("default"))
+            .clone();
+
+        install_panic_hook();
+        terminal::enable_raw_mode()?;
+        TERMINAL_TAKEN.store(true, Ordering::SeqCst);
+        let mut out = BufWriter::new(std::io::stdout());
+        execute!(
+            out,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+
+        Ok(TuiView {
+            out,
+            theme,
+            paused: false,
+            quit: false,
+            speed_level: DEFAULT_SPEED_LEVEL,
+            steps_since_render: 0,
+            last_render: Instant::now(),
+            last_key_check: Instant::now(),
+        })
+    }
+
+    /// True if the user asked to end the simulation from the terminal view
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    fn steps_per_render(&self) -> u64 {
+        1u64 << self.speed_level
+    }
+
+    /// Called once every simulation step. Renders a new frame when enough steps have been taken
+    /// at the current speed and handles the keyboard at least once per frame interval, also at
+    /// high speeds with many steps per frame. While paused this blocks until the user resumes,
+    /// single steps or quits.
+    pub fn update(&mut self, model: &SimModel, cutter_center: &Vector) {
+        self.steps_since_render += 1;
+        if !self.paused && self.steps_since_render < self.steps_per_render() {
+            if self.last_key_check.elapsed() < FRAME_INTERVAL {
+                return;
+            }
+            self.last_key_check = Instant::now();
+            self.process_keys(Duration::ZERO);
+            // A pause starts at once with a new frame, other keys take effect without one
+            if !self.paused {
+                return;
+            }
+        }
+        self.steps_since_render = 0;
+        self.render(model, cutter_center);
+
+        let mut step_requested = self.process_keys(Duration::ZERO);
+        while self.paused && !self.quit && !step_requested {
+            step_requested = self.process_keys(Duration::from_millis(100));
+            // Redraw so that changes in speed or pause state are reflected in the status line
+            self.render(model, cutter_center);
+        }
+
+        // Keep a steady frame pace at low speeds
+        let elapsed = self.last_render.elapsed();
+        if !self.paused && elapsed < FRAME_INTERVAL {
+            std::thread::sleep(FRAME_INTERVAL - elapsed);
+        }
+        self.last_render = Instant::now();
+        self.last_key_check = self.last_render;
+    }
+
+    /// Handle all pending key events, waiting at most `timeout` for the first one.
+    /// Returns true if a single step was requested.
+    fn process_keys(&mut self, timeout: Duration) -> bool {
+        let mut step_requested = false;
+        let mut wait = timeout;
+        while event::poll(wait).unwrap_or(false) {
+            wait = Duration::ZERO;
+            let Ok(Event::Key(key)) = event::read() else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.quit = true
+                }
+                KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+                KeyCode::Char(' ') | KeyCode::Char('p') => self.paused = !self.paused,
+                KeyCode::Char('s') | KeyCode::Char('n') | KeyCode::Right if self.paused => {
+                    step_requested = true
+                }
+                KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Up => {
+                    self.speed_level = (self.speed_level + 1).min(MAX_SPEED_LEVEL)
+                }
+                KeyCode::Char('-') | KeyCode::Down => {
+                    self.speed_level = self.speed_level.saturating_sub(1)
+                }
+                _ => {}
+            }
+        }
+        step_requested
+    }
+
+    fn render(&mut self, model: &SimModel, cutter_center: &Vector) {
+        // Rendering errors are not fatal for the simulation, the next frame will try again
+        let _ = self.try_render(model, cutter_center);
+    }
+
+    fn try_render(&mut self, model: &SimModel, cutter_center: &Vector) -> std::io::Result<()> {
+        let grid = model
+            .grid
+            .as_ref()
+            .expect("Failed to get grid. Internal BUG!");
+        let (cols, rows) = terminal::size()?;
+        let map_rows = rows.saturating_sub(STATUS_LINES).max(1) as usize;
+        let max_px_w = cols.max(1) as usize;
+        let max_px_h = map_rows * 2;
+
+        // Number of grid cells per terminal pixel, same in both directions to keep the aspect ratio
+        let scale =
+            (grid.cells_x as f64 / max_px_w as f64).max(grid.cells_y as f64 / max_px_h as f64);
+        let px_w = ((grid.cells_x as f64 / scale).ceil() as usize).clamp(1, max_px_w);
+        let px_h = ((grid.cells_y as f64 / scale).ceil() as usize).clamp(1, max_px_h);
+
+        let cutter_px = (
+            (grid.world_coordinate_to_grid_x(cutter_center.x) as f64 / scale) as usize,
+            (grid.world_coordinate_to_grid_y(cutter_center.y) as f64 / scale) as usize,
+        );
+
+        queue!(self.out, cursor::MoveTo(0, 0))?;
+        for row in 0..px_h.div_ceil(2) {
+            queue!(self.out, cursor::MoveTo(0, row as u16))?;
+            let mut last_colors: Option<([u8; 3], [u8; 3])> = None;
+            for px in 0..px_w {
+                // Screen y grows downwards, world y grows upwards
+                let upper = self.pixel_color(model, px, px_h - 1 - row * 2, scale, cutter_px);
+                let lower = if row * 2 + 1 < px_h {
+                    self.pixel_color(model, px, px_h - 2 - row * 2, scale, cutter_px)
+                } else {
+                    [0, 0, 0]
+                };
+                if last_colors != Some((upper, lower)) {
+                    queue!(
+                        self.out,
+                        SetColors(Colors::new(to_color(upper), to_color(lower)))
+                    )?;
+                    last_colors = Some((upper, lower));
+                }
+                queue!(self.out, Print(HALF_BLOCK))?;
+            }
+            queue!(
+                self.out,
+                ResetColor,
+                terminal::Clear(terminal::ClearType::UntilNewLine)
+            )?;
+        }
+
+        let status_row = px_h.div_ceil(2) as u16;
+        queue!(
+            self.out,
+            cursor::MoveTo(0, status_row),
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )?;
+        for (i, line) in self.status_lines(model, cutter_center).iter().enumerate() {
+            let line: String = line.chars().take(cols as usize).collect();
+            queue!(
+                self.out,
+                cursor::MoveTo(0, status_row + i as u16),
+                Print(line)
+            )?;
+        }
+        self.out.flush()
+    }
+
+    /// Get the color of one terminal pixel by downsampling the block of grid cells it covers.
+    /// Obstacles take precedence, otherwise the most visited cell decides the shade.
+    fn pixel_color(
+        &self,
+        model: &SimModel,
+        px: usize,
+        py: usize,
+        scale: f64,
+        cutter_px: (usize, usize),
+    ) -> [u8; 3] {
+        if (px, py) == cutter_px {
+            return CUTTER_COLOR;
+        }
+        let grid = model
+            .grid
+            .as_ref()
+            .expect("Failed to get grid. Internal BUG!");
+        let x0 = ((px as f64 * scale) as usize).min(grid.cells_x - 1);
+        let x1 = (((px + 1) as f64 * scale) as usize).clamp(x0 + 1, grid.cells_x);
+        let y0 = ((py as f64 * scale) as usize).min(grid.cells_y - 1);
+        let y1 = (((py + 1) as f64 * scale) as usize).clamp(y0 + 1, grid.cells_y);
+        let step_x = (x1 - x0).div_ceil(MAX_SAMPLES_PER_AXIS).max(1);
+        let step_y = (y1 - y0).div_ceil(MAX_SAMPLES_PER_AXIS).max(1);
+
+        let mut max_visited = 0;
+        let mut center = false;
+        for x in (x0..x1).step_by(step_x) {
+            for y in (y0..y1).step_by(step_y) {
+                match &grid.cells[x][y] {
+                    Cell::Obstacle => return self.theme.obstacle_color,
+                    Cell::VirtualFence => return self.theme.virtual_fence_color,
+                    Cell::NoCut => return self.theme.no_cut_color,
+                    Cell::Covered(info) => max_visited = max_visited.max(info.times_visited),
+                    Cell::CenterPoint(info) => {
+                        max_visited = max_visited.max(info.times_visited);
+                        center = true;
+                    }
+                    Cell::Empty => {}
+                }
+            }
+        }
+
+        if center && model.track_center {
+            self.theme.center_color
+        } else if max_visited > 0 {
+            self.theme.get_coverage_color(max_visited)
+        } else {
+            self.theme.grid_background_color
+        }
+    }
+
+    fn status_lines(&self, model: &SimModel, cutter_center: &Vector) -> Vec<String> {
+        let grid = model
+            .grid
+            .as_ref()
+            .expect("Failed to get grid. Internal BUG!");
+        let (covered, percent) = grid.get_coverage();
+        let total = grid.get_lawn_cell_count();
+        let t = model.sim_time_elapsed as u64;
+
+        let mut first = format!(
+            "Coverage: {:>6.2}% ({}/{} cells)  Bounces: {}  Distance: {:.2}  Sim-Time: {:02}:{:02}:{:02}",
+            percent,
+            covered,
+            total,
+            model.segment_number,
+            model.distance_covered,
+            t / 3600,
+            (t % 3600) / 60,
+            t % 60
+        );
+        if model.battery_run_time > 0.0 {
+            first.push_str(&format!(
+                "  Battery: {:>5.1}% (charges: {})",
+                model.battery_charge_left, model.battery_charge_count
+            ));
+        }
+
+        let second = format!(
+            "Position: ({:.2}, {:.2})  Steps: {}  Speed: {} steps/frame{}",
+            cutter_center.x,
+            cutter_center.y,
+            model.sim_steps,
+            self.steps_per_render(),
+            if self.paused { "  [PAUSED]" } else { "" }
+        );
+        let third = "[space] pause/resume  [s] single step  [+/-] speed  [q] quit".to_string();
+        vec![first, second, third]
+    }
+}
+
+impl SimObserver for TuiView {
+    fn on_step(
+        &mut self,
+        model: &mut SimModel,
+        cutter_center: &Vector,
+        _events: &StepEvents,
+    ) -> ControlFlow<()> {
+        self.update(model, cutter_center);
+        if self.quit_requested() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+impl Drop for TuiView {
+    fn drop(&mut self) {
+        restore_terminal(&mut self.out);
+    }
+}
+
+/// True while a view owns the terminal
+static TERMINAL_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Leave the alternate screen and raw mode if a view has taken the terminal
+fn restore_terminal(out: &mut impl Write) {
+    if !TERMINAL_TAKEN.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let _ = execute!(out, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
+
+/// Restore the terminal before the panic message is printed. The hook runs before `Drop` of the
+/// view, so without it the message would be written in raw mode on the alternate screen and lost.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal(&mut std::io::stdout());
+            previous_hook(info);
+        }));
+    });
+}
+
+fn to_color(c: [u8; 3]) -> Color {
+    Color::Rgb {
+        r: c[0],
+        g: c[1],
+        b: c[2],
+    }
+}